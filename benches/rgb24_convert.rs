@@ -0,0 +1,60 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! RGBA to RGB24 conversion throughput on a 1024x1024 rectangle.
+//!
+//! Run with: `cargo bench --bench rgb24_convert`
+//!
+//! Compares the per-byte push loop Tight full-color encoding used to run with
+//! the block-based `rgba_to_rgb24_bytes`.
+
+use rfb_encodings::rgba_to_rgb24_bytes;
+use std::hint::black_box;
+use std::time::Instant;
+
+const PIXELS: usize = 1024 * 1024;
+const ITERATIONS: u32 = 50;
+
+fn naive(pixels: &[u8]) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(pixels.len() / 4 * 3);
+    for chunk in pixels.chunks_exact(4) {
+        rgb.push(chunk[0]);
+        rgb.push(chunk[1]);
+        rgb.push(chunk[2]);
+    }
+    rgb
+}
+
+fn bench(name: &str, pixels: &[u8], convert: fn(&[u8]) -> Vec<u8>) {
+    black_box(convert(black_box(pixels)));
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(convert(black_box(pixels)));
+    }
+    let per_frame = start.elapsed() / ITERATIONS;
+    #[allow(clippy::cast_precision_loss)] // Reporting only
+    let mib_per_sec = (pixels.len() as f64 / (1024.0 * 1024.0)) / per_frame.as_secs_f64();
+
+    println!("{name:>8}: {per_frame:>10?} per rectangle, {mib_per_sec:>8.0} MiB/s RGBA in");
+}
+
+fn main() {
+    #[allow(clippy::cast_possible_truncation)] // Pattern values wrap intentionally
+    let pixels: Vec<u8> = (0..PIXELS * 4).map(|i| (i * 7) as u8).collect();
+    assert_eq!(naive(&pixels), rgba_to_rgb24_bytes(&pixels));
+
+    bench("naive", &pixels, naive);
+    bench("chunked", &pixels, rgba_to_rgb24_bytes);
+}
@@ -0,0 +1,79 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ZRLE per-tile overhead benchmark on a 1080p frame.
+//!
+//! Run with: `cargo bench --bench zrle_tiles`
+//!
+//! Reports the average time per 64x64 tile for pixel formats that take the
+//! 3-byte CPIXEL path (RGBA32) and the full-width path (RGB565).
+
+use flate2::{Compress, Compression};
+use rfb_encodings::zrle::encode_zrle_persistent;
+use rfb_encodings::PixelFormat;
+use std::hint::black_box;
+use std::time::Instant;
+
+const WIDTH: u16 = 1920;
+const HEIGHT: u16 = 1080;
+const ITERATIONS: u32 = 20;
+
+/// Desktop-like frame: flat panels, text-like stripes and a gradient band.
+#[allow(clippy::cast_possible_truncation)] // Pattern values wrap intentionally
+fn make_frame(bpp: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(WIDTH as usize * HEIGHT as usize * bpp);
+    for y in 0..HEIGHT as usize {
+        for x in 0..WIDTH as usize {
+            let value: u32 = if y < 360 {
+                0x00C0_C0C0
+            } else if y < 720 {
+                if (x / 3 + y) % 7 == 0 {
+                    0
+                } else {
+                    0x00FF_FFFF
+                }
+            } else {
+                ((x * 255 / WIDTH as usize) as u32) | (((y * 3) as u32 & 0xFF) << 8)
+            };
+            data.extend_from_slice(&value.to_le_bytes()[..bpp]);
+        }
+    }
+    data
+}
+
+fn bench(name: &str, pf: &PixelFormat) {
+    let data = make_frame((pf.bits_per_pixel / 8) as usize);
+    let tiles = (WIDTH as usize).div_ceil(64) * (HEIGHT as usize).div_ceil(64);
+
+    // Warm up
+    let mut compressor = Compress::new(Compression::fast(), true);
+    black_box(encode_zrle_persistent(&data, WIDTH, HEIGHT, pf, &mut compressor).unwrap());
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(encode_zrle_persistent(&data, WIDTH, HEIGHT, pf, &mut compressor).unwrap());
+    }
+    let elapsed = start.elapsed();
+    let per_tile = elapsed / (ITERATIONS * u32::try_from(tiles).unwrap());
+
+    println!(
+        "{name:>8}: {:>8.2} ms/frame, {per_tile:>10?} per tile ({tiles} tiles)",
+        elapsed.as_secs_f64() * 1000.0 / f64::from(ITERATIONS)
+    );
+}
+
+fn main() {
+    bench("rgba32", &PixelFormat::rgba32());
+    bench("rgb565", &PixelFormat::rgb565());
+}
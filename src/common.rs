@@ -15,7 +15,19 @@
 //! Common helper functions shared across multiple VNC encodings.
 
 use bytes::{BufMut, BytesMut};
+#[cfg(any(feature = "zrle", feature = "tight"))]
+use flate2::Compression;
+use flate2::{Compress, FlushCompress, Status};
+#[cfg(any(feature = "zrle", feature = "tight"))]
+use miniz_oxide::deflate::core::{
+    create_comp_flags_from_zip_params, CompressionStrategy, CompressorOxide,
+};
+#[cfg(any(feature = "zrle", feature = "tight"))]
+use miniz_oxide::{MZError, MZFlush, MZStatus};
 use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io;
+use std::time::Duration;
 
 /// Represents a subrectangle in RRE/CoRRE/Hextile encoding.
 #[derive(Debug)]
@@ -32,6 +44,248 @@ pub struct Subrect {
     pub h: u16,
 }
 
+/// Order in which framebuffer rows are stored in the input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RowOrder {
+    /// The first row is the top of the image (the RFB wire order).
+    #[default]
+    TopDown,
+    /// The first row is the bottom of the image, as on some display surfaces.
+    /// Encoders read rows in reverse so the client still receives a top-down image.
+    BottomUp,
+}
+
+/// Reverse the order of `row_bytes`-sized rows in place.
+/// Trailing bytes short of a full row are left untouched.
+pub fn flip_rows(data: &mut [u8], row_bytes: usize) {
+    if row_bytes == 0 {
+        return;
+    }
+    let rows = data.len() / row_bytes;
+    for top in 0..rows / 2 {
+        let bottom = rows - 1 - top;
+        let (upper, lower) = data.split_at_mut(bottom * row_bytes);
+        upper[top * row_bytes..(top + 1) * row_bytes].swap_with_slice(&mut lower[..row_bytes]);
+    }
+}
+
+/// Reports whether `data` is byte-identical to the frame last passed with `prev_hash`,
+/// so callers can skip encoding static frames entirely.
+///
+/// `prev_hash` holds a 64-bit hash of the previous frame (start with 0) and is
+/// updated to the hash of `data`. Two different frames are reported unchanged only
+/// on a hash collision, about 1 in 2^64. Hash values are not stable across Rust
+/// releases, so do not persist them.
+#[must_use]
+pub fn frame_unchanged(prev_hash: &mut u64, data: &[u8]) -> bool {
+    let mut hasher = std::hash::DefaultHasher::new();
+    hasher.write(data);
+    let hash = hasher.finish();
+    std::mem::replace(prev_hash, hash) == hash
+}
+
+/// Stable 64-bit hash (FNV-1a) of an encoded payload, for deduplicating identical
+/// rectangles, e.g. in a proxy that serves several clients.
+///
+/// Unlike the hash behind [`frame_unchanged`], the value is the same on every
+/// platform and Rust release, so it may be stored or compared across processes.
+/// Equal payloads always hash equal; different payloads collide with probability
+/// about 1 in 2^64.
+#[must_use]
+pub fn output_hash(encoded: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    encoded.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Share of `frame_budget` available to each of `rect_count` rectangles, e.g. about
+/// 4.2 ms each for 4 rectangles at 60 fps (16.7 ms per frame).
+///
+/// A frame with no rectangles gets the whole budget. Combine with
+/// [`should_downgrade`] to switch to cheaper settings (lower compression, JPEG)
+/// once a frame runs late.
+#[must_use]
+pub fn encode_time_budget(frame_budget: Duration, rect_count: usize) -> Duration {
+    frame_budget / u32::try_from(rect_count.max(1)).unwrap_or(u32::MAX)
+}
+
+/// Whether encoding that has taken `elapsed` is over `budget` and the remaining
+/// rectangles should use faster settings.
+///
+/// Checked cooperatively between rectangles: pass a rectangle's own time and its
+/// [`encode_time_budget`], or the time spent on the frame so far and the budget of
+/// the rectangles done, to catch up over the rest of the frame.
+#[must_use]
+pub fn should_downgrade(elapsed: Duration, budget: Duration) -> bool {
+    elapsed > budget
+}
+
+/// Largest horizontal or vertical shift, in pixels, that [`detect_region_move`] searches.
+pub const MOVE_SEARCH_RADIUS: u16 = 64;
+
+/// Finds where the pixels of `region` in `cur` were in `prev`, for sending a moved
+/// window or block as a `CopyRect` instead of re-encoding it.
+///
+/// `prev` and `cur` are RGBA frames of `width` x `height`, and `region` is
+/// (x, y, width, height) in `cur`. Returns the move `(dx, dy)` such that the region
+/// matches `prev` at `(x - dx, y - dy)`, which is the `CopyRect` source. Shifts of
+/// up to [`MOVE_SEARCH_RADIUS`] in each direction are tried nearest first, so the
+/// cost is bounded by the region size times the window, and most candidates are
+/// rejected on their first row. A region that did not change (offset 0, 0), whose
+/// source would lie outside the frame, or that matches nowhere in the window gives
+/// `None`, as do frames or regions out of bounds.
+#[must_use]
+pub fn detect_region_move(
+    prev: &[u8],
+    cur: &[u8],
+    width: u16,
+    height: u16,
+    region: (u16, u16, u16, u16),
+) -> Option<(i16, i16)> {
+    let (x, y, w, h) = (
+        i32::from(region.0),
+        i32::from(region.1),
+        i32::from(region.2),
+        i32::from(region.3),
+    );
+    let (fb_w, fb_h) = (i32::from(width), i32::from(height));
+    let frame_len = width as usize * height as usize * 4;
+    if w == 0 || h == 0 || x + w > fb_w || y + h > fb_h {
+        return None;
+    }
+    if prev.len() < frame_len || cur.len() < frame_len {
+        return None;
+    }
+
+    let row_bytes = width as usize * 4;
+    let span = region.2 as usize * 4;
+    // Only called with in-bounds coordinates, so the casts never see negatives
+    #[allow(clippy::cast_sign_loss)]
+    let start = |left: i32, top: i32| top as usize * row_bytes + left as usize * 4;
+    let matches_at = |dx: i32, dy: i32| {
+        let (sx, sy) = (x - dx, y - dy);
+        sx >= 0
+            && sy >= 0
+            && sx + w <= fb_w
+            && sy + h <= fb_h
+            && (0..h).all(|r| {
+                let (at_cur, at_prev) = (start(x, y + r), start(sx, sy + r));
+                cur[at_cur..at_cur + span] == prev[at_prev..at_prev + span]
+            })
+    };
+
+    // An unchanged region is no move, even if a uniform or repeating pattern would
+    // also match at some shift
+    if matches_at(0, 0) {
+        return None;
+    }
+
+    let radius = i32::from(MOVE_SEARCH_RADIUS);
+    for ring in 1..=radius {
+        for dy in -ring..=ring {
+            // Interior rows of the ring only have its left and right ends
+            let step = if dy.abs() == ring {
+                1
+            } else {
+                usize::try_from(2 * ring).unwrap_or(1)
+            };
+            for dx in (-ring..=ring).step_by(step) {
+                if matches_at(dx, dy) {
+                    return Some((i16::try_from(dx).ok()?, i16::try_from(dy).ok()?));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Number of ZRLE tiles or Tight rectangles sent with each coding mode, for
+/// reporting how frames were encoded (e.g. 40% solid, 30% palette RLE).
+///
+/// ZRLE tiles and Tight rectangles map onto the same modes so one report can
+/// cover both. Counts accumulate across calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubEncodingHistogram {
+    /// ZRLE solid tiles and Tight fill rectangles.
+    pub solid: usize,
+    /// ZRLE raw tiles and Tight rectangles with the copy filter.
+    pub raw: usize,
+    /// ZRLE plain RLE tiles.
+    pub plain_rle: usize,
+    /// ZRLE palette RLE tiles.
+    pub palette_rle: usize,
+    /// ZRLE packed palette tiles and Tight rectangles with the palette filter.
+    pub packed_palette: usize,
+    /// Tight rectangles with the gradient filter.
+    pub gradient: usize,
+    /// Tight JPEG rectangles, with or without a separate alpha plane.
+    pub jpeg: usize,
+    /// Tight PNG rectangles.
+    pub png: usize,
+}
+
+impl SubEncodingHistogram {
+    /// Total number of tiles and rectangles counted.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.entries().iter().map(|&(_, count)| count).sum()
+    }
+
+    /// Counts labelled by mode, in field order, e.g. for a dashboard legend.
+    #[must_use]
+    pub fn entries(&self) -> [(&'static str, usize); 8] {
+        [
+            ("solid", self.solid),
+            ("raw", self.raw),
+            ("plain_rle", self.plain_rle),
+            ("palette_rle", self.palette_rle),
+            ("packed_palette", self.packed_palette),
+            ("gradient", self.gradient),
+            ("jpeg", self.jpeg),
+            ("png", self.png),
+        ]
+    }
+}
+
+/// Pre-multiplies the RGB channels of RGBA pixels by their alpha in place.
+///
+/// Channels are rounded to nearest, so a fully transparent pixel becomes RGB 0 and
+/// a fully opaque one is unchanged. Trailing bytes short of a pixel are ignored.
+pub fn premultiply_alpha(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        for channel in &mut pixel[..3] {
+            *channel = premultiply_channel(*channel, alpha);
+        }
+    }
+}
+
+/// Reverses [`premultiply_alpha`], turning pre-multiplied RGBA back into straight alpha.
+///
+/// Channels are rounded to nearest and clamped to 255, since a malformed pixel may
+/// carry a channel larger than its alpha. Pixels with alpha 0 get RGB 0. The round
+/// trip reconstructs each channel within ±1 for alpha of 128 and above; lower alpha
+/// keeps fewer distinct levels, so the error grows to about `255 / (2 * alpha)`.
+/// Trailing bytes short of a pixel are ignored.
+pub fn unpremultiply_alpha(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = u32::from(pixel[3]);
+        for channel in &mut pixel[..3] {
+            *channel = (u32::from(*channel) * 255 + alpha / 2)
+                .checked_div(alpha)
+                .map_or(0, |straight| u8::try_from(straight).unwrap_or(u8::MAX));
+        }
+    }
+}
+
+/// Pre-multiplies a color channel by alpha, rounding to nearest.
+#[inline]
+#[allow(clippy::cast_possible_truncation)] // (c * a + 127) / 255 is at most 255
+pub(crate) fn premultiply_channel(channel: u8, alpha: u8) -> u8 {
+    ((u16::from(channel) * u16::from(alpha) + 127) / 255) as u8
+}
+
 /// Convert RGBA (4 bytes/pixel) to RGB24 pixel values in VNC pixel format.
 /// Our pixel format has: `red_shift=0`, `green_shift=8`, `blue_shift=16`, little-endian
 /// So pixel = (R << 0) | (G << 8) | (B << 16) = 0x00BBGGRR
@@ -46,7 +300,59 @@ pub fn rgba_to_rgb24_pixels(data: &[u8]) -> Vec<u32> {
         .collect()
 }
 
+/// Convert RGBA (4 bytes/pixel) to packed RGB (3 bytes/pixel) by dropping alpha.
+/// Works on blocks of 4 pixels (16 bytes in, 12 bytes out) to avoid per-byte
+/// bounds checks on large rectangles, one shuffle per block with the `simd`
+/// feature. Trailing bytes short of a pixel are ignored.
+#[must_use]
+pub fn rgba_to_rgb24_bytes(data: &[u8]) -> Vec<u8> {
+    let pixels = data.len() / 4;
+    let mut rgb = vec![0u8; pixels * 3];
+    #[cfg(feature = "simd")]
+    let done = crate::simd::pack_rgb24(&data[..pixels * 4], &mut rgb);
+    #[cfg(not(feature = "simd"))]
+    let done = 0;
+    pack_rgb24_scalar(&data[done * 4..pixels * 4], &mut rgb[done * 3..]);
+    rgb
+}
+
+/// Scalar body of [`rgba_to_rgb24_bytes`], and the reference for its SIMD path.
+pub(crate) fn pack_rgb24_scalar(data: &[u8], rgb: &mut [u8]) {
+    let (src_blocks, src_tail) = data.as_chunks::<16>();
+    let (dst_blocks, dst_tail) = rgb.as_chunks_mut::<12>();
+    for (s, d) in src_blocks.iter().zip(dst_blocks) {
+        *d = [
+            s[0], s[1], s[2], s[4], s[5], s[6], s[8], s[9], s[10], s[12], s[13], s[14],
+        ];
+    }
+    for (s, d) in src_tail.chunks_exact(4).zip(dst_tail.chunks_exact_mut(3)) {
+        d.copy_from_slice(&s[..3]);
+    }
+}
+
+/// Split RGBA (4 bytes/pixel) into separate R, G, B and A planes, for per-channel
+/// analysis such as entropy estimates. Each plane is allocated once and filled in a
+/// single pass. Trailing bytes short of a pixel are ignored.
+#[must_use]
+pub fn split_channels(rgba: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>) {
+    let pixels = rgba.len() / 4;
+    let mut planes = (
+        Vec::with_capacity(pixels),
+        Vec::with_capacity(pixels),
+        Vec::with_capacity(pixels),
+        Vec::with_capacity(pixels),
+    );
+    for &[r, g, b, a] in rgba.as_chunks::<4>().0 {
+        planes.0.push(r);
+        planes.1.push(g);
+        planes.2.push(b);
+        planes.3.push(a);
+    }
+    planes
+}
+
 /// Find the most common color in the pixel array.
+/// Ties go to the color that appears first.
 #[must_use]
 pub fn get_background_color(pixels: &[u32]) -> u32 {
     if pixels.is_empty() {
@@ -58,10 +364,18 @@ pub fn get_background_color(pixels: &[u32]) -> u32 {
         *counts.entry(pixel).or_insert(0) += 1;
     }
 
-    counts
-        .into_iter()
-        .max_by_key(|(_, count)| *count)
-        .map_or(pixels[0], |(color, _)| color)
+    most_frequent_color(pixels, &counts)
+}
+
+/// Pick the most frequent color, breaking ties by first appearance in `pixels`.
+/// Keeps the choice independent of `HashMap` iteration order so output is deterministic.
+fn most_frequent_color(pixels: &[u32], counts: &HashMap<u32, usize>) -> u32 {
+    let max = counts.values().copied().max().unwrap_or(0);
+    pixels
+        .iter()
+        .copied()
+        .find(|pixel| counts.get(pixel) == Some(&max))
+        .unwrap_or(pixels[0])
 }
 
 /// Find subrectangles of non-background pixels.
@@ -188,13 +502,17 @@ pub fn analyze_tile_colors(pixels: &[u32]) -> (bool, bool, u32, u32) {
         return (true, true, pixels[0], 0);
     }
 
+    let bg = most_frequent_color(pixels, &colors);
+
     if colors.len() == 2 {
-        let mut sorted: Vec<_> = colors.into_iter().collect();
-        sorted.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
-        return (false, true, sorted[0].0, sorted[1].0);
+        let fg = colors
+            .keys()
+            .copied()
+            .find(|&color| color != bg)
+            .unwrap_or(bg);
+        return (false, true, bg, fg);
     }
 
-    let bg = get_background_color(pixels);
     (false, false, bg, 0)
 }
 
@@ -216,74 +534,94 @@ pub fn put_pixel24(buf: &mut BytesMut, pixel: u32) {
 }
 
 /// Translate a single RGB pixel to the client's pixel format for TIGHT encoding.
-/// This properly handles `red_shift`, `green_shift`, `blue_shift` values for
-/// correct pixel format translation.
 ///
-/// IMPORTANT: TIGHT encoding uses 24-bit pixel format (3 bytes) when the client has
-/// depth=24 with 8-bit color components, even if `bits_per_pixel=32`. This is a
-/// standard optimization for reducing bandwidth.
+/// Returns the pixel's `TPIXEL`: for a client with depth 24
+/// and 8-bit color components, the red, green and blue bytes, even if
+/// `bits_per_pixel=32`; otherwise the full pixel in the client's format.
 ///
 /// Input pixel format: RGB stored in bits 0-23 (R=bits 0-7, G=bits 8-15, B=bits 16-23)
-/// Output: Translated bytes in client's pixel format (3 or 4 bytes depending on format)
 #[must_use]
 #[allow(clippy::cast_possible_truncation)] // Intentionally extracting byte components from pixel values
 pub fn translate_pixel_to_client_format(pixel: u32, client_format: &crate::PixelFormat) -> Vec<u8> {
     use crate::translate::translate_pixels;
     use crate::PixelFormat;
 
-    // Check if we should use 24-bit format (3 bytes) instead of 32-bit (4 bytes)
-    let use_24bit = client_format.depth == 24
+    let rgba_bytes = [
+        (pixel & 0xFF) as u8,         // R
+        ((pixel >> 8) & 0xFF) as u8,  // G
+        ((pixel >> 16) & 0xFF) as u8, // B
+        0,                            // A
+    ];
+    let translated = translate_pixels(&rgba_bytes, &PixelFormat::rgba32(), client_format);
+    let mut tpixel = Vec::with_capacity(4);
+    put_tpixel(
+        &mut tpixel,
+        pixel_value(&translated, client_format.big_endian_flag != 0),
+        client_format,
+    );
+    tpixel
+}
+
+/// Size of a `TPIXEL` in `client_format`: 3 bytes for 24-bit depth formats,
+/// otherwise the pixel size
+pub(crate) fn tpixel_len(client_format: &crate::PixelFormat) -> usize {
+    if client_format.depth == 24
         && client_format.red_max == 255
         && client_format.green_max == 255
-        && client_format.blue_max == 255;
-
-    if use_24bit {
-        // Send only 3 bytes for 24-bit depth clients (TIGHT optimization)
-        // Match Pack24 behavior exactly: pack pixel then extract using shifts
-
-        // Pack RGB components into client's pixel format
-        let r = (pixel & 0xFF) as u8;
-        let g = ((pixel >> 8) & 0xFF) as u8;
-        let b = ((pixel >> 16) & 0xFF) as u8;
-
-        // Create pixel value using client's bit layout
-        let pixel_value = (u32::from(r) << client_format.red_shift)
-            | (u32::from(g) << client_format.green_shift)
-            | (u32::from(b) << client_format.blue_shift);
-
-        // Extract 3 bytes in the order they appear in memory (like Pack24)
-        // For little-endian with shifts 0/8/16: pixel_value = 0x00BBGGRR
-        // So bytes are [RR, GG, BB] in memory order
-        if client_format.big_endian_flag != 0 {
-            // Big-endian: extract from high to low
-            vec![
-                (pixel_value >> 16) as u8,
-                (pixel_value >> 8) as u8,
-                pixel_value as u8,
-            ]
-        } else {
-            // Little-endian: extract from low to high
-            vec![
-                pixel_value as u8,
-                (pixel_value >> 8) as u8,
-                (pixel_value >> 16) as u8,
-            ]
-        }
+        && client_format.blue_max == 255
+    {
+        3
     } else {
-        // Create RGBA32 bytes for this single pixel
-        let rgba_bytes = [
-            (pixel & 0xFF) as u8,         // R
-            ((pixel >> 8) & 0xFF) as u8,  // G
-            ((pixel >> 16) & 0xFF) as u8, // B
-            0,                            // A
-        ];
+        (client_format.bits_per_pixel / 8) as usize
+    }
+}
+
+/// Value of a pixel stored with the given byte order
+pub(crate) fn pixel_value(bytes: &[u8], big_endian: bool) -> u32 {
+    if big_endian {
+        bytes
+            .iter()
+            .fold(0, |value, &b| (value << 8) | u32::from(b))
+    } else {
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &b| (value << 8) | u32::from(b))
+    }
+}
 
-        // Use existing translation logic to convert to client format
-        let server_format = PixelFormat::rgba32();
-        let translated = translate_pixels(&rgba_bytes, &server_format, client_format);
+/// Appends the `TPIXEL` for pixel `value` in `client_format`.
+///
+/// 24-bit depth formats send the red, green and blue channels as one byte each, in
+/// that order whatever their shifts; other formats send the whole pixel in the
+/// format's byte order.
+#[allow(clippy::cast_possible_truncation)] // Channels of 24-bit depth formats are 8 bits
+pub(crate) fn put_tpixel(out: &mut Vec<u8>, value: u32, client_format: &crate::PixelFormat) {
+    let len = tpixel_len(client_format);
+    if len == 3 {
+        for shift in [
+            client_format.red_shift,
+            client_format.green_shift,
+            client_format.blue_shift,
+        ] {
+            out.push(((value >> shift) & 0xFF) as u8);
+        }
+    } else if client_format.big_endian_flag != 0 {
+        out.extend_from_slice(&value.to_be_bytes()[4 - len..]);
+    } else {
+        out.extend_from_slice(&value.to_le_bytes()[..len]);
+    }
+}
 
-        // Return as Vec for easier handling
-        translated.to_vec()
+/// Pixel value in `client_format` of a `TPIXEL` written by [`put_tpixel`]
+#[cfg(feature = "tight")]
+pub(crate) fn tpixel_value(tpixel: &[u8], client_format: &crate::PixelFormat) -> u32 {
+    if tpixel_len(client_format) == 3 {
+        (u32::from(tpixel[0]) << client_format.red_shift)
+            | (u32::from(tpixel[1]) << client_format.green_shift)
+            | (u32::from(tpixel[2]) << client_format.blue_shift)
+    } else {
+        pixel_value(tpixel, client_format.big_endian_flag != 0)
     }
 }
 
@@ -314,3 +652,727 @@ pub fn build_palette(pixels: &[u32]) -> Vec<u32> {
     palette.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
     palette.into_iter().map(|(color, _)| color).collect()
 }
+
+/// Append the 4-byte big-endian length prefix used by Zlib, `ZlibHex` and ZRLE.
+/// Payloads of 4 GiB or more cannot be framed and are rejected rather than truncated.
+pub(crate) fn put_length_prefix(buf: &mut BytesMut, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("compressed payload of {len} bytes does not fit a 4-byte length prefix"),
+        )
+    })?;
+    buf.put_u32(len);
+    Ok(())
+}
+
+/// Bytes that end every zlib sync flush: the LEN/NLEN of an empty stored block.
+pub const ZLIB_SYNC_MARKER: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// Compresses `input` on a persistent zlib stream, ending with a sync flush.
+///
+/// Deflate is called until all input is consumed and the output buffer is not full,
+/// so the flush is never cut short by a small buffer. C zlib emits nothing when a
+/// stream has no new input since its last flush; with `strict_flush` an empty stored
+/// block is appended in that case, so the output still ends with
+/// [`ZLIB_SYNC_MARKER`]. The previous sync flush left the stream byte-aligned, which
+/// makes the extra block valid.
+#[allow(clippy::cast_possible_truncation)] // Input consumed by one call is bounded by `input.len()`
+pub(crate) fn compress_sync_flush(
+    compressor: &mut impl DeflateStream,
+    input: &[u8],
+    strict_flush: bool,
+) -> io::Result<Vec<u8>> {
+    // From zlib.h: compressed size <= uncompressed + (uncompressed/1000) + 12
+    let mut output = Vec::with_capacity(input.len() + input.len() / 1000 + 64);
+    let mut consumed = 0;
+    loop {
+        let before_in = compressor.total_in();
+        compressor.compress_vec(&input[consumed..], &mut output, FlushCompress::Sync)?;
+        consumed += (compressor.total_in() - before_in) as usize;
+        if consumed == input.len() && output.len() < output.capacity() {
+            break;
+        }
+        output.reserve(output.capacity());
+    }
+    if strict_flush && !output.ends_with(&ZLIB_SYNC_MARKER) {
+        output.push(0x00);
+        output.extend_from_slice(&ZLIB_SYNC_MARKER);
+    }
+    Ok(output)
+}
+
+/// Compresses `input` as one complete zlib stream on a fresh `compressor`.
+#[cfg(feature = "zrle")]
+#[allow(clippy::cast_possible_truncation)] // Input consumed by one call is bounded by `input.len()`
+pub(crate) fn compress_finish(
+    compressor: &mut impl DeflateStream,
+    input: &[u8],
+) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 1000 + 64);
+    let mut consumed = 0;
+    loop {
+        let before_in = compressor.total_in();
+        let status =
+            compressor.compress_vec(&input[consumed..], &mut output, FlushCompress::Finish)?;
+        consumed += (compressor.total_in() - before_in) as usize;
+        if status == Status::StreamEnd {
+            return Ok(output);
+        }
+        output.reserve(output.capacity());
+    }
+}
+
+/// Deflate strategy for the zlib streams of the Tight and ZRLE encoders.
+///
+/// `flate2` has no strategy setting, so streams using anything but
+/// [`ZlibStrategy::Default`] are driven by `miniz_oxide` directly. The default keeps
+/// the plain `flate2` stream, so its output is unchanged.
+#[cfg(any(feature = "zrle", feature = "tight"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZlibStrategy {
+    /// zlib's `Z_DEFAULT_STRATEGY`.
+    #[default]
+    Default,
+    /// `Z_FILTERED`: only matches of 5 bytes or more, for filtered data such as the
+    /// residuals of Tight's gradient filter.
+    Filtered,
+    /// `Z_HUFFMAN_ONLY`: no string matching, Huffman coding only.
+    HuffmanOnly,
+    /// `Z_RLE`: matches at distance one only. Faster than the default and good on
+    /// runs of one color, but repeats further apart, such as the same glyph twice on
+    /// a line, are no longer found.
+    Rle,
+}
+
+#[cfg(any(feature = "zrle", feature = "tight"))]
+impl ZlibStrategy {
+    fn miniz(self) -> CompressionStrategy {
+        match self {
+            Self::Default => CompressionStrategy::Default,
+            Self::Filtered => CompressionStrategy::Filtered,
+            Self::HuffmanOnly => CompressionStrategy::HuffmanOnly,
+            Self::Rle => CompressionStrategy::RLE,
+        }
+    }
+}
+
+/// A persistent zlib stream that [`compress_sync_flush`] and [`compress_finish`] can
+/// drive: a `flate2` [`Compress`] or a [`ZlibStream`].
+pub(crate) trait DeflateStream {
+    /// Total bytes consumed from the input so far.
+    fn total_in(&self) -> u64;
+    /// Compresses into the spare capacity of `output`, like [`Compress::compress_vec`].
+    fn compress_vec(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        flush: FlushCompress,
+    ) -> io::Result<Status>;
+}
+
+impl DeflateStream for Compress {
+    fn total_in(&self) -> u64 {
+        Compress::total_in(self)
+    }
+
+    fn compress_vec(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        flush: FlushCompress,
+    ) -> io::Result<Status> {
+        Ok(Compress::compress_vec(self, input, output, flush)?)
+    }
+}
+
+/// A persistent zlib stream with a [`ZlibStrategy`].
+#[cfg(any(feature = "zrle", feature = "tight"))]
+pub(crate) enum ZlibStream {
+    /// The default strategy, on `flate2`
+    Flate(Compress),
+    /// Any other strategy, on `miniz_oxide`
+    Oxide {
+        compressor: Box<CompressorOxide>,
+        total_in: u64,
+        total_out: u64,
+    },
+}
+
+#[cfg(any(feature = "zrle", feature = "tight"))]
+impl ZlibStream {
+    pub(crate) fn new(level: Compression, strategy: ZlibStrategy) -> Self {
+        if strategy == ZlibStrategy::Default {
+            return Self::Flate(Compress::new(level, true));
+        }
+        // Positive window bits select the zlib header, as flate2's `zlib_header`
+        #[allow(clippy::cast_possible_wrap)] // Levels are 0-9
+        let flags =
+            create_comp_flags_from_zip_params(level.level() as i32, 15, strategy.miniz() as i32);
+        Self::Oxide {
+            compressor: Box::new(CompressorOxide::new(flags)),
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    /// Total bytes produced so far.
+    #[cfg(feature = "tight")]
+    pub(crate) fn total_out(&self) -> u64 {
+        match self {
+            Self::Flate(compressor) => compressor.total_out(),
+            Self::Oxide { total_out, .. } => *total_out,
+        }
+    }
+
+    /// Starts a new zlib stream with the same level and strategy.
+    #[cfg(feature = "zrle")]
+    pub(crate) fn reset(&mut self) {
+        match self {
+            Self::Flate(compressor) => compressor.reset(),
+            Self::Oxide {
+                compressor,
+                total_in,
+                total_out,
+            } => {
+                compressor.reset();
+                *total_in = 0;
+                *total_out = 0;
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "zrle", feature = "tight"))]
+impl DeflateStream for ZlibStream {
+    fn total_in(&self) -> u64 {
+        match self {
+            Self::Flate(compressor) => compressor.total_in(),
+            Self::Oxide { total_in, .. } => *total_in,
+        }
+    }
+
+    fn compress_vec(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        flush: FlushCompress,
+    ) -> io::Result<Status> {
+        let (compressor, total_in, total_out) = match self {
+            Self::Flate(compressor) => {
+                return DeflateStream::compress_vec(compressor, input, output, flush)
+            }
+            Self::Oxide {
+                compressor,
+                total_in,
+                total_out,
+            } => (compressor, total_in, total_out),
+        };
+        let flush = match flush {
+            FlushCompress::None => MZFlush::None,
+            FlushCompress::Finish => MZFlush::Finish,
+            _ => MZFlush::Sync,
+        };
+        let start = output.len();
+        output.resize(output.capacity(), 0);
+        let result =
+            miniz_oxide::deflate::stream::deflate(compressor, input, &mut output[start..], flush);
+        output.truncate(start + result.bytes_written);
+        *total_in += result.bytes_consumed as u64;
+        *total_out += result.bytes_written as u64;
+        match result.status {
+            Ok(MZStatus::StreamEnd) => Ok(Status::StreamEnd),
+            Ok(_) => Ok(Status::Ok),
+            Err(MZError::Buf) => Ok(Status::BufError),
+            Err(error) => Err(io::Error::other(format!("deflate failed: {error:?}"))),
+        }
+    }
+}
+
+/// Why an encoded rectangle's framing does not match its length, as reported by
+/// [`crate::tight::validate_framing`] and [`crate::zrle::validate_framing`].
+#[cfg(any(feature = "zrle", feature = "tight"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingError {
+    /// The data ends before the content its framing declares.
+    Truncated {
+        /// Bytes needed to read the next field, counted from the start.
+        expected: usize,
+        /// Bytes present.
+        actual: usize,
+    },
+    /// Bytes follow the declared content.
+    TrailingBytes {
+        /// Bytes the framing declares.
+        expected: usize,
+        /// Bytes present.
+        actual: usize,
+    },
+    /// A Tight control byte whose compression type is unknown.
+    UnknownControl(u8),
+    /// A Tight filter ID that is unknown.
+    UnknownFilter(u8),
+}
+
+#[cfg(any(feature = "zrle", feature = "tight"))]
+impl std::fmt::Display for FramingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated { expected, actual } => write!(
+                f,
+                "Framing: truncated, {actual} bytes present, at least {expected} needed"
+            ),
+            Self::TrailingBytes { expected, actual } => write!(
+                f,
+                "Framing: {} trailing bytes after the {expected} declared",
+                actual - expected
+            ),
+            Self::UnknownControl(control) => {
+                write!(f, "Framing: unknown control byte 0x{control:02x}")
+            }
+            Self::UnknownFilter(filter) => write!(f, "Framing: unknown filter {filter}"),
+        }
+    }
+}
+
+#[cfg(any(feature = "zrle", feature = "tight"))]
+impl std::error::Error for FramingError {}
+
+/// Walks the fields of an encoded rectangle for [`FramingError`] checks.
+#[cfg(any(feature = "zrle", feature = "tight"))]
+pub(crate) struct FramingCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+#[cfg(any(feature = "zrle", feature = "tight"))]
+impl<'a> FramingCursor<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], FramingError> {
+        let end = self.pos + len;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(FramingError::Truncated {
+                expected: end,
+                actual: self.data.len(),
+            })?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    #[cfg(feature = "tight")]
+    pub(crate) fn u8(&mut self) -> Result<u8, FramingError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Checks that every byte has been read.
+    pub(crate) fn finish(self) -> Result<(), FramingError> {
+        if self.pos < self.data.len() {
+            return Err(FramingError::TrailingBytes {
+                expected: self.pos,
+                actual: self.data.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Check that `data` holds at least `width * height` RGBA pixels, so the subrectangle
+/// searches can index it without panicking. `encoding` names the caller in the error.
+pub(crate) fn check_rgba_len(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    encoding: &str,
+) -> io::Result<()> {
+    let expected_size = width as usize * height as usize * 4;
+    if data.len() < expected_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{encoding}: input buffer size mismatch: got {} bytes, expected {expected_size} bytes for {width}x{height} image",
+                data.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Runs an infallible `Encoding::encode` body, turning a panic into a Raw rectangle.
+///
+/// A defensive stopgap so a server worker thread survives encoder bugs: the panic
+/// is caught, logged with the `debug-logging` feature, and the same pixels are
+/// returned Raw-encoded. The infallible API cannot report the switch, so a client
+/// expecting the primary encoding sees a corrupt rectangle, but the connection and
+/// the worker live on. `encoding` names the encoder in the log.
+#[cfg(any(feature = "zrle", feature = "tight"))]
+#[cfg_attr(not(feature = "debug-logging"), allow(unused_variables))]
+pub(crate) fn encode_or_raw_on_panic(
+    encoding: &str,
+    data: &[u8],
+    width: u16,
+    height: u16,
+    encode: impl FnOnce() -> BytesMut,
+) -> BytesMut {
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(encode));
+    result.unwrap_or_else(|_| {
+        #[cfg(feature = "debug-logging")]
+        log::error!("{encoding} encoder panicked on a {width}x{height} rectangle; sending Raw");
+        crate::encode_fallback(crate::ENCODING_RAW, data, width, height, 0, 0).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "tight")]
+    #[test]
+    fn test_tpixels_send_red_green_blue_whatever_the_shifts() {
+        use crate::PixelFormat;
+
+        for pf in [
+            PixelFormat::rgba32(),
+            PixelFormat::bgrx32(),
+            PixelFormat::xrgb32(),
+            PixelFormat::abgr32(),
+        ] {
+            let value = (0x11 << pf.red_shift) | (0x22 << pf.green_shift) | (0x33 << pf.blue_shift);
+            let mut tpixel = Vec::new();
+            put_tpixel(&mut tpixel, value, &pf);
+            assert_eq!(tpixel, [0x11, 0x22, 0x33], "{pf:?}");
+            assert_eq!(tpixel_value(&tpixel, &pf), value);
+        }
+
+        // Other depths send the whole pixel in its byte order
+        let mut pf = PixelFormat::rgb565();
+        let mut tpixel = Vec::new();
+        put_tpixel(&mut tpixel, 0xF800, &pf);
+        pf.big_endian_flag = 1;
+        put_tpixel(&mut tpixel, 0xF800, &pf);
+        assert_eq!(tpixel, [0x00, 0xF8, 0xF8, 0x00]);
+        assert_eq!(tpixel_value(&tpixel[2..], &pf), 0xF800);
+    }
+
+    #[test]
+    fn test_flip_rows() {
+        let mut data = [1, 1, 2, 2, 3, 3, 9];
+        flip_rows(&mut data, 2);
+        assert_eq!(data, [3, 3, 2, 2, 1, 1, 9]);
+    }
+
+    #[test]
+    fn test_frame_unchanged_detects_single_byte_change() {
+        let mut frame = vec![7u8; 64 * 64 * 4];
+        let mut hash = 0;
+        assert!(!frame_unchanged(&mut hash, &frame), "first frame is new");
+        assert!(frame_unchanged(&mut hash, &frame));
+        frame[1234] ^= 1;
+        assert!(!frame_unchanged(&mut hash, &frame));
+        assert!(frame_unchanged(&mut hash, &frame));
+    }
+
+    #[test]
+    fn test_length_prefix_is_big_endian() {
+        let mut buf = BytesMut::new();
+        put_length_prefix(&mut buf, 258).unwrap();
+        assert_eq!(&buf[..], &[0, 0, 1, 2]);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_length_prefix_rejects_4gib() {
+        let mut buf = BytesMut::new();
+        put_length_prefix(&mut buf, u32::MAX as usize).unwrap();
+        assert!(put_length_prefix(&mut buf, u32::MAX as usize + 1).is_err());
+        assert_eq!(buf.len(), 4, "nothing written on error");
+    }
+
+    #[cfg(any(feature = "zrle", feature = "tight"))]
+    #[test]
+    fn test_encoder_panic_falls_back_to_raw() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(8 * 8 * 4).collect();
+        let raw = crate::get_encoder(crate::ENCODING_RAW)
+            .unwrap()
+            .encode(&data, 8, 8, 0, 0);
+
+        let encoded = BytesMut::from(&b"encoded"[..]);
+        let output = encode_or_raw_on_panic("Test", &data, 8, 8, || encoded.clone());
+        assert_eq!(output, encoded);
+
+        let output = std::panic::catch_unwind(|| {
+            encode_or_raw_on_panic("Test", &data, 8, 8, || panic!("encoder bug"))
+        });
+        assert_eq!(
+            output.expect("the panic must not unwind out of encode"),
+            raw
+        );
+
+        // Short input is padded like any other Raw fallback
+        let output = encode_or_raw_on_panic("Test", &data[..16], 8, 8, || panic!("encoder bug"));
+        assert_eq!(output.len(), raw.len());
+    }
+
+    #[test]
+    fn test_premultiply_round_trip_within_one() {
+        let colors = [[255, 255, 255], [255, 0, 0], [200, 100, 50], [17, 128, 240]];
+        for (alpha, tolerance) in [(255u8, 1), (200, 1), (128, 1), (64, 2), (16, 8)] {
+            let mut rgba: Vec<u8> = colors
+                .iter()
+                .flat_map(|&[r, g, b]| [r, g, b, alpha])
+                .collect();
+            let original = rgba.clone();
+            premultiply_alpha(&mut rgba);
+            unpremultiply_alpha(&mut rgba);
+            for (out, input) in rgba.iter().zip(&original) {
+                assert!(
+                    out.abs_diff(*input) <= tolerance,
+                    "alpha {alpha}: {input} came back as {out}"
+                );
+            }
+        }
+
+        let mut half_red = [255, 0, 0, 128];
+        premultiply_alpha(&mut half_red);
+        assert_eq!(half_red, [128, 0, 0, 128]);
+    }
+
+    #[test]
+    fn test_zero_alpha_premultiplies_and_unpremultiplies_to_black() {
+        let mut rgba = [200, 100, 50, 0];
+        premultiply_alpha(&mut rgba);
+        assert_eq!(rgba, [0, 0, 0, 0]);
+
+        let mut malformed = [9, 9, 9, 0, 255, 255, 255, 10];
+        unpremultiply_alpha(&mut malformed);
+        assert_eq!(malformed, [0, 0, 0, 0, 255, 255, 255, 10]);
+    }
+
+    #[test]
+    fn test_split_channels_interleave_back_to_rgba() {
+        let rgba: Vec<u8> = (0..37u8 * 4).map(|i| i.wrapping_mul(71)).collect();
+        let (r, g, b, a) = split_channels(&rgba);
+        assert_eq!(r.len(), 37);
+        assert_eq!(a[1], rgba[7]);
+
+        let interleaved: Vec<u8> = (0..r.len())
+            .flat_map(|i| [r[i], g[i], b[i], a[i]])
+            .collect();
+        assert_eq!(interleaved, rgba);
+
+        // A partial trailing pixel is dropped
+        let (r, _, _, a) = split_channels(&rgba[..10]);
+        assert_eq!((r.len(), a.len()), (2, 2));
+    }
+
+    #[test]
+    fn test_encode_time_budget_splits_frame_budgets() {
+        let frame_60fps = Duration::from_secs(1) / 60;
+        assert_eq!(
+            encode_time_budget(frame_60fps, 4),
+            Duration::from_nanos(4_166_666)
+        );
+        assert_eq!(encode_time_budget(frame_60fps, 1), frame_60fps);
+        assert_eq!(encode_time_budget(frame_60fps, 0), frame_60fps);
+        assert_eq!(
+            encode_time_budget(Duration::from_millis(33), 100),
+            Duration::from_micros(330)
+        );
+        // Counts beyond u32 saturate instead of panicking
+        assert_eq!(
+            encode_time_budget(Duration::from_secs(1), usize::MAX),
+            Duration::ZERO
+        );
+    }
+
+    /// 200x150 RGBA noise frame, the same for every call
+    fn noise_frame() -> Vec<u8> {
+        let mut state = 0x2468_ACE1u32;
+        (0..200 * 150 * 4)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state.to_be_bytes()[0]
+            })
+            .collect()
+    }
+
+    /// Copies a `w` x `h` block of a 200-pixel-wide frame from `src` to `dst`.
+    fn copy_block(frame: &mut [u8], src: (usize, usize), dst: (usize, usize), w: usize, h: usize) {
+        let block: Vec<Vec<u8>> = (0..h)
+            .map(|r| {
+                let start = ((src.1 + r) * 200 + src.0) * 4;
+                frame[start..start + w * 4].to_vec()
+            })
+            .collect();
+        for (r, row) in block.iter().enumerate() {
+            let start = ((dst.1 + r) * 200 + dst.0) * 4;
+            frame[start..start + w * 4].copy_from_slice(row);
+        }
+    }
+
+    #[test]
+    fn test_detect_region_move_finds_shifted_block() {
+        let prev = noise_frame();
+        let mut cur = prev.clone();
+        // A 32x24 block at (50, 60) moves right 40 and up 20
+        copy_block(&mut cur, (50, 60), (90, 40), 32, 24);
+        assert_eq!(
+            detect_region_move(&prev, &cur, 200, 150, (90, 40, 32, 24)),
+            Some((40, -20))
+        );
+
+        // Unchanged and fresh regions have no move to report
+        assert_eq!(
+            detect_region_move(&prev, &cur, 200, 150, (0, 100, 32, 24)),
+            None
+        );
+        let fresh: Vec<u8> = prev.iter().map(|b| b ^ 0x5A).collect();
+        assert_eq!(
+            detect_region_move(&prev, &fresh, 200, 150, (90, 40, 32, 24)),
+            None
+        );
+
+        // Moves beyond the search window are not found
+        let mut far = prev.clone();
+        copy_block(&mut far, (0, 0), (100, 0), 32, 24);
+        assert_eq!(
+            detect_region_move(&prev, &far, 200, 150, (100, 0, 32, 24)),
+            None
+        );
+
+        // Out-of-bounds regions and short frames are rejected
+        assert_eq!(
+            detect_region_move(&prev, &cur, 200, 150, (180, 40, 32, 24)),
+            None
+        );
+        assert_eq!(
+            detect_region_move(&prev[..100], &cur, 200, 150, (90, 40, 32, 24)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_region_move_ignores_unchanged_solid_region() {
+        let solid = [40u8, 80, 120, 255].repeat(200 * 150);
+        assert_eq!(
+            detect_region_move(&solid, &solid, 200, 150, (90, 40, 32, 24)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_should_downgrade_only_when_over_budget() {
+        let budget = encode_time_budget(Duration::from_secs(1) / 60, 4);
+        assert!(!should_downgrade(Duration::from_millis(2), budget));
+        assert!(!should_downgrade(budget, budget));
+        assert!(should_downgrade(Duration::from_millis(5), budget));
+
+        // Frame-level check: 3 rectangles done in 14 ms against 3 rectangle budgets
+        assert!(should_downgrade(Duration::from_millis(14), budget * 3));
+        assert!(!should_downgrade(Duration::from_millis(11), budget * 3));
+    }
+
+    /// Dark strokes with grey edges on a white page, in 8x12 character cells
+    #[cfg(all(feature = "zrle", feature = "tight"))]
+    fn text_like_frame(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                let (cx, cy) = (x % 8, y % 12);
+                let glyph = (x / 8 * 31 + y / 12 * 17) % 23;
+                let in_cell = cx < 6 && (1..10).contains(&cy);
+                let stem = glyph % 6;
+                let value = if in_cell && (cx == stem || cy == glyph % 9 + 1) {
+                    16
+                } else if in_cell && (cx + 1 == stem || cx == stem + 1) {
+                    160
+                } else {
+                    255
+                };
+                [value, value, value, 255]
+            })
+            .collect()
+    }
+
+    /// The RLE zlib strategy decodes like the default on text. It only finds runs, not
+    /// repeated glyphs, so it is never smaller than the default here, but still compresses
+    #[cfg(all(feature = "zrle", feature = "tight"))]
+    #[test]
+    fn test_zlib_rle_strategy_round_trips_text_like_frames() {
+        use crate::decode::{decode_zrle, TightDecoder};
+        use crate::tight::{try_encode_tight_rects, SimpleTightCompressor, TightOptions};
+        use crate::zrle::{encode_zrle, encode_zrle_with_options, ZrleOptions, ZrleSession};
+        use crate::PixelFormat;
+        use flate2::Decompress;
+
+        let (width, height) = (256u16, 96u16);
+        let data = text_like_frame(256, 96);
+        let pf = PixelFormat::rgba32();
+        let same_rgb = |decoded: &[u8], input: &[u8]| {
+            decoded
+                .chunks_exact(4)
+                .zip(input.chunks_exact(4))
+                .all(|(out, pixel)| out[..3] == pixel[..3])
+        };
+
+        let zrle_options = |strategy| ZrleOptions {
+            strategy,
+            ..ZrleOptions::default()
+        };
+        let zrle = |strategy| {
+            encode_zrle_with_options(&data, width, height, &pf, 6, &zrle_options(strategy)).unwrap()
+        };
+        let (default, rle) = (zrle(ZlibStrategy::Default), zrle(ZlibStrategy::Rle));
+        assert_eq!(default, encode_zrle(&data, width, height, &pf, 6).unwrap());
+        assert_ne!(rle, default);
+        let decoded = decode_zrle(&rle, width, height, &pf, &mut Decompress::new(true)).unwrap();
+        assert!(same_rgb(&decoded, &data));
+        assert!(
+            default.len() <= rle.len() && rle.len() < data.len() / 8,
+            "ZRLE: RLE {} bytes, default {} bytes",
+            rle.len(),
+            default.len()
+        );
+
+        // Two frames on one persistent stream
+        let mut session = ZrleSession::with_options(6, zrle_options(ZlibStrategy::Rle));
+        let mut decompressor = Decompress::new(true);
+        for _ in 0..2 {
+            let encoded = session.encode(&data, width, height, &pf).unwrap();
+            let decoded = decode_zrle(&encoded, width, height, &pf, &mut decompressor).unwrap();
+            assert!(same_rgb(&decoded, &data));
+        }
+
+        let tight = |strategy| {
+            let mut compressor = SimpleTightCompressor::with_strategy(6, strategy);
+            let options = TightOptions::default();
+            try_encode_tight_rects(&data, width, height, 10, 6, &pf, &options, &mut compressor)
+                .unwrap()
+        };
+        let (default, rle) = (tight(ZlibStrategy::Default), tight(ZlibStrategy::Rle));
+        let total = |rects: &[(u16, u16, u16, u16, BytesMut)]| -> usize {
+            rects.iter().map(|rect| rect.4.len()).sum()
+        };
+        assert!(
+            total(&default) <= total(&rle) && total(&rle) < data.len() / 8,
+            "Tight: RLE {} bytes, default {} bytes",
+            total(&rle),
+            total(&default)
+        );
+        let mut tight_decoder = TightDecoder::new();
+        for (x, y, w, h, buf) in &rle {
+            let decoded = tight_decoder.decode_rect(buf, *w, *h, &pf).unwrap();
+            let region: Vec<u8> = (*y..y + h)
+                .flat_map(|row| {
+                    let start = (usize::from(row) * 256 + usize::from(*x)) * 4;
+                    data[start..start + usize::from(*w) * 4].to_vec()
+                })
+                .collect();
+            assert!(same_rgb(&decoded, &region), "rect at ({x}, {y})");
+        }
+    }
+}
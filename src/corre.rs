@@ -17,19 +17,25 @@
 //! `CoRRE` is like RRE but uses compact subrectangles with u8 coordinates.
 //! More efficient for small rectangles.
 
-use super::common::{find_subrects, get_background_color, rgba_to_rgb24_pixels};
+use super::common::{check_rgba_len, find_subrects, get_background_color, rgba_to_rgb24_pixels};
 use crate::Encoding;
 use bytes::{BufMut, BytesMut};
+use std::io;
+
+/// Largest rectangle side `CoRRE` can address with its u8 subrectangle coordinates.
+pub const CORRE_MAX_SIZE: u16 = 255;
 
 /// Implements the VNC "`CoRRE`" (Compact RRE) encoding.
 ///
 /// `CoRRE` is like RRE but uses compact subrectangles with u8 coordinates.
 /// Format: \[bgColor\]\[nSubrects(u8)\]\[subrect1\]...\[subrectN\]
 /// Each subrect: \[color\]\[x(u8)\]\[y(u8)\]\[w(u8)\]\[h(u8)\]
+///
+/// The [`Encoding`] trait cannot report errors, so invalid input produces empty
+/// output; use [`try_encode_corre`] to detect it.
 pub struct CorRreEncoding;
 
 impl Encoding for CorRreEncoding {
-    #[allow(clippy::cast_possible_truncation)] // CoRRE protocol uses u8 coordinates/dimensions per RFC 6143
     fn encode(
         &self,
         data: &[u8],
@@ -38,49 +44,105 @@ impl Encoding for CorRreEncoding {
         _quality: u8,
         _compression: u8,
     ) -> BytesMut {
-        // CoRRE format per RFC 6143:
-        // Protocol layer writes: FramebufferUpdateRectHeader + nSubrects count
-        // Encoder writes: bgColor + subrects
-        // Each subrect: color(4) + x(1) + y(1) + w(1) + h(1)
-        let pixels = rgba_to_rgb24_pixels(data);
-        let bg_color = get_background_color(&pixels);
-
-        // Find subrectangles
-        let subrects = find_subrects(&pixels, width as usize, height as usize, bg_color);
-
-        // Encoder output: background color + subrectangle data
-        // Protocol layer will write nSubrects separately
-        let mut buf = BytesMut::with_capacity(4 + subrects.len() * 8);
-        buf.put_u32_le(bg_color); // background pixel value (little-endian)
-
-        // Write subrectangles
-        for subrect in &subrects {
-            buf.put_u32_le(subrect.color); // pixel color (little-endian)
-            buf.put_u8(subrect.x as u8); // x coordinate (u8)
-            buf.put_u8(subrect.y as u8); // y coordinate (u8)
-            buf.put_u8(subrect.w as u8); // width (u8)
-            buf.put_u8(subrect.h as u8); // height (u8)
-        }
-
-        #[cfg(feature = "debug-logging")]
-        {
-            // HEX DUMP: Log the exact bytes being encoded
-            let hex_str: String = buf
-                .iter()
-                .take(32) // Only show first 32 bytes
-                .map(|b| format!("{b:02x}"))
-                .collect::<Vec<String>>()
-                .join(" ");
-            log::info!(
-                "CoRRE encoded {}x{}: {} bytes ({}subrects) = [{}...]",
-                width,
-                height,
-                buf.len(),
-                subrects.len(),
-                hex_str
-            );
-        }
-
-        buf
+        try_encode_corre(data, width, height).unwrap_or_default()
+    }
+}
+
+/// Encodes RGBA data as `CoRRE`, checking the buffer size and dimensions first.
+///
+/// # Errors
+///
+/// Returns an error if `data` holds fewer than `width * height` RGBA pixels, or if
+/// either dimension exceeds [`CORRE_MAX_SIZE`] (split larger areas into tiles first).
+#[allow(clippy::cast_possible_truncation)] // CoRRE protocol uses u8 coordinates/dimensions per RFC 6143
+pub fn try_encode_corre(data: &[u8], width: u16, height: u16) -> io::Result<BytesMut> {
+    check_rgba_len(data, width, height, "CoRRE")?;
+    if width > CORRE_MAX_SIZE || height > CORRE_MAX_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("CoRRE: {width}x{height} rectangle exceeds the {CORRE_MAX_SIZE}x{CORRE_MAX_SIZE} limit of u8 coordinates"),
+        ));
+    }
+    let data = &data[..width as usize * height as usize * 4];
+
+    // CoRRE format per RFC 6143:
+    // Protocol layer writes: FramebufferUpdateRectHeader + nSubrects count
+    // Encoder writes: bgColor + subrects
+    // Each subrect: color(4) + x(1) + y(1) + w(1) + h(1)
+    let pixels = rgba_to_rgb24_pixels(data);
+    let bg_color = get_background_color(&pixels);
+
+    // Find subrectangles
+    let subrects = find_subrects(&pixels, width as usize, height as usize, bg_color);
+
+    // Encoder output: background color + subrectangle data
+    // Protocol layer will write nSubrects separately
+    let mut buf = BytesMut::with_capacity(4 + subrects.len() * 8);
+    buf.put_u32_le(bg_color); // background pixel value (little-endian)
+
+    // Write subrectangles
+    for subrect in &subrects {
+        buf.put_u32_le(subrect.color); // pixel color (little-endian)
+        buf.put_u8(subrect.x as u8); // x coordinate (u8)
+        buf.put_u8(subrect.y as u8); // y coordinate (u8)
+        buf.put_u8(subrect.w as u8); // width (u8)
+        buf.put_u8(subrect.h as u8); // height (u8)
+    }
+
+    #[cfg(feature = "debug-logging")]
+    {
+        // HEX DUMP: Log the exact bytes being encoded
+        let hex_str: String = buf
+            .iter()
+            .take(32) // Only show first 32 bytes
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<String>>()
+            .join(" ");
+        log::info!(
+            "CoRRE encoded {}x{}: {} bytes ({}subrects) = [{}...]",
+            width,
+            height,
+            buf.len(),
+            subrects.len(),
+            hex_str
+        );
+    }
+
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corre_rejects_undersized_buffers() {
+        // Two vertical bands on a grey background
+        let data: Vec<u8> = (0..16 * 16)
+            .flat_map(|i| match i % 16 {
+                2..=4 => [255, 0, 0, 255],
+                9 => [0, 0, 255, 255],
+                _ => [128, 128, 128, 255],
+            })
+            .collect();
+        let short = &data[..data.len() - 4];
+
+        let err = try_encode_corre(short, 16, 16).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // The infallible trait entry point returns empty output instead of panicking
+        assert!(CorRreEncoding.encode(short, 16, 16, 0, 0).is_empty());
+
+        let encoded = try_encode_corre(&data, 16, 16).unwrap();
+        assert_eq!(encoded, CorRreEncoding.encode(&data, 16, 16, 0, 0));
+        // Background pixel plus two subrectangles
+        assert_eq!(encoded.len(), 4 + 2 * 8);
+    }
+
+    #[test]
+    fn test_corre_rejects_rectangles_beyond_u8_coordinates() {
+        let input = vec![0u8; 256 * 4];
+        let err = try_encode_corre(&input, 256, 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(try_encode_corre(&input[..255 * 4], 255, 1).is_ok());
     }
 }
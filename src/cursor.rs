@@ -0,0 +1,211 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cursor pseudo-encoding support.
+//!
+//! The Cursor With Alpha pseudo-encoding (-314) lets clients draw an RGBA cursor
+//! locally. The rectangle header carries the hotspot as x/y and the cursor size
+//! as width/height; the payload is a 4-byte encoding type followed by the cursor
+//! pixels. Pixels are always 32bpp in R, G, B, A byte order with pre-multiplied
+//! alpha, regardless of the pixel format negotiated via `SetPixelFormat`.
+//!
+//! Clients that only support the legacy Cursor pseudo-encoding (-239) need a
+//! 1-bpp transparency mask instead; [`cursor_mask_from_alpha`] derives one from an
+//! RGBA cursor.
+
+use crate::common::premultiply_channel;
+use crate::ENCODING_RAW;
+use bytes::{BufMut, BytesMut};
+use std::io;
+
+/// Encodes an RGBA cursor image for the Cursor With Alpha pseudo-encoding.
+///
+/// The pixel data is sent with the Raw encoding. Input colors use straight
+/// (non-premultiplied) alpha and are pre-multiplied as required by the extension.
+///
+/// # Arguments
+/// * `width` - Cursor width in pixels
+/// * `height` - Cursor height in pixels
+/// * `rgba` - Cursor pixels (RGBA, 4 bytes per pixel, straight alpha)
+///
+/// # Returns
+///
+/// 4-byte big-endian encoding type + `width * height * 4` bytes of pixel data
+///
+/// # Errors
+///
+/// Returns an error if `rgba` is not exactly `width * height * 4` bytes
+pub fn encode_cursor_alpha(width: u16, height: u16, rgba: &[u8]) -> io::Result<BytesMut> {
+    let expected_size = width as usize * height as usize * 4;
+    if rgba.len() != expected_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Cursor: input buffer size mismatch: got {} bytes, expected {} bytes for {}x{} cursor",
+                rgba.len(),
+                expected_size,
+                width,
+                height
+            ),
+        ));
+    }
+
+    let mut buf = BytesMut::with_capacity(4 + expected_size);
+    buf.put_i32(ENCODING_RAW);
+
+    for chunk in rgba.chunks_exact(4) {
+        let alpha = chunk[3];
+        buf.put_u8(premultiply_channel(chunk[0], alpha));
+        buf.put_u8(premultiply_channel(chunk[1], alpha));
+        buf.put_u8(premultiply_channel(chunk[2], alpha));
+        buf.put_u8(alpha);
+    }
+
+    Ok(buf)
+}
+
+/// Derives the 1-bpp bitmask of the legacy Cursor pseudo-encoding from RGBA alpha.
+///
+/// A bit is set where the pixel's alpha is at least `alpha_threshold`. Bits are
+/// MSB first, and each row is padded to a whole byte with zero bits, giving
+/// `height * ceil(width / 8)` bytes as the Cursor pseudo-encoding expects.
+///
+/// # Errors
+///
+/// Returns an error if `rgba` is not exactly `width * height * 4` bytes
+pub fn cursor_mask_from_alpha(
+    rgba: &[u8],
+    width: u16,
+    height: u16,
+    alpha_threshold: u8,
+) -> io::Result<Vec<u8>> {
+    let width = width as usize;
+    let height = height as usize;
+    let expected_size = width * height * 4;
+    if rgba.len() != expected_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Cursor mask: input buffer size mismatch: got {} bytes, expected {} bytes for {}x{} cursor",
+                rgba.len(),
+                expected_size,
+                width,
+                height
+            ),
+        ));
+    }
+
+    let row_bytes = width.div_ceil(8);
+    let mut mask = vec![0u8; row_bytes * height];
+    if width == 0 {
+        return Ok(mask);
+    }
+
+    for (y, row) in rgba.chunks_exact(width * 4).enumerate() {
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            if pixel[3] >= alpha_threshold {
+                mask[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32x32 white cursor whose alpha fades out linearly over the right half.
+    fn soft_edge_cursor() -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(32 * 32 * 4);
+        for _y in 0..32 {
+            for x in 0..32u8 {
+                let alpha = if x < 16 { 255 } else { 255 - (x - 15) * 15 };
+                rgba.extend_from_slice(&[255, 255, 255, alpha]);
+            }
+        }
+        rgba
+    }
+
+    #[test]
+    fn test_cursor_alpha_payload_length() {
+        let buf = encode_cursor_alpha(32, 32, &soft_edge_cursor()).unwrap();
+        assert_eq!(buf.len(), 4 + 32 * 32 * 4);
+        assert_eq!(&buf[..4], &[0, 0, 0, 0], "pixel data uses Raw encoding");
+    }
+
+    #[test]
+    fn test_cursor_alpha_values_roundtrip() {
+        let rgba = soft_edge_cursor();
+        let buf = encode_cursor_alpha(32, 32, &rgba).unwrap();
+        let pixels = &buf[4..];
+
+        // Opaque pixel is unchanged
+        assert_eq!(&pixels[0..4], &[255, 255, 255, 255]);
+
+        // Alpha survives unchanged and color is pre-multiplied
+        for x in [16usize, 24, 31] {
+            let offset = x * 4;
+            let alpha = rgba[offset + 3];
+            assert_eq!(pixels[offset + 3], alpha);
+            assert_eq!(pixels[offset], alpha, "white pre-multiplied equals alpha");
+        }
+    }
+
+    #[test]
+    fn test_cursor_alpha_premultiplies_color() {
+        let buf = encode_cursor_alpha(1, 1, &[200, 100, 0, 128]).unwrap();
+        assert_eq!(&buf[4..], &[100, 50, 0, 128]);
+    }
+
+    #[test]
+    fn test_cursor_alpha_rejects_wrong_length() {
+        assert!(encode_cursor_alpha(32, 32, &[0u8; 32 * 32 * 3]).is_err());
+        assert!(encode_cursor_alpha(2, 2, &[0u8; 17]).is_err());
+    }
+
+    #[test]
+    fn test_cursor_mask_threshold_and_padding() {
+        // 12x2 cursor: alpha ramps 0, 20, 40, ... across each row
+        let mut rgba = Vec::new();
+        for _y in 0..2 {
+            for x in 0..12u8 {
+                rgba.extend_from_slice(&[0, 0, 0, x * 20]);
+            }
+        }
+
+        let mask = cursor_mask_from_alpha(&rgba, 12, 2, 128).unwrap();
+        // Two bytes per row; alpha >= 128 from x = 7 (140) onward, padding bits clear
+        assert_eq!(
+            mask,
+            vec![0b0000_0001, 0b1111_0000, 0b0000_0001, 0b1111_0000]
+        );
+    }
+
+    #[test]
+    fn test_cursor_mask_soft_edge_cursor() {
+        let mask = cursor_mask_from_alpha(&soft_edge_cursor(), 32, 32, 128).unwrap();
+        assert_eq!(mask.len(), 32 * 4);
+        // Opaque left half, alpha drops below 128 at x = 24 (135 at x = 23)
+        for row in mask.chunks_exact(4) {
+            assert_eq!(row, &[0xFF, 0xFF, 0xFF, 0x00]);
+        }
+    }
+
+    #[test]
+    fn test_cursor_mask_rejects_wrong_length() {
+        assert!(cursor_mask_from_alpha(&[0u8; 15], 2, 2, 128).is_err());
+    }
+}
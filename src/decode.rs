@@ -0,0 +1,875 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoders for the encodings produced by this crate.
+//!
+//! These are used to verify encoder output (round-trip testing, debugging tools).
+//! Decoded pixels are returned in the pixel format the data was encoded in,
+//! `width * height * bytes_per_pixel` bytes in row-major order.
+
+#[cfg(feature = "tight")]
+use crate::common::{put_tpixel, tpixel_len, tpixel_value};
+#[cfg(feature = "tight")]
+use crate::tight::{
+    gradient_filter, TIGHT_EXPLICIT_FILTER, TIGHT_FILL, TIGHT_FILTER_COPY, TIGHT_FILTER_GRADIENT,
+    TIGHT_FILTER_PALETTE, TIGHT_JPEG, TIGHT_MIN_TO_COMPRESS, TIGHT_NO_ZLIB,
+};
+use crate::{
+    PixelFormat, HEXTILE_ANY_SUBRECTS, HEXTILE_BACKGROUND_SPECIFIED, HEXTILE_FOREGROUND_SPECIFIED,
+    HEXTILE_RAW, HEXTILE_SUBRECTS_COLOURED,
+};
+use flate2::{Decompress, FlushDecompress};
+use std::io;
+
+/// Sequential reader over an encoded payload that reports truncation as an error.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    name: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], name: &'static str) -> Self {
+        Self { data, pos: 0, name }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{}: unexpected end of data at offset {} (need {} bytes)",
+                    self.name, self.pos, len
+                ),
+            ));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Tight compact length: 1-3 bytes, 7 bits each, low bits first.
+    #[cfg(feature = "tight")]
+    fn compact_length(&mut self) -> io::Result<usize> {
+        let mut len = 0;
+        for shift in [0, 7, 14] {
+            let byte = self.u8()?;
+            if shift == 14 {
+                return Ok(len | ((byte as usize) << 14));
+            }
+            len |= ((byte & 0x7F) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// Decodes Hextile data into pixels in `pixel_format`.
+///
+/// # Errors
+///
+/// Returns an error if the data is truncated, a subrectangle lies outside its tile,
+/// or a tile uses the `ZlibHex`-only sub-encoding bits.
+pub fn decode_hextile(
+    encoded: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> io::Result<Vec<u8>> {
+    let width = width as usize;
+    let height = height as usize;
+    let bpp = (pixel_format.bits_per_pixel / 8) as usize;
+    let mut output = vec![0u8; width * height * bpp];
+    let mut reader = Reader::new(encoded, "Hextile");
+
+    let mut bg: &[u8] = &[0; 4][..bpp];
+    let mut fg: &[u8] = &[0; 4][..bpp];
+
+    for tile_y in (0..height).step_by(16) {
+        for tile_x in (0..width).step_by(16) {
+            let tile_w = (width - tile_x).min(16);
+            let tile_h = (height - tile_y).min(16);
+
+            let subencoding = reader.u8()?;
+            if subencoding & !0x1F != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Hextile: unsupported sub-encoding 0x{subencoding:02x}"),
+                ));
+            }
+
+            if subencoding & HEXTILE_RAW != 0 {
+                for row in 0..tile_h {
+                    let src = reader.take(tile_w * bpp)?;
+                    let start = ((tile_y + row) * width + tile_x) * bpp;
+                    output[start..start + tile_w * bpp].copy_from_slice(src);
+                }
+                continue;
+            }
+
+            if subencoding & HEXTILE_BACKGROUND_SPECIFIED != 0 {
+                bg = reader.take(bpp)?;
+            }
+            if subencoding & HEXTILE_FOREGROUND_SPECIFIED != 0 {
+                fg = reader.take(bpp)?;
+            }
+            let tile = (tile_x, tile_y);
+            fill_rect(&mut output, width, bpp, tile, (0, 0, tile_w, tile_h), bg);
+
+            if subencoding & HEXTILE_ANY_SUBRECTS != 0 {
+                let count = reader.u8()?;
+                for _ in 0..count {
+                    let color = if subencoding & HEXTILE_SUBRECTS_COLOURED != 0 {
+                        reader.take(bpp)?
+                    } else {
+                        fg
+                    };
+                    let xy = reader.u8()?;
+                    let wh = reader.u8()?;
+                    let (x, y) = ((xy >> 4) as usize, (xy & 0x0F) as usize);
+                    let (w, h) = ((wh >> 4) as usize + 1, (wh & 0x0F) as usize + 1);
+                    if x + w > tile_w || y + h > tile_h {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Hextile: subrect {w}x{h} at ({x}, {y}) exceeds {tile_w}x{tile_h} tile"
+                            ),
+                        ));
+                    }
+                    fill_rect(&mut output, width, bpp, tile, (x, y, w, h), color);
+                }
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Fills `rect` (x, y, w, h relative to `tile`) of the output with `pixel`.
+fn fill_rect(
+    output: &mut [u8],
+    width: usize,
+    bpp: usize,
+    tile: (usize, usize),
+    rect: (usize, usize, usize, usize),
+    pixel: &[u8],
+) {
+    let (x, y, w, h) = rect;
+    for row in y..y + h {
+        let start = ((tile.1 + row) * width + tile.0 + x) * bpp;
+        for dst in output[start..start + w * bpp].chunks_exact_mut(bpp) {
+            dst.copy_from_slice(pixel);
+        }
+    }
+}
+
+/// Decodes Zlib data produced by [`crate::encode_zlib_persistent`].
+///
+/// The payload is a 4-byte big-endian length followed by zlib data that inflates to
+/// raw pixels in `pixel_format`. `decompressor` must be the client-side counterpart
+/// of the encoder's compressor and is reused across calls.
+///
+/// # Errors
+///
+/// Returns an error if the length prefix is truncated, inflation fails, or the
+/// inflated data is not `width * height` pixels
+pub fn decode_zlib(
+    encoded: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    decompressor: &mut Decompress,
+) -> io::Result<Vec<u8>> {
+    let compressed = length_prefixed(encoded, "Zlib")?;
+    let pixels = inflate_sync(decompressor, compressed)?;
+    let expected = width as usize * height as usize * (pixel_format.bits_per_pixel / 8) as usize;
+    if pixels.len() != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Zlib: expected {expected} pixel bytes, got {}",
+                pixels.len()
+            ),
+        ));
+    }
+    Ok(pixels)
+}
+
+/// Decodes `ZlibHex` data produced by [`crate::encode_zlibhex_persistent`].
+///
+/// The payload is a 4-byte big-endian length followed by zlib data on a single
+/// persistent stream, which inflates to Hextile data. `decompressor` must be the
+/// client-side counterpart of the encoder's compressor and is reused across calls.
+///
+/// # Errors
+///
+/// Returns an error if the length prefix is truncated, inflation fails, or the
+/// inflated Hextile data is invalid
+pub fn decode_zlibhex(
+    encoded: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    decompressor: &mut Decompress,
+) -> io::Result<Vec<u8>> {
+    let compressed = length_prefixed(encoded, "ZlibHex")?;
+    let hextile_data = inflate_sync(decompressor, compressed)?;
+    decode_hextile(&hextile_data, width, height, pixel_format)
+}
+
+/// The zlib data after the 4-byte big-endian length prefix of Zlib, `ZlibHex` and ZRLE.
+fn length_prefixed<'a>(encoded: &'a [u8], name: &'static str) -> io::Result<&'a [u8]> {
+    let mut reader = Reader::new(encoded, name);
+    let len_bytes = reader.take(4)?;
+    let len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]);
+    reader.take(len as usize)
+}
+
+/// Decodes a complete ZRLE rectangle (length prefix and zlib data) into pixels in
+/// `pixel_format`. `decompressor` must be the client-side counterpart of the
+/// encoder's compressor and is reused across calls.
+///
+/// # Errors
+///
+/// Returns an error if the length prefix is truncated, inflation fails, or the tile
+/// stream is invalid (see [`decode_zrle_tiles`])
+#[cfg(feature = "zrle")]
+pub fn decode_zrle(
+    encoded: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    decompressor: &mut Decompress,
+) -> io::Result<Vec<u8>> {
+    let tiles = inflate_sync(decompressor, length_prefixed(encoded, "ZRLE")?)?;
+    decode_zrle_tiles(&tiles, width, height, pixel_format)
+}
+
+/// Inflates one sync-flushed chunk of a persistent zlib stream.
+#[allow(clippy::cast_possible_truncation)] // Per-call zlib totals limited to buffer size
+fn inflate_sync(decompressor: &mut Decompress, input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 4 + 1024);
+    let mut consumed = 0;
+
+    loop {
+        if output.len() == output.capacity() {
+            output.reserve(output.capacity());
+        }
+
+        let before_in = decompressor.total_in();
+        let before_out = decompressor.total_out();
+        let status =
+            decompressor.decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)?;
+        let read = (decompressor.total_in() - before_in) as usize;
+        let written = (decompressor.total_out() - before_out) as usize;
+        consumed += read;
+
+        if status == flate2::Status::StreamEnd
+            || (consumed == input.len() && output.len() < output.capacity())
+        {
+            return Ok(output);
+        }
+        if read == 0 && written == 0 && output.len() < output.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "zlib stream made no progress (truncated data?)",
+            ));
+        }
+    }
+}
+
+/// Decodes a ZRLE tile stream (the inflated payload, see [`crate::zrle::zrle_to_trle`])
+/// into pixels in `pixel_format`.
+///
+/// # Errors
+///
+/// Returns an error if the data is truncated, a run overflows its tile, a palette
+/// index is out of range or a tile uses an unassigned sub-encoding
+#[cfg(feature = "zrle")]
+pub fn decode_zrle_tiles(
+    tiles: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> io::Result<Vec<u8>> {
+    decode_zrle_tiles_impl(tiles, width, height, pixel_format, None, false)
+}
+
+/// Decodes a tile stream in which edge tiles were padded to 64x64 (see
+/// [`crate::zrle::ZrleOptions::edge_tiles`]), keeping only the pixels inside the
+/// `width` x `height` frame.
+///
+/// # Errors
+///
+/// Returns an error for the same reasons as [`decode_zrle_tiles`]
+#[cfg(feature = "zrle")]
+pub fn decode_zrle_full_tiles(
+    tiles: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> io::Result<Vec<u8>> {
+    decode_zrle_tiles_impl(tiles, width, height, pixel_format, None, true)
+}
+
+/// Decodes a tile stream from a `ZrleDeltaSession` in XOR mode: tiles marked
+/// [`crate::zrle::ZRLE_XOR_DELTA`] are applied by XOR to `previous`, the pixels decoded
+/// for the preceding frame; other tiles decode as standard ZRLE.
+///
+/// # Errors
+///
+/// Returns an error if `previous` is smaller than the frame, or for any of the
+/// reasons [`decode_zrle_tiles`] fails
+#[cfg(feature = "zrle-xor-delta")]
+pub fn decode_zrle_xor_delta(
+    tiles: &[u8],
+    previous: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> io::Result<Vec<u8>> {
+    decode_zrle_tiles_impl(tiles, width, height, pixel_format, Some(previous), false)
+}
+
+#[cfg(feature = "zrle")]
+fn decode_zrle_tiles_impl(
+    tiles: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    previous: Option<&[u8]>,
+    full_tiles: bool,
+) -> io::Result<Vec<u8>> {
+    const TILE: usize = 64;
+    let (width, height) = (width as usize, height as usize);
+    let bpp = (pixel_format.bits_per_pixel / 8) as usize;
+    let mut output = vec![0u8; width * height * bpp];
+    if previous.is_some_and(|p| p.len() < output.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("ZRLE: previous frame is smaller than {width}x{height}"),
+        ));
+    }
+    let mut reader = Reader::new(tiles, "ZRLE");
+
+    for tile_y in (0..height).step_by(TILE) {
+        for tile_x in (0..width).step_by(TILE) {
+            let tile_w = (width - tile_x).min(TILE);
+            let tile_h = (height - tile_y).min(TILE);
+            let mut subencoding = reader.u8()?;
+            let reference = match previous {
+                Some(previous) if subencoding == crate::zrle::ZRLE_XOR_DELTA => {
+                    subencoding = reader.u8()?;
+                    Some(previous)
+                }
+                _ => None,
+            };
+            let (coded_w, coded_h) = if full_tiles {
+                (TILE, TILE)
+            } else {
+                (tile_w, tile_h)
+            };
+            let pixels =
+                decode_zrle_tile(&mut reader, subencoding, coded_w, coded_h, pixel_format)?;
+
+            for (i, &pixel) in pixels.iter().enumerate() {
+                let (row, col) = (i / coded_w, i % coded_w);
+                if row >= tile_h || col >= tile_w {
+                    continue; // Padding past the frame edge
+                }
+                let offset = ((tile_y + row) * width + tile_x + col) * bpp;
+                let out = &mut output[offset..offset + bpp];
+                if pixel_format.big_endian_flag != 0 {
+                    out.copy_from_slice(&pixel.to_be_bytes()[4 - bpp..]);
+                } else {
+                    out.copy_from_slice(&pixel.to_le_bytes()[..bpp]);
+                }
+                if let Some(reference) = reference {
+                    for (o, r) in out.iter_mut().zip(&reference[offset..offset + bpp]) {
+                        *o ^= r;
+                    }
+                }
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Decodes one ZRLE tile after its sub-encoding byte into row-major pixel values.
+#[cfg(feature = "zrle")]
+fn decode_zrle_tile(
+    reader: &mut Reader,
+    subencoding: u8,
+    tile_w: usize,
+    tile_h: usize,
+    pixel_format: &PixelFormat,
+) -> io::Result<Vec<u32>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let cpixel_size = crate::zrle::cpixel_bytes(0, pixel_format).len();
+    let cpixel = |reader: &mut Reader| -> io::Result<u32> {
+        Ok(crate::zrle::read_cpixel(reader.take(cpixel_size)?, pixel_format).0)
+    };
+    let palette = |reader: &mut Reader, size: usize| -> io::Result<Vec<u32>> {
+        (0..size).map(|_| cpixel(reader)).collect()
+    };
+    let run_length = |reader: &mut Reader| -> io::Result<usize> {
+        let mut len = 1;
+        loop {
+            let byte = reader.u8()?;
+            len += byte as usize;
+            if byte != 255 {
+                return Ok(len);
+            }
+        }
+    };
+    let count = tile_w * tile_h;
+    let mut pixels = Vec::with_capacity(count);
+
+    match subencoding {
+        0 => {
+            for _ in 0..count {
+                pixels.push(cpixel(reader)?);
+            }
+        }
+        1 => pixels.resize(count, cpixel(reader)?),
+        2..=16 => {
+            let palette = palette(reader, subencoding as usize)?;
+            let bits = match subencoding {
+                2 => 1,
+                3 | 4 => 2,
+                _ => 4,
+            };
+            for _ in 0..tile_h {
+                let row = reader.take((tile_w * bits).div_ceil(8))?;
+                for col in 0..tile_w {
+                    let bit = col * bits;
+                    let index = (row[bit / 8] >> (8 - bits - bit % 8)) as usize & ((1 << bits) - 1);
+                    let color = palette.get(index).ok_or_else(|| {
+                        invalid(format!("ZRLE: palette index {index} out of range"))
+                    })?;
+                    pixels.push(*color);
+                }
+            }
+        }
+        128 => {
+            while pixels.len() < count {
+                let color = cpixel(reader)?;
+                let len = run_length(reader)?;
+                if pixels.len() + len > count {
+                    return Err(invalid("ZRLE: run overflows its tile".to_string()));
+                }
+                pixels.resize(pixels.len() + len, color);
+            }
+        }
+        130..=255 => {
+            let palette = palette(reader, (subencoding - 128) as usize)?;
+            while pixels.len() < count {
+                let byte = reader.u8()?;
+                let index = (byte & 0x7F) as usize;
+                let color = *palette
+                    .get(index)
+                    .ok_or_else(|| invalid(format!("ZRLE: palette index {index} out of range")))?;
+                let len = if byte & 0x80 != 0 {
+                    run_length(reader)?
+                } else {
+                    1
+                };
+                if pixels.len() + len > count {
+                    return Err(invalid("ZRLE: run overflows its tile".to_string()));
+                }
+                pixels.resize(pixels.len() + len, color);
+            }
+        }
+        _ => {
+            return Err(invalid(format!(
+                "ZRLE: unassigned sub-encoding {subencoding}"
+            )))
+        }
+    }
+    Ok(pixels)
+}
+
+/// Client-side Tight decoder holding the four persistent zlib streams.
+///
+/// Decodes the per-rectangle payloads returned by [`crate::tight::try_encode_tight_rects`],
+/// which must be fed in encoding order to the same decoder. Fill and basic
+/// compression with the copy, palette and gradient filters are supported, and JPEG
+/// with the `turbojpeg` feature, as is the non-standard shared palette with the
+/// `tight-shared-palette` feature. Pixels are `TPIXEL`s: red, green and blue bytes for
+/// 24-bit depth formats (widened to `bytes_per_pixel` in the output), otherwise full pixels.
+#[cfg(feature = "tight")]
+pub struct TightDecoder {
+    streams: [Decompress; 4],
+    /// Palette from the last shared-palette definition rectangle
+    #[cfg(feature = "tight-shared-palette")]
+    shared_palette: Vec<Vec<u8>>,
+}
+
+#[cfg(feature = "tight")]
+impl Default for TightDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "tight")]
+impl TightDecoder {
+    /// Creates a decoder with fresh zlib streams.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            streams: std::array::from_fn(|_| Decompress::new(true)),
+            #[cfg(feature = "tight-shared-palette")]
+            shared_palette: Vec::new(),
+        }
+    }
+
+    /// Decodes one Tight rectangle into pixels in `pixel_format`.
+    ///
+    /// A shared-palette definition is a 0x0 rectangle: it is stored for the
+    /// rectangles that reference it and decodes to no pixels.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is truncated, inflation fails, the payload uses
+    /// an unknown filter (or JPEG without the `turbojpeg` feature), references a
+    /// shared palette that was never defined, or a palette index is out of range
+    pub fn decode_rect(
+        &mut self,
+        encoded: &[u8],
+        width: u16,
+        height: u16,
+        pixel_format: &PixelFormat,
+    ) -> io::Result<Vec<u8>> {
+        let width = width as usize;
+        let height = height as usize;
+        let bpp = (pixel_format.bits_per_pixel / 8) as usize;
+        let tpixel_size = tpixel_len(pixel_format);
+        let mut reader = Reader::new(encoded, "Tight");
+
+        let control = reader.u8()?;
+        for (id, stream) in self.streams.iter_mut().enumerate() {
+            if control & (1 << id) != 0 {
+                *stream = Decompress::new(true);
+            }
+        }
+
+        let comp = control >> 4;
+        #[cfg(feature = "tight-shared-palette")]
+        if comp == crate::tight::TIGHT_SHARED_PALETTE {
+            self.shared_palette = read_palette(&mut reader, pixel_format)?;
+            return Ok(Vec::new());
+        }
+        if comp == TIGHT_FILL {
+            let pixel = tpixel_to_pixel(reader.take(tpixel_size)?, pixel_format);
+            let mut output = vec![0u8; width * height * bpp];
+            fill_rect(
+                &mut output,
+                width,
+                bpp,
+                (0, 0),
+                (0, 0, width, height),
+                &pixel,
+            );
+            return Ok(output);
+        }
+        if comp == TIGHT_JPEG {
+            #[cfg(feature = "turbojpeg")]
+            {
+                let len = reader.compact_length()?;
+                return decode_jpeg(reader.take(len)?, width, height, pixel_format);
+            }
+            #[cfg(not(feature = "turbojpeg"))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Tight: JPEG rectangles need the turbojpeg feature",
+            ));
+        }
+
+        let no_zlib = comp & TIGHT_NO_ZLIB == TIGHT_NO_ZLIB;
+        let stream_id = (comp & 0x03) as usize;
+        let filter = if comp & TIGHT_EXPLICIT_FILTER != 0 {
+            reader.u8()?
+        } else {
+            TIGHT_FILTER_COPY
+        };
+
+        let palette = match filter {
+            TIGHT_FILTER_COPY | TIGHT_FILTER_GRADIENT => Vec::new(),
+            TIGHT_FILTER_PALETTE => read_palette(&mut reader, pixel_format)?,
+            #[cfg(feature = "tight-shared-palette")]
+            crate::tight::TIGHT_FILTER_SHARED_PALETTE => {
+                if self.shared_palette.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Tight: shared palette referenced before it was defined",
+                    ));
+                }
+                self.shared_palette.clone()
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Tight: unsupported filter {filter}"),
+                ))
+            }
+        };
+
+        let data_len = match palette.len() {
+            0 => width * height * tpixel_size,
+            2 => width.div_ceil(8) * height,
+            _ => width * height,
+        };
+        let data = if data_len < TIGHT_MIN_TO_COMPRESS {
+            reader.take(data_len)?.to_vec()
+        } else {
+            let len = reader.compact_length()?;
+            let payload = reader.take(len)?;
+            if no_zlib {
+                payload.to_vec()
+            } else {
+                inflate_sync(&mut self.streams[stream_id], payload)?
+            }
+        };
+        if data.len() != data_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Tight: expected {data_len} data bytes, got {}", data.len()),
+            ));
+        }
+
+        if filter == TIGHT_FILTER_GRADIENT {
+            let tpixels = undo_gradient_filter(&data, width, pixel_format);
+            return expand_tight_data(&tpixels, &palette, width, pixel_format);
+        }
+
+        expand_tight_data(&data, &palette, width, pixel_format)
+    }
+}
+
+/// Reads a Tight palette: the color count minus one, then that many `TPIXEL`s.
+#[cfg(feature = "tight")]
+fn read_palette(reader: &mut Reader<'_>, pixel_format: &PixelFormat) -> io::Result<Vec<Vec<u8>>> {
+    let count = reader.u8()? as usize + 1;
+    (0..count)
+        .map(|_| {
+            Ok(tpixel_to_pixel(
+                reader.take(tpixel_len(pixel_format))?,
+                pixel_format,
+            ))
+        })
+        .collect()
+}
+
+/// Reconstructs `TPIXEL`s from gradient-filtered data.
+#[cfg(feature = "tight")]
+fn undo_gradient_filter(data: &[u8], width: usize, pixel_format: &PixelFormat) -> Vec<u8> {
+    let mut values: Vec<u32> = data
+        .chunks_exact(tpixel_len(pixel_format))
+        .map(|tpixel| tpixel_value(tpixel, pixel_format))
+        .collect();
+    gradient_filter(&mut values, width, pixel_format, true);
+    let mut tpixels = Vec::with_capacity(data.len());
+    for value in values {
+        put_tpixel(&mut tpixels, value, pixel_format);
+    }
+    tpixels
+}
+
+/// Decodes the JPEG image of a Tight JPEG rectangle into pixels in `pixel_format`.
+#[cfg(feature = "turbojpeg")]
+fn decode_jpeg(
+    jpeg: &[u8],
+    width: usize,
+    height: usize,
+    pixel_format: &PixelFormat,
+) -> io::Result<Vec<u8>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("Tight: {e}"));
+    let (w, h) = (
+        u16::try_from(width).map_err(|e| invalid(e.to_string()))?,
+        u16::try_from(height).map_err(|e| invalid(e.to_string()))?,
+    );
+    let rgb = crate::jpeg::TurboJpegDecoder::new()
+        .and_then(|mut decoder| decoder.decompress_rgb(jpeg, w, h))
+        .map_err(invalid)?;
+    let rgba: Vec<u8> = rgb
+        .chunks_exact(3)
+        .flat_map(|p| [p[0], p[1], p[2], 255])
+        .collect();
+    Ok(crate::translate::translate_pixels(&rgba, &PixelFormat::rgba32(), pixel_format).to_vec())
+}
+
+/// Expands filtered Tight data (`TPIXEL`s, 1-bit or 8-bit palette indices) to pixels.
+#[cfg(feature = "tight")]
+fn expand_tight_data(
+    data: &[u8],
+    palette: &[Vec<u8>],
+    width: usize,
+    pixel_format: &PixelFormat,
+) -> io::Result<Vec<u8>> {
+    let bpp = (pixel_format.bits_per_pixel / 8) as usize;
+    let mut output = Vec::with_capacity(data.len() * bpp);
+    match palette.len() {
+        0 => {
+            for tpixel in data.chunks_exact(tpixel_len(pixel_format)) {
+                output.extend_from_slice(&tpixel_to_pixel(tpixel, pixel_format));
+            }
+        }
+        2 => {
+            for row in data.chunks_exact(width.div_ceil(8)) {
+                for x in 0..width {
+                    let bit = (row[x / 8] >> (7 - x % 8)) & 1;
+                    output.extend_from_slice(&palette[bit as usize]);
+                }
+            }
+        }
+        _ => {
+            for &index in data {
+                let pixel = palette.get(index as usize).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Tight: palette index {index} out of range"),
+                    )
+                })?;
+                output.extend_from_slice(pixel);
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Widens a `TPIXEL` to a full pixel in `pixel_format`.
+#[cfg(feature = "tight")]
+fn tpixel_to_pixel(tpixel: &[u8], pixel_format: &PixelFormat) -> Vec<u8> {
+    let bpp = (pixel_format.bits_per_pixel / 8) as usize;
+    let value = tpixel_value(tpixel, pixel_format);
+    if pixel_format.big_endian_flag != 0 {
+        value.to_be_bytes()[4 - bpp..].to_vec()
+    } else {
+        value.to_le_bytes()[..bpp].to_vec()
+    }
+}
+
+/// A rectangle decoded by [`decode_jpeg_with_alpha`].
+#[cfg(feature = "alpha-jpeg")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JpegWithAlpha {
+    /// JPEG image holding the RGB channels.
+    pub jpeg: Vec<u8>,
+    /// One alpha byte per pixel, row-major, or `None` for an opaque rectangle.
+    pub alpha: Option<Vec<u8>>,
+}
+
+/// Splits the output of [`crate::tight::encode_jpeg_with_alpha`] into its JPEG image
+/// and exact alpha plane. Decoding the JPEG is left to the caller's JPEG decoder.
+///
+/// # Errors
+///
+/// Returns an error if the data is truncated, has an unexpected control byte, or the
+/// alpha plane does not hold exactly `width * height` values
+#[cfg(feature = "alpha-jpeg")]
+pub fn decode_jpeg_with_alpha(
+    encoded: &[u8],
+    width: u16,
+    height: u16,
+) -> io::Result<JpegWithAlpha> {
+    use crate::tight::TIGHT_JPEG_ALPHA;
+    use std::io::Read;
+
+    let mut reader = Reader::new(encoded, "Tight JPEG+alpha");
+    let control = reader.u8()? >> 4;
+    if control != TIGHT_JPEG && control != TIGHT_JPEG_ALPHA {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Tight JPEG+alpha: unexpected control type 0x{control:x}"),
+        ));
+    }
+    let len = reader.compact_length()?;
+    let jpeg = reader.take(len)?.to_vec();
+    if control == TIGHT_JPEG {
+        return Ok(JpegWithAlpha { jpeg, alpha: None });
+    }
+
+    let len = reader.compact_length()?;
+    let mut runs = Vec::new();
+    flate2::read::ZlibDecoder::new(reader.take(len)?).read_to_end(&mut runs)?;
+    let pixel_count = usize::from(width) * usize::from(height);
+    let mut alpha = Vec::with_capacity(pixel_count);
+    for pair in runs.chunks_exact(2) {
+        alpha.extend(std::iter::repeat_n(pair[1], usize::from(pair[0])));
+    }
+    if alpha.len() != pixel_count || runs.len() % 2 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Tight JPEG+alpha: alpha plane has {} values, expected {pixel_count}",
+                alpha.len()
+            ),
+        ));
+    }
+    Ok(JpegWithAlpha {
+        jpeg,
+        alpha: Some(alpha),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_hextile_mono_subrect() {
+        // One 4x4 tile: background 0x11, foreground 0x22, one 2x2 subrect at (1, 1)
+        let pf = PixelFormat::bgr233();
+        let encoded = [
+            HEXTILE_BACKGROUND_SPECIFIED | HEXTILE_FOREGROUND_SPECIFIED | HEXTILE_ANY_SUBRECTS,
+            0x11,
+            0x22,
+            1,
+            0x11,
+            0x11,
+        ];
+        let decoded = decode_hextile(&encoded, 4, 4, &pf).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            0x11, 0x11, 0x11, 0x11,
+            0x11, 0x22, 0x22, 0x11,
+            0x11, 0x22, 0x22, 0x11,
+            0x11, 0x11, 0x11, 0x11,
+        ];
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_decode_hextile_rejects_truncated_and_oversized() {
+        let pf = PixelFormat::bgr233();
+        assert!(decode_hextile(&[HEXTILE_RAW, 0, 0], 2, 2, &pf).is_err());
+        // 4x4 subrect does not fit in a 2x2 tile
+        let encoded = [
+            HEXTILE_ANY_SUBRECTS | HEXTILE_SUBRECTS_COLOURED,
+            1,
+            0xFF,
+            0x00,
+            0x33,
+        ];
+        assert!(decode_hextile(&encoded, 2, 2, &pf).is_err());
+    }
+}
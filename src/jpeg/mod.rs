@@ -16,9 +16,26 @@
 //!
 //! This module provides JPEG compression functionality for VNC Tight encoding.
 //! `TurboJPEG` support is optional and can be enabled with the `turbojpeg` feature.
+//!
+//! Image quality is controlled by the scalar JPEG quality only. Custom luma/chroma
+//! quantization tables are not supported: neither `tjCompress2` nor the `tj3Set`
+//! parameters of `TurboJPEG` 3 accept them, and installing tables requires the
+//! lower-level libjpeg API (`jpeg_add_quant_table`), which this crate does not bind.
 
 #[cfg(feature = "turbojpeg")]
 pub mod turbojpeg;
 
+/// Whether Tight's JPEG output can be compared byte for byte against stored goldens.
+///
+/// With the `turbojpeg` feature it cannot: a given build is repeatable, but the
+/// bytes depend on the installed libjpeg-turbo version and the SIMD code it picks,
+/// so byte-exact goldens need a pinned library. Tests should check JPEG structure
+/// (markers, quantization tables) instead. Without the feature no JPEG is produced
+/// and Tight falls back to lossless coding, which is deterministic.
+#[must_use]
+pub const fn jpeg_is_deterministic() -> bool {
+    !cfg!(feature = "turbojpeg")
+}
+
 #[cfg(feature = "turbojpeg")]
-pub use turbojpeg::TurboJpegEncoder;
+pub use turbojpeg::{TurboJpegDecoder, TurboJpegEncoder};
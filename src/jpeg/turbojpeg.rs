@@ -77,9 +77,36 @@ extern "C" {
         flags: c_int,
     ) -> c_int;
     fn tjFree(buffer: *mut c_uchar);
+    fn tjInitDecompress() -> TjHandle;
+    fn tjDecompress2(
+        handle: TjHandle,
+        jpeg_buf: *const c_uchar,
+        jpeg_size: c_ulong,
+        dst_buf: *mut c_uchar,
+        width: c_int,
+        pitch: c_int,
+        height: c_int,
+        pixel_format: c_int,
+        flags: c_int,
+    ) -> c_int;
     fn tjGetErrorStr2(handle: TjHandle) -> *const c_char;
 }
 
+/// Last error message for a `TurboJPEG` handle.
+///
+/// # Safety
+///
+/// `handle` must be a live handle returned by `tjInitCompress` or `tjInitDecompress`.
+unsafe fn error_string(handle: TjHandle) -> String {
+    let c_str = tjGetErrorStr2(handle);
+    if c_str.is_null() {
+        return "Unknown error".to_string();
+    }
+    std::ffi::CStr::from_ptr(c_str)
+        .to_string_lossy()
+        .into_owned()
+}
+
 /// Safe Rust wrapper for `TurboJPEG` compression.
 pub struct TurboJpegEncoder {
     handle: TjHandle,
@@ -105,7 +132,8 @@ impl TurboJpegEncoder {
     /// * `rgb_data` - RGB pixel data (3 bytes per pixel)
     /// * `width` - Image width in pixels
     /// * `height` - Image height in pixels
-    /// * `quality` - JPEG quality (1-100, where 100 is best quality)
+    /// * `quality` - JPEG quality (1-100, where 100 is best quality). This scales the
+    ///   standard quantization tables; `TurboJPEG` has no way to pass custom ones.
     ///
     /// # Returns
     ///
@@ -173,15 +201,7 @@ impl TurboJpegEncoder {
 
     /// Gets the last error message from `TurboJPEG`.
     fn get_error_string(&self) -> String {
-        unsafe {
-            let c_str = tjGetErrorStr2(self.handle);
-            if c_str.is_null() {
-                return "Unknown error".to_string();
-            }
-            std::ffi::CStr::from_ptr(c_str)
-                .to_string_lossy()
-                .into_owned()
-        }
+        unsafe { error_string(self.handle) }
     }
 }
 
@@ -195,6 +215,72 @@ impl Drop for TurboJpegEncoder {
 
 unsafe impl Send for TurboJpegEncoder {}
 
+/// Safe Rust wrapper for `TurboJPEG` decompression, used to verify JPEG output.
+pub struct TurboJpegDecoder {
+    handle: TjHandle,
+}
+
+impl TurboJpegDecoder {
+    /// Creates a new `TurboJPEG` decoder.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `TurboJPEG` initialization fails
+    pub fn new() -> Result<Self, String> {
+        let handle = unsafe { tjInitDecompress() };
+        if handle.is_null() {
+            return Err("Failed to initialize TurboJPEG decompressor".to_string());
+        }
+        Ok(Self { handle })
+    }
+
+    /// Decompresses a JPEG image of `width`x`height` pixels to RGB (3 bytes per pixel).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is not a valid JPEG image of that size
+    pub fn decompress_rgb(
+        &mut self,
+        jpeg_data: &[u8],
+        width: u16,
+        height: u16,
+    ) -> Result<Vec<u8>, String> {
+        let jpeg_size = c_ulong::try_from(jpeg_data.len())
+            .map_err(|_| format!("JPEG data too large: {} bytes", jpeg_data.len()))?;
+        let mut rgb_data = vec![0u8; width as usize * height as usize * 3];
+
+        let result = unsafe {
+            tjDecompress2(
+                self.handle,
+                jpeg_data.as_ptr(),
+                jpeg_size,
+                rgb_data.as_mut_ptr(),
+                c_int::from(width),
+                0, // pitch = 0 means width * pixel_size
+                c_int::from(height),
+                TJPF_RGB,
+                0, // flags
+            )
+        };
+
+        if result != 0 {
+            let error_msg = unsafe { error_string(self.handle) };
+            return Err(format!("TurboJPEG decompression failed: {error_msg}"));
+        }
+        Ok(rgb_data)
+    }
+}
+
+impl Drop for TurboJpegDecoder {
+    fn drop(&mut self) {
+        unsafe {
+            tjDestroy(self.handle);
+        }
+    }
+}
+
+unsafe impl Send for TurboJpegDecoder {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -17,6 +17,11 @@
 //! This crate provides encoding implementations for the VNC/RFB protocol,
 //! including all standard encodings: Raw, RRE, `CoRRE`, Hextile, Tight, `TightPng`,
 //! Zlib, `ZlibHex`, ZRLE, and ZYWRLE.
+//!
+//! Every encoding except Raw and Zlib sits behind a cargo feature of the same
+//! name (`zrle`, `tight`, `tightpng`, `hextile`, `rre`, `corre`, `zlibhex`,
+//! `zywrle`). All of them are enabled by default; build with
+//! `default-features = false` and pick the ones you need to shrink the binary.
 
 #![deny(missing_docs)]
 #![warn(clippy::pedantic)]
@@ -26,17 +31,33 @@ use std::io;
 
 // Encoding modules
 pub mod common;
+#[cfg(feature = "corre")]
 pub mod corre;
+pub mod cursor;
+pub mod decode;
+#[cfg(feature = "hextile")]
 pub mod hextile;
 pub mod jpeg;
 pub mod raw;
+#[cfg(feature = "image")]
+pub mod rgba_image;
+#[cfg(feature = "rre")]
 pub mod rre;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(feature = "tight")]
 pub mod tight;
+#[cfg(feature = "tightpng")]
 pub mod tightpng;
 pub mod translate;
+pub mod update;
+pub mod verify;
 pub mod zlib;
+#[cfg(feature = "zlibhex")]
 pub mod zlibhex;
+#[cfg(feature = "zrle")]
 pub mod zrle;
+#[cfg(feature = "zywrle")]
 pub mod zywrle;
 
 // Encoding type constants (from RFC 6143)
@@ -74,17 +95,68 @@ pub const ENCODING_ZYWRLE: i32 = 17;
 /// Encoding type: `TightPng`.
 pub const ENCODING_TIGHTPNG: i32 = -260;
 
+// Pseudo-encoding type constants
+
+/// Pseudo-encoding: `LastRect`.
+pub const ENCODING_LAST_RECT: i32 = -224;
+
+/// Pseudo-encoding: Cursor With Alpha.
+pub const ENCODING_CURSOR_WITH_ALPHA: i32 = -314;
+
+/// Pseudo-encoding: JPEG quality level 0; levels 1-9 follow at -31 to -23.
+pub const ENCODING_QUALITY_LEVEL_0: i32 = -32;
+
+/// Pseudo-encoding: compression level 0; levels 1-9 follow at -255 to -247.
+pub const ENCODING_COMPRESS_LEVEL_0: i32 = -256;
+
+/// Maps a JPEG quality pseudo-encoding (-32 to -23) to the `quality` argument of
+/// this crate's encoders.
+///
+/// Clients request level N as `-32 + N`, where 9 is the best quality. This crate's
+/// Tight encoder counts the other way (0 is the best JPEG, 9 the smallest), so level
+/// N maps to `9 - N`. Returns `None` for any other encoding number.
+#[must_use]
+pub fn parse_quality_pseudo(encoding: i32) -> Option<u8> {
+    let level = u8::try_from(encoding.checked_sub(ENCODING_QUALITY_LEVEL_0)?).ok()?;
+    (level <= 9).then(|| 9 - level)
+}
+
+/// Maps a compression level pseudo-encoding (-256 to -247) to the `compression`
+/// argument of this crate's encoders.
+///
+/// Level N is requested as `-256 + N` and used as is: 0 is the fastest, 9 the
+/// smallest. Returns `None` for any other encoding number.
+#[must_use]
+pub fn parse_compression_pseudo(encoding: i32) -> Option<u8> {
+    let level = u8::try_from(encoding.checked_sub(ENCODING_COMPRESS_LEVEL_0)?).ok()?;
+    (level <= 9).then_some(level)
+}
+
 // Re-export common types
 pub use common::*;
+#[cfg(feature = "corre")]
 pub use corre::CorRreEncoding;
+pub use cursor::encode_cursor_alpha;
+#[cfg(feature = "hextile")]
 pub use hextile::HextileEncoding;
-pub use raw::RawEncoding;
+pub use raw::{encode_raw_with_row_order, RawEncoding};
+#[cfg(feature = "rre")]
 pub use rre::RreEncoding;
+#[cfg(feature = "tight")]
 pub use tight::TightEncoding;
+#[cfg(feature = "tightpng")]
 pub use tightpng::TightPngEncoding;
-pub use zlib::encode_zlib_persistent;
+pub use update::{encode_last_rect, merge_updates, EncodedRect, FramebufferUpdateBuilder};
+pub use verify::{encode_and_verify, VerifyError};
+pub use zlib::{encode_zlib_persistent, encode_zlib_persistent_with_format, ZlibEncoding};
+#[cfg(feature = "zlibhex")]
 pub use zlibhex::encode_zlibhex_persistent;
-pub use zrle::encode_zrle_persistent;
+#[cfg(feature = "zrle")]
+pub use zrle::{
+    encode_zrle_persistent, encode_zrle_tile, EdgeTiles, ZrleCompat, ZrleDeltaFrame,
+    ZrleDeltaSession, ZrleOptions, ZrleSession,
+};
+#[cfg(feature = "zywrle")]
 pub use zywrle::zywrle_analyze;
 
 // Hextile subencoding flags
@@ -159,6 +231,102 @@ impl PixelFormat {
         }
     }
 
+    /// Creates a 32-bit RGBX pixel format.
+    ///
+    /// Little-endian with depth 24; each pixel is stored in memory as `[R, G, B, X]`.
+    /// The layout is identical to [`PixelFormat::rgba32`]; the fourth byte is padding.
+    ///
+    /// # Returns
+    ///
+    /// A `PixelFormat` instance configured for 32-bit RGBX.
+    #[must_use]
+    pub fn rgbx32() -> Self {
+        Self {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian_flag: 0,
+            true_colour_flag: 1,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 0,
+            green_shift: 8,
+            blue_shift: 16,
+        }
+    }
+
+    /// Creates a 32-bit BGRX pixel format.
+    ///
+    /// Little-endian with depth 24; each pixel is stored in memory as `[B, G, R, X]`.
+    /// This is the common BGRA/BGRX layout of Windows and many capture APIs; the fourth byte is padding.
+    ///
+    /// # Returns
+    ///
+    /// A `PixelFormat` instance configured for 32-bit BGRX.
+    #[must_use]
+    pub fn bgrx32() -> Self {
+        Self {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian_flag: 0,
+            true_colour_flag: 1,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        }
+    }
+
+    /// Creates a 32-bit XRGB pixel format.
+    ///
+    /// Little-endian with depth 24; each pixel is stored in memory as `[X, R, G, B]`.
+    /// The first byte is padding, so ZRLE sends the upper three bytes as its 3-byte CPIXEL.
+    ///
+    /// # Returns
+    ///
+    /// A `PixelFormat` instance configured for 32-bit XRGB.
+    #[must_use]
+    pub fn xrgb32() -> Self {
+        Self {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian_flag: 0,
+            true_colour_flag: 1,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 8,
+            green_shift: 16,
+            blue_shift: 24,
+        }
+    }
+
+    /// Creates a 32-bit ABGR pixel format.
+    ///
+    /// Little-endian with depth 24; each pixel is stored in memory as `[A, B, G, R]`.
+    /// The first byte is alpha and is ignored, so ZRLE sends the upper three bytes as its 3-byte CPIXEL.
+    ///
+    /// # Returns
+    ///
+    /// A `PixelFormat` instance configured for 32-bit ABGR.
+    #[must_use]
+    pub fn abgr32() -> Self {
+        Self {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian_flag: 0,
+            true_colour_flag: 1,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 24,
+            green_shift: 16,
+            blue_shift: 8,
+        }
+    }
+
     /// Checks if this `PixelFormat` is compatible with the standard 32-bit RGBA format.
     ///
     /// # Returns
@@ -265,6 +433,8 @@ impl PixelFormat {
     /// Creates a 16-bit RGB555 pixel format.
     ///
     /// RGB555 uses 5 bits for each of red, green, and blue, with 1 unused bit.
+    /// It shares the 2-byte pixel size of [`PixelFormat::rgb565`] but has depth 15,
+    /// so green is scaled to 0-31 instead of 0-63.
     ///
     /// # Returns
     ///
@@ -404,16 +574,314 @@ pub trait Encoding {
 ///
 /// # Returns
 ///
-/// `Some(Box<dyn Encoding>)` if the encoding is supported, `None` otherwise
+/// `Some(Box<dyn Encoding>)` if the encoding is supported, `None` otherwise.
+/// Encodings whose cargo feature is disabled are reported as unsupported.
 #[must_use]
 pub fn get_encoder(encoding_type: i32) -> Option<Box<dyn Encoding>> {
     match encoding_type {
         ENCODING_RAW => Some(Box::new(RawEncoding)),
+        ENCODING_ZLIB => Some(Box::new(ZlibEncoding::default())),
+        #[cfg(feature = "rre")]
         ENCODING_RRE => Some(Box::new(RreEncoding)),
+        #[cfg(feature = "corre")]
         ENCODING_CORRE => Some(Box::new(CorRreEncoding)),
+        #[cfg(feature = "hextile")]
         ENCODING_HEXTILE => Some(Box::new(HextileEncoding)),
+        #[cfg(feature = "tight")]
         ENCODING_TIGHT => Some(Box::new(TightEncoding)),
+        #[cfg(feature = "tightpng")]
         ENCODING_TIGHTPNG => Some(Box::new(TightPngEncoding)),
         _ => None,
     }
 }
+
+/// Encodes one rectangle and returns only its encoding payload, for callers that
+/// write the RFB framing themselves.
+///
+/// The result never includes the `FramebufferUpdate` message header or the 12-byte
+/// rectangle header (position, size and encoding type). Everything the encoding
+/// itself defines after that header is kept, so the payload can follow the rectangle
+/// header on the wire as is:
+///
+/// * Raw: none, the payload is exactly `width * height` pixels
+/// * RRE, `CoRRE`: the 4-byte big-endian subrectangle count, then the background
+///   pixel and the subrectangles (unlike [`CorRreEncoding`], whose output starts at
+///   the background pixel and leaves the count to the caller)
+/// * Hextile: the per-tile subencoding bytes
+/// * Zlib, ZRLE: the mandatory 4-byte big-endian length of the zlib data
+/// * Tight, `TightPng`: the control byte and compact lengths of each subencoding
+///
+/// Zlib, ZRLE and Tight are compressed on fresh zlib streams; connections sending
+/// several such rectangles must use [`encode_zlib_persistent`], [`ZrleSession`] or
+/// [`tight::try_encode_tight_rects`] instead.
+///
+/// # Errors
+///
+/// Returns an error if `encoding_type` is not supported, ZRLE or Tight encoding
+/// fails, or Tight splits the rectangle into several sub-rectangles (which need a
+/// rectangle header each; use [`tight::try_encode_tight_rects`] for those)
+pub fn encode_payload_only(
+    encoding_type: i32,
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+) -> io::Result<BytesMut> {
+    match encoding_type {
+        #[cfg(feature = "zrle")]
+        ENCODING_ZRLE => {
+            let encoded =
+                zrle::encode_zrle(data, width, height, &PixelFormat::rgba32(), compression)?;
+            return Ok(BytesMut::from(&encoded[..]));
+        }
+        #[cfg(feature = "tight")]
+        ENCODING_TIGHT => {
+            let format = PixelFormat::rgba32();
+            return encode_tight_payload(data, width, height, quality, compression, &format);
+        }
+        #[cfg(feature = "corre")]
+        ENCODING_CORRE => {
+            let body = corre::try_encode_corre(data, width, height)?;
+            // Background pixel plus 8 bytes per subrectangle
+            let count = u32::try_from((body.len() - 4) / 8).unwrap_or(u32::MAX);
+            let mut payload = BytesMut::with_capacity(4 + body.len());
+            payload.put_u32(count);
+            payload.extend_from_slice(&body);
+            return Ok(payload);
+        }
+        _ => {}
+    }
+    let encoder = get_encoder(encoding_type).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported encoding {encoding_type}"),
+        )
+    })?;
+    Ok(encoder.encode(data, width, height, quality, compression))
+}
+
+/// Encodes `data` as a single Tight rectangle in `format` on fresh zlib streams.
+#[cfg(feature = "tight")]
+fn encode_tight_payload(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+    format: &PixelFormat,
+) -> io::Result<BytesMut> {
+    let mut compressor = tight::SimpleTightCompressor::new(compression);
+    let mut rects = tight::try_encode_tight_rects(
+        data,
+        width,
+        height,
+        quality,
+        compression,
+        format,
+        &tight::TightOptions::default(),
+        &mut compressor,
+    )?;
+    if rects.len() != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Tight split the {width}x{height} rectangle into {} sub-rectangles",
+                rects.len()
+            ),
+        ));
+    }
+    Ok(rects.remove(0).4)
+}
+
+/// Encodes a rectangle with the `fallback` encoding after the primary encoding failed.
+///
+/// The input is first cut or zero-padded to exactly `width * height` RGBA pixels, so
+/// the fallback output always matches the rectangle header even when the primary
+/// failed on an undersized buffer; missing pixels are sent black.
+///
+/// # Errors
+///
+/// Returns an error if `fallback` is not supported by [`get_encoder`].
+pub fn encode_fallback(
+    fallback: i32,
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+) -> io::Result<BytesMut> {
+    let encoder = get_encoder(fallback).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported fallback encoding {fallback}"),
+        )
+    })?;
+    let expected_size = width as usize * height as usize * 4;
+    let padded;
+    let data = if data.len() < expected_size {
+        padded = [data, &vec![0; expected_size - data.len()]].concat();
+        &padded[..]
+    } else {
+        &data[..expected_size]
+    };
+    Ok(encoder.encode(data, width, height, quality, compression))
+}
+
+/// Encodes one region of a framebuffer once per client pixel format.
+///
+/// Servers whose clients negotiated different pixel formats can share a single
+/// source frame: the region is cropped once and then translated and encoded for
+/// each entry of `formats`, returned in the same order. Every encoder in this
+/// crate only reads its input, so `framebuffer` can equally be shared across
+/// threads (for example one `std::thread::scope` thread per format, each calling
+/// this function with a one-element `formats` slice).
+///
+/// Formats compatible with RGBA32 can use any encoding [`get_encoder`] supports.
+/// Other formats are supported by Raw, Zlib, ZRLE and Tight, which encode pixels in
+/// the client's format. Every rectangle is compressed on a fresh zlib stream and
+/// framed as by [`encode_payload_only`], so Tight fails on a region it splits.
+///
+/// # Arguments
+/// * `encoding_type` - RFB encoding type constant
+/// * `framebuffer` - Framebuffer pixel data (RGBA format, row stride `fb_width`)
+/// * `fb_width`, `fb_height` - Framebuffer dimensions
+/// * `region` - (x, y, width, height) of the area to encode
+/// * `formats` - Client pixel formats to encode for
+/// * `quality` - Quality level for lossy encodings
+/// * `compression` - Compression level (0-9)
+///
+/// # Errors
+///
+/// Returns an error if the framebuffer is smaller than `fb_width * fb_height`
+/// pixels, `region` lies outside it, or an encoding does not support one of the
+/// formats
+#[allow(clippy::too_many_arguments)] // Mirrors encode_payload_only plus region and formats
+pub fn encode_multi_format(
+    encoding_type: i32,
+    framebuffer: &[u8],
+    fb_width: u16,
+    fb_height: u16,
+    region: (u16, u16, u16, u16),
+    formats: &[PixelFormat],
+    quality: u8,
+    compression: u8,
+) -> io::Result<Vec<(PixelFormat, BytesMut)>> {
+    let (x, y, width, height) = region;
+    let (x, y, w, h) = (x as usize, y as usize, width as usize, height as usize);
+    if framebuffer.len() < fb_width as usize * fb_height as usize * 4 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "framebuffer has {} bytes, expected {fb_width}x{fb_height} RGBA pixels",
+                framebuffer.len()
+            ),
+        ));
+    }
+    if x + w > fb_width as usize || y + h > fb_height as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("region {region:?} exceeds the {fb_width}x{fb_height} framebuffer"),
+        ));
+    }
+
+    let row_bytes = fb_width as usize * 4;
+    let mut pixels = Vec::with_capacity(w * h * 4);
+    for row in framebuffer[y * row_bytes..].chunks(row_bytes).take(h) {
+        pixels.extend_from_slice(&row[x * 4..(x + w) * 4]);
+    }
+
+    formats
+        .iter()
+        .map(|format| {
+            let encoded = if format.is_compatible_with_rgba32() {
+                encode_payload_only(encoding_type, &pixels, width, height, quality, compression)?
+            } else {
+                encode_translated(
+                    encoding_type,
+                    &pixels,
+                    width,
+                    height,
+                    format,
+                    quality,
+                    compression,
+                )?
+            };
+            Ok((format.clone(), encoded))
+        })
+        .collect()
+}
+
+/// Encodes RGBA `pixels` for a client format other than RGBA32 with one of the
+/// encodings that take pixels in the client's format.
+#[cfg_attr(not(all(feature = "zrle", feature = "tight")), allow(unused_variables))]
+fn encode_translated(
+    encoding_type: i32,
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    format: &PixelFormat,
+    quality: u8,
+    compression: u8,
+) -> io::Result<BytesMut> {
+    match encoding_type {
+        ENCODING_RAW => Ok(translate::translate_pixels(
+            pixels,
+            &PixelFormat::rgba32(),
+            format,
+        )),
+        ENCODING_ZLIB => {
+            let level = flate2::Compression::new(u32::from(compression.min(9)));
+            let mut compressor = flate2::Compress::new(level, true);
+            let encoded = encode_zlib_persistent_with_format(pixels, format, &mut compressor)?;
+            Ok(BytesMut::from(&encoded[..]))
+        }
+        #[cfg(feature = "zrle")]
+        ENCODING_ZRLE => {
+            let translated = translate::translate_pixels(pixels, &PixelFormat::rgba32(), format);
+            let encoded = zrle::encode_zrle(&translated, width, height, format, compression)?;
+            Ok(BytesMut::from(&encoded[..]))
+        }
+        #[cfg(feature = "tight")]
+        ENCODING_TIGHT => encode_tight_payload(pixels, width, height, quality, compression, format),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "encoding {encoding_type} does not support {}bpp depth {} client formats",
+                format.bits_per_pixel, format.depth
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_pseudo_encodings_map_to_encoder_quality() {
+        for level in 0..=9u8 {
+            // The best requested quality is the crate's lowest (best) quality value
+            assert_eq!(
+                parse_quality_pseudo(-32 + i32::from(level)),
+                Some(9 - level)
+            );
+        }
+        for encoding in [-33, -22, -256, 0, 7, i32::MIN, i32::MAX] {
+            assert_eq!(parse_quality_pseudo(encoding), None, "{encoding}");
+        }
+    }
+
+    #[test]
+    fn test_compression_pseudo_encodings_map_to_compression_level() {
+        for level in 0..=9u8 {
+            assert_eq!(
+                parse_compression_pseudo(-256 + i32::from(level)),
+                Some(level)
+            );
+        }
+        for encoding in [-257, -246, -32, 0, i32::MIN, i32::MAX] {
+            assert_eq!(parse_compression_pseudo(encoding), None, "{encoding}");
+        }
+    }
+}
@@ -17,7 +17,7 @@
 //! The simplest encoding that sends pixel data directly without compression.
 //! High bandwidth but universally supported.
 
-use crate::Encoding;
+use crate::{Encoding, RowOrder};
 use bytes::{BufMut, BytesMut};
 
 /// Implements the VNC "Raw" encoding, which sends pixel data directly without compression.
@@ -47,3 +47,31 @@ impl Encoding for RawEncoding {
         buf
     }
 }
+
+/// Encodes RGBA data as Raw (RGBX) while reading rows in `row_order`.
+///
+/// With [`RowOrder::BottomUp`] the last input row is sent first, so the client sees
+/// a top-down image. Rows are read in place; the frame is not copied first.
+#[must_use]
+pub fn encode_raw_with_row_order(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    row_order: RowOrder,
+) -> BytesMut {
+    let row_bytes = width as usize * 4;
+    let mut buf = BytesMut::with_capacity(row_bytes * height as usize);
+    let mut put_row = |row: usize| {
+        for chunk in data[row * row_bytes..(row + 1) * row_bytes].chunks_exact(4) {
+            buf.put_u8(chunk[0]); // R at byte 0
+            buf.put_u8(chunk[1]); // G at byte 1
+            buf.put_u8(chunk[2]); // B at byte 2
+            buf.put_u8(0); // Padding at byte 3 (not alpha)
+        }
+    };
+    match row_order {
+        RowOrder::TopDown => (0..height as usize).for_each(&mut put_row),
+        RowOrder::BottomUp => (0..height as usize).rev().for_each(&mut put_row),
+    }
+    buf
+}
@@ -0,0 +1,129 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversions between `image::RgbaImage` and the RGBA framebuffers this crate encodes.
+//!
+//! Enabled with the `image` feature. Meant for tools and tests: load a screenshot,
+//! encode it, and turn decoded rectangles back into images for inspection.
+
+use crate::translate::extract_rgb;
+use crate::PixelFormat;
+use image::RgbaImage;
+use std::io;
+
+/// An RGBA framebuffer in the layout the encoders expect: `width * height` pixels
+/// of 4 bytes (`[R, G, B, A]`), rows top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaFrame {
+    /// Pixel data, `width * height * 4` bytes.
+    pub data: Vec<u8>,
+    /// Width in pixels.
+    pub width: u16,
+    /// Height in pixels.
+    pub height: u16,
+}
+
+impl TryFrom<&RgbaImage> for RgbaFrame {
+    type Error = io::Error;
+
+    /// Copies the image's pixels. Fails if either dimension does not fit the
+    /// protocol's 16-bit rectangle size.
+    fn try_from(image: &RgbaImage) -> io::Result<Self> {
+        let (width, height) = image.dimensions();
+        let too_large = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{width}x{height} image exceeds the 65535x65535 RFB limit"),
+            )
+        };
+        Ok(Self {
+            data: image.as_raw().clone(),
+            width: u16::try_from(width).map_err(|_| too_large())?,
+            height: u16::try_from(height).map_err(|_| too_large())?,
+        })
+    }
+}
+
+impl From<RgbaFrame> for (Vec<u8>, u16, u16) {
+    fn from(frame: RgbaFrame) -> Self {
+        (frame.data, frame.width, frame.height)
+    }
+}
+
+/// Converts a decoded rectangle back into an `RgbaImage` for visualization.
+///
+/// `pixels` holds `width * height` pixels in `pixel_format`, as returned by the
+/// decoders in [`crate::decode`] or carried by a Raw payload. RFB pixels have no
+/// alpha, so every output pixel is opaque.
+///
+/// # Errors
+///
+/// Returns an error if `pixels` is not exactly `width * height` pixels long
+pub fn decoded_to_rgba_image(
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> io::Result<RgbaImage> {
+    let bpp = (pixel_format.bits_per_pixel / 8).max(1) as usize;
+    let expected = width as usize * height as usize * bpp;
+    if pixels.len() != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{width}x{height} rectangle needs {expected} bytes, got {}",
+                pixels.len()
+            ),
+        ));
+    }
+    let rgba = pixels
+        .chunks_exact(bpp)
+        .flat_map(|pixel| {
+            let (r, g, b) = extract_rgb(pixel, pixel_format);
+            [r, g, b, 255]
+        })
+        .collect();
+    RgbaImage::from_raw(u32::from(width), u32::from(height), rgba)
+        .ok_or_else(|| io::Error::other("RGBA buffer does not match the image size"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "zrle")]
+    #[test]
+    fn test_rgba_image_roundtrip_through_zrle() {
+        use crate::decode::decode_zrle;
+        use crate::zrle::encode_zrle;
+        use flate2::Decompress;
+        use image::Rgba;
+
+        let image = RgbaImage::from_fn(40, 24, |x, y| {
+            let c = |v: u32| u8::try_from(v % 256).unwrap();
+            Rgba([c(x * 6), c(y * 10), c((x + y) * 3), 255])
+        });
+        let (data, width, height) = RgbaFrame::try_from(&image).unwrap().into();
+        assert_eq!((width, height), (40, 24));
+
+        let pf = PixelFormat::rgba32();
+        let encoded = encode_zrle(&data, width, height, &pf, 6).unwrap();
+        let decoded = decode_zrle(&encoded, width, height, &pf, &mut Decompress::new(true))
+            .expect("ZRLE decode failed");
+        assert_eq!(
+            decoded_to_rgba_image(&decoded, width, height, &pf).unwrap(),
+            image
+        );
+    }
+}
@@ -17,19 +17,22 @@
 //! RRE encodes a rectangle as a background color plus a list of subrectangles
 //! with their own colors. Effective for large solid regions.
 
-use super::common::{find_subrects, get_background_color, rgba_to_rgb24_pixels};
+use super::common::{check_rgba_len, find_subrects, get_background_color, rgba_to_rgb24_pixels};
 use crate::Encoding;
 use bytes::{BufMut, BytesMut};
+use std::io;
 
 /// Implements the VNC "RRE" (Rise-and-Run-length Encoding).
 ///
 /// RRE encodes a rectangle as a background color plus a list of subrectangles
 /// with their own colors. Format: \[nSubrects(u32)\]\[bgColor\]\[subrect1\]...\[subrectN\]
 /// Each subrect: \[color\]\[x(u16)\]\[y(u16)\]\[w(u16)\]\[h(u16)\]
+///
+/// The [`Encoding`] trait cannot report errors, so undersized input produces empty
+/// output; use [`try_encode_rre`] to detect it.
 pub struct RreEncoding;
 
 impl Encoding for RreEncoding {
-    #[allow(clippy::cast_possible_truncation)] // Subrectangle count limited to image size per VNC protocol
     fn encode(
         &self,
         data: &[u8],
@@ -38,34 +41,74 @@ impl Encoding for RreEncoding {
         _quality: u8,
         _compression: u8,
     ) -> BytesMut {
-        // Convert RGBA to RGB pixels (u32 format: 0RGB)
-        let pixels = rgba_to_rgb24_pixels(data);
+        try_encode_rre(data, width, height).unwrap_or_default()
+    }
+}
+
+/// Encodes RGBA data as RRE, checking the buffer size first.
+///
+/// # Errors
+///
+/// Returns an error if `data` holds fewer than `width * height` RGBA pixels.
+#[allow(clippy::cast_possible_truncation)] // Subrectangle count limited to image size per VNC protocol
+pub fn try_encode_rre(data: &[u8], width: u16, height: u16) -> io::Result<BytesMut> {
+    check_rgba_len(data, width, height, "RRE")?;
+    let data = &data[..width as usize * height as usize * 4];
+
+    // Convert RGBA to RGB pixels (u32 format: 0RGB)
+    let pixels = rgba_to_rgb24_pixels(data);
 
-        // Find background color (most common pixel)
-        let bg_color = get_background_color(&pixels);
+    // Find background color (most common pixel)
+    let bg_color = get_background_color(&pixels);
 
-        // Find all subrectangles
-        let subrects = find_subrects(&pixels, width as usize, height as usize, bg_color);
+    // Find all subrectangles
+    let subrects = find_subrects(&pixels, width as usize, height as usize, bg_color);
 
-        // Always encode all pixels to avoid data loss
-        // (Even if RRE is inefficient, we must preserve the image correctly)
-        let encoded_size = 4 + 4 + (subrects.len() * (4 + 8)); // header + bg + subrects
+    // Always encode all pixels to avoid data loss
+    // (Even if RRE is inefficient, we must preserve the image correctly)
+    let encoded_size = 4 + 4 + (subrects.len() * (4 + 8)); // header + bg + subrects
+
+    let mut buf = BytesMut::with_capacity(encoded_size);
+
+    // Write header
+    buf.put_u32(subrects.len() as u32); // number of subrectangles (big-endian)
+    buf.put_u32_le(bg_color); // background color in client pixel format (little-endian)
+
+    // Write subrectangles
+    for subrect in subrects {
+        buf.put_u32_le(subrect.color); // pixel in client format (little-endian)
+        buf.put_u16(subrect.x); // protocol coordinates (big-endian)
+        buf.put_u16(subrect.y);
+        buf.put_u16(subrect.w);
+        buf.put_u16(subrect.h);
+    }
+
+    Ok(buf)
+}
 
-        let mut buf = BytesMut::with_capacity(encoded_size);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Write header
-        buf.put_u32(subrects.len() as u32); // number of subrectangles (big-endian)
-        buf.put_u32_le(bg_color); // background color in client pixel format (little-endian)
+    #[test]
+    fn test_rre_rejects_undersized_buffers() {
+        // Two vertical bands on a grey background
+        let data: Vec<u8> = (0..16 * 16)
+            .flat_map(|i| match i % 16 {
+                2..=4 => [255, 0, 0, 255],
+                9 => [0, 0, 255, 255],
+                _ => [128, 128, 128, 255],
+            })
+            .collect();
+        let short = &data[..data.len() - 4];
 
-        // Write subrectangles
-        for subrect in subrects {
-            buf.put_u32_le(subrect.color); // pixel in client format (little-endian)
-            buf.put_u16(subrect.x); // protocol coordinates (big-endian)
-            buf.put_u16(subrect.y);
-            buf.put_u16(subrect.w);
-            buf.put_u16(subrect.h);
-        }
+        let err = try_encode_rre(short, 16, 16).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // The infallible trait entry point returns empty output instead of panicking
+        assert!(RreEncoding.encode(short, 16, 16, 0, 0).is_empty());
 
-        buf
+        let encoded = try_encode_rre(&data, 16, 16).unwrap();
+        assert_eq!(encoded, RreEncoding.encode(&data, 16, 16, 0, 0));
+        assert_eq!(encoded[..4], 2u32.to_be_bytes());
     }
 }
@@ -0,0 +1,120 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Byte-shuffle kernels for the `simd` feature.
+//!
+//! Each kernel handles the bulk of a buffer and reports how much it did; the
+//! caller finishes the rest with its scalar code, which is also the reference the
+//! kernels are tested against. SSSE3 is detected at runtime on `x86_64`; NEON is
+//! part of the `aarch64` baseline. Other targets do nothing here.
+
+/// Packs RGBA pixels from `src` into RGB24 in `dst`, four pixels per 16-byte
+/// shuffle, and returns the number of pixels packed.
+///
+/// Each store writes 16 bytes for 12 bytes of pixels, so the last pixels are left
+/// for the caller rather than written past the end of `dst`.
+pub(crate) fn pack_rgb24(src: &[u8], dst: &mut [u8]) -> usize {
+    let blocks = (src.len() / 16).min(dst.len().saturating_sub(4) / 12);
+    pack_rgb24_blocks(src, dst, blocks) * 4
+}
+
+#[cfg(target_arch = "x86_64")]
+fn pack_rgb24_blocks(src: &[u8], dst: &mut [u8], blocks: usize) -> usize {
+    if !is_x86_feature_detected!("ssse3") {
+        return 0;
+    }
+    // SAFETY: SSSE3 is available, and `pack_rgb24` sized `blocks` so every load
+    // and store stays in bounds
+    unsafe { pack_rgb24_ssse3(src, dst, blocks) };
+    blocks
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "ssse3")]
+unsafe fn pack_rgb24_ssse3(src: &[u8], dst: &mut [u8], blocks: usize) {
+    use std::arch::x86_64::{_mm_loadu_si128, _mm_setr_epi8, _mm_shuffle_epi8, _mm_storeu_si128};
+
+    // Indices with the high bit set zero the four spare output bytes
+    let shuffle = _mm_setr_epi8(0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, -1, -1, -1, -1);
+    for block in 0..blocks {
+        let pixels = _mm_loadu_si128(src.as_ptr().add(block * 16).cast());
+        _mm_storeu_si128(
+            dst.as_mut_ptr().add(block * 12).cast(),
+            _mm_shuffle_epi8(pixels, shuffle),
+        );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn pack_rgb24_blocks(src: &[u8], dst: &mut [u8], blocks: usize) -> usize {
+    use std::arch::aarch64::{vld1q_u8, vqtbl1q_u8, vst1q_u8};
+
+    // Out-of-range indices zero the four spare output bytes
+    let shuffle: [u8; 16] = [0, 1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14, 255, 255, 255, 255];
+    // SAFETY: NEON is part of the aarch64 baseline, and `pack_rgb24` sized `blocks`
+    // so every load and store stays in bounds
+    unsafe {
+        let shuffle = vld1q_u8(shuffle.as_ptr());
+        for block in 0..blocks {
+            let pixels = vld1q_u8(src.as_ptr().add(block * 16));
+            vst1q_u8(
+                dst.as_mut_ptr().add(block * 12),
+                vqtbl1q_u8(pixels, shuffle),
+            );
+        }
+    }
+    blocks
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn pack_rgb24_blocks(_src: &[u8], _dst: &mut [u8], _blocks: usize) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{pack_rgb24_scalar, rgba_to_rgb24_bytes};
+
+    #[test]
+    fn test_pack_rgb24_matches_scalar_1920x1080() {
+        let pixel_count = 1920 * 1080;
+        let pixels: Vec<u8> = (0..pixel_count * 4u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24).to_le_bytes()[0])
+            .collect();
+        let mut scalar = vec![0u8; pixels.len() / 4 * 3];
+        pack_rgb24_scalar(&pixels, &mut scalar);
+        assert_eq!(rgba_to_rgb24_bytes(&pixels), scalar);
+
+        // The kernel on its own leaves the last block to the scalar code
+        let mut packed = vec![0u8; scalar.len()];
+        let done = pack_rgb24(&pixels, &mut packed);
+        assert_eq!(packed[..done * 3], scalar[..done * 3]);
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("ssse3") {
+            assert_eq!(done, pixel_count as usize - 4);
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(done, pixel_count as usize - 4);
+
+        // Unaligned input, and lengths ending anywhere around a block
+        let shifted = &pixels[1..=4000];
+        let mut expected = vec![0u8; 3000];
+        pack_rgb24_scalar(shifted, &mut expected);
+        assert_eq!(rgba_to_rgb24_bytes(shifted), expected);
+        for len in (0..=40).step_by(4) {
+            assert_eq!(rgba_to_rgb24_bytes(&pixels[..len]), scalar[..len / 4 * 3]);
+        }
+    }
+}
@@ -22,7 +22,7 @@
 //! - Rectangle splitting and subdivision
 //! - Solid area detection and extraction
 //! - Recursive optimization for best encoding
-//! - Size limit enforcement (`TIGHT_MAX_RECT_SIZE`, `TIGHT_MAX_RECT_WIDTH`)
+//! - Size limit enforcement (configurable through [`TightLimits`])
 //!
 //! ## Layer 2: Low-Level Encoding
 //! - Palette analysis
@@ -45,6 +45,7 @@
 //! 3. **Indexed palette** (3-16 colors) - control byte 0x60 or 0xA0
 //!    - Wire format: `[control][0x01][n-1][colors...][length][indices]`
 //!    - Each pixel encoded as palette index (1 byte)
+//!    - Only chosen with `TightOptions::indexed_palette`
 //!
 //! 4. **Full-color zlib** - control byte 0x00 or 0xA0
 //!    - Wire format: `[control][length][zlib compressed RGB24]`
@@ -56,29 +57,49 @@
 //!
 //! # Configuration Constants
 //!
+//! The last four are defaults of [`TightLimits`]. The first is fixed by the
+//! protocol: clients tell from the data size whether a length follows.
+//!
 //! ```text
 //! TIGHT_MIN_TO_COMPRESS = 12      (data < 12 bytes sent raw)
+//! MAX_SPLIT_TILE_SIZE = 16        (tile size for solid detection)
 //! MIN_SPLIT_RECT_SIZE = 4096      (split rectangles >= 4096 pixels)
 //! MIN_SOLID_SUBRECT_SIZE = 2048   (solid areas must be >= 2048 pixels)
-//! MAX_SPLIT_TILE_SIZE = 16        (tile size for solid detection)
 //! TIGHT_MAX_RECT_SIZE = 65536     (max pixels per rectangle)
 //! TIGHT_MAX_RECT_WIDTH = 2048     (max rectangle width)
 //! ```
 
-use super::common::translate_pixel_to_client_format;
+use super::common::{
+    check_rgba_len, compress_sync_flush, pixel_value, put_tpixel, rgba_to_rgb24_bytes, tpixel_len,
+    translate_pixel_to_client_format, DeflateStream, FramingCursor, FramingError,
+    SubEncodingHistogram, ZlibStrategy, ZlibStream,
+};
+use crate::tightpng::encode_tightpng_png;
 use crate::{Encoding, PixelFormat};
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::io;
 
 // Tight encoding protocol constants (RFC 6143 section 7.7.4)
-const TIGHT_EXPLICIT_FILTER: u8 = 0x04;
-const TIGHT_FILL: u8 = 0x08;
-#[allow(dead_code)]
-const TIGHT_JPEG: u8 = 0x09;
-const TIGHT_NO_ZLIB: u8 = 0x0A;
+pub(crate) const TIGHT_EXPLICIT_FILTER: u8 = 0x04;
+pub(crate) const TIGHT_FILL: u8 = 0x08;
+pub(crate) const TIGHT_JPEG: u8 = 0x09;
+pub(crate) const TIGHT_NO_ZLIB: u8 = 0x0A;
+/// Non-standard control type for JPEG with a separate alpha plane
+#[cfg(feature = "alpha-jpeg")]
+pub(crate) const TIGHT_JPEG_ALPHA: u8 = 0x0B;
+/// Non-standard control type for a 0x0 rectangle defining the frame's shared palette
+#[cfg(feature = "tight-shared-palette")]
+pub(crate) const TIGHT_SHARED_PALETTE: u8 = 0x0C;
 
 // Filter types
-const TIGHT_FILTER_PALETTE: u8 = 0x01;
+pub(crate) const TIGHT_FILTER_COPY: u8 = 0x00;
+pub(crate) const TIGHT_FILTER_PALETTE: u8 = 0x01;
+pub(crate) const TIGHT_FILTER_GRADIENT: u8 = 0x02;
+/// Non-standard filter type: indices into the frame's shared palette
+#[cfg(feature = "tight-shared-palette")]
+pub(crate) const TIGHT_FILTER_SHARED_PALETTE: u8 = 0x03;
 
 /// Zlib stream ID for full-color data (RFC 6143 section 7.7.4)
 pub const STREAM_ID_FULL_COLOR: u8 = 0;
@@ -87,17 +108,40 @@ pub const STREAM_ID_MONO: u8 = 1;
 /// Zlib stream ID for indexed palette data (RFC 6143 section 7.7.4)
 pub const STREAM_ID_INDEXED: u8 = 2;
 
+/// Default for [`TightOptions::max_solid_searches`]: enough to scan a 1024x1024
+/// rectangle made entirely of small solid tiles
+pub const DEFAULT_MAX_SOLID_SEARCHES: usize = 4096;
+
+/// Quality level at which Tight stops sending JPEG.
+///
+/// Levels 0-9 enable JPEG, 0 being the best; 10 disables it, so a caller counting
+/// levels 1-10 gets no JPEG at its top level. Levels above 10 behave exactly like 10
+/// and are logged as a warning with the `debug-logging` feature.
+pub const JPEG_DISABLED_QUALITY: u8 = 10;
+
+/// Whether `quality` enables JPEG: levels below [`JPEG_DISABLED_QUALITY`] do.
+#[must_use]
+pub const fn jpeg_enabled(quality: u8) -> bool {
+    quality < JPEG_DISABLED_QUALITY
+}
+
+/// Lowest quality level (JPEG quality 32) that [`TightOptions::max_rect_bytes`]
+/// lowers an oversized JPEG rectangle to before splitting it instead
+pub const BUDGET_JPEG_QUALITY_FLOOR: u8 = 9;
+
 // Compression thresholds for Tight encoding optimization
-const TIGHT_MIN_TO_COMPRESS: usize = 12;
+pub(crate) const TIGHT_MIN_TO_COMPRESS: usize = 12;
 const MIN_SPLIT_RECT_SIZE: usize = 4096;
 const MIN_SOLID_SUBRECT_SIZE: usize = 2048;
 const MAX_SPLIT_TILE_SIZE: u16 = 16;
 const TIGHT_MAX_RECT_SIZE: usize = 65536;
 const TIGHT_MAX_RECT_WIDTH: u16 = 2048;
+const TIGHT_MAX_PALETTE_COLORS: usize = 16;
 
 /// Compression configuration for different quality levels
 struct TightConf {
     mono_min_rect_size: usize,
+    idx_max_colors_divisor: usize,
     idx_zlib_level: u8,
     mono_zlib_level: u8,
     raw_zlib_level: u8,
@@ -106,24 +150,28 @@ struct TightConf {
 const TIGHT_CONF: [TightConf; 4] = [
     TightConf {
         mono_min_rect_size: 6,
+        idx_max_colors_divisor: 4,
         idx_zlib_level: 0,
         mono_zlib_level: 0,
         raw_zlib_level: 0,
     }, // Level 0
     TightConf {
         mono_min_rect_size: 32,
+        idx_max_colors_divisor: 8,
         idx_zlib_level: 1,
         mono_zlib_level: 1,
         raw_zlib_level: 1,
     }, // Level 1
     TightConf {
         mono_min_rect_size: 32,
+        idx_max_colors_divisor: 24,
         idx_zlib_level: 3,
         mono_zlib_level: 3,
         raw_zlib_level: 2,
     }, // Level 2
     TightConf {
         mono_min_rect_size: 32,
+        idx_max_colors_divisor: 32,
         idx_zlib_level: 7,
         mono_zlib_level: 7,
         raw_zlib_level: 5,
@@ -145,6 +193,11 @@ struct EncodeResult {
 }
 
 /// Implements the VNC "Tight" encoding (RFC 6143 section 7.7.4).
+///
+/// [`Encoding::encode`] cannot report errors, so when the input holds fewer than
+/// `width * height` pixels (or the encoder fails or panics) the pixels are returned
+/// Raw-encoded instead, with missing pixels zero-filled, as
+/// [`crate::encode_fallback`] does. Use [`try_encode_tight_rects`] to see the error.
 pub struct TightEncoding;
 
 impl Encoding for TightEncoding {
@@ -156,34 +209,51 @@ impl Encoding for TightEncoding {
         quality: u8,
         compression: u8,
     ) -> BytesMut {
-        // Simple wrapper - for full optimization, use encode_rect_optimized
-        // Default to RGBA32 format for backward compatibility (old API doesn't have client format)
-        // Create a temporary compressor for this call (old API doesn't have persistent streams)
-        let mut compressor = SimpleTightCompressor::new(compression);
-
-        let rect = Rect {
-            x: 0,
-            y: 0,
-            w: width,
-            h: height,
-        };
-        let default_format = PixelFormat::rgba32();
-        let result = encode_rect_optimized(
-            data,
-            width,
-            &rect,
-            quality,
-            compression,
-            &default_format,
-            &mut compressor,
-        );
+        crate::common::encode_or_raw_on_panic("Tight", data, width, height, || {
+            // Simple wrapper - for full optimization, use encode_rect_optimized
+            // Default to RGBA32 format for backward compatibility (old API doesn't have client format)
+            // Create a temporary compressor for this call (old API doesn't have persistent streams)
+            let mut compressor = SimpleTightCompressor::new(compression);
+
+            let rect = Rect {
+                x: 0,
+                y: 0,
+                w: width,
+                h: height,
+            };
+            let default_format = PixelFormat::rgba32();
+            // Default options only fail on short input (JPEG errors fall back to
+            // full-color), which is sent Raw like a panic
+            let Ok(result) = check_rgba_len(data, width, height, "Tight").and_then(|()| {
+                encode_rect_optimized(
+                    data,
+                    width,
+                    &rect,
+                    quality,
+                    compression,
+                    &default_format,
+                    &TightOptions::default(),
+                    &mut compressor,
+                )
+            }) else {
+                return crate::encode_fallback(
+                    crate::ENCODING_RAW,
+                    data,
+                    width,
+                    height,
+                    quality,
+                    compression,
+                )
+                .unwrap_or_default();
+            };
 
-        // Concatenate all rectangles
-        let mut output = BytesMut::new();
-        for (_rect, buf) in result.rectangles {
-            output.extend_from_slice(&buf);
-        }
-        output
+            // Concatenate all rectangles
+            let mut output = BytesMut::new();
+            for (_rect, buf) in result.rectangles {
+                output.extend_from_slice(&buf);
+            }
+            output
+        })
     }
 }
 
@@ -192,6 +262,7 @@ impl Encoding for TightEncoding {
 #[allow(clippy::similar_names)] // dx_end and dy_end are clear in context (delta x/y end coordinates)
 #[allow(clippy::too_many_lines)] // Complex algorithm implementing RFC 6143 Tight encoding optimization
 #[allow(clippy::cast_possible_truncation)] // Rectangle dimensions limited to u16 per VNC protocol
+#[allow(clippy::too_many_arguments)] // Options are threaded through every encoding stage
 fn encode_rect_optimized<C: TightStreamCompressor>(
     framebuffer: &[u8],
     fb_width: u16,
@@ -199,12 +270,20 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
     quality: u8,
     compression: u8,
     client_format: &PixelFormat,
+    options: &TightOptions,
     compressor: &mut C,
-) -> EncodeResult {
+) -> io::Result<EncodeResult> {
     #[cfg(feature = "debug-logging")]
     log::info!("DEBUG: encode_rect_optimized called: rect={}x{} at ({}, {}), quality={}, compression={}, bpp={}",
         rect.w, rect.h, rect.x, rect.y, quality, compression, client_format.bits_per_pixel);
 
+    #[cfg(feature = "debug-logging")]
+    if quality > JPEG_DISABLED_QUALITY {
+        log::warn!(
+            "Tight: quality {quality} is above {JPEG_DISABLED_QUALITY} and disables JPEG the same way"
+        );
+    }
+
     let mut rectangles = Vec::new();
 
     // Normalize compression level based on quality settings
@@ -217,15 +296,19 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
     let rect_size = rect.w as usize * rect.h as usize;
 
     #[cfg(feature = "debug-logging")]
-    log::info!("DEBUG: rect_size={rect_size}, MIN_SPLIT_RECT_SIZE={MIN_SPLIT_RECT_SIZE}");
+    log::info!(
+        "DEBUG: rect_size={rect_size}, min_split_rect_size={}",
+        options.limits.min_split_rect_size
+    );
 
-    if rect_size < MIN_SPLIT_RECT_SIZE {
+    // With skip_solid_scan, solid areas are only found by per-subrect palette analysis
+    if rect_size < options.limits.min_split_rect_size || options.skip_solid_scan {
         #[cfg(feature = "debug-logging")]
-        log::info!("DEBUG: Rectangle too small for optimization");
+        log::info!("DEBUG: Rectangle too small for optimization or solid scan disabled");
 
         // Too small for optimization - but still check if it needs splitting due to size limits
-        if rect.w > TIGHT_MAX_RECT_WIDTH
-            || ((rect.w as usize) * (rect.h as usize)) > TIGHT_MAX_RECT_SIZE
+        if rect.w > options.limits.max_width()
+            || ((rect.w as usize) * (rect.h as usize)) > options.limits.max_size()
         {
             #[cfg(feature = "debug-logging")]
             log::info!("DEBUG: But rectangle needs splitting - calling encode_large_rect");
@@ -238,23 +321,24 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                 quality,
                 compression,
                 client_format,
+                options,
                 compressor,
-            ));
+            )?);
         } else {
             #[cfg(feature = "debug-logging")]
             log::info!("DEBUG: Rectangle small enough - encode directly");
 
             // Small enough - encode directly
-            let buf = encode_subrect_single(
+            rectangles.extend(encode_subrect(
                 framebuffer,
                 fb_width,
                 rect,
                 quality,
                 compression,
                 client_format,
+                options,
                 compressor,
-            );
-            rectangles.push((rect.clone(), buf));
+            )?);
         }
 
         #[cfg(feature = "debug-logging")]
@@ -263,21 +347,26 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
             rectangles.len()
         );
 
-        return EncodeResult { rectangles };
+        return Ok(EncodeResult { rectangles });
     }
 
     #[cfg(feature = "debug-logging")]
     log::info!("DEBUG: Rectangle large enough for optimization - continuing");
 
-    // Calculate maximum rows per rectangle
-    let n_max_width = rect.w.min(TIGHT_MAX_RECT_WIDTH);
-    let n_max_rows = (TIGHT_MAX_RECT_SIZE / n_max_width as usize) as u16;
+    // Calculate maximum rows per rectangle; a 1-pixel-wide rect allows 65536 rows,
+    // which saturates to u16::MAX instead of truncating to 0
+    let n_max_width = rect.w.min(options.limits.max_width());
+    let n_max_rows =
+        u16::try_from(options.limits.max_size() / n_max_width as usize).unwrap_or(u16::MAX);
 
     // Try to find large solid-color areas for optimization
     // Track the current scan position and base position (like C code's y and h)
     let mut current_y = rect.y;
     let mut base_y = rect.y; // Corresponds to C code's 'y' variable
     let mut remaining_h = rect.h; // Corresponds to C code's 'h' variable
+    let mut searches_left = options
+        .max_solid_searches
+        .unwrap_or(DEFAULT_MAX_SOLID_SEARCHES);
 
     #[cfg(feature = "debug-logging")]
     log::info!(
@@ -286,7 +375,7 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
         rect.h
     );
 
-    while current_y < base_y + remaining_h {
+    'scan: while current_y < base_y + remaining_h {
         #[cfg(feature = "debug-logging")]
         log::info!("DEBUG: Loop iteration: current_y={current_y}, base_y={base_y}, remaining_h={remaining_h}");
         // Check if rectangle becomes too large (like C code: if (dy - y >= nMaxRows))
@@ -298,7 +387,7 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                 h: n_max_rows,
             };
             // Chunk might still be too wide - check and split if needed
-            if chunk_rect.w > TIGHT_MAX_RECT_WIDTH {
+            if chunk_rect.w > options.limits.max_width() {
                 rectangles.extend(encode_large_rect(
                     framebuffer,
                     fb_width,
@@ -306,19 +395,20 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                     quality,
                     compression,
                     client_format,
+                    options,
                     compressor,
-                ));
+                )?);
             } else {
-                let buf = encode_subrect_single(
+                rectangles.extend(encode_subrect(
                     framebuffer,
                     fb_width,
                     &chunk_rect,
                     quality,
                     compression,
                     client_format,
+                    options,
                     compressor,
-                );
-                rectangles.push((chunk_rect, buf));
+                )?);
             }
             // Like C code: y += nMaxRows; h -= nMaxRows;
             base_y += n_max_rows;
@@ -344,9 +434,22 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
             }
 
             // Check if tile is solid
-            if let Some(color_value) =
-                check_solid_tile(framebuffer, fb_width, current_x, current_y, dw, dh, None)
-            {
+            if let Some(color_value) = check_solid_tile(
+                framebuffer,
+                fb_width,
+                current_x,
+                current_y,
+                dw,
+                dh,
+                None,
+                options.solid_include_alpha,
+            ) {
+                // Out of budget: stop optimizing and encode what is left directly
+                if searches_left == 0 {
+                    break 'scan;
+                }
+                searches_left -= 1;
+
                 // Find best solid area
                 let (w_best, h_best) = find_best_solid_area(
                     framebuffer,
@@ -356,11 +459,12 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                     rect.w - (current_x - rect.x),
                     remaining_h - (current_y - base_y),
                     color_value,
+                    options.solid_include_alpha,
                 );
 
                 // Check if solid area is large enough
                 if (w_best as usize * h_best as usize) != (rect.w as usize * remaining_h as usize)
-                    && (w_best as usize * h_best as usize) < MIN_SOLID_SUBRECT_SIZE
+                    && (w_best as usize * h_best as usize) < options.limits.min_solid_subrect_size
                 {
                     current_x += dw;
                     continue;
@@ -375,6 +479,7 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                     rect.w,
                     remaining_h,
                     color_value,
+                    options.solid_include_alpha,
                     current_x,
                     current_y,
                     w_best,
@@ -390,8 +495,9 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                         h: y_best - base_y,
                     };
                     // top_rect might be too wide - check and split if needed
-                    if top_rect.w > TIGHT_MAX_RECT_WIDTH
-                        || ((top_rect.w as usize) * (top_rect.h as usize)) > TIGHT_MAX_RECT_SIZE
+                    if top_rect.w > options.limits.max_width()
+                        || ((top_rect.w as usize) * (top_rect.h as usize))
+                            > options.limits.max_size()
                     {
                         rectangles.extend(encode_large_rect(
                             framebuffer,
@@ -400,19 +506,20 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        ));
+                        )?);
                     } else {
-                        let buf = encode_subrect_single(
+                        rectangles.extend(encode_subrect(
                             framebuffer,
                             fb_width,
                             &top_rect,
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        );
-                        rectangles.push((top_rect, buf));
+                        )?);
                     }
                 }
 
@@ -424,8 +531,9 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                         h: h_best,
                     };
                     // Don't recursively optimize - just check size and encode
-                    if left_rect.w > TIGHT_MAX_RECT_WIDTH
-                        || ((left_rect.w as usize) * (left_rect.h as usize)) > TIGHT_MAX_RECT_SIZE
+                    if left_rect.w > options.limits.max_width()
+                        || ((left_rect.w as usize) * (left_rect.h as usize))
+                            > options.limits.max_size()
                     {
                         rectangles.extend(encode_large_rect(
                             framebuffer,
@@ -434,31 +542,39 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        ));
+                        )?);
                     } else {
-                        let buf = encode_subrect_single(
+                        rectangles.extend(encode_subrect(
                             framebuffer,
                             fb_width,
                             &left_rect,
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        );
-                        rectangles.push((left_rect, buf));
+                        )?);
                     }
                 }
 
-                // Send solid rectangle
-                let solid_rect = Rect {
-                    x: x_best,
-                    y: y_best,
-                    w: w_best,
-                    h: h_best,
-                };
-                let buf = encode_solid_rect(color_value, client_format);
-                rectangles.push((solid_rect, buf));
+                // Send solid rectangle; fills carry no pixel data, so only the
+                // width limit applies
+                // Drop the alpha bits that solid_include_alpha may have added
+                let buf = encode_solid_rect(color_value & 0x00FF_FFFF, client_format);
+                let mut x = x_best;
+                while x < x_best + w_best {
+                    let w = (x_best + w_best - x).min(options.limits.max_width());
+                    let solid_rect = Rect {
+                        x,
+                        y: y_best,
+                        w,
+                        h: h_best,
+                    };
+                    rectangles.push((solid_rect, buf.clone()));
+                    x += w;
+                }
 
                 // Send remaining rectangles
                 if x_best + w_best != rect.x + rect.w {
@@ -469,8 +585,9 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                         h: h_best,
                     };
                     // Don't recursively optimize - just check size and encode
-                    if right_rect.w > TIGHT_MAX_RECT_WIDTH
-                        || ((right_rect.w as usize) * (right_rect.h as usize)) > TIGHT_MAX_RECT_SIZE
+                    if right_rect.w > options.limits.max_width()
+                        || ((right_rect.w as usize) * (right_rect.h as usize))
+                            > options.limits.max_size()
                     {
                         rectangles.extend(encode_large_rect(
                             framebuffer,
@@ -479,19 +596,20 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        ));
+                        )?);
                     } else {
-                        let buf = encode_subrect_single(
+                        rectangles.extend(encode_subrect(
                             framebuffer,
                             fb_width,
                             &right_rect,
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        );
-                        rectangles.push((right_rect, buf));
+                        )?);
                     }
                 }
 
@@ -503,9 +621,9 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                         h: remaining_h - (y_best - base_y) - h_best,
                     };
                     // Don't recursively optimize - just check size and encode
-                    if bottom_rect.w > TIGHT_MAX_RECT_WIDTH
+                    if bottom_rect.w > options.limits.max_width()
                         || ((bottom_rect.w as usize) * (bottom_rect.h as usize))
-                            > TIGHT_MAX_RECT_SIZE
+                            > options.limits.max_size()
                     {
                         rectangles.extend(encode_large_rect(
                             framebuffer,
@@ -514,23 +632,24 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        ));
+                        )?);
                     } else {
-                        let buf = encode_subrect_single(
+                        rectangles.extend(encode_subrect(
                             framebuffer,
                             fb_width,
                             &bottom_rect,
                             quality,
                             compression,
                             client_format,
+                            options,
                             compressor,
-                        );
-                        rectangles.push((bottom_rect, buf));
+                        )?);
                     }
                 }
 
-                return EncodeResult { rectangles };
+                return Ok(EncodeResult { rectangles });
             }
 
             current_x += dw;
@@ -548,9 +667,16 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
     #[cfg(feature = "debug-logging")]
     log::info!("DEBUG: Exited optimization loop, no solid areas found");
 
-    // No solid areas found - encode normally (but check if it needs splitting)
-    if rect.w > TIGHT_MAX_RECT_WIDTH
-        || ((rect.w as usize) * (rect.h as usize)) > TIGHT_MAX_RECT_SIZE
+    // No solid areas found (or the search budget ran out) - encode the rows not yet
+    // sent as chunks normally (but check if it needs splitting)
+    let rect = &Rect {
+        x: rect.x,
+        y: base_y,
+        w: rect.w,
+        h: remaining_h,
+    };
+    if rect.w > options.limits.max_width()
+        || ((rect.w as usize) * (rect.h as usize)) > options.limits.max_size()
     {
         #[cfg(feature = "debug-logging")]
         log::info!("DEBUG: Rectangle needs splitting, calling encode_large_rect");
@@ -562,22 +688,23 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
             quality,
             compression,
             client_format,
+            options,
             compressor,
-        ));
+        )?);
     } else {
         #[cfg(feature = "debug-logging")]
         log::info!("DEBUG: Rectangle small enough, encoding directly");
 
-        let buf = encode_subrect_single(
+        rectangles.extend(encode_subrect(
             framebuffer,
             fb_width,
             rect,
             quality,
             compression,
             client_format,
+            options,
             compressor,
-        );
-        rectangles.push((rect.clone(), buf));
+        )?);
     }
 
     #[cfg(feature = "debug-logging")]
@@ -586,7 +713,7 @@ fn encode_rect_optimized<C: TightStreamCompressor>(
         rectangles.len()
     );
 
-    EncodeResult { rectangles }
+    Ok(EncodeResult { rectangles })
 }
 
 /// Normalize compression level based on JPEG quality
@@ -596,7 +723,7 @@ fn normalize_compression_level(compression: u8, quality: u8) -> u8 {
 
     // JPEG enabled (quality < 10): enforce minimum level 1, maximum level 2
     // This ensures better compression performance with JPEG
-    if quality < 10 {
+    if jpeg_enabled(quality) {
         level = level.clamp(1, 2);
     }
     // JPEG disabled (quality >= 10): cap at level 1
@@ -615,6 +742,7 @@ fn normalize_compression_level(compression: u8, quality: u8) -> u8 {
 /// Low-level encoding: analyze and encode a single subrectangle
 /// Analyzes palette and selects optimal encoding mode
 /// Never splits - assumes rectangle is within size limits
+#[allow(clippy::too_many_arguments)] // Options are threaded through every encoding stage
 fn encode_subrect_single<C: TightStreamCompressor>(
     framebuffer: &[u8],
     fb_width: u16,
@@ -622,28 +750,48 @@ fn encode_subrect_single<C: TightStreamCompressor>(
     quality: u8,
     compression: u8,
     client_format: &PixelFormat,
+    options: &TightOptions,
     compressor: &mut C,
-) -> BytesMut {
+) -> io::Result<BytesMut> {
     // This function assumes rect is within size limits (called from encode_large_rect or for small rects)
+    debug_assert!(
+        rect.w <= options.limits.max_width()
+            && rect.w as usize * rect.h as usize <= options.limits.max_size(),
+        "Tight: {}x{} subrectangle exceeds the size limits",
+        rect.w,
+        rect.h
+    );
 
     // Extract pixel data for this rectangle
     let pixels = extract_rect_rgba(framebuffer, fb_width, rect);
 
     // Analyze palette
-    let palette = analyze_palette(&pixels, rect.w as usize * rect.h as usize, compression);
+    let (width, height) = (rect.w as usize, rect.h as usize);
+    let palette = analyze_palette(&pixels, width, height, compression, options.indexed_palette);
+
+    #[cfg(feature = "tight-shared-palette")]
+    if let Some(buf) = encode_shared_palette_rect(
+        &pixels,
+        rect,
+        palette.num_colors,
+        compression,
+        options,
+        compressor,
+    ) {
+        return Ok(buf);
+    }
 
     // Route to appropriate encoder based on palette
-    match palette.num_colors {
-        0 => {
-            // Truecolor - use JPEG or full-color
-            if quality < 10 {
-                // Convert VNC quality (0-9, lower is better) to JPEG quality (0-100, higher is better)
-                let jpeg_quality = 95_u8.saturating_sub(quality * 7);
-                encode_jpeg_rect(&pixels, rect.w, rect.h, jpeg_quality, compressor)
-            } else {
-                encode_full_color_rect(&pixels, rect.w, rect.h, compression, compressor)
-            }
-        }
+    let buf = match palette.num_colors {
+        0 => encode_truecolor_rect(
+            &pixels,
+            rect,
+            quality,
+            compression,
+            client_format,
+            options,
+            compressor,
+        )?,
         1 => {
             // Solid color
             encode_solid_rect(palette.colors[0], client_format)
@@ -656,7 +804,7 @@ fn encode_subrect_single<C: TightStreamCompressor>(
                 rect.h,
                 palette.colors[0],
                 palette.colors[1],
-                compression,
+                options.zlib_levels.mono_level(compression),
                 client_format,
                 compressor,
             )
@@ -668,17 +816,244 @@ fn encode_subrect_single<C: TightStreamCompressor>(
                 rect.w,
                 rect.h,
                 &palette.colors[..palette.num_colors],
-                compression,
+                options.zlib_levels.indexed_level(compression),
                 client_format,
                 compressor,
-            )
+            )?
+        }
+    };
+
+    Ok(buf)
+}
+
+/// Encode a rectangle with too many colors for a palette as PNG, JPEG or full color,
+/// as selected by `quality` and `options`
+#[allow(clippy::too_many_arguments)] // Options are threaded through every encoding stage
+fn encode_truecolor_rect<C: TightStreamCompressor>(
+    pixels: &[u8],
+    rect: &Rect,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    options: &TightOptions,
+    compressor: &mut C,
+) -> io::Result<BytesMut> {
+    let png = match options.truecolor_codec {
+        TruecolorCodec::Jpeg => false,
+        TruecolorCodec::Png => true,
+        TruecolorCodec::Auto => !has_photo_color_variety(
+            pixels
+                .chunks_exact(4)
+                .map(|p| rgba_to_rgb24(p[0], p[1], p[2]))
+                .collect(),
+        ),
+    };
+    // Truecolor - use PNG, JPEG or full-color
+    if png {
+        Ok(encode_tightpng_png(pixels, rect.w, rect.h, compression))
+    } else if jpeg_enabled(quality) && !(options.text_aware && is_text_like(pixels, rect.w, rect.h))
+    {
+        // Convert VNC quality (0-9, lower is better) to JPEG quality (0-100, higher is better)
+        let jpeg_quality = 95_u8.saturating_sub(quality * 7);
+        encode_jpeg_rect(
+            pixels,
+            rect.w,
+            rect.h,
+            jpeg_quality,
+            options.strict_jpeg,
+            options.zlib_levels.full_color_level(compression),
+            options.explicit_copy_filter,
+            client_format,
+            compressor,
+        )
+    } else if options.gradient_filter {
+        Ok(encode_gradient_rect(
+            pixels,
+            rect.w,
+            rect.h,
+            options.zlib_levels.full_color_level(compression),
+            client_format,
+            compressor,
+        ))
+    } else {
+        Ok(encode_full_color_rect(
+            pixels,
+            rect.w,
+            rect.h,
+            options.zlib_levels.full_color_level(compression),
+            options.explicit_copy_filter,
+            client_format,
+            compressor,
+        ))
+    }
+}
+
+/// Encode a subrectangle, first splitting mixed photo/flat content into separate
+/// rectangles when `options.split_photo_regions` is set and JPEG is enabled
+///
+/// Photo regions keep `quality`; flat regions are encoded without JPEG.
+#[allow(clippy::too_many_arguments)] // Options are threaded through every encoding stage
+fn encode_subrect<C: TightStreamCompressor>(
+    framebuffer: &[u8],
+    fb_width: u16,
+    rect: &Rect,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    options: &TightOptions,
+    compressor: &mut C,
+) -> io::Result<Vec<(Rect, BytesMut)>> {
+    if options.split_photo_regions && jpeg_enabled(quality) {
+        if let Some(regions) = split_photo_regions(framebuffer, fb_width, rect) {
+            let mut rectangles = Vec::with_capacity(regions.len());
+            for (region, photo) in regions {
+                let buf = encode_subrect_single(
+                    framebuffer,
+                    fb_width,
+                    &region,
+                    if photo {
+                        quality
+                    } else {
+                        JPEG_DISABLED_QUALITY
+                    },
+                    compression,
+                    client_format,
+                    options,
+                    compressor,
+                )?;
+                rectangles.push((region, buf));
+            }
+            return Ok(rectangles);
+        }
+    }
+
+    let buf = encode_subrect_single(
+        framebuffer,
+        fb_width,
+        rect,
+        quality,
+        compression,
+        client_format,
+        options,
+        compressor,
+    )?;
+    Ok(vec![(rect.clone(), buf)])
+}
+
+/// Classify `rect` into photo and flat regions on a 16x16 block grid
+///
+/// A block counts as photo when more than a quarter of its pixels have distinct
+/// colors; flat UI (solid fills, text, borders) uses far fewer. Runs of equal blocks
+/// in each block row form a region, and regions are merged downwards while the
+/// next row has a run with the same columns and class. Returns `None` when every
+/// block has the same class, so the rectangle is left whole.
+#[allow(clippy::cast_possible_truncation)] // Block offsets stay within the u16 rectangle
+fn split_photo_regions(
+    framebuffer: &[u8],
+    fb_width: u16,
+    rect: &Rect,
+) -> Option<Vec<(Rect, bool)>> {
+    let block = MAX_SPLIT_TILE_SIZE;
+    let is_photo = |x: u16, y: u16, w: u16, h: u16| {
+        has_photo_color_variety(
+            (y..y + h)
+                .flat_map(|row| {
+                    let start = (row as usize * fb_width as usize + x as usize) * 4;
+                    framebuffer[start..start + w as usize * 4]
+                        .chunks_exact(4)
+                        .map(|p| rgba_to_rgb24(p[0], p[1], p[2]))
+                })
+                .collect(),
+        )
+    };
+
+    let mut regions: Vec<(Rect, bool)> = Vec::new();
+    let (mut any_photo, mut any_flat) = (false, false);
+    for y in (rect.y..rect.y + rect.h).step_by(block as usize) {
+        let h = block.min(rect.y + rect.h - y);
+
+        // Runs of equal blocks across this block row
+        let mut runs: Vec<(Rect, bool)> = Vec::new();
+        for x in (rect.x..rect.x + rect.w).step_by(block as usize) {
+            let w = block.min(rect.x + rect.w - x);
+            let photo = is_photo(x, y, w, h);
+            any_photo |= photo;
+            any_flat |= !photo;
+            match runs.last_mut() {
+                Some((last, last_photo)) if *last_photo == photo => last.w += w,
+                _ => runs.push((Rect { x, y, w, h }, photo)),
+            }
+        }
+
+        // Extend the region directly above when it covers the same columns and class
+        for (run, photo) in runs {
+            let above = regions
+                .iter_mut()
+                .find(|(r, p)| *p == photo && r.x == run.x && r.w == run.w && r.y + r.h == y);
+            if let Some((above, _)) = above {
+                above.h += run.h;
+            } else {
+                regions.push((run, photo));
+            }
+        }
+    }
+
+    (any_photo && any_flat).then_some(regions)
+}
+
+/// Whether more than a quarter of `colors` are distinct, which marks photographic
+/// content; flat UI (solid fills, text, borders) uses far fewer colors
+fn has_photo_color_variety(mut colors: Vec<u32>) -> bool {
+    let pixel_count = colors.len();
+    colors.sort_unstable();
+    colors.dedup();
+    colors.len() * 4 > pixel_count
+}
+
+/// Summed RGB difference above which two neighbouring pixels count as a hard edge
+const TEXT_EDGE_DELTA: u32 = 192;
+
+/// Whether an RGBA rectangle looks like text or line art rather than a photo
+///
+/// Counts neighbouring pixel pairs (horizontal and vertical) whose summed RGB
+/// difference exceeds a hard-edge threshold; the rectangle is text-like when at
+/// least one pair in ten is such an edge. Glyphs and checkerboards are dense in
+/// hard edges, while photos and gradients change gradually. Very noisy content
+/// also qualifies, which is harmless since JPEG does poorly on it too.
+///
+/// `pixels` must hold `width * height` RGBA pixels. Rectangles without any
+/// neighbouring pairs (a single pixel or empty) are not text-like.
+#[must_use]
+pub fn is_text_like(pixels: &[u8], width: u16, height: u16) -> bool {
+    let (w, h) = (width as usize, height as usize);
+    let pairs = (w.saturating_sub(1)) * h + w * h.saturating_sub(1);
+    if pairs == 0 {
+        return false;
+    }
+
+    let delta = |a: usize, b: usize| -> u32 {
+        (0..3)
+            .map(|c| u32::from(pixels[a * 4 + c].abs_diff(pixels[b * 4 + c])))
+            .sum()
+    };
+    let mut edges = 0;
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            if x + 1 < w && delta(i, i + 1) > TEXT_EDGE_DELTA {
+                edges += 1;
+            }
+            if y + 1 < h && delta(i, i + w) > TEXT_EDGE_DELTA {
+                edges += 1;
+            }
         }
     }
+    edges * 10 >= pairs
 }
 
 /// Encode large rectangle by splitting it into smaller tiles
 /// Returns a vector of individual rectangles with their encoded data
-#[allow(clippy::cast_possible_truncation)] // Tight max rect size divided by width always fits in u16
+#[allow(clippy::too_many_arguments)] // Options are threaded through every encoding stage
 fn encode_large_rect<C: TightStreamCompressor>(
     framebuffer: &[u8],
     fb_width: u16,
@@ -686,10 +1061,12 @@ fn encode_large_rect<C: TightStreamCompressor>(
     quality: u8,
     compression: u8,
     client_format: &PixelFormat,
+    options: &TightOptions,
     compressor: &mut C,
-) -> Vec<(Rect, BytesMut)> {
-    let subrect_max_width = rect.w.min(TIGHT_MAX_RECT_WIDTH);
-    let subrect_max_height = (TIGHT_MAX_RECT_SIZE / subrect_max_width as usize) as u16;
+) -> io::Result<Vec<(Rect, BytesMut)>> {
+    let subrect_max_width = rect.w.min(options.limits.max_width());
+    let subrect_max_height =
+        u16::try_from(options.limits.max_size() / subrect_max_width as usize).unwrap_or(u16::MAX);
 
     let mut rectangles = Vec::new();
 
@@ -697,7 +1074,7 @@ fn encode_large_rect<C: TightStreamCompressor>(
     while dy < rect.h {
         let mut dx = 0;
         while dx < rect.w {
-            let rw = (rect.w - dx).min(TIGHT_MAX_RECT_WIDTH);
+            let rw = (rect.w - dx).min(options.limits.max_width());
             let rh = (rect.h - dy).min(subrect_max_height);
 
             let sub_rect = Rect {
@@ -708,27 +1085,31 @@ fn encode_large_rect<C: TightStreamCompressor>(
             };
 
             // Encode this sub-rectangle (recursive call, but sub_rect is guaranteed to be small enough)
-            let buf = encode_subrect_single(
+            rectangles.extend(encode_subrect(
                 framebuffer,
                 fb_width,
                 &sub_rect,
                 quality,
                 compression,
                 client_format,
+                options,
                 compressor,
-            );
-            rectangles.push((sub_rect, buf));
+            )?);
 
-            dx += TIGHT_MAX_RECT_WIDTH;
+            dx += options.limits.max_width();
         }
         dy += subrect_max_height;
     }
 
-    rectangles
+    Ok(rectangles)
 }
 
 /// Check if a tile is all the same color
 /// Used for solid area detection optimization
+///
+/// Colors are compared as [`solid_key`] values, so alpha only matters when
+/// `include_alpha` is set.
+#[allow(clippy::too_many_arguments)] // Tile geometry plus the comparison mode
 fn check_solid_tile(
     framebuffer: &[u8],
     fb_width: u16,
@@ -737,21 +1118,25 @@ fn check_solid_tile(
     w: u16,
     h: u16,
     need_same_color: Option<u32>,
+    include_alpha: bool,
 ) -> Option<u32> {
     let offset = (y as usize * fb_width as usize + x as usize) * 4;
 
-    // Get first pixel color (RGB24)
-    let fb_r = framebuffer[offset];
-    let fb_g = framebuffer[offset + 1];
-    let fb_b = framebuffer[offset + 2];
-    let first_color = rgba_to_rgb24(fb_r, fb_g, fb_b);
+    // Get first pixel color (RGB24, plus alpha if requested)
+    let first_color = solid_key(&framebuffer[offset..offset + 4], include_alpha);
 
     #[cfg(feature = "debug-logging")]
     if x == 0 && y == 0 {
         // Log first pixel of each solid tile
-        log::info!("check_solid_tile: fb[{}]=[{:02x},{:02x},{:02x},{:02x}] -> R={:02x} G={:02x} B={:02x} color=0x{:06x}",
-            offset, framebuffer[offset], framebuffer[offset+1], framebuffer[offset+2], framebuffer[offset+3],
-            fb_r, fb_g, fb_b, first_color);
+        log::info!(
+            "check_solid_tile: fb[{}]=[{:02x},{:02x},{:02x},{:02x}] -> color=0x{:06x}",
+            offset,
+            framebuffer[offset],
+            framebuffer[offset + 1],
+            framebuffer[offset + 2],
+            framebuffer[offset + 3],
+            first_color
+        );
     }
 
     // Check if we need a specific color
@@ -766,11 +1151,7 @@ fn check_solid_tile(
         let row_offset = ((y + dy) as usize * fb_width as usize + x as usize) * 4;
         for dx in 0..w {
             let pix_offset = row_offset + dx as usize * 4;
-            let color = rgba_to_rgb24(
-                framebuffer[pix_offset],
-                framebuffer[pix_offset + 1],
-                framebuffer[pix_offset + 2],
-            );
+            let color = solid_key(&framebuffer[pix_offset..pix_offset + 4], include_alpha);
             if color != first_color {
                 return None;
             }
@@ -782,6 +1163,7 @@ fn check_solid_tile(
 
 /// Find best solid area dimensions
 /// Determines optimal size for solid color subrectangle
+#[allow(clippy::too_many_arguments)] // Tile geometry plus the comparison mode
 fn find_best_solid_area(
     framebuffer: &[u8],
     fb_width: u16,
@@ -790,6 +1172,7 @@ fn find_best_solid_area(
     w: u16,
     h: u16,
     color_value: u32,
+    include_alpha: bool,
 ) -> (u16, u16) {
     let mut w_best = 0;
     let mut h_best = 0;
@@ -800,7 +1183,18 @@ fn find_best_solid_area(
         let dh = (h - dy).min(MAX_SPLIT_TILE_SIZE);
         let dw = w_prev.min(MAX_SPLIT_TILE_SIZE);
 
-        if check_solid_tile(framebuffer, fb_width, x, y + dy, dw, dh, Some(color_value)).is_none() {
+        if check_solid_tile(
+            framebuffer,
+            fb_width,
+            x,
+            y + dy,
+            dw,
+            dh,
+            Some(color_value),
+            include_alpha,
+        )
+        .is_none()
+        {
             break;
         }
 
@@ -815,6 +1209,7 @@ fn find_best_solid_area(
                 dw_check,
                 dh,
                 Some(color_value),
+                include_alpha,
             )
             .is_none()
             {
@@ -837,6 +1232,11 @@ fn find_best_solid_area(
 
 /// Extend solid area to maximum size
 /// Expands solid region in all directions
+///
+/// Each direction grows geometrically (1, 2, 4, ... rows or columns per check) and
+/// halves the step after a miss, so long thin solid regions need O(log n) calls to
+/// `check_solid_tile` instead of one per row or column. The detected region is the
+/// same as extending one row or column at a time.
 #[allow(clippy::too_many_arguments)] // Tight encoding algorithm requires all geometric parameters for region expansion
 fn extend_solid_area(
     framebuffer: &[u8],
@@ -846,59 +1246,147 @@ fn extend_solid_area(
     max_w: u16,
     max_h: u16,
     color_value: u32,
+    include_alpha: bool,
     mut x: u16,
     mut y: u16,
     mut w: u16,
     mut h: u16,
 ) -> (u16, u16, u16, u16) {
+    let color = Some(color_value);
+
     // Extend upwards
-    while y > base_y {
-        if check_solid_tile(framebuffer, fb_width, x, y - 1, w, 1, Some(color_value)).is_none() {
-            break;
-        }
-        y -= 1;
-        h += 1;
-    }
+    let up = gallop_extend(y - base_y, |ext, len| {
+        check_solid_tile(
+            framebuffer,
+            fb_width,
+            x,
+            y - ext - len,
+            w,
+            len,
+            color,
+            include_alpha,
+        )
+        .is_some()
+    });
+    y -= up;
+    h += up;
 
     // Extend downwards
-    while y + h < base_y + max_h {
-        if check_solid_tile(framebuffer, fb_width, x, y + h, w, 1, Some(color_value)).is_none() {
-            break;
-        }
-        h += 1;
-    }
+    let down = gallop_extend(base_y + max_h - (y + h), |ext, len| {
+        check_solid_tile(
+            framebuffer,
+            fb_width,
+            x,
+            y + h + ext,
+            w,
+            len,
+            color,
+            include_alpha,
+        )
+        .is_some()
+    });
+    h += down;
 
     // Extend left
-    while x > base_x {
-        if check_solid_tile(framebuffer, fb_width, x - 1, y, 1, h, Some(color_value)).is_none() {
-            break;
-        }
-        x -= 1;
-        w += 1;
-    }
+    let left = gallop_extend(x - base_x, |ext, len| {
+        check_solid_tile(
+            framebuffer,
+            fb_width,
+            x - ext - len,
+            y,
+            len,
+            h,
+            color,
+            include_alpha,
+        )
+        .is_some()
+    });
+    x -= left;
+    w += left;
 
     // Extend right
-    while x + w < base_x + max_w {
-        if check_solid_tile(framebuffer, fb_width, x + w, y, 1, h, Some(color_value)).is_none() {
+    let right = gallop_extend(base_x + max_w - (x + w), |ext, len| {
+        check_solid_tile(
+            framebuffer,
+            fb_width,
+            x + w + ext,
+            y,
+            len,
+            h,
+            color,
+            include_alpha,
+        )
+        .is_some()
+    });
+    w += right;
+
+    (x, y, w, h)
+}
+
+/// Find how far a solid region can grow in one direction, up to `available` steps
+///
+/// `is_solid(extended, len)` reports whether the `len` rows (or columns) following the
+/// `extended` already accepted ones are solid. The step doubles while checks succeed
+/// and halves after the first miss, narrowing in on the first non-solid row.
+fn gallop_extend(available: u16, mut is_solid: impl FnMut(u16, u16) -> bool) -> u16 {
+    let mut extended = 0;
+    let mut step: u16 = 1;
+    let mut missed = false;
+
+    while extended < available {
+        let len = step.min(available - extended);
+        if is_solid(extended, len) {
+            extended += len;
+            if missed {
+                step = (step / 2).max(1);
+            } else {
+                step = step.saturating_mul(2);
+            }
+        } else if len == 1 {
             break;
+        } else {
+            missed = true;
+            step = len / 2;
         }
-        w += 1;
     }
 
-    (x, y, w, h)
+    extended
 }
 
 /// Palette analysis result
 struct Palette {
     num_colors: usize,
-    colors: [u32; 256],
+    colors: [u32; TIGHT_MAX_PALETTE_COLORS],
     mono_background: u32,
     mono_foreground: u32,
 }
 
+/// Whether a mono rectangle is smaller than the same rectangle sent as uncompressed
+/// full color (control byte plus 3 bytes per pixel)
+///
+/// Mono costs the control, filter and palette-size bytes, two palette entries (at
+/// most 4 bytes each) and a 1-bit bitmap with byte-aligned rows.
+fn mono_beats_full_color(width: usize, height: usize) -> bool {
+    let mono_bytes = 3 + 2 * 4 + width.div_ceil(8) * height;
+    let full_color_bytes = 1 + width * height * 3;
+    mono_bytes < full_color_bytes
+}
+
 /// Analyze palette from pixel data
 /// Determines color count and encoding mode selection
-fn analyze_palette(pixels: &[u8], pixel_count: usize, compression: u8) -> Palette {
+///
+/// Colors are listed in first-appearance order (mono swaps them so the background
+/// comes first), so indexed output is deterministic and can be golden-tested.
+/// Palettes of 3 or more colors are only detected with `indexed`; otherwise such
+/// rectangles report no palette and are sent as full color.
+fn analyze_palette(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    compression: u8,
+    indexed: bool,
+) -> Palette {
+    let pixel_count = width * height;
     let conf_idx = match compression {
         0 => 0,
         1 => 1,
@@ -909,7 +1397,7 @@ fn analyze_palette(pixels: &[u8], pixel_count: usize, compression: u8) -> Palett
 
     let mut palette = Palette {
         num_colors: 0,
-        colors: [0; 256],
+        colors: [0; TIGHT_MAX_PALETTE_COLORS],
         mono_background: 0,
         mono_foreground: 0,
     };
@@ -934,59 +1422,80 @@ fn analyze_palette(pixels: &[u8], pixel_count: usize, compression: u8) -> Palett
         return palette;
     }
 
-    // Check for 2-color (mono) case
-    if pixel_count >= conf.mono_min_rect_size {
-        let n0 = i / 4;
-        let c1 = rgba_to_rgb24(pixels[i], pixels[i + 1], pixels[i + 2]);
-        let mut n1 = 0;
+    // Indexed palettes must pay for themselves: allow one color per
+    // `idx_max_colors_divisor` pixels, but always allow mono for large enough rects,
+    // and for smaller ones whenever mono beats uncompressed full color
+    let mut max_colors = if indexed {
+        (pixel_count / conf.idx_max_colors_divisor).min(TIGHT_MAX_PALETTE_COLORS)
+    } else {
+        0
+    };
+    if pixel_count >= conf.mono_min_rect_size || mono_beats_full_color(width, height) {
+        max_colors = max_colors.max(2);
+    }
+    if max_colors < 2 {
+        return palette;
+    }
 
-        i += 4;
-        while i < pixels.len() {
-            let color = rgba_to_rgb24(pixels[i], pixels[i + 1], pixels[i + 2]);
-            if color == c0 {
-                // n0 already counted
-            } else if color == c1 {
-                n1 += 1;
-            } else {
-                break;
-            }
-            i += 4;
+    // Collect colors in first-appearance order, with pixel counts
+    let mut counts = [0usize; TIGHT_MAX_PALETTE_COLORS];
+    let mut index_of: HashMap<u32, usize> = HashMap::with_capacity(max_colors);
+    palette.colors[0] = c0;
+    counts[0] = i / 4;
+    index_of.insert(c0, 0);
+    palette.num_colors = 1;
+    for chunk in pixels[i..].chunks_exact(4) {
+        let color = rgba_to_rgb24(chunk[0], chunk[1], chunk[2]);
+        if let Some(&idx) = index_of.get(&color) {
+            counts[idx] += 1;
+        } else if palette.num_colors == max_colors {
+            // Too many colors - full color or JPEG
+            palette.num_colors = 0;
+            return palette;
+        } else {
+            index_of.insert(color, palette.num_colors);
+            palette.colors[palette.num_colors] = color;
+            counts[palette.num_colors] = 1;
+            palette.num_colors += 1;
         }
+    }
 
-        if i >= pixels.len() {
-            // Only 2 colors found
-            palette.num_colors = 2;
-            if n0 > n1 {
-                palette.mono_background = c0;
-                palette.mono_foreground = c1;
-                palette.colors[0] = c0;
-                palette.colors[1] = c1;
-            } else {
-                palette.mono_background = c1;
-                palette.mono_foreground = c0;
-                palette.colors[0] = c1;
-                palette.colors[1] = c0;
-            }
-            return palette;
+    if palette.num_colors == 2 {
+        // Mono: the more frequent color is the background
+        let (c1, n0, n1) = (palette.colors[1], counts[0], counts[1]);
+        if n0 > n1 {
+            palette.mono_background = c0;
+            palette.mono_foreground = c1;
+        } else {
+            palette.mono_background = c1;
+            palette.mono_foreground = c0;
+            palette.colors[0] = c1;
+            palette.colors[1] = c0;
         }
     }
 
-    // More than 2 colors - full palette or truecolor
-    palette.num_colors = 0;
     palette
 }
 
 /// Extract RGBA rectangle from framebuffer
-fn extract_rect_rgba(framebuffer: &[u8], fb_width: u16, rect: &Rect) -> Vec<u8> {
-    let mut pixels = Vec::with_capacity(rect.w as usize * rect.h as usize * 4);
+///
+/// Borrows straight from `framebuffer` when the rectangle is contiguous there (it
+/// spans the full framebuffer width, or is a single row), so analysis of
+/// full-width updates reads the source without copying; otherwise copies row by row
+fn extract_rect_rgba<'a>(framebuffer: &'a [u8], fb_width: u16, rect: &Rect) -> Cow<'a, [u8]> {
+    let row_bytes = rect.w as usize * 4;
+    let start = (rect.y as usize * fb_width as usize + rect.x as usize) * 4;
+    if rect.h <= 1 || (rect.x == 0 && rect.w == fb_width) {
+        return Cow::Borrowed(&framebuffer[start..start + row_bytes * rect.h as usize]);
+    }
 
+    let mut pixels = Vec::with_capacity(row_bytes * rect.h as usize);
     for y in 0..rect.h {
-        let row_offset = ((rect.y + y) as usize * fb_width as usize + rect.x as usize) * 4;
-        let row_end = row_offset + rect.w as usize * 4;
-        pixels.extend_from_slice(&framebuffer[row_offset..row_end]);
+        let row_offset = start + y as usize * fb_width as usize * 4;
+        pixels.extend_from_slice(&framebuffer[row_offset..row_offset + row_bytes]);
     }
 
-    pixels
+    Cow::Owned(pixels)
 }
 
 /// Convert RGBA to RGB24
@@ -997,6 +1506,17 @@ fn rgba_to_rgb24(r: u8, g: u8, b: u8) -> u32 {
     u32::from(r) | (u32::from(g) << 8) | (u32::from(b) << 16)
 }
 
+/// Color key used by solid detection for one RGBA pixel
+/// RGB24 in bits 0-23, with alpha in bits 24-31 only when `include_alpha` is set
+fn solid_key(pixel: &[u8], include_alpha: bool) -> u32 {
+    let rgb = rgba_to_rgb24(pixel[0], pixel[1], pixel[2]);
+    if include_alpha {
+        rgb | (u32::from(pixel[3]) << 24)
+    } else {
+        rgb
+    }
+}
+
 /// Encode solid rectangle
 /// Implements solid fill encoding mode (1 color)
 /// Uses client's pixel format for color encoding
@@ -1042,18 +1562,10 @@ fn encode_mono_rect<C: TightStreamCompressor>(
     height: u16,
     bg: u32,
     fg: u32,
-    compression: u8,
+    zlib_level: u8,
     client_format: &PixelFormat,
     compressor: &mut C,
 ) -> BytesMut {
-    let conf_idx = match compression {
-        0 => 0,
-        1 => 1,
-        2 | 3 => 2,
-        _ => 3,
-    };
-    let zlib_level = TIGHT_CONF[conf_idx].mono_zlib_level;
-
     // Encode bitmap
     let bitmap = encode_mono_bitmap(pixels, width, height, bg);
 
@@ -1101,38 +1613,40 @@ fn encode_mono_rect<C: TightStreamCompressor>(
     buf
 }
 
-/// Encode indexed palette rectangle (3-16 colors)
+/// Encode indexed palette rectangle (3-256 colors)
 /// Implements palette-based encoding with color indices
 /// Uses client's pixel format for palette colors
-#[allow(clippy::cast_possible_truncation)] // Palette limited to 16 colors, indices fit in u8
+///
+/// Every pixel's color must be in `palette`; a missing color means palette analysis
+/// and this encoder disagree, and is returned as an error rather than sent as index 0.
+#[allow(clippy::cast_possible_truncation)] // Palette limited to 256 colors, indices fit in u8
 fn encode_indexed_rect<C: TightStreamCompressor>(
     pixels: &[u8],
     width: u16,
     height: u16,
     palette: &[u32],
-    compression: u8,
+    zlib_level: u8,
     client_format: &PixelFormat,
     compressor: &mut C,
-) -> BytesMut {
-    let conf_idx = match compression {
-        0 => 0,
-        1 => 1,
-        2 | 3 => 2,
-        _ => 3,
-    };
-    let zlib_level = TIGHT_CONF[conf_idx].idx_zlib_level;
-
-    // Build color-to-index map
-    let mut color_map = HashMap::new();
-    for (idx, &color) in palette.iter().enumerate() {
-        color_map.insert(color, idx as u8);
+) -> io::Result<BytesMut> {
+    // Encode indices in the palette's own order; palettes from
+    // `encode_tight_indexed_with_palette` have up to 256 colors, so look them up by
+    // hash (the first entry wins if a caller's palette repeats a color)
+    let mut index_of: HashMap<u32, u8> = HashMap::with_capacity(palette.len());
+    for (i, &color) in palette.iter().enumerate() {
+        index_of.entry(color).or_insert(i as u8);
     }
-
-    // Encode indices
     let mut indices = Vec::with_capacity(width as usize * height as usize);
     for chunk in pixels.chunks_exact(4) {
         let color = rgba_to_rgb24(chunk[0], chunk[1], chunk[2]);
-        indices.push(*color_map.get(&color).unwrap_or(&0));
+        let Some(&index) = index_of.get(&color) else {
+            // The palette comes from analysing these same pixels, so this is a bug
+            return Err(io::Error::other(format!(
+                "Tight: color 0x{color:06x} is missing from the {}-color palette",
+                palette.len()
+            )));
+        };
+        indices.push(index);
     }
 
     let mut buf = BytesMut::new();
@@ -1172,43 +1686,56 @@ fn encode_indexed_rect<C: TightStreamCompressor>(
         buf.len(),
         client_format.bits_per_pixel
     );
-    buf
+    Ok(buf)
 }
 
 /// Encode full-color rectangle
 /// Implements full-color zlib encoding for truecolor images
+///
+/// With `explicit_filter` set, the copy filter is sent explicitly (filter id 0)
+/// instead of being implied by a control byte without the filter bit.
 fn encode_full_color_rect<C: TightStreamCompressor>(
     pixels: &[u8],
     width: u16,
     height: u16,
-    compression: u8,
+    zlib_level: u8,
+    explicit_filter: bool,
+    client_format: &PixelFormat,
     compressor: &mut C,
 ) -> BytesMut {
-    let conf_idx = match compression {
-        0 => 0,
-        1 => 1,
-        2 | 3 => 2,
-        _ => 3,
+    // Convert RGBA to TPIXELs; RGBA32 clients take RGB24 as is
+    let pixels = &pixels[..width as usize * height as usize * 4];
+    let rgb_data = if client_format.is_compatible_with_rgba32() {
+        rgba_to_rgb24_bytes(pixels)
+    } else {
+        let client_pixels =
+            crate::translate::translate_pixels(pixels, &PixelFormat::rgba32(), client_format);
+        let bpp = (client_format.bits_per_pixel / 8) as usize;
+        let big_endian = client_format.big_endian_flag != 0;
+        let mut data = Vec::with_capacity(pixels.len() / 4 * tpixel_len(client_format));
+        for pixel in client_pixels.chunks_exact(bpp) {
+            put_tpixel(&mut data, pixel_value(pixel, big_endian), client_format);
+        }
+        data
     };
-    let zlib_level = TIGHT_CONF[conf_idx].raw_zlib_level;
-
-    // Convert RGBA to RGB24
-    let mut rgb_data = Vec::with_capacity(width as usize * height as usize * 3);
-    for chunk in pixels.chunks_exact(4) {
-        rgb_data.push(chunk[0]);
-        rgb_data.push(chunk[1]);
-        rgb_data.push(chunk[2]);
-    }
 
     let mut buf = BytesMut::new();
 
     // Control byte
-    let control_byte = if zlib_level == 0 {
-        TIGHT_NO_ZLIB << 4
+    let stream = if zlib_level == 0 {
+        TIGHT_NO_ZLIB
     } else {
-        STREAM_ID_FULL_COLOR << 4
+        STREAM_ID_FULL_COLOR
+    };
+    let control_byte = if explicit_filter {
+        (stream | TIGHT_EXPLICIT_FILTER) << 4
+    } else {
+        stream << 4
     };
     buf.put_u8(control_byte);
+    if explicit_filter {
+        buf.put_u8(TIGHT_FILTER_COPY);
+    }
 
     #[cfg(feature = "debug-logging")]
     log::info!(
@@ -1239,15 +1766,137 @@ fn encode_full_color_rect<C: TightStreamCompressor>(
     buf
 }
 
-/// Encode JPEG rectangle
+/// Encode full-color rectangle with the gradient filter
+/// Pixels are sent as `TPIXEL`s in the client's pixel format, each channel replaced
+/// by its residual from [`gradient_filter`]
+fn encode_gradient_rect<C: TightStreamCompressor>(
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    zlib_level: u8,
+    client_format: &PixelFormat,
+    compressor: &mut C,
+) -> BytesMut {
+    let w = width as usize;
+    let client_pixels = crate::translate::translate_pixels(
+        &pixels[..w * height as usize * 4],
+        &PixelFormat::rgba32(),
+        client_format,
+    );
+    let bpp = (client_format.bits_per_pixel / 8) as usize;
+    let big_endian = client_format.big_endian_flag != 0;
+    let mut values: Vec<u32> = client_pixels
+        .chunks_exact(bpp)
+        .map(|p| pixel_value(p, big_endian))
+        .collect();
+    gradient_filter(&mut values, w, client_format, false);
+
+    let mut data = Vec::with_capacity(values.len() * tpixel_len(client_format));
+    for value in values {
+        put_tpixel(&mut data, value, client_format);
+    }
+
+    let mut buf = BytesMut::new();
+    let stream = if zlib_level == 0 {
+        TIGHT_NO_ZLIB
+    } else {
+        STREAM_ID_FULL_COLOR
+    };
+    buf.put_u8((stream | TIGHT_EXPLICIT_FILTER) << 4);
+    buf.put_u8(TIGHT_FILTER_GRADIENT);
+    compress_data(
+        &mut buf,
+        &data,
+        zlib_level,
+        STREAM_ID_FULL_COLOR,
+        compressor,
+    );
+
+    #[cfg(feature = "debug-logging")]
+    log::info!(
+        "Tight gradient: {}x{}, {} bytes ({}bpp)",
+        width,
+        height,
+        buf.len(),
+        client_format.bits_per_pixel
+    );
+    buf
+}
+
+/// Applies the Tight gradient filter in place to row-major pixel values in
+/// `client_format`, or undoes it when `reverse` is set.
+///
+/// Each channel is predicted as left + above - above-left, clamped to the channel's
+/// max from `client_format`, and the residual is taken modulo max + 1, so reduced
+/// depths such as 5-6-5 reconstruct exactly. Neighbors outside the rectangle count
+/// as 0, and bits outside the three channels are dropped.
+pub(crate) fn gradient_filter(
+    values: &mut [u32],
+    width: usize,
+    client_format: &PixelFormat,
+    reverse: bool,
+) {
+    let channels = [
+        (u32::from(client_format.red_max), client_format.red_shift),
+        (
+            u32::from(client_format.green_max),
+            client_format.green_shift,
+        ),
+        (u32::from(client_format.blue_max), client_format.blue_shift),
+    ];
+    let len = values.len();
+    let mut step = |i: usize| {
+        let (x, y) = (i % width, i / width);
+        let left = if x > 0 { values[i - 1] } else { 0 };
+        let above = if y > 0 { values[i - width] } else { 0 };
+        let above_left = if x > 0 && y > 0 {
+            values[i - width - 1]
+        } else {
+            0
+        };
+        let mut result = 0;
+        for (max, shift) in channels {
+            let channel = |value: u32| (value >> shift) & max;
+            let prediction = (channel(left) + channel(above))
+                .saturating_sub(channel(above_left))
+                .min(max);
+            let residual = if reverse {
+                channel(values[i]).wrapping_add(prediction)
+            } else {
+                channel(values[i]).wrapping_sub(prediction)
+            };
+            result |= (residual & max) << shift;
+        }
+        values[i] = result;
+    };
+    // Filtering reads the original neighbors, so it runs backwards; undoing it
+    // reads the reconstructed ones, so it runs forwards
+    if reverse {
+        (0..len).for_each(&mut step);
+    } else {
+        (0..len).rev().for_each(&mut step);
+    }
+}
+
+/// Encode JPEG rectangle
 /// Implements lossy JPEG compression for photographic content
+///
+/// When `strict` is set, `TurboJPEG` failures are returned as errors instead of
+/// falling back to full-color zlib encoding. `fallback_zlib_level` and
+/// `explicit_filter` apply to that fallback (see [`encode_full_color_rect`]).
+#[cfg_attr(not(feature = "turbojpeg"), allow(clippy::unnecessary_wraps))] // Only fallible with TurboJPEG
+#[allow(clippy::too_many_arguments)] // Fallback settings are passed alongside the JPEG ones
 fn encode_jpeg_rect<C: TightStreamCompressor>(
     pixels: &[u8],
     width: u16,
     height: u16,
     #[allow(unused_variables)] quality: u8,
+    strict: bool,
+    fallback_zlib_level: u8,
+    explicit_filter: bool,
+    client_format: &PixelFormat,
     compressor: &mut C,
-) -> BytesMut {
+) -> io::Result<BytesMut> {
     #[cfg(feature = "turbojpeg")]
     {
         use crate::jpeg::TurboJpegEncoder;
@@ -1261,21 +1910,28 @@ fn encode_jpeg_rect<C: TightStreamCompressor>(
         }
 
         // Compress with TurboJPEG
-        let jpeg_data = match TurboJpegEncoder::new() {
-            Ok(mut encoder) => match encoder.compress_rgb(&rgb_data, width, height, quality) {
-                Ok(data) => data,
-                #[allow(unused_variables)]
-                Err(e) => {
-                    #[cfg(feature = "debug-logging")]
-                    log::info!("TurboJPEG failed: {e}, using full-color");
-                    return encode_full_color_rect(pixels, width, height, 6, compressor);
-                }
-            },
-            #[allow(unused_variables)]
+        let jpeg_result = TurboJpegEncoder::new()
+            .map_err(|e| format!("TurboJPEG init failed: {e}"))
+            .and_then(|mut encoder| encoder.compress_rgb(&rgb_data, width, height, quality));
+        let jpeg_data = match jpeg_result {
+            Ok(data) => data,
             Err(e) => {
+                if strict {
+                    return Err(io::Error::other(e));
+                }
                 #[cfg(feature = "debug-logging")]
-                log::info!("TurboJPEG init failed: {e}, using full-color");
-                return encode_full_color_rect(pixels, width, height, 6, compressor);
+                log::info!("TurboJPEG failed: {e}, using full-color");
+                #[cfg(not(feature = "debug-logging"))]
+                let _ = e;
+                return Ok(encode_full_color_rect(
+                    pixels,
+                    width,
+                    height,
+                    fallback_zlib_level,
+                    explicit_filter,
+                    client_format,
+                    compressor,
+                ));
             }
         };
 
@@ -1292,15 +1948,165 @@ fn encode_jpeg_rect<C: TightStreamCompressor>(
             quality,
             jpeg_data.len()
         );
-        buf
+        Ok(buf)
     }
 
     #[cfg(not(feature = "turbojpeg"))]
     {
+        // Without TurboJPEG there is no JPEG attempt to fail, so strict mode has no effect
+        let _ = strict;
         #[cfg(feature = "debug-logging")]
         log::info!("TurboJPEG not enabled, using full-color (quality={quality})");
-        encode_full_color_rect(pixels, width, height, 6, compressor)
+        Ok(encode_full_color_rect(
+            pixels,
+            width,
+            height,
+            fallback_zlib_level,
+            explicit_filter,
+            client_format,
+            compressor,
+        ))
+    }
+}
+
+/// Encode an RGBA rectangle as JPEG, keeping its alpha channel in a separate plane
+///
+/// **Non-standard:** only clients that decode this framing (see
+/// [`crate::decode::decode_jpeg_with_alpha`]) can use the output. Fully opaque
+/// rectangles produce a standard Tight JPEG rectangle; otherwise the output is
+///
+/// ```text
+/// [0xB0]                        control byte (type 0x0B, unused by Tight)
+/// [compact length][JPEG data]   RGB, as in a Tight JPEG rectangle
+/// [compact length][zlib data]   alpha: (run length 1-255, alpha) byte pairs, row-major
+/// ```
+///
+/// # Arguments
+/// * `pixels` - Rectangle pixel data (RGBA format)
+/// * `width`, `height` - Rectangle dimensions
+/// * `quality` - JPEG quality (1-100)
+///
+/// # Errors
+///
+/// Returns an error if `pixels` is smaller than `width * height` pixels or JPEG
+/// compression fails
+#[cfg(feature = "alpha-jpeg")]
+pub fn encode_jpeg_with_alpha(
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+) -> io::Result<BytesMut> {
+    use crate::jpeg::TurboJpegEncoder;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    let expected_size = width as usize * height as usize * 4;
+    if pixels.len() < expected_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Tight: buffer size mismatch: got {} bytes, expected {expected_size} bytes",
+                pixels.len()
+            ),
+        ));
+    }
+    let pixels = &pixels[..expected_size];
+
+    let jpeg_data = TurboJpegEncoder::new()
+        .and_then(|mut encoder| {
+            encoder.compress_rgb(&rgba_to_rgb24_bytes(pixels), width, height, quality)
+        })
+        .map_err(io::Error::other)?;
+
+    let mut buf = BytesMut::new();
+    let opaque = pixels.chunks_exact(4).all(|p| p[3] == 255);
+    buf.put_u8(if opaque { TIGHT_JPEG } else { TIGHT_JPEG_ALPHA } << 4);
+    write_compact_length(&mut buf, jpeg_data.len());
+    buf.put_slice(&jpeg_data);
+    if opaque {
+        return Ok(buf);
+    }
+
+    let mut runs = Vec::new();
+    let mut alphas = pixels.chunks_exact(4).map(|p| p[3]).peekable();
+    while let Some(alpha) = alphas.next() {
+        let mut run = 1u8;
+        while run < u8::MAX && alphas.next_if_eq(&alpha).is_some() {
+            run += 1;
+        }
+        runs.extend_from_slice(&[run, alpha]);
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&runs)?;
+    let alpha_data = encoder.finish()?;
+    write_compact_length(&mut buf, alpha_data.len());
+    buf.put_slice(&alpha_data);
+
+    Ok(buf)
+}
+
+/// Encode the 0x0 rectangle defining the frame's shared palette
+/// Non-standard: control type `TIGHT_SHARED_PALETTE`, the color count minus one,
+/// then each color as a `TPIXEL` in the client's pixel format
+#[cfg(feature = "tight-shared-palette")]
+#[allow(clippy::cast_possible_truncation)] // Shared palettes hold at most 256 colors
+fn encode_shared_palette_definition(palette: &[u32], client_format: &PixelFormat) -> BytesMut {
+    let mut buf = BytesMut::new();
+    buf.put_u8(TIGHT_SHARED_PALETTE << 4);
+    buf.put_u8((palette.len() - 1) as u8);
+    for &color in palette {
+        buf.extend_from_slice(&translate_pixel_to_client_format(color, client_format));
+    }
+    buf
+}
+
+/// Encode a rectangle as indices into the frame's shared palette
+/// Non-standard: filter `TIGHT_FILTER_SHARED_PALETTE` followed by 8-bit indices,
+/// compressed as for the palette filter. Returns `None` without a shared palette in
+/// `options`, for rectangles of `num_colors` 1 or 2 (fills and two-color bitmaps
+/// are smaller), or if a pixel's color is not in the shared palette.
+#[cfg(feature = "tight-shared-palette")]
+#[allow(clippy::cast_possible_truncation)] // Shared palettes hold at most 256 colors, indices fit in u8
+fn encode_shared_palette_rect<C: TightStreamCompressor>(
+    pixels: &[u8],
+    rect: &Rect,
+    num_colors: usize,
+    compression: u8,
+    options: &TightOptions,
+    compressor: &mut C,
+) -> Option<BytesMut> {
+    let shared = options.shared_palette.as_deref()?;
+    if matches!(num_colors, 1 | 2) {
+        return None;
     }
+    let index_of: HashMap<u32, u8> = shared
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| (color, i as u8))
+        .collect();
+    let indices = pixels[..rect.w as usize * rect.h as usize * 4]
+        .chunks_exact(4)
+        .map(|p| index_of.get(&rgba_to_rgb24(p[0], p[1], p[2])).copied())
+        .collect::<Option<Vec<u8>>>()?;
+
+    let zlib_level = options.zlib_levels.indexed_level(compression);
+    let mut buf = BytesMut::new();
+    let stream = if zlib_level == 0 {
+        TIGHT_NO_ZLIB
+    } else {
+        STREAM_ID_INDEXED
+    };
+    buf.put_u8((stream | TIGHT_EXPLICIT_FILTER) << 4);
+    buf.put_u8(TIGHT_FILTER_SHARED_PALETTE);
+    compress_data(
+        &mut buf,
+        &indices,
+        zlib_level,
+        STREAM_ID_INDEXED,
+        compressor,
+    );
+    Some(buf)
 }
 
 /// Compress data with zlib using persistent streams or send uncompressed
@@ -1465,80 +2271,290 @@ pub trait TightStreamCompressor {
 /// This creates separate persistent zlib streams for each stream ID (full-color, mono, indexed).
 /// Used when encoding without access to a VNC client's stream manager.
 pub struct SimpleTightCompressor {
-    streams: [Option<flate2::Compress>; 4],
+    streams: [Option<ZlibStream>; 4],
     level: u8,
+    strategy: ZlibStrategy,
 }
 
 impl SimpleTightCompressor {
     /// Creates a new `SimpleTightCompressor` with the specified compression level.
+    ///
+    /// Levels above 9 are clamped to 9, the highest zlib level.
     #[must_use]
     pub fn new(level: u8) -> Self {
+        Self::with_strategy(level, ZlibStrategy::Default)
+    }
+
+    /// Creates a `SimpleTightCompressor` whose zlib streams use `strategy`.
+    ///
+    /// [`ZlibStrategy::Rle`] trades size for speed; [`ZlibStrategy::Filtered`] suits
+    /// the residuals of the gradient filter.
+    #[must_use]
+    pub fn with_strategy(level: u8, strategy: ZlibStrategy) -> Self {
         Self {
             streams: [None, None, None, None],
-            level,
+            level: level.min(9),
+            strategy,
         }
     }
+
+    /// Returns the total bytes fed into and produced by zlib stream `stream_id`.
+    ///
+    /// A stream whose output grows close to its input has stopped compressing well,
+    /// e.g. because its dictionary is full of unrelated content; comparing two
+    /// snapshots gives the ratio over the frames in between. Streams that have not
+    /// been used yet, and IDs outside 0-3, report `(0, 0)`.
+    #[must_use]
+    pub fn stream_stats(&self, stream_id: u8) -> (u64, u64) {
+        self.streams
+            .get(stream_id as usize)
+            .and_then(Option::as_ref)
+            .map_or((0, 0), |stream| (stream.total_in(), stream.total_out()))
+    }
 }
 
 impl TightStreamCompressor for SimpleTightCompressor {
-    #[allow(clippy::cast_possible_truncation)] // Zlib total_out limited to buffer size
     fn compress_tight_stream(
         &mut self,
         stream_id: u8,
         level: u8,
         input: &[u8],
     ) -> Result<Vec<u8>, String> {
-        use flate2::{Compress, Compression, FlushCompress};
-
         let stream_idx = stream_id as usize;
         if stream_idx >= 4 {
             return Err(format!("Invalid stream ID: {stream_id}"));
         }
 
-        // Initialize stream if needed
-        if self.streams[stream_idx].is_none() {
-            self.streams[stream_idx] = Some(Compress::new(
-                Compression::new(u32::from(level.min(self.level))),
-                true,
-            ));
-        }
+        let strategy = self.strategy;
+        let stream = self.streams[stream_idx].get_or_insert_with(|| {
+            ZlibStream::new(
+                flate2::Compression::new(u32::from(level.min(self.level).min(9))),
+                strategy,
+            )
+        });
+        compress_sync_flush(stream, input, false).map_err(|e| format!("Compression failed: {e}"))
+    }
+}
+
+/// Options controlling Tight encoder behaviour.
+///
+/// The defaults match the behaviour of [`encode_tight_rects`].
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)] // Independent opt-in switches, not a state machine
+pub struct TightOptions {
+    /// Return an error when JPEG compression fails instead of silently falling
+    /// back to full-color zlib encoding. Useful for servers that need to know
+    /// JPEG failed, e.g. to renegotiate encodings with the client.
+    pub strict_jpeg: bool,
+    /// Compare alpha as well as RGB when detecting solid areas, so pixels that
+    /// differ only in alpha are not merged into one solid area. Off by default,
+    /// since Tight output carries no alpha. Only the solid-area search is
+    /// affected; per-rectangle palette analysis still works on RGB.
+    pub solid_include_alpha: bool,
+    /// Upper bound on each rectangle's encoded size in bytes, for transports with
+    /// payload caps. Oversized JPEG rectangles are first re-encoded one quality level
+    /// lower at a time, down to [`BUDGET_JPEG_QUALITY_FLOOR`]; rectangles still over
+    /// the limit are split in half until they fit. Encoding fails if a single pixel
+    /// cannot fit. The split is planned on scratch zlib streams, so (rarely) a
+    /// rectangle encoded on the real streams comes out slightly over the limit; it is
+    /// still returned, since its bytes are already part of the streams. Every
+    /// rectangle is encoded about three times: once to find solid areas, once or
+    /// more to measure each piece, and once on the real streams.
+    pub max_rect_bytes: Option<usize>,
+    /// Split rectangles that mix photographic and flat content (judged per 16x16
+    /// block by color variety) so only the photo parts use JPEG and flat UI stays
+    /// lossless. Has no effect when JPEG is disabled (quality 10 or above).
+    pub split_photo_regions: bool,
+    /// Codec for truecolor rectangles (those with too many colors for a palette).
+    /// See [`TruecolorCodec`].
+    pub truecolor_codec: TruecolorCodec,
+    /// Send the copy filter explicitly on full-color rectangles (control byte with
+    /// the explicit-filter bit, then filter id 0) for strict clients that reject
+    /// the implicit form. Decodes to the same pixels; costs one byte per rectangle.
+    pub explicit_copy_filter: bool,
+    /// Per-mode zlib level overrides, e.g. to send palette rectangles uncompressed
+    /// while full-color data stays compressed. See [`TightZlibLevels`].
+    pub zlib_levels: TightZlibLevels,
+    /// Size limits for the rectangles sent and for solid-area extraction, e.g. for
+    /// clients that reject rectangles wider than 1024 pixels. See [`TightLimits`].
+    pub limits: TightLimits,
+    /// Encode truecolor rectangles that look like text (see [`is_text_like`])
+    /// losslessly instead of with JPEG, avoiding ringing around glyphs. Only
+    /// matters when JPEG is enabled (quality below 10).
+    pub text_aware: bool,
+    /// Skip the tile-based scan for large solid areas and rely on per-rectangle
+    /// palette analysis alone, which still sends wholly solid rectangles as fills.
+    /// Saves scanning CPU on photo-heavy frames at the cost of solid regions inside
+    /// mixed rectangles being encoded with their surroundings.
+    pub skip_solid_scan: bool,
+    /// Cap on solid-area searches per rectangle: how many solid 16x16 tiles the scan
+    /// may grow into candidate areas before it gives up and encodes the rest of the
+    /// rectangle without solid extraction. Bounds CPU on adversarial content such as
+    /// a fine solid/noise checkerboard, where every tile starts a search that is then
+    /// rejected as too small. `None` uses [`DEFAULT_MAX_SOLID_SEARCHES`].
+    pub max_solid_searches: Option<usize>,
+    /// Send lossless full-color rectangles with the gradient filter, which predicts
+    /// each pixel from its neighbors so smooth content compresses better. Pixels are
+    /// sent in `client_format`, with prediction and residuals in its channel
+    /// precision (e.g. 5-6-5). Rectangles sent as JPEG or PNG are unaffected.
+    pub gradient_filter: bool,
+    /// Send rectangles of 3-16 colors with the palette filter (8-bit indices into a
+    /// per-rectangle palette) instead of as full color. A rectangle may use one color
+    /// per 4-32 pixels, depending on the compression level, so small palettes pay
+    /// for themselves. Off by default, which keeps the output of earlier releases:
+    /// only solid and two-color (mono) rectangles use a palette.
+    pub indexed_palette: bool,
+    /// Non-standard: the frame-wide palette (colors in the internal RGB24 format) the
+    /// client already holds from a shared-palette definition rectangle. Rectangles of
+    /// three or more colors that are all in it are sent as indices into it instead
+    /// of with their own palette. Set by [`encode_regions_with_shared_palette`].
+    #[cfg(feature = "tight-shared-palette")]
+    pub shared_palette: Option<Vec<u32>>,
+}
 
-        let stream = self.streams[stream_idx].as_mut().unwrap();
-        let mut output = vec![0u8; input.len() + 64];
-        let before_out = stream.total_out();
+/// Zlib levels for each Tight compression mode, overriding the levels the
+/// compression setting would otherwise pick.
+///
+/// `None` keeps the level derived from the compression setting. Levels above 9 are
+/// clamped to 9. Level 0 sends that mode's data uncompressed with the
+/// `TIGHT_NO_ZLIB` control, skipping zlib entirely.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TightZlibLevels {
+    /// Level for two-color (mono) rectangles.
+    pub mono: Option<u8>,
+    /// Level for indexed palette rectangles (3-16 colors, see
+    /// [`TightOptions::indexed_palette`]).
+    pub indexed: Option<u8>,
+    /// Level for full-color rectangles, including the fallback when JPEG fails.
+    pub full_color: Option<u8>,
+}
 
-        match stream.compress(input, &mut output, FlushCompress::Sync) {
-            Ok(flate2::Status::Ok | flate2::Status::StreamEnd) => {
-                let total_out = (stream.total_out() - before_out) as usize;
-                output.truncate(total_out);
-                Ok(output)
-            }
-            Ok(flate2::Status::BufError) => Err("Compression buffer error".to_string()),
-            Err(e) => Err(format!("Compression failed: {e}")),
+impl TightZlibLevels {
+    /// Zlib level for mono rectangles at `compression`
+    fn mono_level(self, compression: u8) -> u8 {
+        self.mono
+            .map_or(TIGHT_CONF[conf_index(compression)].mono_zlib_level, |l| {
+                l.min(9)
+            })
+    }
+
+    /// Zlib level for indexed rectangles at `compression`
+    fn indexed_level(self, compression: u8) -> u8 {
+        self.indexed
+            .map_or(TIGHT_CONF[conf_index(compression)].idx_zlib_level, |l| {
+                l.min(9)
+            })
+    }
+
+    /// Zlib level for full-color rectangles at `compression`
+    fn full_color_level(self, compression: u8) -> u8 {
+        self.full_color
+            .map_or(TIGHT_CONF[conf_index(compression)].raw_zlib_level, |l| {
+                l.min(9)
+            })
+    }
+}
+
+/// Rectangle size limits of the Tight encoder, set through [`TightOptions::limits`].
+///
+/// The defaults are the limits of the reference implementation. A width of 0 is
+/// treated as 1, and a pixel limit below one full row as one full row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TightLimits {
+    /// Largest width of a rectangle sent; wider ones are split. Default 2048.
+    pub max_rect_width: u16,
+    /// Largest number of pixels in a rectangle sent; larger ones are split.
+    /// Default 65536.
+    pub max_rect_size: usize,
+    /// Rectangles with fewer pixels are encoded whole, without searching for solid
+    /// areas. Default 4096.
+    pub min_split_rect_size: usize,
+    /// Solid areas with fewer pixels are not extracted. Default 2048.
+    pub min_solid_subrect_size: usize,
+}
+
+impl Default for TightLimits {
+    fn default() -> Self {
+        Self {
+            max_rect_width: TIGHT_MAX_RECT_WIDTH,
+            max_rect_size: TIGHT_MAX_RECT_SIZE,
+            min_split_rect_size: MIN_SPLIT_RECT_SIZE,
+            min_solid_subrect_size: MIN_SOLID_SUBRECT_SIZE,
         }
     }
 }
 
-/// Encode Tight with persistent zlib streams, returning individual sub-rectangles
+impl TightLimits {
+    fn max_width(self) -> u16 {
+        self.max_rect_width.max(1)
+    }
+
+    fn max_size(self) -> usize {
+        self.max_rect_size.max(usize::from(self.max_width()))
+    }
+}
+
+/// Index into [`TIGHT_CONF`] for a compression level (0-9)
+fn conf_index(compression: u8) -> usize {
+    match compression {
+        0 => 0,
+        1 => 1,
+        2 | 3 => 2,
+        _ => 3,
+    }
+}
+
+/// Codec used for Tight truecolor rectangles.
+///
+/// PNG rectangles use the `TightPng` control byte (`0xA0`), which only clients that
+/// negotiated `TightPng` (encoding -260) understand. Only select [`TruecolorCodec::Png`]
+/// or [`TruecolorCodec::Auto`] for those clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TruecolorCodec {
+    /// JPEG when the quality level enables it (below 10), otherwise full-color zlib.
+    #[default]
+    Jpeg,
+    /// Always PNG, which is lossless regardless of the quality level.
+    Png,
+    /// PNG for rectangles with little color variety, such as text and UI, and
+    /// [`TruecolorCodec::Jpeg`] behaviour for photographic ones.
+    Auto,
+}
+
+/// Fallible variant of [`encode_tight_rects`] that honours [`TightOptions`]
 /// Returns a vector of (x, y, width, height, `encoded_data`) for each sub-rectangle
 ///
+/// `data` must hold `width * height` RGBA pixels with a row stride of `width`; any
+/// bytes past that are ignored. To encode part of a larger framebuffer, use
+/// [`encode_region`], which checks the bounds instead of reading the wrong rows.
+///
 /// # Arguments
 /// * `data` - Framebuffer pixel data (RGBA format)
 /// * `width` - Rectangle width
 /// * `height` - Rectangle height
-/// * `quality` - JPEG quality level (0-9, or 10+ to disable JPEG)
+/// * `quality` - JPEG quality level (0-9, or [`JPEG_DISABLED_QUALITY`] to disable JPEG)
 /// * `compression` - Compression level (0-9)
 /// * `client_format` - Client's pixel format for palette color translation
+/// * `options` - Encoder options (see [`TightOptions`])
 /// * `compressor` - Zlib stream compressor for persistent compression streams
-pub fn encode_tight_rects<C: TightStreamCompressor>(
+///
+/// # Errors
+///
+/// Returns an error if `data` holds fewer than `width * height` pixels, if JPEG
+/// compression fails while `options.strict_jpeg` is set, or if a rectangle cannot be
+/// made to fit `options.max_rect_bytes`
+#[allow(clippy::too_many_arguments)] // Mirrors encode_tight_rects plus encoder options
+#[allow(clippy::type_complexity)] // Same tuple shape as encode_tight_rects
+pub fn try_encode_tight_rects<C: TightStreamCompressor>(
     data: &[u8],
     width: u16,
     height: u16,
     quality: u8,
     compression: u8,
     client_format: &PixelFormat,
+    options: &TightOptions,
     compressor: &mut C,
-) -> Vec<(u16, u16, u16, u16, BytesMut)> {
+) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
     #[cfg(feature = "debug-logging")]
     log::info!(
         "DEBUG: encode_tight_rects called: {}x{}, data_len={}, quality={}, compression={}, bpp={}",
@@ -1550,6 +2566,8 @@ pub fn encode_tight_rects<C: TightStreamCompressor>(
         client_format.bits_per_pixel
     );
 
+    check_rgba_len(data, width, height, "Tight")?;
+
     let rect = Rect {
         x: 0,
         y: 0,
@@ -1557,76 +2575,3339 @@ pub fn encode_tight_rects<C: TightStreamCompressor>(
         h: height,
     };
 
-    #[cfg(feature = "debug-logging")]
-    log::info!("DEBUG: Calling encode_rect_optimized");
-
-    let result = encode_rect_optimized(
+    encode_tight_region(
         data,
         width,
         &rect,
         quality,
         compression,
         client_format,
+        options,
         compressor,
-    );
-
-    #[cfg(feature = "debug-logging")]
-    log::info!(
-        "DEBUG: encode_rect_optimized returned {} rectangles",
-        result.rectangles.len()
-    );
+    )
+}
 
-    // Convert EncodeResult to public format
-    let rects: Vec<(u16, u16, u16, u16, BytesMut)> = result
-        .rectangles
+/// Encodes like [`try_encode_tight_rects`] and also returns [`crate::output_hash`] of
+/// each sub-rectangle's encoded bytes.
+///
+/// Returns (x, y, width, height, `encoded_data`, hash) for each sub-rectangle. Tight's
+/// zlib streams persist across rectangles, so identical input only gives identical
+/// output, and hash, when the compressor streams are in the same state, e.g. fresh
+/// ones or level 0. Solid fills and JPEG rectangles do not use the streams.
+///
+/// # Errors
+///
+/// Returns an error for the same reasons as [`try_encode_tight_rects`]
+#[allow(clippy::too_many_arguments)] // Mirrors try_encode_tight_rects
+#[allow(clippy::type_complexity)] // encode_tight_rects tuple plus the hash
+pub fn try_encode_tight_rects_with_hashes<C: TightStreamCompressor>(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    options: &TightOptions,
+    compressor: &mut C,
+) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut, u64)>> {
+    let rects = try_encode_tight_rects(
+        data,
+        width,
+        height,
+        quality,
+        compression,
+        client_format,
+        options,
+        compressor,
+    )?;
+    Ok(rects
         .into_iter()
-        .map(|(r, buf)| {
-            #[cfg(feature = "debug-logging")]
-            log::info!(
-                "DEBUG: Sub-rect: {}x{} at ({}, {}), encoded_len={}",
-                r.w,
-                r.h,
-                r.x,
-                r.y,
-                buf.len()
-            );
-            (r.x, r.y, r.w, r.h, buf)
+        .map(|(x, y, w, h, buf)| {
+            let hash = crate::common::output_hash(&buf);
+            (x, y, w, h, buf, hash)
         })
-        .collect();
-
-    #[cfg(feature = "debug-logging")]
-    log::info!(
-        "DEBUG: encode_tight_rects returning {} rectangles",
-        rects.len()
-    );
-
-    rects
+        .collect())
 }
 
-/// Encode Tight with persistent zlib streams (for use with VNC client streams)
-/// Returns concatenated data (legacy API - consider using `encode_tight_rects` instead)
-pub fn encode_tight_with_streams<C: TightStreamCompressor>(
+/// Encodes like [`try_encode_tight_rects`] while counting the compression mode of
+/// every sub-rectangle into `modes`.
+///
+/// Modes are read from each rectangle's control byte and filter ID, so they report
+/// what was actually sent, e.g. full color where JPEG was unavailable. Output is
+/// identical to [`try_encode_tight_rects`].
+///
+/// # Errors
+///
+/// Returns an error for the same reasons as [`try_encode_tight_rects`]
+#[allow(clippy::too_many_arguments)] // Mirrors try_encode_tight_rects plus the histogram
+#[allow(clippy::type_complexity)] // Same tuple shape as encode_tight_rects
+pub fn try_encode_tight_rects_with_stats<C: TightStreamCompressor>(
     data: &[u8],
     width: u16,
     height: u16,
     quality: u8,
     compression: u8,
     client_format: &PixelFormat,
+    options: &TightOptions,
     compressor: &mut C,
-) -> BytesMut {
-    // Concatenate all sub-rectangles
-    let rects = encode_tight_rects(
+    modes: &mut SubEncodingHistogram,
+) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
+    let rects = try_encode_tight_rects(
         data,
         width,
         height,
         quality,
         compression,
         client_format,
+        options,
         compressor,
-    );
-    let mut output = BytesMut::new();
-    for (_x, _y, _w, _h, buf) in rects {
-        output.extend_from_slice(&buf);
+    )?;
+    for (.., buf) in &rects {
+        count_mode(modes, buf);
+    }
+    Ok(rects)
+}
+
+/// Whether a rectangle whose control type is `TIGHT_PNG` holds PNG data.
+///
+/// `TIGHT_NO_ZLIB` full color without an explicit filter has the same control type,
+/// so this checks for the PNG signature after the compact length.
+fn is_png_rect(buf: &[u8]) -> bool {
+    let mut length_end = 1;
+    while length_end < 3 && buf.get(length_end).is_some_and(|b| b & 0x80 != 0) {
+        length_end += 1;
+    }
+    buf.get(length_end + 1..length_end + 5) == Some(&b"\x89PNG"[..])
+}
+
+/// Checks that an encoded Tight rectangle of `width` x `height` holds exactly the
+/// bytes its control byte, filter and compact lengths declare, so a proxy can catch
+/// truncated or padded rectangles before forwarding them.
+///
+/// Basic compression sends data shorter than 12 bytes without a length, so the
+/// rectangle size and the client's `pixel_format` are needed to tell where it
+/// ends; a proxy has both from the rectangle header and `SetPixelFormat`. Zlib and
+/// image data are not decoded: they belong to the connection's persistent streams.
+///
+/// # Errors
+///
+/// Returns [`FramingError::Truncated`] if a field or the data is cut short,
+/// [`FramingError::TrailingBytes`] if bytes follow the declared data, and
+/// [`FramingError::UnknownControl`] or [`FramingError::UnknownFilter`] for
+/// compression types and filters this crate does not produce
+pub fn validate_framing(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> Result<(), FramingError> {
+    let mut cursor = FramingCursor::new(data);
+    let control = cursor.u8()?;
+    let tpixel = tpixel_len(pixel_format);
+    match control >> 4 {
+        TIGHT_FILL => {
+            cursor.take(tpixel)?;
+        }
+        TIGHT_JPEG => skip_compact(&mut cursor)?,
+        #[cfg(feature = "alpha-jpeg")]
+        TIGHT_JPEG_ALPHA => {
+            skip_compact(&mut cursor)?;
+            skip_compact(&mut cursor)?;
+        }
+        #[cfg(feature = "tight-shared-palette")]
+        TIGHT_SHARED_PALETTE => {
+            let count = usize::from(cursor.u8()?) + 1;
+            cursor.take(count * tpixel)?;
+        }
+        crate::TIGHT_PNG if is_png_rect(data) => skip_compact(&mut cursor)?,
+        // Basic compression, on a zlib stream or (as the decoder reads it) without zlib
+        comp if is_basic_compression(comp) => {
+            let data_len = read_basic_header(&mut cursor, comp, width, height, tpixel)?;
+            if data_len < TIGHT_MIN_TO_COMPRESS {
+                cursor.take(data_len)?;
+            } else {
+                skip_compact(&mut cursor)?;
+            }
+        }
+        _ => return Err(FramingError::UnknownControl(control)),
+    }
+    cursor.finish()
+}
+
+/// Whether a rectangle of `width` x `height` encoded as `data` carries a compact
+/// length before its pixel data.
+///
+/// Solid fill and shared palette definitions never do. JPEG and PNG always do.
+/// Basic compression (full color, mono and indexed, with or without zlib) does
+/// unless its uncompressed data is shorter than 12 bytes, which is sent raw; that
+/// size depends on the filter and palette after the control byte, so this reads
+/// them and needs the client's `pixel_format`, as [`validate_framing`] does.
+///
+/// # Errors
+///
+/// Returns [`FramingError::Truncated`] if `data` ends before the length would
+/// start, and [`FramingError::UnknownControl`] or [`FramingError::UnknownFilter`]
+/// for compression types and filters this crate does not produce
+pub fn payload_has_length(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> Result<bool, FramingError> {
+    let mut cursor = FramingCursor::new(data);
+    let control = cursor.u8()?;
+    match control >> 4 {
+        TIGHT_FILL => Ok(false),
+        TIGHT_JPEG => Ok(true),
+        #[cfg(feature = "alpha-jpeg")]
+        TIGHT_JPEG_ALPHA => Ok(true),
+        #[cfg(feature = "tight-shared-palette")]
+        TIGHT_SHARED_PALETTE => Ok(false),
+        crate::TIGHT_PNG if is_png_rect(data) => Ok(true),
+        comp if is_basic_compression(comp) => {
+            let tpixel = tpixel_len(pixel_format);
+            let data_len = read_basic_header(&mut cursor, comp, width, height, tpixel)?;
+            Ok(data_len >= TIGHT_MIN_TO_COMPRESS)
+        }
+        _ => Err(FramingError::UnknownControl(control)),
+    }
+}
+
+/// Whether compression type `comp` is basic compression, on a zlib stream or (as
+/// the decoder reads it) without zlib.
+const fn is_basic_compression(comp: u8) -> bool {
+    comp < TIGHT_FILL || comp & TIGHT_NO_ZLIB == TIGHT_NO_ZLIB
+}
+
+/// Reads the filter and any palette of a basic-compression rectangle and returns
+/// the size of its uncompressed data.
+fn read_basic_header(
+    cursor: &mut FramingCursor<'_>,
+    comp: u8,
+    width: u16,
+    height: u16,
+    tpixel: usize,
+) -> Result<usize, FramingError> {
+    let filter = if comp & TIGHT_EXPLICIT_FILTER == 0 {
+        TIGHT_FILTER_COPY
+    } else {
+        cursor.u8()?
+    };
+    let (w, h) = (usize::from(width), usize::from(height));
+    match filter {
+        TIGHT_FILTER_COPY | TIGHT_FILTER_GRADIENT => Ok(w * h * tpixel),
+        TIGHT_FILTER_PALETTE => {
+            let count = usize::from(cursor.u8()?) + 1;
+            cursor.take(count * tpixel)?;
+            Ok(if count == 2 { w.div_ceil(8) * h } else { w * h })
+        }
+        #[cfg(feature = "tight-shared-palette")]
+        TIGHT_FILTER_SHARED_PALETTE => Ok(w * h),
+        _ => Err(FramingError::UnknownFilter(filter)),
+    }
+}
+
+/// Reads a compact length and skips that many bytes.
+fn skip_compact(cursor: &mut FramingCursor<'_>) -> Result<(), FramingError> {
+    let mut len = 0;
+    for shift in [0, 7, 14] {
+        let byte = cursor.u8()?;
+        if shift == 14 {
+            len |= usize::from(byte) << 14;
+            break;
+        }
+        len |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    cursor.take(len)?;
+    Ok(())
+}
+
+/// Counts one encoded Tight rectangle into `modes` by its control byte.
+fn count_mode(modes: &mut SubEncodingHistogram, buf: &[u8]) {
+    let control = buf[0] >> 4;
+    match control {
+        TIGHT_FILL => modes.solid += 1,
+        TIGHT_JPEG => modes.jpeg += 1,
+        #[cfg(feature = "alpha-jpeg")]
+        TIGHT_JPEG_ALPHA => modes.jpeg += 1,
+        // A palette definition carries no pixels
+        #[cfg(feature = "tight-shared-palette")]
+        TIGHT_SHARED_PALETTE => {}
+        crate::TIGHT_PNG if is_png_rect(buf) => modes.png += 1,
+        // Basic compression, with or without zlib; the copy filter is implicit
+        _ if control & TIGHT_EXPLICIT_FILTER == 0 => modes.raw += 1,
+        _ => match buf[1] {
+            TIGHT_FILTER_PALETTE => modes.packed_palette += 1,
+            TIGHT_FILTER_GRADIENT => modes.gradient += 1,
+            #[cfg(feature = "tight-shared-palette")]
+            TIGHT_FILTER_SHARED_PALETTE => modes.packed_palette += 1,
+            _ => modes.raw += 1,
+        },
+    }
+}
+
+/// Encode a region of a larger framebuffer with Tight, with bounds checking
+/// Returns a vector of (x, y, width, height, `encoded_data`) for each sub-rectangle,
+/// in framebuffer coordinates
+///
+/// # Arguments
+/// * `framebuffer` - Framebuffer pixel data (RGBA format, row stride `fb_width`)
+/// * `fb_width`, `fb_height` - Framebuffer dimensions
+/// * `region` - (x, y, width, height) of the area to encode
+/// * `quality` - JPEG quality level (0-9, or [`JPEG_DISABLED_QUALITY`] to disable JPEG)
+/// * `compression` - Compression level (0-9)
+/// * `client_format` - Client's pixel format for palette color translation
+/// * `options` - Encoder options (see [`TightOptions`])
+/// * `compressor` - Zlib stream compressor for persistent compression streams
+///
+/// # Errors
+///
+/// Returns an error if `framebuffer` holds fewer than `fb_width * fb_height` pixels,
+/// if `region` does not lie within the framebuffer, or for the same reasons as
+/// [`try_encode_tight_rects`]
+#[allow(clippy::too_many_arguments)] // Mirrors try_encode_tight_rects plus framebuffer geometry
+#[allow(clippy::type_complexity)] // Same tuple shape as encode_tight_rects
+pub fn encode_region<C: TightStreamCompressor>(
+    framebuffer: &[u8],
+    fb_width: u16,
+    fb_height: u16,
+    region: (u16, u16, u16, u16),
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    options: &TightOptions,
+    compressor: &mut C,
+) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
+    check_region(framebuffer, fb_width, fb_height, region)?;
+    let (x, y, w, h) = region;
+    encode_tight_region(
+        framebuffer,
+        fb_width,
+        &Rect { x, y, w, h },
+        quality,
+        compression,
+        client_format,
+        options,
+        compressor,
+    )
+}
+
+/// Checks that `framebuffer` holds `fb_width * fb_height` pixels and that `region`
+/// lies within it
+fn check_region(
+    framebuffer: &[u8],
+    fb_width: u16,
+    fb_height: u16,
+    region: (u16, u16, u16, u16),
+) -> io::Result<()> {
+    let expected_size = fb_width as usize * fb_height as usize * 4;
+    if framebuffer.len() < expected_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Tight: framebuffer size mismatch: got {} bytes, expected {expected_size} bytes for {fb_width}x{fb_height}",
+                framebuffer.len()
+            ),
+        ));
+    }
+
+    let (x, y, w, h) = region;
+    if u32::from(x) + u32::from(w) > u32::from(fb_width)
+        || u32::from(y) + u32::from(h) > u32::from(fb_height)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Tight: region {w}x{h} at ({x}, {y}) exceeds the {fb_width}x{fb_height} framebuffer"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Encode several regions of a framebuffer as one update, with a non-standard
+/// palette shared by the whole frame
+/// Returns a vector of (x, y, width, height, `encoded_data`) for each rectangle, in
+/// framebuffer coordinates and region order
+///
+/// When the regions hold 3-256 colors in total, the first rectangle is a 0x0
+/// palette definition at (0, 0): control type 0xC, the color count minus one, and
+/// the colors as `TPIXEL`s. Later rectangles of three or more colors, all in that
+/// palette, are sent with filter 3 and 8-bit indices into it, compressed as for the
+/// palette filter, instead of each carrying its own palette. Other rectangles, and every
+/// rectangle of a frame with more colors, are encoded as [`encode_region`] does.
+///
+/// This is not part of RFC 6143: only send it to clients that decode the extension,
+/// such as [`crate::decode::TightDecoder`], which keeps the palette for later
+/// rectangles. The rectangles must be sent in order on one connection.
+///
+/// # Errors
+///
+/// Returns an error if `framebuffer` holds fewer than `fb_width * fb_height` pixels,
+/// if a region does not lie within the framebuffer, or for the same reasons as
+/// [`try_encode_tight_rects`]
+#[cfg(feature = "tight-shared-palette")]
+#[allow(clippy::too_many_arguments)] // Mirrors encode_region with a list of regions
+#[allow(clippy::type_complexity)] // Same tuple shape as encode_tight_rects
+pub fn encode_regions_with_shared_palette<C: TightStreamCompressor>(
+    framebuffer: &[u8],
+    fb_width: u16,
+    fb_height: u16,
+    regions: &[(u16, u16, u16, u16)],
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    options: &TightOptions,
+    compressor: &mut C,
+) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
+    for &region in regions {
+        check_region(framebuffer, fb_width, fb_height, region)?;
+    }
+
+    let mut options = options.clone();
+    options.shared_palette = frame_palette(framebuffer, fb_width, regions);
+    let mut rectangles = Vec::new();
+    if let Some(palette) = &options.shared_palette {
+        rectangles.push((
+            0,
+            0,
+            0,
+            0,
+            encode_shared_palette_definition(palette, client_format),
+        ));
+    }
+    for &region in regions {
+        rectangles.extend(encode_region(
+            framebuffer,
+            fb_width,
+            fb_height,
+            region,
+            quality,
+            compression,
+            client_format,
+            &options,
+            compressor,
+        )?);
+    }
+    Ok(rectangles)
+}
+
+/// Colors of all `regions`, in order of first appearance, or `None` unless there
+/// are 3-256 of them; two-color rectangles never use the shared palette
+#[cfg(feature = "tight-shared-palette")]
+fn frame_palette(
+    framebuffer: &[u8],
+    fb_width: u16,
+    regions: &[(u16, u16, u16, u16)],
+) -> Option<Vec<u32>> {
+    let mut palette = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    for &(x, y, w, h) in regions {
+        for row in usize::from(y)..usize::from(y) + usize::from(h) {
+            let start = (row * usize::from(fb_width) + usize::from(x)) * 4;
+            for p in framebuffer[start..start + usize::from(w) * 4].chunks_exact(4) {
+                let color = rgba_to_rgb24(p[0], p[1], p[2]);
+                if seen.insert(color) {
+                    palette.push(color);
+                    if palette.len() > 256 {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+    (palette.len() >= 3).then_some(palette)
+}
+
+/// Shared implementation of [`try_encode_tight_rects`] and [`encode_region`]
+#[allow(clippy::too_many_arguments)] // Options are threaded through every encoding stage
+#[allow(clippy::type_complexity)] // Same tuple shape as encode_tight_rects
+fn encode_tight_region<C: TightStreamCompressor>(
+    data: &[u8],
+    width: u16,
+    rect: &Rect,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    options: &TightOptions,
+    compressor: &mut C,
+) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
+    #[cfg(feature = "debug-logging")]
+    log::info!("DEBUG: Calling encode_rect_optimized");
+
+    let rectangles = if let Some(max_bytes) = options.max_rect_bytes {
+        encode_within_budget(
+            data,
+            width,
+            rect,
+            quality,
+            compression,
+            client_format,
+            options,
+            max_bytes,
+            compressor,
+        )?
+    } else {
+        encode_rect_optimized(
+            data,
+            width,
+            rect,
+            quality,
+            compression,
+            client_format,
+            options,
+            compressor,
+        )?
+        .rectangles
+    };
+
+    #[cfg(feature = "debug-logging")]
+    log::info!(
+        "DEBUG: encode_rect_optimized returned {} rectangles",
+        rectangles.len()
+    );
+
+    // Convert EncodeResult to public format
+    let rects: Vec<(u16, u16, u16, u16, BytesMut)> = rectangles
+        .into_iter()
+        .map(|(r, buf)| {
+            #[cfg(feature = "debug-logging")]
+            log::info!(
+                "DEBUG: Sub-rect: {}x{} at ({}, {}), encoded_len={}",
+                r.w,
+                r.h,
+                r.x,
+                r.y,
+                buf.len()
+            );
+            (r.x, r.y, r.w, r.h, buf)
+        })
+        .collect();
+
+    #[cfg(feature = "debug-logging")]
+    log::info!(
+        "DEBUG: encode_tight_rects returning {} rectangles",
+        rects.len()
+    );
+
+    Ok(rects)
+}
+
+/// Two-pass Tight encoding: a quick low-quality JPEG draft, then a lossless refinement
+///
+/// [`ProgressiveEncoder::draft`] encodes a region with JPEG at the draft quality and
+/// remembers every rectangle that actually came out as JPEG. A later
+/// [`ProgressiveEncoder::refine`] re-encodes those rectangles from the current
+/// framebuffer with JPEG disabled, so the client ends up with exact pixels. Palette
+/// and solid rectangles are lossless already and are never queued, nor is anything
+/// when JPEG is unavailable and drafts fall back to full color.
+///
+/// Both passes write to the client's Tight zlib streams, so pass the same
+/// compressor used for the client's other Tight rectangles.
+#[derive(Debug, Clone)]
+pub struct ProgressiveEncoder {
+    draft_quality: u8,
+    compression: u8,
+    options: TightOptions,
+    pending: Vec<(u16, u16, u16, u16)>,
+}
+
+impl ProgressiveEncoder {
+    /// Creates an encoder drafting at VNC quality level `draft_quality` (0-9, where 9
+    /// is the smallest JPEG) and encoding at `compression` (0-9).
+    #[must_use]
+    pub fn new(draft_quality: u8, compression: u8) -> Self {
+        Self::with_options(draft_quality, compression, TightOptions::default())
+    }
+
+    /// Creates an encoder with non-default [`TightOptions`], used for both passes.
+    #[must_use]
+    pub fn with_options(draft_quality: u8, compression: u8, options: TightOptions) -> Self {
+        Self {
+            draft_quality: draft_quality.min(9),
+            compression,
+            options,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Rectangles drafted as JPEG and not yet refined, as (x, y, width, height).
+    #[must_use]
+    pub fn pending(&self) -> &[(u16, u16, u16, u16)] {
+        &self.pending
+    }
+
+    /// Encodes `region` of the framebuffer as a low-quality draft and queues its JPEG
+    /// rectangles for [`ProgressiveEncoder::refine`].
+    ///
+    /// Returns (x, y, width, height, `encoded_data`) for each sub-rectangle, as
+    /// [`encode_region`] does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`encode_region`].
+    #[allow(clippy::type_complexity)] // Same tuple shape as encode_region
+    pub fn draft<C: TightStreamCompressor>(
+        &mut self,
+        framebuffer: &[u8],
+        fb_width: u16,
+        fb_height: u16,
+        region: (u16, u16, u16, u16),
+        client_format: &PixelFormat,
+        compressor: &mut C,
+    ) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
+        let rects = encode_region(
+            framebuffer,
+            fb_width,
+            fb_height,
+            region,
+            self.draft_quality,
+            self.compression,
+            client_format,
+            &self.options,
+            compressor,
+        )?;
+        for (x, y, w, h, data) in &rects {
+            let rect = (*x, *y, *w, *h);
+            if data.first() == Some(&(TIGHT_JPEG << 4)) && !self.pending.contains(&rect) {
+                self.pending.push(rect);
+            }
+        }
+        Ok(rects)
+    }
+
+    /// Losslessly re-encodes every pending rectangle from the current framebuffer
+    /// and clears the queue.
+    ///
+    /// Returns an empty list when nothing is pending.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `framebuffer` holds fewer than `fb_width * fb_height`
+    /// pixels or a pending rectangle no longer lies within it, for example because
+    /// the framebuffer shrank since the draft. Every rectangle is checked before any
+    /// is encoded, so the queue and `compressor` are left untouched in that case.
+    /// Other errors from [`encode_region`] (such as a zlib failure) can occur after
+    /// earlier rectangles were written to the streams; the client must then be reset.
+    #[allow(clippy::type_complexity)] // Same tuple shape as encode_region
+    pub fn refine<C: TightStreamCompressor>(
+        &mut self,
+        framebuffer: &[u8],
+        fb_width: u16,
+        fb_height: u16,
+        client_format: &PixelFormat,
+        compressor: &mut C,
+    ) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
+        // The streams cannot be rewound, so fail before the first region is encoded
+        for &region in &self.pending {
+            check_region(framebuffer, fb_width, fb_height, region)?;
+        }
+
+        let mut rects = Vec::new();
+        for &region in &self.pending {
+            rects.extend(encode_region(
+                framebuffer,
+                fb_width,
+                fb_height,
+                region,
+                JPEG_DISABLED_QUALITY,
+                self.compression,
+                client_format,
+                &self.options,
+                compressor,
+            )?);
+        }
+        self.pending.clear();
+        Ok(rects)
+    }
+}
+
+/// How a [`MultiDisplaySession`] assigns Tight zlib streams to displays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamSharing {
+    /// All displays write to one set of four streams.
+    ///
+    /// Required when the displays travel over a single RFB connection, since the
+    /// client keeps only one set of inflate streams per connection. The 32 KiB zlib
+    /// window is shared too: displays showing similar content (the same theme,
+    /// fonts or application) find matches in each other's history and compress
+    /// better, while unrelated displays push each other's history out of the window
+    /// and compress worse than they would alone.
+    #[default]
+    Shared,
+    /// Each display gets its own set of four streams.
+    ///
+    /// For displays whose output is decoded independently, e.g. one connection per
+    /// display. Every display keeps its own full window, so the ratio does not depend
+    /// on what the other displays show, at the cost of up to four more zlib states
+    /// (roughly 256 KiB each) per display.
+    PerDisplay,
+}
+
+/// Tight encoding for several independent framebuffers, such as the heads of a
+/// multi-monitor server
+///
+/// Displays are identified by caller-chosen IDs. With [`StreamSharing::PerDisplay`]
+/// the streams for a display are created on first use; the matching client must
+/// then decode each display with its own decoder, in the order encoded.
+pub struct MultiDisplaySession {
+    sharing: StreamSharing,
+    compression: u8,
+    shared: SimpleTightCompressor,
+    per_display: HashMap<u32, SimpleTightCompressor>,
+}
+
+impl MultiDisplaySession {
+    /// Creates a session encoding at `compression` (0-9) with the given stream layout.
+    #[must_use]
+    pub fn new(sharing: StreamSharing, compression: u8) -> Self {
+        Self {
+            sharing,
+            compression,
+            shared: SimpleTightCompressor::new(compression),
+            per_display: HashMap::new(),
+        }
+    }
+
+    /// The stream layout this session was created with.
+    #[must_use]
+    pub fn sharing(&self) -> StreamSharing {
+        self.sharing
+    }
+
+    /// Encodes `region` of display `display_id`'s framebuffer.
+    ///
+    /// Returns (x, y, width, height, `encoded_data`) for each sub-rectangle, as
+    /// [`encode_region`] does, on the streams chosen by the session's
+    /// [`StreamSharing`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`encode_region`].
+    #[allow(clippy::too_many_arguments)] // Mirrors encode_region plus the display ID
+    #[allow(clippy::type_complexity)] // Same tuple shape as encode_region
+    pub fn encode_display(
+        &mut self,
+        display_id: u32,
+        framebuffer: &[u8],
+        width: u16,
+        height: u16,
+        region: (u16, u16, u16, u16),
+        quality: u8,
+        client_format: &PixelFormat,
+        options: &TightOptions,
+    ) -> io::Result<Vec<(u16, u16, u16, u16, BytesMut)>> {
+        let compression = self.compression;
+        let compressor = match self.sharing {
+            StreamSharing::Shared => &mut self.shared,
+            StreamSharing::PerDisplay => self
+                .per_display
+                .entry(display_id)
+                .or_insert_with(|| SimpleTightCompressor::new(compression)),
+        };
+        encode_region(
+            framebuffer,
+            width,
+            height,
+            region,
+            quality,
+            compression,
+            client_format,
+            options,
+            compressor,
+        )
+    }
+
+    /// Drops the streams of a display that went away.
+    ///
+    /// A later frame for the same ID starts on fresh streams, so its client must
+    /// reset its decoder too. Does nothing with [`StreamSharing::Shared`].
+    pub fn remove_display(&mut self, display_id: u32) {
+        self.per_display.remove(&display_id);
+    }
+}
+
+/// Encode with every rectangle's output at most `max_bytes` (see [`TightOptions::max_rect_bytes`])
+///
+/// Encoded data cannot be taken back from the persistent zlib streams, so the split is
+/// planned on scratch streams first and only the final rectangles are encoded with
+/// `compressor`. Solid fills from the trial encode never touch the streams and are
+/// kept as they are; they may be larger than a Tight rectangle could otherwise be.
+///
+/// The real streams carry history the scratch streams lack, so a final rectangle can
+/// differ in size from its measurement. One that ends up over `max_bytes` is returned
+/// anyway: failing at that point would leave the client's inflaters out of step with
+/// streams that already consumed it.
+///
+/// Planning costs extra encodes: the trial pass over the whole rectangle, at least
+/// one measurement per planned piece (more when JPEG quality is lowered or a piece is
+/// split), and the final encode.
+#[allow(clippy::too_many_arguments)] // Options are threaded through every encoding stage
+fn encode_within_budget<C: TightStreamCompressor>(
+    framebuffer: &[u8],
+    fb_width: u16,
+    rect: &Rect,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    options: &TightOptions,
+    max_bytes: usize,
+    compressor: &mut C,
+) -> io::Result<Vec<(Rect, BytesMut)>> {
+    // Each rectangle is measured on fresh streams, so data seen while planning
+    // cannot make a later measurement look smaller than it is
+    let measure = |r: &Rect, quality: u8| -> io::Result<(usize, bool)> {
+        let buf = encode_subrect_single(
+            framebuffer,
+            fb_width,
+            r,
+            quality,
+            compression,
+            client_format,
+            options,
+            &mut SimpleTightCompressor::new(compression),
+        )?;
+        Ok((buf.len(), buf[0] == TIGHT_JPEG << 4))
+    };
+
+    let trial = encode_rect_optimized(
+        framebuffer,
+        fb_width,
+        rect,
+        quality,
+        compression,
+        client_format,
+        options,
+        &mut SimpleTightCompressor::new(compression),
+    )?;
+
+    // Lower the quality of oversized JPEG rectangles down to the floor, then halve
+    // whatever is still oversized along its longer side until every piece fits
+    let mut pending: Vec<(Rect, Option<BytesMut>)> = trial
+        .rectangles
+        .into_iter()
+        .rev()
+        .map(|(r, buf)| {
+            let fill = buf[0] == TIGHT_FILL << 4 && buf.len() <= max_bytes;
+            (r, fill.then_some(buf))
+        })
+        .collect();
+    let mut plan = Vec::new();
+    while let Some((r, fill)) = pending.pop() {
+        if fill.is_some() {
+            plan.push((r, quality, fill));
+            continue;
+        }
+        let (mut len, jpeg) = measure(&r, quality)?;
+        let mut level = quality;
+        while jpeg && len > max_bytes && level < BUDGET_JPEG_QUALITY_FLOOR {
+            level += 1;
+            len = measure(&r, level)?.0;
+        }
+        if len <= max_bytes {
+            plan.push((r, level, None));
+            continue;
+        }
+        if r.w == 1 && r.h == 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Tight: a single pixel needs {len} bytes, over the {max_bytes} byte limit"),
+            ));
+        }
+        if r.w >= r.h {
+            let half = r.w / 2;
+            let right = Rect {
+                x: r.x + half,
+                w: r.w - half,
+                ..r
+            };
+            pending.push((right, None));
+            pending.push((Rect { w: half, ..r }, None));
+        } else {
+            let half = r.h / 2;
+            let bottom = Rect {
+                y: r.y + half,
+                h: r.h - half,
+                ..r
+            };
+            pending.push((bottom, None));
+            pending.push((Rect { h: half, ..r }, None));
+        }
+    }
+
+    let mut rectangles = Vec::with_capacity(plan.len());
+    for (r, level, fill) in plan {
+        let buf = match fill {
+            Some(buf) => buf,
+            None => encode_subrect_single(
+                framebuffer,
+                fb_width,
+                &r,
+                level,
+                compression,
+                client_format,
+                options,
+                compressor,
+            )?,
+        };
+        // Over max_bytes only if the real streams diverged from the plan; see above
+        rectangles.push((r, buf));
+    }
+
+    Ok(rectangles)
+}
+
+/// Encode Tight with persistent zlib streams, returning individual sub-rectangles
+/// Returns a vector of (x, y, width, height, `encoded_data`) for each sub-rectangle
+///
+/// # Arguments
+/// * `data` - Framebuffer pixel data (RGBA format)
+/// * `width` - Rectangle width
+/// * `height` - Rectangle height
+/// * `quality` - JPEG quality level (0-9, or [`JPEG_DISABLED_QUALITY`] to disable JPEG)
+/// * `compression` - Compression level (0-9)
+/// * `client_format` - Client's pixel format for palette color translation
+/// * `compressor` - Zlib stream compressor for persistent compression streams
+///
+/// Returns an empty list, without touching `compressor`, when `data` holds fewer than
+/// `width * height` pixels; use [`try_encode_tight_rects`] to get the error instead.
+pub fn encode_tight_rects<C: TightStreamCompressor>(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    compressor: &mut C,
+) -> Vec<(u16, u16, u16, u16, BytesMut)> {
+    // With default options only short input fails (JPEG errors fall back to
+    // full-color), and it is rejected before any stream is written
+    try_encode_tight_rects(
+        data,
+        width,
+        height,
+        quality,
+        compression,
+        client_format,
+        &TightOptions::default(),
+        compressor,
+    )
+    .unwrap_or_default()
+}
+
+/// Encode Tight with persistent zlib streams (for use with VNC client streams)
+/// Returns concatenated data (legacy API - consider using `encode_tight_rects` instead)
+///
+/// Like [`encode_tight_rects`], returns nothing when `data` is too short.
+pub fn encode_tight_with_streams<C: TightStreamCompressor>(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    compressor: &mut C,
+) -> BytesMut {
+    // Concatenate all sub-rectangles
+    let rects = encode_tight_rects(
+        data,
+        width,
+        height,
+        quality,
+        compression,
+        client_format,
+        compressor,
+    );
+    let mut output = BytesMut::new();
+    for (_x, _y, _w, _h, buf) in rects {
+        output.extend_from_slice(&buf);
+    }
+    output
+}
+
+/// Encode Tight with persistent zlib streams, returning each sub-rectangle's data as
+/// its own [`Bytes`] instead of concatenating them
+///
+/// Returns (x, y, width, height, `encoded_data`) for each sub-rectangle, as
+/// [`encode_tight_rects`] does, so the caller can write a rectangle header before
+/// each buffer and hand the lot to a socket as scatter-gather I/O (iovecs) without
+/// the copy [`encode_tight_with_streams`] makes. The buffers joined in order are
+/// identical to its output.
+#[allow(clippy::type_complexity)] // Same tuple shape as encode_tight_rects
+pub fn encode_tight_vectored<C: TightStreamCompressor>(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    compressor: &mut C,
+) -> Vec<(u16, u16, u16, u16, Bytes)> {
+    encode_tight_rects(
+        data,
+        width,
+        height,
+        quality,
+        compression,
+        client_format,
+        compressor,
+    )
+    .into_iter()
+    .map(|(x, y, w, h, buf)| (x, y, w, h, buf.freeze()))
+    .collect()
+}
+
+/// How [`encode_tight_indexed_with_palette`] handles pixels whose color is not in the palette
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnmatchedColor {
+    /// Fail with [`io::ErrorKind::InvalidInput`].
+    #[default]
+    Error,
+    /// Use the palette entry closest in RGB (squared distance, first entry on ties).
+    Nearest,
+}
+
+/// Encode a rectangle as indexed Tight using a palette supplied by the caller
+///
+/// Skips palette analysis entirely, so the output is always a palette rectangle:
+/// 1-bit indices for a 2-color palette, 8-bit indices otherwise. Useful for UIs
+/// with a fixed color scheme, such as a 16-color terminal.
+///
+/// # Arguments
+/// * `pixels` - Rectangle pixel data (RGBA format)
+/// * `width`, `height` - Rectangle dimensions
+/// * `palette` - 2-256 colors in the internal RGB24 format (`0x00BBGGRR`, as
+///   returned by [`crate::common::rgba_to_rgb24_pixels`])
+/// * `unmatched` - What to do with pixels whose color is not in `palette`
+/// * `compression` - Compression level (0-9)
+/// * `client_format` - Client's pixel format for palette color translation
+/// * `compressor` - Zlib stream compressor for persistent compression streams
+///
+/// # Errors
+///
+/// Returns an error if `pixels` is smaller than `width * height` pixels, if the
+/// palette has fewer than 2 or more than 256 colors, or if a pixel is not in the
+/// palette and `unmatched` is [`UnmatchedColor::Error`]
+#[allow(clippy::too_many_arguments)] // Mirrors the internal encoders plus palette handling
+pub fn encode_tight_indexed_with_palette<C: TightStreamCompressor>(
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    palette: &[u32],
+    unmatched: UnmatchedColor,
+    compression: u8,
+    client_format: &PixelFormat,
+    compressor: &mut C,
+) -> io::Result<BytesMut> {
+    encode_snapped_to_palette(
+        pixels,
+        width,
+        height,
+        palette,
+        |color| match unmatched {
+            UnmatchedColor::Nearest => Ok(nearest_palette_index(color, palette)),
+            UnmatchedColor::Error => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Tight: color 0x{color:06x} is not in the palette"),
+            )),
+        },
+        compression,
+        client_format,
+        compressor,
+    )
+}
+
+/// Encode a rectangle as indexed Tight, mapping out-of-palette colors with `nearest`
+///
+/// Same as [`encode_tight_indexed_with_palette`] with [`UnmatchedColor::Nearest`],
+/// except that pixels whose color is not in `palette` are snapped to the entry
+/// whose index `nearest` returns, so callers can apply their own color metric
+/// (CIELAB, a terminal theme's mapping) instead of RGB distance. `nearest` is
+/// called with the pixel's internal RGB24 color (`0x00BBGGRR`).
+///
+/// # Errors
+///
+/// Returns an error if `pixels` is smaller than `width * height` pixels, if the
+/// palette has fewer than 2 or more than 256 colors, or if `nearest` returns an
+/// index past the end of `palette`
+#[allow(clippy::too_many_arguments)] // Mirrors encode_tight_indexed_with_palette
+pub fn encode_tight_indexed_with_nearest<C: TightStreamCompressor>(
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    palette: &[u32],
+    nearest: impl Fn(u32) -> u8,
+    compression: u8,
+    client_format: &PixelFormat,
+    compressor: &mut C,
+) -> io::Result<BytesMut> {
+    encode_snapped_to_palette(
+        pixels,
+        width,
+        height,
+        palette,
+        |color| {
+            let index = nearest(color);
+            if usize::from(index) < palette.len() {
+                Ok(index)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Tight: nearest-color index {index} is out of range for a {}-color palette",
+                        palette.len()
+                    ),
+                ))
+            }
+        },
+        compression,
+        client_format,
+        compressor,
+    )
+}
+
+/// Snaps every pixel to a palette color, using `unmatched` to pick the palette
+/// index for colors not in it, and encodes the result as mono or indexed Tight
+#[allow(clippy::too_many_arguments)] // Shared body of the two public entry points
+fn encode_snapped_to_palette<C: TightStreamCompressor>(
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    palette: &[u32],
+    mut unmatched: impl FnMut(u32) -> io::Result<u8>,
+    compression: u8,
+    client_format: &PixelFormat,
+    compressor: &mut C,
+) -> io::Result<BytesMut> {
+    let expected_size = width as usize * height as usize * 4;
+    if pixels.len() < expected_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Tight: buffer size mismatch: got {} bytes, expected {expected_size} bytes",
+                pixels.len()
+            ),
+        ));
+    }
+    if !(2..=256).contains(&palette.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Tight: palette must have 2-256 colors, got {}",
+                palette.len()
+            ),
+        ));
+    }
+
+    // Snap every pixel to a palette color so the internal encoders find them all
+    let mut mapped = Vec::with_capacity(expected_size);
+    for chunk in pixels[..expected_size].chunks_exact(4) {
+        let color = rgba_to_rgb24(chunk[0], chunk[1], chunk[2]);
+        let entry = if palette.contains(&color) {
+            color
+        } else {
+            palette[usize::from(unmatched(color)?)]
+        };
+        let [r, g, b, _] = entry.to_le_bytes();
+        mapped.extend_from_slice(&[r, g, b, chunk[3]]);
+    }
+
+    if palette.len() == 2 {
+        Ok(encode_mono_rect(
+            &mapped,
+            width,
+            height,
+            palette[0],
+            palette[1],
+            TightZlibLevels::default().mono_level(compression),
+            client_format,
+            compressor,
+        ))
+    } else {
+        encode_indexed_rect(
+            &mapped,
+            width,
+            height,
+            palette,
+            TightZlibLevels::default().indexed_level(compression),
+            client_format,
+            compressor,
+        )
+    }
+}
+
+/// Index of the palette entry with the smallest squared RGB distance to `color`
+/// (first on ties); `palette` holds at most 256 entries
+fn nearest_palette_index(color: u32, palette: &[u32]) -> u8 {
+    let [r, g, b, _] = color.to_le_bytes();
+    let distance = |entry: u32| {
+        let [pr, pg, pb, _] = entry.to_le_bytes();
+        [(r, pr), (g, pg), (b, pb)]
+            .iter()
+            .map(|&(a, b)| u32::from(a.abs_diff(b)).pow(2))
+            .sum::<u32>()
+    };
+    let index = (0..palette.len())
+        .min_by_key(|&i| distance(palette[i]))
+        .unwrap_or(0);
+    index.to_le_bytes()[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Internal RGB24 values (0x00BBGGRR) for the primary test colors.
+    const RED: u32 = 0x0000_00FF;
+    const GREEN: u32 = 0x0000_FF00;
+    const BLUE: u32 = 0x00FF_0000;
+    const WHITE: u32 = 0x00FF_FFFF;
+
+    #[test]
+    fn test_solid_rect_rgb565_colors() {
+        let pf = PixelFormat::rgb565();
+        for (color, expected) in [
+            (RED, 0xF800u16),
+            (GREEN, 0x07E0),
+            (BLUE, 0x001F),
+            (WHITE, 0xFFFF),
+        ] {
+            let buf = encode_solid_rect(color, &pf);
+            assert_eq!(buf.len(), 3, "16bpp solid rect must be control + 2 bytes");
+            assert_eq!(buf[0], TIGHT_FILL << 4);
+            let value = u16::from_le_bytes([buf[1], buf[2]]);
+            assert_eq!(value, expected, "color 0x{color:06x}");
+        }
+    }
+
+    #[test]
+    fn test_solid_rect_rgb565_big_endian() {
+        let mut pf = PixelFormat::rgb565();
+        pf.big_endian_flag = 1;
+        let buf = encode_solid_rect(RED, &pf);
+        assert_eq!(&buf[1..], &[0xF8, 0x00]);
+    }
+
+    #[test]
+    fn test_solid_rect_rgb555_colors() {
+        let pf = PixelFormat::rgb555();
+        for (color, expected) in [
+            (RED, 0x7C00u16),
+            (GREEN, 0x03E0),
+            (BLUE, 0x001F),
+            (WHITE, 0x7FFF),
+        ] {
+            let buf = encode_solid_rect(color, &pf);
+            assert_eq!(buf.len(), 3, "16bpp solid rect must be control + 2 bytes");
+            assert_eq!(buf[0], TIGHT_FILL << 4);
+            let value = u16::from_le_bytes([buf[1], buf[2]]);
+            assert_eq!(value, expected, "color 0x{color:06x}");
+        }
+    }
+
+    #[test]
+    fn test_solid_frame_green_differs_between_555_and_565() {
+        // 8x8 solid green frame in RGBA
+        let data: Vec<u8> = [0u8, 255, 0, 255].repeat(64);
+        let encode = |pf: &PixelFormat| {
+            let mut compressor = SimpleTightCompressor::new(6);
+            let rects = encode_tight_rects(&data, 8, 8, 10, 6, pf, &mut compressor);
+            assert_eq!(rects.len(), 1);
+            rects[0].4.to_vec()
+        };
+
+        // 565 packs green as 6 bits (0x3F << 5), 555 as 5 bits (0x1F << 5)
+        assert_eq!(
+            encode(&PixelFormat::rgb565()),
+            [TIGHT_FILL << 4, 0xE0, 0x07]
+        );
+        assert_eq!(
+            encode(&PixelFormat::rgb555()),
+            [TIGHT_FILL << 4, 0xE0, 0x03]
+        );
+    }
+
+    #[test]
+    fn test_solid_rect_bgr233_colors() {
+        let pf = PixelFormat::bgr233();
+        for (color, expected) in [(RED, 0x07u8), (GREEN, 0x38), (BLUE, 0xC0), (WHITE, 0xFF)] {
+            let buf = encode_solid_rect(color, &pf);
+            assert_eq!(buf.len(), 2, "8bpp solid rect must be control + 1 byte");
+            assert_eq!(buf[0], TIGHT_FILL << 4);
+            assert_eq!(buf[1], expected, "color 0x{color:06x}");
+        }
+    }
+
+    #[test]
+    fn test_solid_frame_rgb565_via_public_api() {
+        // 8x8 solid red frame in RGBA
+        let data: Vec<u8> = [255u8, 0, 0, 255].repeat(64);
+        let pf = PixelFormat::rgb565();
+        let mut compressor = SimpleTightCompressor::new(6);
+        let rects = encode_tight_rects(&data, 8, 8, 10, 6, &pf, &mut compressor);
+        assert_eq!(rects.len(), 1);
+        assert_eq!(&rects[0].4[..], &[TIGHT_FILL << 4, 0x00, 0xF8]);
+    }
+
+    #[cfg(feature = "alpha-jpeg")]
+    #[test]
+    fn test_jpeg_with_alpha_keeps_soft_alpha_exact() {
+        use crate::decode::decode_jpeg_with_alpha;
+
+        // Photo-like colors under a soft radial alpha falloff
+        let data: Vec<u8> = (0..32u8)
+            .flat_map(|y| {
+                (0..32u8).flat_map(move |x| {
+                    let (dx, dy) = (u16::from(x.abs_diff(16)), u16::from(y.abs_diff(16)));
+                    let alpha = 255 - u8::try_from((dx * dx + dy * dy).min(255)).unwrap();
+                    [x * 8, y * 8, x.wrapping_mul(y), alpha]
+                })
+            })
+            .collect();
+
+        let buf = encode_jpeg_with_alpha(&data, 32, 32, 80).unwrap();
+        assert_eq!(buf[0], TIGHT_JPEG_ALPHA << 4);
+        let decoded = decode_jpeg_with_alpha(&buf, 32, 32).unwrap();
+        assert_eq!(&decoded.jpeg[..2], &[0xFF, 0xD8], "RGB is a JPEG image");
+        let alpha: Vec<u8> = data.chunks_exact(4).map(|p| p[3]).collect();
+        assert_eq!(decoded.alpha, Some(alpha));
+
+        // Opaque rectangles stay standard Tight JPEG
+        let opaque: Vec<u8> = data
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect();
+        let buf = encode_jpeg_with_alpha(&opaque, 32, 32, 80).unwrap();
+        assert_eq!(buf[0], TIGHT_JPEG << 4);
+        assert_eq!(decode_jpeg_with_alpha(&buf, 32, 32).unwrap().alpha, None);
+    }
+
+    #[cfg(feature = "turbojpeg")]
+    #[test]
+    fn test_strict_jpeg_reports_failure() {
+        // A zero-width image makes TurboJPEG reject the compression request
+        let pf = PixelFormat::rgba32();
+        let mut compressor = SimpleTightCompressor::new(6);
+        let result = encode_jpeg_rect(&[], 0, 4, 80, true, 6, false, &pf, &mut compressor);
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "turbojpeg")]
+    #[test]
+    fn test_jpeg_failure_falls_back_by_default() {
+        let pf = PixelFormat::rgba32();
+        let mut compressor = SimpleTightCompressor::new(6);
+        let buf = encode_jpeg_rect(&[], 0, 4, 80, false, 6, false, &pf, &mut compressor).unwrap();
+        assert_ne!(buf[0], TIGHT_JPEG << 4, "expected full-color fallback");
+    }
+
+    #[test]
+    fn test_solid_check_alpha_mode() {
+        // 16x16 tile of one RGB color whose alpha varies per pixel
+        let fb: Vec<u8> = (0..=255u8).flat_map(|a| [40, 80, 120, a]).collect();
+        let color = rgba_to_rgb24(40, 80, 120);
+
+        assert_eq!(
+            check_solid_tile(&fb, 16, 0, 0, 16, 16, None, false),
+            Some(color)
+        );
+        assert_eq!(check_solid_tile(&fb, 16, 0, 0, 16, 16, None, true), None);
+    }
+
+    #[test]
+    fn test_rgb24_bytes_matches_naive_1024x1024() {
+        let pixels: Vec<u8> = (0..1024 * 1024 * 4u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24).to_le_bytes()[0])
+            .collect();
+        let mut naive = Vec::with_capacity(1024 * 1024 * 3);
+        for chunk in pixels.chunks_exact(4) {
+            naive.push(chunk[0]);
+            naive.push(chunk[1]);
+            naive.push(chunk[2]);
+        }
+
+        assert_eq!(rgba_to_rgb24_bytes(&pixels), naive);
+        // Lengths that leave a partial 4-pixel block
+        for len in [0, 4, 8, 12, 20, 28] {
+            assert_eq!(rgba_to_rgb24_bytes(&pixels[..len]), naive[..len / 4 * 3]);
+        }
+    }
+
+    /// RGBA frame cycling through `colors` distinct colors in diagonal bands.
+    /// Colors first appear in index order, so the expected palette is `palette_color(0..colors)`.
+    fn banded_frame(colors: usize, width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                palette_color((x / 3 + y) % colors)
+            })
+            .collect()
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // Test palettes have at most 16 colors
+    fn palette_color(index: usize) -> [u8; 4] {
+        let i = index as u8;
+        [i * 16, 255 - i * 8, i * 5 + 3, 255]
+    }
+
+    /// Encode a banded frame twice on one stream set and decode both with `TightDecoder`
+    fn roundtrip_indexed(colors: usize, compression: u8) {
+        let (width, height) = (32u16, 32u16);
+        let data = banded_frame(colors, width as usize, height as usize);
+        let pf = PixelFormat::rgba32();
+        let mut compressor = SimpleTightCompressor::new(compression);
+        let mut decoder = crate::decode::TightDecoder::new();
+        let control = if compression == 0 {
+            (TIGHT_NO_ZLIB | TIGHT_EXPLICIT_FILTER) << 4
+        } else {
+            (STREAM_ID_INDEXED | TIGHT_EXPLICIT_FILTER) << 4
+        };
+
+        for _ in 0..2 {
+            let rects = try_encode_tight_rects(
+                &data,
+                width,
+                height,
+                10,
+                compression,
+                &pf,
+                &TightOptions {
+                    indexed_palette: true,
+                    ..TightOptions::default()
+                },
+                &mut compressor,
+            )
+            .unwrap();
+            assert_eq!(rects.len(), 1);
+            let buf = &rects[0].4;
+
+            assert_eq!(
+                buf[0], control,
+                "{colors} colors, compression {compression}"
+            );
+            assert_eq!(buf[1], TIGHT_FILTER_PALETTE);
+            assert_eq!(buf[2] as usize, colors - 1);
+            let palette: Vec<u8> = (0..colors)
+                .flat_map(|i| palette_color(i)[..3].to_vec())
+                .collect();
+            assert_eq!(
+                &buf[3..3 + colors * 3],
+                &palette[..],
+                "first-appearance order"
+            );
+
+            let pixels = decoder.decode_rect(buf, width, height, &pf).unwrap();
+            for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+                assert_eq!(out[..3], input[..3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_indexed_roundtrip_uncompressed() {
+        for colors in [3, 8, 16] {
+            roundtrip_indexed(colors, 0);
+        }
+    }
+
+    #[test]
+    fn test_indexed_roundtrip_compressed() {
+        for colors in [3, 8, 16] {
+            roundtrip_indexed(colors, 9);
+        }
+    }
+
+    #[test]
+    fn test_indexed_rect_requires_every_color_in_palette() {
+        let pf = PixelFormat::rgba32();
+        let mut compressor = SimpleTightCompressor::new(6);
+        for colors in 3..=16 {
+            let data = banded_frame(colors, 32, 32);
+            let palette = analyze_palette(&data, 32, 32, 6, true);
+            assert_eq!(palette.num_colors, colors);
+            let analyzed = &palette.colors[..palette.num_colors];
+            assert!(
+                encode_indexed_rect(&data, 32, 32, analyzed, 6, &pf, &mut compressor).is_ok(),
+                "{colors} colors"
+            );
+        }
+
+        // A palette missing one of the frame's colors is an analysis bug, not index 0
+        let data = banded_frame(4, 32, 32);
+        let palette = analyze_palette(&data, 32, 32, 6, true);
+        let inconsistent = &palette.colors[..palette.num_colors - 1];
+        let err =
+            encode_indexed_rect(&data, 32, 32, inconsistent, 6, &pf, &mut compressor).unwrap_err();
+        assert!(err.to_string().contains("missing from the 3-color palette"));
+    }
+
+    #[test]
+    fn test_small_two_color_rect_uses_mono_when_smaller() {
+        let pf = PixelFormat::rgba32();
+        let encode = |data: &[u8], width, height| {
+            let mut compressor = SimpleTightCompressor::new(9);
+            let rects = try_encode_tight_rects(
+                data,
+                width,
+                height,
+                10,
+                9,
+                &pf,
+                &TightOptions::default(),
+                &mut compressor,
+            )
+            .unwrap();
+            assert_eq!(rects.len(), 1);
+            rects[0].4.clone()
+        };
+
+        // 4x4 checkerboard: below mono_min_rect_size (32) at level 9
+        let data: Vec<u8> = (0..16)
+            .flat_map(|i| palette_color((i + i / 4) % 2))
+            .collect();
+        let buf = encode(&data, 4, 4);
+        assert_eq!(buf[0], (STREAM_ID_MONO | TIGHT_EXPLICIT_FILTER) << 4);
+        assert_eq!((buf[1], buf[2]), (TIGHT_FILTER_PALETTE, 1));
+        // Control, filter, size, two 3-byte colors and one bitmap byte per row
+        assert_eq!(buf.len(), 3 + 6 + 4);
+        assert!(buf.len() < 1 + 16 * 3);
+        let pixels = crate::decode::TightDecoder::new()
+            .decode_rect(&buf, 4, 4, &pf)
+            .unwrap();
+        for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+            assert_eq!(out[..3], input[..3]);
+        }
+
+        // 2x1: the mono header outweighs 6 bytes of color
+        let buf = encode(&data[..8], 2, 1);
+        assert_eq!(buf[0], STREAM_ID_FULL_COLOR << 4);
+    }
+
+    #[test]
+    fn test_zlib_level_override_sends_indexed_uncompressed() {
+        let data = banded_frame(4, 32, 32);
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions {
+            zlib_levels: TightZlibLevels {
+                indexed: Some(0),
+                ..TightZlibLevels::default()
+            },
+            indexed_palette: true,
+            ..TightOptions::default()
+        };
+        let mut compressor = SimpleTightCompressor::new(9);
+        let rects =
+            try_encode_tight_rects(&data, 32, 32, 10, 9, &pf, &options, &mut compressor).unwrap();
+        assert_eq!(rects.len(), 1);
+        let buf = &rects[0].4;
+
+        assert_eq!(buf[0], (TIGHT_NO_ZLIB | TIGHT_EXPLICIT_FILTER) << 4);
+        assert_eq!((buf[1], buf[2]), (TIGHT_FILTER_PALETTE, 3));
+        // Four 3-byte colors, then a compact length of 1024 and one raw index per pixel
+        let indices = &buf[3 + 12..];
+        assert_eq!(indices[..2], [0x80, 0x08]);
+        assert_eq!(indices.len(), 2 + 32 * 32);
+        assert!(indices[2..].iter().all(|&i| i < 4));
+        assert_eq!(compressor.stream_stats(STREAM_ID_INDEXED), (0, 0));
+
+        let pixels = crate::decode::TightDecoder::new()
+            .decode_rect(buf, 32, 32, &pf)
+            .unwrap();
+        for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+            assert_eq!(out[..3], input[..3]);
+        }
+    }
+
+    #[test]
+    fn test_explicit_copy_filter_decodes_identically() {
+        let data = noise_frame(32, 32);
+        let pf = PixelFormat::rgba32();
+        let encode = |explicit_copy_filter| {
+            let options = TightOptions {
+                explicit_copy_filter,
+                ..TightOptions::default()
+            };
+            let mut compressor = SimpleTightCompressor::new(6);
+            let rects =
+                try_encode_tight_rects(&data, 32, 32, 10, 6, &pf, &options, &mut compressor)
+                    .unwrap();
+            assert_eq!(rects.len(), 1);
+            rects[0].4.clone()
+        };
+
+        let implicit = encode(false);
+        let explicit = encode(true);
+        assert_eq!(implicit[0], STREAM_ID_FULL_COLOR << 4);
+        assert_eq!(
+            explicit[0],
+            (STREAM_ID_FULL_COLOR | TIGHT_EXPLICIT_FILTER) << 4
+        );
+        assert_eq!(explicit[1], TIGHT_FILTER_COPY);
+        assert_eq!(explicit[2..], implicit[1..], "same length and zlib data");
+
+        for buf in [&implicit, &explicit] {
+            let pixels = crate::decode::TightDecoder::new()
+                .decode_rect(buf, 32, 32, &pf)
+                .unwrap();
+            for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+                assert_eq!(out[..3], input[..3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_indexed_with_palette_exact_match() {
+        let (width, height) = (32u16, 32u16);
+        let pf = PixelFormat::rgba32();
+        let mut decoder = crate::decode::TightDecoder::new();
+        for colors in [2, 16] {
+            let data = banded_frame(colors, width as usize, height as usize);
+            // Supplied order is kept, even though it differs from first appearance
+            let palette: Vec<u32> = (0..colors)
+                .rev()
+                .map(|i| {
+                    let [r, g, b, _] = palette_color(i);
+                    rgba_to_rgb24(r, g, b)
+                })
+                .collect();
+            let mut compressor = SimpleTightCompressor::new(6);
+            let buf = encode_tight_indexed_with_palette(
+                &data,
+                width,
+                height,
+                &palette,
+                UnmatchedColor::Error,
+                6,
+                &pf,
+                &mut compressor,
+            )
+            .unwrap();
+
+            assert_eq!(buf[1], TIGHT_FILTER_PALETTE);
+            assert_eq!(buf[2] as usize, colors - 1);
+            assert_eq!(&buf[3..6], &palette_color(colors - 1)[..3]);
+
+            let pixels = decoder.decode_rect(&buf, width, height, &pf).unwrap();
+            for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+                assert_eq!(out[..3], input[..3], "{colors} colors");
+            }
+        }
+    }
+
+    #[test]
+    fn test_indexed_with_palette_256_colors() {
+        let (width, height) = (16u16, 16u16);
+        let pf = PixelFormat::rgba32();
+        // 255 distinct colors; the palette lists them in reverse, then repeats its first
+        let color = |i: u8| [i, !i, i / 2, 255];
+        let data: Vec<u8> = (0..=255u8).flat_map(|i| color(i % 255)).collect();
+        let mut palette: Vec<u32> = (0..255u8)
+            .rev()
+            .map(|i| rgba_to_rgb24(i, !i, i / 2))
+            .collect();
+        palette.push(palette[0]);
+        let mut compressor = SimpleTightCompressor::new(6);
+        let buf = encode_tight_indexed_with_palette(
+            &data,
+            width,
+            height,
+            &palette,
+            UnmatchedColor::Error,
+            0,
+            &pf,
+            &mut compressor,
+        )
+        .unwrap();
+
+        assert_eq!(buf[2], 255);
+        // Uncompressed indices end the rectangle; a repeated color uses its first entry
+        let indices = &buf[buf.len() - 256..];
+        assert_eq!(indices[..2], [254, 253]);
+        assert_eq!(indices[254..], [0, 254]);
+        let pixels = crate::decode::TightDecoder::new()
+            .decode_rect(&buf, width, height, &pf)
+            .unwrap();
+        for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+            assert_eq!(out[..3], input[..3]);
+        }
+    }
+
+    #[test]
+    fn test_indexed_with_palette_nearest_match() {
+        let (width, height) = (16u16, 16u16);
+        let pf = PixelFormat::rgba32();
+        let exact = banded_frame(4, width as usize, height as usize);
+        // Nudge every pixel off its palette color
+        let data: Vec<u8> = exact
+            .chunks_exact(4)
+            .flat_map(|p| {
+                [
+                    p[0].saturating_add(2),
+                    p[1].saturating_sub(3),
+                    p[2] ^ 1,
+                    255,
+                ]
+            })
+            .collect();
+        let palette: Vec<u32> = (0..4)
+            .map(|i| {
+                let [r, g, b, _] = palette_color(i);
+                rgba_to_rgb24(r, g, b)
+            })
+            .collect();
+        let encode = |unmatched| {
+            let mut compressor = SimpleTightCompressor::new(6);
+            encode_tight_indexed_with_palette(
+                &data,
+                width,
+                height,
+                &palette,
+                unmatched,
+                6,
+                &pf,
+                &mut compressor,
+            )
+        };
+
+        let err = encode(UnmatchedColor::Error).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let buf = encode(UnmatchedColor::Nearest).unwrap();
+        let pixels = crate::decode::TightDecoder::new()
+            .decode_rect(&buf, width, height, &pf)
+            .unwrap();
+        for (out, want) in pixels.chunks_exact(4).zip(exact.chunks_exact(4)) {
+            assert_eq!(out[..3], want[..3]);
+        }
+
+        // A palette must have 2-256 colors
+        let mut compressor = SimpleTightCompressor::new(6);
+        assert!(encode_tight_indexed_with_palette(
+            &exact,
+            width,
+            height,
+            &palette[..1],
+            UnmatchedColor::Nearest,
+            6,
+            &pf,
+            &mut compressor,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_indexed_with_nearest_honors_callback() {
+        let (width, height) = (8u16, 8u16);
+        let pf = PixelFormat::rgba32();
+        let palette = [
+            rgba_to_rgb24(0, 0, 0),
+            rgba_to_rgb24(255, 255, 255),
+            rgba_to_rgb24(255, 0, 0),
+        ];
+        // Dark red pixels, which are closer to black than to red in RGB, plus exact whites
+        let data: Vec<u8> = (0..64)
+            .flat_map(|i| {
+                if i % 2 == 0 {
+                    [100, 10, 10, 255]
+                } else {
+                    [255, 255, 255, 255]
+                }
+            })
+            .collect();
+        // A hue-based metric: anything predominantly red maps to the red entry
+        let calls = std::cell::Cell::new(0);
+        let by_hue = |color: u32| {
+            calls.set(calls.get() + 1);
+            let [r, g, b, _] = color.to_le_bytes();
+            if r > g && r > b {
+                2
+            } else {
+                0
+            }
+        };
+        let buf = encode_tight_indexed_with_nearest(
+            &data,
+            width,
+            height,
+            &palette,
+            by_hue,
+            6,
+            &pf,
+            &mut SimpleTightCompressor::new(6),
+        )
+        .unwrap();
+        let pixels = crate::decode::TightDecoder::new()
+            .decode_rect(&buf, width, height, &pf)
+            .unwrap();
+        for (i, out) in pixels.chunks_exact(4).enumerate() {
+            let want: [u8; 3] = if i % 2 == 0 { [255, 0, 0] } else { [255; 3] };
+            assert_eq!(out[..3], want, "pixel {i}");
+        }
+        // Colors already in the palette never reach the callback
+        assert_eq!(calls.get(), 32);
+
+        // The built-in RGB distance picks black for the same pixels
+        let buf = encode_tight_indexed_with_palette(
+            &data,
+            width,
+            height,
+            &palette,
+            UnmatchedColor::Nearest,
+            6,
+            &pf,
+            &mut SimpleTightCompressor::new(6),
+        )
+        .unwrap();
+        let pixels = crate::decode::TightDecoder::new()
+            .decode_rect(&buf, width, height, &pf)
+            .unwrap();
+        assert_eq!(pixels[..3], [0, 0, 0]);
+
+        // An index past the palette is rejected
+        let err = encode_tight_indexed_with_nearest(
+            &data,
+            width,
+            height,
+            &palette,
+            |_| 3,
+            6,
+            &pf,
+            &mut SimpleTightCompressor::new(6),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "turbojpeg")]
+    #[test]
+    fn test_max_rect_bytes_lowers_jpeg_quality_before_splitting() {
+        // Smooth truecolor gradient: JPEG shrinks steadily as quality drops
+        let data: Vec<u8> = (0..64u8)
+            .flat_map(|y| (0..64u8).flat_map(move |x| [x * 4, y * 4, x.wrapping_mul(y), 255]))
+            .collect();
+        let pf = PixelFormat::rgba32();
+        let encode = |quality, max_rect_bytes| {
+            let options = TightOptions {
+                max_rect_bytes,
+                ..TightOptions::default()
+            };
+            let mut compressor = SimpleTightCompressor::new(6);
+            try_encode_tight_rects(&data, 64, 64, quality, 6, &pf, &options, &mut compressor)
+                .unwrap()
+        };
+
+        let best = encode(0, None);
+        let floor = encode(BUDGET_JPEG_QUALITY_FLOOR, None);
+        assert_eq!((best.len(), floor.len()), (1, 1));
+        assert_eq!(best[0].4[0], TIGHT_JPEG << 4);
+        let (best_len, floor_len) = (best[0].4.len(), floor[0].4.len());
+        assert!(floor_len < best_len);
+
+        // A budget between the two is met by lowering quality, not by splitting
+        let budget = floor_len + (best_len - floor_len) / 2;
+        let rects = encode(0, Some(budget));
+        assert_eq!(rects.len(), 1);
+        assert_eq!(rects[0].4[0], TIGHT_JPEG << 4);
+        assert!(rects[0].4.len() <= budget);
+        assert!(rects[0].4.len() < best_len, "quality was reduced");
+
+        // Below what the floor quality can reach, the rectangle is split
+        let rects = encode(0, Some(floor_len - 1));
+        assert!(rects.len() > 1);
+        assert!(rects.iter().all(|r| r.4.len() < floor_len));
+    }
+
+    #[test]
+    fn test_max_rect_bytes_splits_photo() {
+        // 64x64 noise: full-color zlib cannot shrink it much below 12 KiB
+        let data = noise_frame(64, 64);
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions {
+            max_rect_bytes: Some(2048),
+            ..TightOptions::default()
+        };
+        let mut compressor = SimpleTightCompressor::new(6);
+        let rects =
+            try_encode_tight_rects(&data, 64, 64, 10, 6, &pf, &options, &mut compressor).unwrap();
+
+        assert!(rects.len() > 1);
+        let mut decoder = crate::decode::TightDecoder::new();
+        let mut area = 0;
+        for (x, y, w, h, buf) in &rects {
+            assert!(buf.len() <= 2048, "{w}x{h} rect is {} bytes", buf.len());
+            area += *w as usize * *h as usize;
+
+            let pixels = decoder.decode_rect(buf, *w, *h, &pf).unwrap();
+            for row in 0..*h as usize {
+                for col in 0..*w as usize {
+                    let src = ((*y as usize + row) * 64 + *x as usize + col) * 4;
+                    let dst = (row * *w as usize + col) * 4;
+                    assert_eq!(pixels[dst..dst + 3], data[src..src + 3]);
+                }
+            }
+        }
+        assert_eq!(area, 64 * 64);
+
+        // A budget no pixel can meet is an error
+        let options = TightOptions {
+            max_rect_bytes: Some(2),
+            ..TightOptions::default()
+        };
+        assert!(
+            try_encode_tight_rects(&data, 64, 64, 10, 6, &pf, &options, &mut compressor).is_err()
+        );
+    }
+
+    #[test]
+    fn test_stream_stats_track_ratio() {
+        let mut compressor = SimpleTightCompressor::new(6);
+        assert_eq!(compressor.stream_stats(1), (0, 0));
+
+        let flat = vec![0x55u8; 4096];
+        compressor.compress_tight_stream(1, 6, &flat).unwrap();
+        let (flat_in, flat_out) = compressor.stream_stats(1);
+        assert_eq!(flat_in, 4096);
+        assert!(
+            flat_out * 20 < flat_in,
+            "flat data compresses well: {flat_out} bytes"
+        );
+
+        let noise = rgba_to_rgb24_bytes(&noise_frame(64, 64));
+        compressor.compress_tight_stream(1, 6, &noise).unwrap();
+        let (total_in, total_out) = compressor.stream_stats(1);
+        let (noise_in, noise_out) = (total_in - flat_in, total_out - flat_out);
+        assert_eq!(noise_in, 64 * 64 * 3);
+        assert!(
+            noise_out * 10 > noise_in * 9,
+            "noise barely compresses: {noise_out} bytes"
+        );
+
+        // Other streams are untouched
+        assert_eq!(compressor.stream_stats(0), (0, 0));
+        assert_eq!(compressor.stream_stats(7), (0, 0));
+    }
+
+    #[test]
+    fn test_contiguous_rects_are_borrowed_from_the_framebuffer() {
+        let data = noise_frame(64, 48);
+        let extract = |x, y, w, h| extract_rect_rgba(&data, 64, &Rect { x, y, w, h });
+
+        // Full-width rectangles and single rows are contiguous in the source
+        assert!(matches!(extract(0, 0, 64, 48), Cow::Borrowed(_)));
+        assert!(matches!(extract(0, 10, 64, 5), Cow::Borrowed(_)));
+        assert!(matches!(extract(8, 3, 20, 1), Cow::Borrowed(_)));
+
+        // A narrower rectangle is strided and is gathered row by row
+        let strided = extract(8, 0, 32, 48);
+        assert!(matches!(strided, Cow::Owned(_)));
+        let expected: Vec<u8> = (0..48)
+            .flat_map(|row| data[(row * 64 + 8) * 4..(row * 64 + 40) * 4].to_vec())
+            .collect();
+        assert_eq!(&strided[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_encode_region_matches_cropped_frame() {
+        let data: Vec<u8> = (0..48u8)
+            .flat_map(|y| (0..64u8).flat_map(move |x| [x * 4, y * 5, 7, 255]))
+            .collect();
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions::default();
+
+        let (x, y, w, h) = (16u16, 8u16, 32u16, 24u16);
+        let cropped = extract_rect_rgba(&data, 64, &Rect { x, y, w, h });
+        let mut compressor = SimpleTightCompressor::new(6);
+        let expected =
+            try_encode_tight_rects(&cropped, w, h, 10, 6, &pf, &options, &mut compressor).unwrap();
+
+        let mut compressor = SimpleTightCompressor::new(6);
+        let rects = encode_region(
+            &data,
+            64,
+            48,
+            (x, y, w, h),
+            10,
+            6,
+            &pf,
+            &options,
+            &mut compressor,
+        )
+        .unwrap();
+        assert_eq!(rects.len(), expected.len());
+        for (got, want) in rects.iter().zip(&expected) {
+            assert_eq!(
+                (got.0, got.1),
+                (want.0 + x, want.1 + y),
+                "framebuffer coordinates"
+            );
+            assert_eq!((got.2, got.3), (want.2, want.3));
+            assert_eq!(got.4, want.4);
+        }
+    }
+
+    #[test]
+    fn test_encode_region_rejects_out_of_bounds() {
+        let data = vec![0u8; 64 * 48 * 4];
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions::default();
+        let mut compressor = SimpleTightCompressor::new(6);
+        let mut encode = |data: &[u8], region| {
+            encode_region(data, 64, 48, region, 10, 6, &pf, &options, &mut compressor)
+        };
+
+        assert!(encode(&data, (0, 0, 64, 48)).is_ok());
+        assert!(
+            encode(&data, (40, 0, 32, 8)).is_err(),
+            "past the right edge"
+        );
+        assert!(
+            encode(&data, (0, 47, 8, 2)).is_err(),
+            "past the bottom edge"
+        );
+        assert!(
+            encode(&data, (u16::MAX, 0, 2, 2)).is_err(),
+            "x + width overflows u16"
+        );
+        assert!(
+            encode(&data[..data.len() - 4], (0, 0, 8, 8)).is_err(),
+            "framebuffer too small"
+        );
+    }
+
+    #[test]
+    fn test_truecolor_codec_auto_picks_png_for_flat_regions() {
+        // 64x48: left 32 columns noise, right 32 columns a vertical grey ramp
+        let mut data = noise_frame(64, 48);
+        for (i, pixel) in data.chunks_exact_mut(4).enumerate() {
+            if i % 64 >= 32 {
+                let grey = u8::try_from(i / 64 * 5).unwrap();
+                pixel.copy_from_slice(&[grey, grey, grey, 255]);
+            }
+        }
+        let pf = PixelFormat::rgba32();
+        let encode = |truecolor_codec| {
+            let options = TightOptions {
+                split_photo_regions: true,
+                truecolor_codec,
+                ..TightOptions::default()
+            };
+            let mut compressor = SimpleTightCompressor::new(6);
+            let rects = try_encode_tight_rects(&data, 64, 48, 5, 6, &pf, &options, &mut compressor)
+                .unwrap();
+            assert_eq!(rects.len(), 2);
+            assert_eq!((rects[0].0, rects[0].2), (0, 32), "photo region first");
+            (rects[0].4[0], rects[1].4[0])
+        };
+        #[cfg(feature = "turbojpeg")]
+        let jpeg = TIGHT_JPEG << 4;
+        #[cfg(not(feature = "turbojpeg"))]
+        let jpeg = STREAM_ID_FULL_COLOR << 4; // JPEG falls back to full color
+        let png = crate::TIGHT_PNG << 4;
+
+        // The 48-color ramp is truecolor but flat enough for PNG; the noise keeps JPEG
+        assert_eq!(encode(TruecolorCodec::Auto), (jpeg, png));
+        assert_eq!(encode(TruecolorCodec::Png), (png, png));
+        let (photo, flat) = encode(TruecolorCodec::Jpeg);
+        assert_eq!(photo, jpeg);
+        assert_ne!(flat, png);
+    }
+
+    #[test]
+    fn test_text_aware_keeps_text_like_truecolor_lossless() {
+        // 32x32 alternating light and dark columns, shaded per row: 64 colors, all hard edges
+        let data: Vec<u8> = (0..32u8)
+            .flat_map(|y| {
+                (0..32).flat_map(move |x| {
+                    if x % 2 == 0 {
+                        [255 - y, 250, 245, 255]
+                    } else {
+                        [y, 5, 10, 255]
+                    }
+                })
+            })
+            .collect();
+        let gradient: Vec<u8> = (0..32u8)
+            .flat_map(|y| (0..32u8).flat_map(move |x| [x * 8, y * 8, 0, 255]))
+            .collect();
+        assert!(is_text_like(&data, 32, 32));
+        assert!(!is_text_like(&gradient, 32, 32));
+        assert!(!is_text_like(&data[..4], 1, 1));
+
+        let pf = PixelFormat::rgba32();
+        let encode = |text_aware| {
+            let options = TightOptions {
+                text_aware,
+                ..TightOptions::default()
+            };
+            let mut compressor = SimpleTightCompressor::new(6);
+            let rects = try_encode_tight_rects(&data, 32, 32, 5, 6, &pf, &options, &mut compressor)
+                .unwrap();
+            assert_eq!(rects.len(), 1);
+            rects[0].4.clone()
+        };
+
+        let lossless = encode(true);
+        assert_eq!(lossless[0], STREAM_ID_FULL_COLOR << 4);
+        let pixels = crate::decode::TightDecoder::new()
+            .decode_rect(&lossless, 32, 32, &pf)
+            .unwrap();
+        for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+            assert_eq!(out[..3], input[..3]);
+        }
+        #[cfg(feature = "turbojpeg")]
+        assert_eq!(encode(false)[0], TIGHT_JPEG << 4);
+    }
+
+    #[test]
+    fn test_progressive_encoder_drafts_jpeg_then_refines_lossless() {
+        let data = noise_frame(32, 32);
+        let pf = PixelFormat::rgba32();
+        let mut compressor = SimpleTightCompressor::new(6);
+        let mut progressive = ProgressiveEncoder::new(9, 6);
+
+        let draft = progressive
+            .draft(&data, 32, 32, (0, 0, 32, 32), &pf, &mut compressor)
+            .unwrap();
+        assert_eq!(draft.len(), 1);
+        #[cfg(feature = "turbojpeg")]
+        {
+            assert_eq!(draft[0].4[0], TIGHT_JPEG << 4);
+            assert_eq!(progressive.pending(), &[(0, 0, 32, 32)]);
+        }
+        #[cfg(not(feature = "turbojpeg"))]
+        {
+            // The draft falls back to lossless full color, leaving nothing to refine
+            assert_eq!(draft[0].4[0], STREAM_ID_FULL_COLOR << 4);
+            assert!(progressive.pending().is_empty());
+            // Queue the region by hand so the refine pass is still exercised
+            progressive.pending.push((0, 0, 32, 32));
+        }
+
+        let refined = progressive
+            .refine(&data, 32, 32, &pf, &mut compressor)
+            .unwrap();
+        assert_eq!(refined.len(), 1);
+        let (x, y, w, h, ref buf) = refined[0];
+        assert_eq!((x, y, w, h), (0, 0, 32, 32));
+        assert_eq!(buf[0], STREAM_ID_FULL_COLOR << 4);
+        // A full-color draft shares zlib stream 0 with the refinement; JPEG does not
+        let mut decoder = crate::decode::TightDecoder::new();
+        if draft[0].4[0] != TIGHT_JPEG << 4 {
+            decoder.decode_rect(&draft[0].4, 32, 32, &pf).unwrap();
+        }
+        let pixels = decoder.decode_rect(buf, 32, 32, &pf).unwrap();
+        for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+            assert_eq!(out[..3], input[..3]);
+        }
+        assert!(progressive.pending().is_empty());
+        assert!(progressive
+            .refine(&data, 32, 32, &pf, &mut compressor)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_progressive_refine_keeps_the_callers_options() {
+        let data = noise_frame(32, 32);
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions {
+            explicit_copy_filter: true,
+            zlib_levels: TightZlibLevels {
+                full_color: Some(0),
+                ..TightZlibLevels::default()
+            },
+            ..TightOptions::default()
+        };
+        let mut progressive = ProgressiveEncoder::with_options(9, 6, options);
+        progressive.pending.push((0, 0, 32, 32));
+
+        let refined = progressive
+            .refine(&data, 32, 32, &pf, &mut SimpleTightCompressor::new(6))
+            .unwrap();
+        let buf = &refined[0].4;
+        assert_eq!(buf[0], (TIGHT_NO_ZLIB | TIGHT_EXPLICIT_FILTER) << 4);
+        assert_eq!(buf[1], TIGHT_FILTER_COPY);
+    }
+
+    #[test]
+    fn test_progressive_refine_checks_every_region_before_encoding() {
+        let data: Vec<u8> = (0..32 * 32)
+            .flat_map(|i: u32| {
+                let [_, _, hi, lo] = i.to_be_bytes();
+                [lo, hi.wrapping_mul(40), lo ^ 0x5A, 255]
+            })
+            .collect();
+        let pf = PixelFormat::rgba32();
+        let mut compressor = SimpleTightCompressor::new(6);
+        let mut progressive = ProgressiveEncoder::new(9, 6);
+        // The first region still fits a 32x16 framebuffer, the second does not
+        progressive.pending.push((0, 0, 16, 16));
+        progressive.pending.push((0, 16, 16, 16));
+
+        let err = progressive
+            .refine(&data[..32 * 16 * 4], 32, 16, &pf, &mut compressor)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(progressive.pending(), &[(0, 0, 16, 16), (0, 16, 16, 16)]);
+        for stream_id in 0..4 {
+            assert_eq!(
+                compressor.stream_stats(stream_id),
+                (0, 0),
+                "stream {stream_id} untouched"
+            );
+        }
+
+        assert_eq!(
+            progressive
+                .refine(&data, 32, 32, &pf, &mut compressor)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_split_photo_regions_half_photo_half_solid() {
+        // 64x48: left 32 columns noise, right 32 columns solid grey
+        let data = noise_frame_with_solid(64, 48, |x, _| x >= 32);
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions {
+            split_photo_regions: true,
+            ..TightOptions::default()
+        };
+        let mut compressor = SimpleTightCompressor::new(6);
+        let rects =
+            try_encode_tight_rects(&data, 64, 48, 5, 6, &pf, &options, &mut compressor).unwrap();
+
+        assert_eq!(rects.len(), 2);
+        let (photo, flat) = (&rects[0], &rects[1]);
+        assert_eq!((photo.0, photo.1, photo.2, photo.3), (0, 0, 32, 48));
+        assert_eq!((flat.0, flat.1, flat.2, flat.3), (32, 0, 32, 48));
+        assert_eq!(flat.4[0], TIGHT_FILL << 4);
+        #[cfg(feature = "turbojpeg")]
+        assert_eq!(photo.4[0], TIGHT_JPEG << 4);
+        #[cfg(not(feature = "turbojpeg"))]
+        assert_eq!(
+            photo.4[0],
+            STREAM_ID_FULL_COLOR << 4,
+            "JPEG falls back to full color"
+        );
+
+        // Without the option the rectangle is encoded whole
+        let whole = try_encode_tight_rects(
+            &data,
+            64,
+            48,
+            5,
+            6,
+            &pf,
+            &TightOptions::default(),
+            &mut compressor,
+        )
+        .unwrap();
+        assert_eq!(whole.len(), 1);
+    }
+
+    /// Reference implementation extending one row or column at a time.
+    #[allow(clippy::too_many_arguments)]
+    fn extend_solid_area_linear(
+        framebuffer: &[u8],
+        fb_width: u16,
+        base_x: u16,
+        base_y: u16,
+        max_w: u16,
+        max_h: u16,
+        color: u32,
+        (mut x, mut y, mut w, mut h): (u16, u16, u16, u16),
+    ) -> (u16, u16, u16, u16) {
+        let need = Some(color);
+        while y > base_y
+            && check_solid_tile(framebuffer, fb_width, x, y - 1, w, 1, need, false).is_some()
+        {
+            y -= 1;
+            h += 1;
+        }
+        while y + h < base_y + max_h
+            && check_solid_tile(framebuffer, fb_width, x, y + h, w, 1, need, false).is_some()
+        {
+            h += 1;
+        }
+        while x > base_x
+            && check_solid_tile(framebuffer, fb_width, x - 1, y, 1, h, need, false).is_some()
+        {
+            x -= 1;
+            w += 1;
+        }
+        while x + w < base_x + max_w
+            && check_solid_tile(framebuffer, fb_width, x + w, y, 1, h, need, false).is_some()
+        {
+            w += 1;
+        }
+        (x, y, w, h)
+    }
+
+    #[test]
+    fn test_extend_solid_area_tall_column() {
+        let fb: Vec<u8> = [10u8, 20, 30, 255].repeat(4096);
+        let color = rgba_to_rgb24(10, 20, 30);
+
+        let region = extend_solid_area(&fb, 1, 0, 0, 1, 4096, color, false, 0, 2000, 1, 16);
+        let expected = extend_solid_area_linear(&fb, 1, 0, 0, 1, 4096, color, (0, 2000, 1, 16));
+        assert_eq!(region, (0, 0, 1, 4096));
+        assert_eq!(region, expected);
+    }
+
+    #[test]
+    fn test_gallop_extend_checks_logarithmically() {
+        for boundary in [0, 1, 2, 3, 100, 1023, 1024, 1999, 2000] {
+            let mut checks = 0;
+            let extended = gallop_extend(2000, |ext, len| {
+                checks += 1;
+                ext + len <= boundary
+            });
+            assert_eq!(extended, boundary);
+            // Doubling up to 2000, then halving back down, instead of one per row
+            assert!(checks <= 32, "boundary {boundary}: {checks} checks");
+        }
+    }
+
+    #[test]
+    fn test_extend_solid_area_matches_linear() {
+        // 64x64 framebuffer: solid block with ragged edges in every direction
+        let (fb_w, fb_h) = (64u16, 64u16);
+        let mut fb = [0u8, 0, 200, 255].repeat(fb_w as usize * fb_h as usize);
+        for (px, py) in [(5u16, 40u16), (50, 3), (61, 22), (30, 60), (0, 0), (20, 9)] {
+            let offset = (py as usize * fb_w as usize + px as usize) * 4;
+            fb[offset] = 255;
+        }
+        let color = rgba_to_rgb24(0, 0, 200);
+
+        for start in [(24, 24, 8, 8), (10, 30, 4, 4), (40, 12, 16, 2)] {
+            let (x, y, w, h) = start;
+            let region = extend_solid_area(&fb, fb_w, 0, 0, fb_w, fb_h, color, false, x, y, w, h);
+            let expected = extend_solid_area_linear(&fb, fb_w, 0, 0, fb_w, fb_h, color, start);
+            assert_eq!(region, expected, "start {start:?}");
+        }
+    }
+
+    /// Opaque noise frame: too many colors for a palette and no solid areas
+    fn noise_frame(width: usize, height: usize) -> Vec<u8> {
+        noise_frame_with_solid(width, height, |_, _| false)
+    }
+
+    /// Noise frame with the pixels selected by `solid` set to one grey
+    fn noise_frame_with_solid(
+        width: usize,
+        height: usize,
+        solid: impl Fn(usize, usize) -> bool,
+    ) -> Vec<u8> {
+        let mut state = 0x1357_9BDFu32;
+        (0..width * height)
+            .flat_map(|i| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let [r, g, b, _] = state.to_be_bytes();
+                if solid(i % width, i / width) {
+                    [90, 90, 90, 255]
+                } else {
+                    [r, g, b, 255]
+                }
+            })
+            .collect()
+    }
+
+    /// Encode without JPEG, check the rectangles tile the frame exactly, and return them
+    #[allow(clippy::type_complexity)] // Same tuple shape as try_encode_tight_rects
+    fn encode_covering(
+        data: &[u8],
+        width: u16,
+        height: u16,
+    ) -> Vec<(u16, u16, u16, u16, BytesMut)> {
+        encode_covering_with(data, width, height, &TightOptions::default())
+    }
+
+    #[allow(clippy::type_complexity)] // Same tuple shape as try_encode_tight_rects
+    fn encode_covering_with(
+        data: &[u8],
+        width: u16,
+        height: u16,
+        options: &TightOptions,
+    ) -> Vec<(u16, u16, u16, u16, BytesMut)> {
+        let mut compressor = SimpleTightCompressor::new(6);
+        let rects = try_encode_tight_rects(
+            data,
+            width,
+            height,
+            10,
+            6,
+            &PixelFormat::rgba32(),
+            options,
+            &mut compressor,
+        )
+        .unwrap();
+        let mut covered = vec![0u8; width as usize * height as usize];
+        for &(x, y, w, h, _) in &rects {
+            assert!(w > 0 && h > 0, "empty rectangle at ({x}, {y})");
+            assert!(x + w <= width && y + h <= height, "rectangle exceeds frame");
+            for row in y..y + h {
+                for col in x..x + w {
+                    covered[row as usize * width as usize + col as usize] += 1;
+                }
+            }
+        }
+        assert!(
+            covered.iter().all(|&c| c == 1),
+            "rectangles must tile the frame"
+        );
+        rects
+    }
+
+    #[test]
+    fn test_solid_search_budget_caps_adversarial_scan() {
+        // 16x16 checkerboard of solid and noise tiles: every solid tile starts a
+        // search that is rejected as too small, so the budget only saves time
+        let (width, height) = (512u16, 512u16);
+        let data = noise_frame_with_solid(512, 512, |x, y| (x / 16 + y / 16) % 2 == 0);
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions {
+            max_solid_searches: Some(8),
+            ..TightOptions::default()
+        };
+        let rects = encode_covering_with(&data, width, height, &options);
+
+        let mut decoder = crate::decode::TightDecoder::new();
+        for (x, y, w, h, buf) in &rects {
+            let pixels = decoder.decode_rect(buf, *w, *h, &pf).unwrap();
+            let row_bytes = *w as usize * 4;
+            for (row, out_row) in pixels.chunks_exact(row_bytes).enumerate() {
+                let src = ((*y as usize + row) * width as usize + *x as usize) * 4;
+                let in_row = &data[src..src + row_bytes];
+                for (out, input) in out_row.chunks_exact(4).zip(in_row.chunks_exact(4)) {
+                    assert_eq!(out[..3], input[..3]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_solid_search_budget_stops_extraction() {
+        // 64 small solid tiles, each rejected as too small, above a large solid block
+        let data = noise_frame_with_solid(128, 512, |x, y| {
+            (y < 256 && (x / 16 + y / 16) % 2 == 0) || (320..448).contains(&y)
+        });
+        let fills = |max_solid_searches| {
+            let options = TightOptions {
+                max_solid_searches,
+                ..TightOptions::default()
+            };
+            encode_covering_with(&data, 128, 512, &options)
+                .iter()
+                .filter(|r| r.4[0] == TIGHT_FILL << 4)
+                .count()
+        };
+
+        assert_eq!(fills(None), 1);
+        // Out of budget, the scan encodes the rest of the rectangle as it is
+        assert_eq!(fills(Some(8)), 0);
+    }
+
+    #[test]
+    fn test_skip_solid_scan_relies_on_palette_analysis() {
+        // 256x512: solid rows 64-319 straddle the split into two 256-row rectangles
+        let data = noise_frame_with_solid(256, 512, |_, y| (64..320).contains(&y));
+        let pf = PixelFormat::rgba32();
+        let encode = |skip_solid_scan| {
+            let options = TightOptions {
+                skip_solid_scan,
+                ..TightOptions::default()
+            };
+            let mut compressor = SimpleTightCompressor::new(6);
+            try_encode_tight_rects(&data, 256, 512, 10, 6, &pf, &options, &mut compressor).unwrap()
+        };
+
+        let scanned = encode(false);
+        let solid = scanned.iter().find(|r| r.4[0] == TIGHT_FILL << 4).unwrap();
+        assert_eq!((solid.0, solid.1, solid.2, solid.3), (0, 64, 256, 256));
+
+        // Without the scan, neither half is solid on its own
+        let rects = encode(true);
+        let geometry: Vec<_> = rects.iter().map(|r| (r.0, r.1, r.2, r.3)).collect();
+        assert_eq!(geometry, [(0, 0, 256, 256), (0, 256, 256, 256)]);
+        assert!(rects.iter().all(|r| r.4[0] == STREAM_ID_FULL_COLOR << 4));
+
+        // A wholly solid rectangle is still sent as a fill by palette analysis
+        let data = noise_frame_with_solid(256, 512, |_, y| y < 256);
+        let rects = encode_covering_with(
+            &data,
+            256,
+            512,
+            &TightOptions {
+                skip_solid_scan: true,
+                ..TightOptions::default()
+            },
+        );
+        assert_eq!(rects[0].4[..], [TIGHT_FILL << 4, 90, 90, 90]);
+    }
+
+    #[test]
+    fn test_vectored_output_matches_concatenated() {
+        let data = noise_frame_with_solid(256, 512, |_, y| y < 256);
+        let pf = PixelFormat::rgba32();
+
+        let mut compressor = SimpleTightCompressor::new(6);
+        let parts = encode_tight_vectored(&data, 256, 512, 10, 6, &pf, &mut compressor);
+        let mut compressor = SimpleTightCompressor::new(6);
+        let joined = encode_tight_with_streams(&data, 256, 512, 10, 6, &pf, &mut compressor);
+
+        let geometry: Vec<_> = parts.iter().map(|&(x, y, w, h, _)| (x, y, w, h)).collect();
+        assert_eq!(geometry, [(0, 0, 256, 256), (0, 256, 256, 256)]);
+        let payloads: Vec<Bytes> = parts.into_iter().map(|part| part.4).collect();
+        assert_eq!(payloads.concat(), joined.to_vec());
+    }
+
+    #[test]
+    fn test_one_pixel_tall_solid_row() {
+        // 2048x17: noise above a solid bottom row, which leaves a 1-row tile band
+        let data = noise_frame_with_solid(2048, 17, |_, y| y == 16);
+        let rects = encode_covering(&data, 2048, 17);
+
+        let solid: Vec<_> = rects.iter().filter(|r| r.4[0] == TIGHT_FILL << 4).collect();
+        assert_eq!(solid.len(), 1);
+        assert_eq!(
+            (solid[0].0, solid[0].1, solid[0].2, solid[0].3),
+            (0, 16, 2048, 1)
+        );
+    }
+
+    #[test]
+    fn test_one_pixel_wide_solid_column() {
+        // 17x2048: noise left of a solid rightmost column, which leaves a 1-column tile
+        let data = noise_frame_with_solid(17, 2048, |x, _| x == 16);
+        let rects = encode_covering(&data, 17, 2048);
+
+        let solid: Vec<_> = rects.iter().filter(|r| r.4[0] == TIGHT_FILL << 4).collect();
+        assert_eq!(solid.len(), 1);
+        assert_eq!(
+            (solid[0].0, solid[0].1, solid[0].2, solid[0].3),
+            (16, 0, 1, 2048)
+        );
+    }
+
+    #[test]
+    fn test_multi_display_shared_and_separate_streams_decode() {
+        let (width, height) = (32u16, 32u16);
+        let pf = PixelFormat::rgba32();
+        let displays = [
+            (7u32, banded_frame(4, 32, 32)),
+            (9u32, banded_frame(12, 32, 32)),
+        ];
+        let region = (0, 0, width, height);
+
+        let mut outputs = Vec::new();
+        for sharing in [StreamSharing::Shared, StreamSharing::PerDisplay] {
+            let mut session = MultiDisplaySession::new(sharing, 6);
+            let mut shared_decoder = crate::decode::TightDecoder::new();
+            let mut decoders: HashMap<u32, crate::decode::TightDecoder> = HashMap::new();
+            let mut encoded = Vec::new();
+            // Alternate displays so each stream carries interleaved frames
+            for _ in 0..2 {
+                for (id, data) in &displays {
+                    let rects = session
+                        .encode_display(
+                            *id,
+                            data,
+                            width,
+                            height,
+                            region,
+                            10,
+                            &pf,
+                            &TightOptions::default(),
+                        )
+                        .unwrap();
+                    for (_, _, w, h, buf) in &rects {
+                        let decoder = match sharing {
+                            StreamSharing::Shared => &mut shared_decoder,
+                            StreamSharing::PerDisplay => decoders.entry(*id).or_default(),
+                        };
+                        let pixels = decoder.decode_rect(buf, *w, *h, &pf).unwrap();
+                        for (out, input) in pixels.chunks_exact(4).zip(data.chunks_exact(4)) {
+                            assert_eq!(out[..3], input[..3], "{sharing:?} display {id}");
+                        }
+                        encoded.push(buf.clone());
+                    }
+                }
+            }
+            outputs.push(encoded);
+        }
+
+        // The first rectangle starts a fresh stream either way; the second display's
+        // continues the first display's stream only when sharing
+        assert_eq!(outputs[0][0], outputs[1][0]);
+        assert_ne!(outputs[0][1], outputs[1][1]);
+    }
+
+    #[test]
+    fn test_rect_hashes_match_for_identical_input() {
+        let pf = PixelFormat::rgba32();
+        let encode = |data: &[u8]| {
+            try_encode_tight_rects_with_hashes(
+                data,
+                32,
+                32,
+                10,
+                6,
+                &pf,
+                &TightOptions::default(),
+                &mut SimpleTightCompressor::new(6),
+            )
+            .unwrap()
+        };
+        let first = encode(&banded_frame(4, 32, 32));
+        let again = encode(&banded_frame(4, 32, 32));
+        let other = encode(&banded_frame(5, 32, 32));
+
+        assert_eq!(first, again);
+        assert_ne!(first[0].5, other[0].5);
+        for (.., buf, hash) in &first {
+            assert_eq!(*hash, crate::output_hash(buf));
+        }
+    }
+
+    #[test]
+    fn test_subrects_stay_within_size_limits_on_large_frame() {
+        // Wider than two maximum-width rectangles, with a solid block to split around
+        let (width, height) = (4100u16, 200u16);
+        let data = noise_frame_with_solid(4100, 200, |x, y| {
+            (1000..2500).contains(&x) && (50..150).contains(&y)
+        });
+        let configs = [
+            (10, TightOptions::default()),
+            (
+                5,
+                TightOptions {
+                    split_photo_regions: true,
+                    ..TightOptions::default()
+                },
+            ),
+            (
+                10,
+                TightOptions {
+                    max_rect_bytes: Some(64 * 1024),
+                    ..TightOptions::default()
+                },
+            ),
+        ];
+
+        for (quality, options) in configs {
+            // encode_subrect_single debug-asserts the limits on every call
+            let rects = try_encode_tight_rects(
+                &data,
+                width,
+                height,
+                quality,
+                6,
+                &PixelFormat::rgba32(),
+                &options,
+                &mut SimpleTightCompressor::new(6),
+            )
+            .unwrap();
+            assert!(!rects.is_empty());
+            for (_, _, w, h, buf) in &rects {
+                if buf[0] != TIGHT_FILL << 4 {
+                    assert!(*w <= TIGHT_MAX_RECT_WIDTH);
+                    assert!(*w as usize * *h as usize <= TIGHT_MAX_RECT_SIZE);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_jpeg_boundary_at_quality_10() {
+        assert!(jpeg_enabled(9));
+        assert!(!jpeg_enabled(JPEG_DISABLED_QUALITY));
+        assert!(!jpeg_enabled(11));
+        assert_eq!(normalize_compression_level(6, 9), 2);
+        assert_eq!(normalize_compression_level(6, 10), 1);
+        assert_eq!(normalize_compression_level(6, 11), 1);
+
+        let data = noise_frame(64, 64);
+        let encode = |quality| {
+            try_encode_tight_rects(
+                &data,
+                64,
+                64,
+                quality,
+                6,
+                &PixelFormat::rgba32(),
+                &TightOptions::default(),
+                &mut SimpleTightCompressor::new(6),
+            )
+            .unwrap()
+        };
+        let has_jpeg = |rects: &[(u16, u16, u16, u16, BytesMut)]| {
+            rects.iter().any(|r| r.4[0] == TIGHT_JPEG << 4)
+        };
+        // Only 9 sends JPEG (when TurboJPEG is available); 11 is the same as 10
+        assert_eq!(has_jpeg(&encode(9)), cfg!(feature = "turbojpeg"));
+        assert!(!has_jpeg(&encode(10)));
+        assert_eq!(encode(11), encode(10));
+    }
+
+    #[test]
+    fn test_compressor_clamps_level_above_9() {
+        let data: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 61) as u8).collect();
+        let mut clamped = SimpleTightCompressor::new(15);
+        let mut best = SimpleTightCompressor::new(9);
+        for stream_id in 0..4 {
+            // The per-call level is clamped too
+            assert_eq!(
+                clamped.compress_tight_stream(stream_id, 15, &data).unwrap(),
+                best.compress_tight_stream(stream_id, 9, &data).unwrap()
+            );
+        }
+
+        let frame = noise_frame_with_solid(64, 64, |x, _| x < 32);
+        let encode = |compressor: &mut SimpleTightCompressor| {
+            try_encode_tight_rects(
+                &frame,
+                64,
+                64,
+                JPEG_DISABLED_QUALITY,
+                9,
+                &PixelFormat::rgba32(),
+                &TightOptions::default(),
+                compressor,
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            encode(&mut SimpleTightCompressor::new(15)),
+            encode(&mut SimpleTightCompressor::new(9))
+        );
+    }
+
+    #[test]
+    fn test_gradient_filter_uses_channel_precision() {
+        let pf = PixelFormat::rgb565();
+        let red = |r: u32| r << 11;
+        let original = [red(0), red(31), red(31), red(31), red(31), red(0)];
+        let mut values = original;
+        gradient_filter(&mut values, 3, &pf, false);
+        // At (1, 1) the prediction 31 + 31 - 0 clamps to the 5-bit max; at (2, 1) the
+        // residual 0 - 31 wraps modulo 32 to 1, not modulo 256 to 225
+        assert_eq!(values, [red(0), red(31), red(0), red(31), red(0), red(1)]);
+        gradient_filter(&mut values, 3, &pf, true);
+        assert_eq!(values, original);
+    }
+
+    #[test]
+    fn test_gradient_rects_round_trip_in_client_format() {
+        let options = TightOptions {
+            gradient_filter: true,
+            ..TightOptions::default()
+        };
+        // Smooth color ramps with noise: too many colors for a palette
+        let (width, height) = (40u16, 30u16);
+        let data: Vec<u8> = (0..usize::from(width) * usize::from(height))
+            .flat_map(|i| {
+                let (x, y) = (i % 40, i / 40);
+                let noise = (i * 7919 % 13).to_le_bytes()[0];
+                [
+                    (x * 6).to_le_bytes()[0].wrapping_add(noise),
+                    (y * 8).to_le_bytes()[0],
+                    ((x + y) * 3).to_le_bytes()[0] ^ noise,
+                    255,
+                ]
+            })
+            .collect();
+
+        for pf in [
+            PixelFormat::rgb565(),
+            PixelFormat::rgb555(),
+            PixelFormat::rgba32(),
+            PixelFormat::bgrx32(),
+            PixelFormat::xrgb32(),
+        ] {
+            let mut compressor = SimpleTightCompressor::new(6);
+            let mut decoder = crate::decode::TightDecoder::new();
+            let rects = try_encode_tight_rects(
+                &data,
+                width,
+                height,
+                JPEG_DISABLED_QUALITY,
+                6,
+                &pf,
+                &options,
+                &mut compressor,
+            )
+            .unwrap();
+            assert_eq!(rects.len(), 1);
+            let buf = &rects[0].4;
+            assert_eq!(buf[0], (STREAM_ID_FULL_COLOR | TIGHT_EXPLICIT_FILTER) << 4);
+            assert_eq!(buf[1], TIGHT_FILTER_GRADIENT);
+
+            let pixels = decoder.decode_rect(buf, width, height, &pf).unwrap();
+            let mut expected =
+                crate::translate::translate_pixels(&data, &PixelFormat::rgba32(), &pf).to_vec();
+            if pf.is_compatible_with_rgba32() {
+                // Only the RGB bytes are sent, so alpha decodes as padding
+                expected.iter_mut().skip(3).step_by(4).for_each(|a| *a = 0);
+            }
+            assert_eq!(pixels, expected, "{pf:?}");
+        }
+    }
+
+    #[cfg(feature = "tight-shared-palette")]
+    #[test]
+    fn test_shared_palette_saves_bytes_and_decodes() {
+        // 256x256 UI-like frame drawn from 16 colors, updated as sixteen 64x64 regions
+        let color = |i: usize| {
+            [
+                (i * 16).to_le_bytes()[0],
+                (255 - i * 16).to_le_bytes()[0],
+                (i * 37).to_le_bytes()[0],
+                255,
+            ]
+        };
+        let data: Vec<u8> = (0..256 * 256)
+            .flat_map(|i| {
+                let (x, y) = (i % 256, i / 256);
+                color(((x / 4 + y / 6) * 7 + (x * y) % 5) % 16)
+            })
+            .collect();
+        let regions: Vec<_> = (0..16u16)
+            .map(|i| (i % 4 * 64, i / 4 * 64, 64, 64))
+            .collect();
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions::default();
+
+        let mut compressor = SimpleTightCompressor::new(6);
+        let standard: usize = regions
+            .iter()
+            .map(|&region| {
+                encode_region(
+                    &data,
+                    256,
+                    256,
+                    region,
+                    JPEG_DISABLED_QUALITY,
+                    6,
+                    &pf,
+                    &options,
+                    &mut compressor,
+                )
+                .unwrap()
+                .iter()
+                .map(|rect| rect.4.len())
+                .sum::<usize>()
+            })
+            .sum();
+
+        let rects = encode_regions_with_shared_palette(
+            &data,
+            256,
+            256,
+            &regions,
+            JPEG_DISABLED_QUALITY,
+            6,
+            &pf,
+            &options,
+            &mut SimpleTightCompressor::new(6),
+        )
+        .unwrap();
+        assert_eq!(rects[0].4[0], TIGHT_SHARED_PALETTE << 4);
+        assert_eq!(rects[0].4[1], 15);
+        assert!(rects[1..]
+            .iter()
+            .all(|rect| rect.4[1] == TIGHT_FILTER_SHARED_PALETTE));
+        let shared: usize = rects.iter().map(|rect| rect.4.len()).sum();
+        assert!(
+            shared * 10 < standard * 9,
+            "shared {shared} bytes vs standard {standard}"
+        );
+
+        let mut decoder = crate::decode::TightDecoder::new();
+        let mut frame = vec![0u8; data.len()];
+        for (x, y, w, h, buf) in &rects {
+            assert_eq!(validate_framing(buf, *w, *h, &pf), Ok(()));
+            let pixels = decoder.decode_rect(buf, *w, *h, &pf).unwrap();
+            for row in 0..usize::from(*h) {
+                let start = ((usize::from(*y) + row) * 256 + usize::from(*x)) * 4;
+                frame[start..start + usize::from(*w) * 4]
+                    .copy_from_slice(&pixels[row * usize::from(*w) * 4..][..usize::from(*w) * 4]);
+            }
+        }
+        for (out, input) in frame.chunks_exact(4).zip(data.chunks_exact(4)) {
+            assert_eq!(out[..3], input[..3]);
+        }
+
+        // A palette reference needs a definition first
+        assert!(crate::decode::TightDecoder::new()
+            .decode_rect(&rects[1].4, rects[1].2, rects[1].3, &pf)
+            .is_err());
+    }
+
+    #[test]
+    fn test_short_input_is_rejected_cleanly() {
+        let pf = PixelFormat::rgba32();
+        let options = TightOptions::default();
+        for len in [0, 4, 16 * 16 * 4 - 1] {
+            let data = vec![0x80; len];
+            let err = try_encode_tight_rects(
+                &data,
+                16,
+                16,
+                JPEG_DISABLED_QUALITY,
+                6,
+                &pf,
+                &options,
+                &mut SimpleTightCompressor::new(6),
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().starts_with("Tight:"), "{err}");
+
+            // The trait sends the zero-filled pixels Raw, like a panic would
+            let raw = TightEncoding.encode(&data, 16, 16, 5, 6);
+            assert_eq!(raw.len(), 16 * 16 * 4);
+            assert!(raw
+                .chunks_exact(4)
+                .all(|p| p == [0x80, 0x80, 0x80, 0] || p == [0; 4]));
+
+            // The infallible wrappers return nothing rather than panicking
+            let mut compressor = SimpleTightCompressor::new(6);
+            assert!(encode_tight_rects(&data, 16, 16, 5, 6, &pf, &mut compressor).is_empty());
+            assert!(
+                encode_tight_with_streams(&data, 16, 16, 5, 6, &pf, &mut compressor).is_empty()
+            );
+            assert!(encode_tight_vectored(&data, 16, 16, 5, 6, &pf, &mut compressor).is_empty());
+        }
+
+        // A zero-sized rectangle needs no pixels
+        let rects = try_encode_tight_rects(
+            &[],
+            0,
+            0,
+            5,
+            6,
+            &pf,
+            &options,
+            &mut SimpleTightCompressor::new(6),
+        );
+        assert!(rects.is_ok());
+    }
+
+    #[test]
+    fn test_payload_has_length_by_control_type() {
+        let pf = PixelFormat::rgba32();
+        let rgb565 = PixelFormat::rgb565();
+        for resets in 0..0x10 {
+            // Solid fill: the TPIXEL follows the control byte directly
+            let fill = [(TIGHT_FILL << 4) | resets, 1, 2, 3];
+            assert_eq!(payload_has_length(&fill, 64, 64, &pf), Ok(false));
+            let jpeg = [(TIGHT_JPEG << 4) | resets, 0];
+            assert_eq!(payload_has_length(&jpeg, 64, 64, &pf), Ok(true));
+
+            // Basic full color: 2x2 RGB24 is 12 bytes and takes a length, 16-bit
+            // TPIXELs make it 8 bytes, sent raw
+            for stream in [0, 1, 2, 3, TIGHT_NO_ZLIB] {
+                let copy = [(stream << 4) | resets];
+                assert_eq!(payload_has_length(&copy, 2, 2, &pf), Ok(true));
+                assert_eq!(payload_has_length(&copy, 2, 2, &rgb565), Ok(false));
+                assert_eq!(payload_has_length(&copy, 1, 3, &pf), Ok(false));
+            }
+        }
+
+        // Palettes: 8 rows of 1-bit mono indices or 11 bytes of indices are raw
+        let mono = [
+            (STREAM_ID_MONO | TIGHT_EXPLICIT_FILTER) << 4,
+            TIGHT_FILTER_PALETTE,
+            1,
+        ];
+        let mono = [&mono[..], &[0; 6]].concat();
+        assert_eq!(payload_has_length(&mono, 8, 8, &pf), Ok(false));
+        assert_eq!(payload_has_length(&mono, 9, 8, &pf), Ok(true));
+        let indexed = [
+            (STREAM_ID_INDEXED | TIGHT_EXPLICIT_FILTER) << 4,
+            TIGHT_FILTER_PALETTE,
+            2,
+        ];
+        let indexed = [&indexed[..], &[0; 9]].concat();
+        assert_eq!(payload_has_length(&indexed, 11, 1, &pf), Ok(false));
+        assert_eq!(payload_has_length(&indexed, 12, 1, &pf), Ok(true));
+
+        // Agrees with the encoder: fills and small mono data carry no length
+        let mut compressor = SimpleTightCompressor::new(6);
+        for (data, (width, height), expected) in [
+            (banded_frame(1, 32, 32), (32, 32), false),
+            (banded_frame(2, 32, 32), (32, 32), true),
+            (banded_frame(2, 4, 2), (4, 2), false),
+            (noise_frame(32, 32), (32, 32), true),
+        ] {
+            let rects = try_encode_tight_rects(
+                &data,
+                width,
+                height,
+                JPEG_DISABLED_QUALITY,
+                6,
+                &pf,
+                &TightOptions::default(),
+                &mut compressor,
+            )
+            .unwrap();
+            let (_, _, w, h, buf) = &rects[0];
+            assert_eq!(rects.len(), 1);
+            assert_eq!(payload_has_length(buf, *w, *h, &pf), Ok(expected));
+            assert_eq!(validate_framing(buf, *w, *h, &pf), Ok(()));
+        }
+
+        assert_eq!(
+            payload_has_length(&[0xD0], 4, 4, &pf),
+            Err(FramingError::UnknownControl(0xD0))
+        );
+    }
+
+    #[test]
+    fn test_chunks_respect_limits_around_max_rect_width() {
+        for width in [2047u16, 2048, 2049] {
+            // Tall enough for several 32-row chunks, with a solid band the scan splits around
+            let data = noise_frame_with_solid(width as usize, 300, |x, y| {
+                (100..1900).contains(&x) && (130..170).contains(&y)
+            });
+            for options in [
+                TightOptions::default(),
+                TightOptions {
+                    skip_solid_scan: true,
+                    ..TightOptions::default()
+                },
+            ] {
+                for (x, y, w, h, buf) in encode_covering_with(&data, width, 300, &options) {
+                    if buf[0] != TIGHT_FILL << 4 {
+                        assert!(
+                            w <= TIGHT_MAX_RECT_WIDTH
+                                && w as usize * h as usize <= TIGHT_MAX_RECT_SIZE,
+                            "{w}x{h} chunk at ({x}, {y}) of a {width}-wide frame"
+                        );
+                    }
+                }
+            }
+        }
+
+        // One pixel wide: 65536 rows per chunk must not truncate to empty chunks
+        let column = noise_frame_with_solid(1, 20_000, |_, y| y >= 10_000);
+        encode_covering(&column, 1, 20_000);
+    }
+
+    #[test]
+    fn test_lowered_limits_bound_every_rectangle() {
+        // A solid band wider than the width limit, in noise
+        let data = noise_frame_with_solid(1500, 120, |x, y| {
+            (100..1400).contains(&x) && (30..90).contains(&y)
+        });
+        let limits = TightLimits {
+            max_rect_width: 512,
+            max_rect_size: 8192,
+            ..TightLimits::default()
+        };
+        let options = TightOptions {
+            limits,
+            ..TightOptions::default()
+        };
+        let rects = encode_covering_with(&data, 1500, 120, &options);
+        let mut fills = 0;
+        for (x, y, w, h, buf) in &rects {
+            assert!(*w <= 512, "{w}x{h} rectangle at ({x}, {y})");
+            if buf[0] == TIGHT_FILL << 4 {
+                fills += 1;
+            } else {
+                assert!(*w as usize * *h as usize <= 8192, "{w}x{h} at ({x}, {y})");
+            }
+        }
+        // The 1300-pixel band is sent as three fills
+        assert_eq!(fills, 3);
+
+        // Solid areas below the minimum size are not extracted: chunks inside the band
+        // are still sent as fills, but no larger than the pixel limit
+        let no_solid = TightOptions {
+            limits: TightLimits {
+                min_solid_subrect_size: 1300 * 60 + 1,
+                ..limits
+            },
+            ..TightOptions::default()
+        };
+        let rects = encode_covering_with(&data, 1500, 120, &no_solid);
+        assert!(rects
+            .iter()
+            .all(|rect| rect.2 as usize * rect.3 as usize <= 8192));
+
+        // A zero width limit sends 1-pixel-wide rectangles instead of hanging
+        let one_wide = TightOptions {
+            limits: TightLimits {
+                max_rect_width: 0,
+                max_rect_size: 0,
+                ..TightLimits::default()
+            },
+            ..TightOptions::default()
+        };
+        let rects = encode_covering_with(&data[..8 * 8 * 4], 8, 8, &one_wide);
+        assert!(rects.iter().all(|rect| rect.2 == 1));
+    }
+
+    #[test]
+    fn test_shifted_32bpp_formats_decode_fill_and_full_color_alike() {
+        // Solid left half next to noise: one fill and one full-color rectangle
+        let (width, height) = (128u16, 64u16);
+        let data = noise_frame_with_solid(128, 64, |x, _| x < 64);
+        for pf in [PixelFormat::bgrx32(), PixelFormat::xrgb32()] {
+            let rects = try_encode_tight_rects(
+                &data,
+                width,
+                height,
+                JPEG_DISABLED_QUALITY,
+                6,
+                &pf,
+                &TightOptions::default(),
+                &mut SimpleTightCompressor::new(6),
+            )
+            .unwrap();
+            let modes: Vec<u8> = rects.iter().map(|rect| rect.4[0] >> 4).collect();
+            assert!(modes.contains(&TIGHT_FILL), "{pf:?}");
+            assert!(modes.contains(&STREAM_ID_FULL_COLOR), "{pf:?}");
+
+            let expected =
+                crate::translate::translate_pixels(&data, &PixelFormat::rgba32(), &pf).to_vec();
+            let mut tight_decoder = crate::decode::TightDecoder::new();
+            let mut decoded = vec![0u8; expected.len()];
+            for (x, y, w, h, buf) in &rects {
+                let pixels = tight_decoder.decode_rect(buf, *w, *h, &pf).unwrap();
+                for (row, line) in pixels.chunks_exact(usize::from(*w) * 4).enumerate() {
+                    let start = ((usize::from(*y) + row) * 128 + usize::from(*x)) * 4;
+                    decoded[start..start + line.len()].copy_from_slice(line);
+                }
+            }
+            assert_eq!(decoded, expected, "{pf:?}");
+        }
+    }
+
+    #[test]
+    fn test_stats_classify_rectangles_by_control_byte() {
+        let pf = PixelFormat::rgba32();
+        let noise = noise_frame(32, 32);
+        let modes_of = |data: &[u8], options: TightOptions| {
+            let mut modes = SubEncodingHistogram::default();
+            try_encode_tight_rects_with_stats(
+                data,
+                32,
+                32,
+                10,
+                6,
+                &pf,
+                &options,
+                &mut SimpleTightCompressor::new(6),
+                &mut modes,
+            )
+            .unwrap();
+            modes
+        };
+
+        let solid = modes_of(&[[7, 7, 7, 255]; 32 * 32].concat(), TightOptions::default());
+        assert_eq!((solid.solid, solid.total()), (1, 1));
+        let indexed = TightOptions {
+            indexed_palette: true,
+            ..TightOptions::default()
+        };
+        let palette = modes_of(&banded_frame(4, 32, 32), indexed);
+        assert_eq!((palette.packed_palette, palette.total()), (1, 1));
+        // Without the option, more than two colors are sent as full color
+        let full_color = modes_of(&banded_frame(4, 32, 32), TightOptions::default());
+        assert_eq!((full_color.raw, full_color.total()), (1, 1));
+        let png = TightOptions {
+            truecolor_codec: TruecolorCodec::Png,
+            ..TightOptions::default()
+        };
+        assert_eq!(modes_of(&noise, png).png, 1);
+        // Uncompressed full color shares the PNG control type but is not PNG
+        let uncompressed = TightOptions {
+            zlib_levels: TightZlibLevels {
+                full_color: Some(0),
+                ..TightZlibLevels::default()
+            },
+            ..TightOptions::default()
+        };
+        let rects = try_encode_tight_rects(
+            &noise,
+            32,
+            32,
+            10,
+            6,
+            &pf,
+            &uncompressed,
+            &mut SimpleTightCompressor::new(6),
+        )
+        .unwrap();
+        assert_eq!(rects[0].4[0], crate::TIGHT_PNG << 4);
+        let raw = modes_of(&noise, uncompressed);
+        assert_eq!((raw.raw, raw.png), (1, 0));
+    }
+
+    #[test]
+    fn test_stats_count_every_rectangle() {
+        let pf = PixelFormat::rgba32();
+        // Too many colors for a palette and no JPEG: full color with the copy filter
+        let noise = noise_frame(64, 64);
+        let mut modes = SubEncodingHistogram::default();
+        let rects = try_encode_tight_rects_with_stats(
+            &noise,
+            64,
+            64,
+            10,
+            6,
+            &pf,
+            &TightOptions::default(),
+            &mut SimpleTightCompressor::new(6),
+            &mut modes,
+        )
+        .unwrap();
+        assert_eq!(modes.total(), rects.len());
+        assert_eq!(modes.raw, rects.len());
+        assert_eq!(modes.entries().iter().filter(|&&(_, n)| n > 0).count(), 1);
+    }
+
+    #[test]
+    fn test_validate_framing_checks_declared_lengths() {
+        let pf = PixelFormat::rgba32();
+        let noise = noise_frame(32, 32);
+        let uncompressed = TightZlibLevels {
+            mono: Some(0),
+            indexed: Some(0),
+            full_color: Some(0),
+        };
+        let cases = [
+            (banded_frame(1, 32, 32), (32, 32), TightOptions::default()),
+            (banded_frame(2, 32, 32), (32, 32), TightOptions::default()),
+            (
+                banded_frame(5, 32, 32),
+                (32, 32),
+                TightOptions {
+                    indexed_palette: true,
+                    ..TightOptions::default()
+                },
+            ),
+            // 6 bytes of color, sent without a length
+            (
+                [palette_color(0), palette_color(1)].concat(),
+                (2, 1),
+                TightOptions::default(),
+            ),
+            (noise.clone(), (32, 32), TightOptions::default()),
+            (
+                noise.clone(),
+                (32, 32),
+                TightOptions {
+                    gradient_filter: true,
+                    ..TightOptions::default()
+                },
+            ),
+            (
+                noise.clone(),
+                (32, 32),
+                TightOptions {
+                    truecolor_codec: TruecolorCodec::Png,
+                    ..TightOptions::default()
+                },
+            ),
+            (
+                banded_frame(5, 32, 32),
+                (32, 32),
+                TightOptions {
+                    zlib_levels: uncompressed,
+                    indexed_palette: true,
+                    ..TightOptions::default()
+                },
+            ),
+            (
+                noise,
+                (32, 32),
+                TightOptions {
+                    zlib_levels: uncompressed,
+                    ..TightOptions::default()
+                },
+            ),
+        ];
+
+        let mut controls = Vec::new();
+        for (data, (width, height), options) in cases {
+            let rects = try_encode_tight_rects(
+                &data,
+                width,
+                height,
+                JPEG_DISABLED_QUALITY,
+                6,
+                &pf,
+                &options,
+                &mut SimpleTightCompressor::new(6),
+            )
+            .unwrap();
+            for (_, _, w, h, buf) in rects {
+                controls.push(buf[0]);
+                assert_eq!(validate_framing(&buf, w, h, &pf), Ok(()));
+                let len = buf.len();
+                assert_eq!(
+                    validate_framing(&buf[..len - 1], w, h, &pf),
+                    Err(FramingError::Truncated {
+                        expected: len,
+                        actual: len - 1
+                    })
+                );
+                let mut padded = buf.to_vec();
+                padded.push(0);
+                assert_eq!(
+                    validate_framing(&padded, w, h, &pf),
+                    Err(FramingError::TrailingBytes {
+                        expected: len,
+                        actual: len + 1
+                    })
+                );
+            }
+        }
+        // Fill, mono, indexed, copy, gradient, PNG and both uncompressed forms
+        controls.sort_unstable();
+        controls.dedup();
+        assert_eq!(controls, [0x00, 0x40, 0x50, 0x60, 0x80, 0xa0, 0xe0]);
+    }
+
+    #[test]
+    fn test_validate_framing_rejects_malformed_headers() {
+        let pf = PixelFormat::rgba32();
+        assert_eq!(
+            validate_framing(&[], 4, 4, &pf),
+            Err(FramingError::Truncated {
+                expected: 1,
+                actual: 0
+            })
+        );
+        assert_eq!(
+            validate_framing(&[0xD0, 0], 4, 4, &pf),
+            Err(FramingError::UnknownControl(0xD0))
+        );
+        assert_eq!(
+            validate_framing(
+                &[(STREAM_ID_FULL_COLOR | TIGHT_EXPLICIT_FILTER) << 4, 7],
+                4,
+                4,
+                &pf
+            ),
+            Err(FramingError::UnknownFilter(7))
+        );
+        // A length byte with its continuation bit set needs another byte
+        assert_eq!(
+            validate_framing(&[TIGHT_JPEG << 4, 0x80], 4, 4, &pf),
+            Err(FramingError::Truncated {
+                expected: 3,
+                actual: 2
+            })
+        );
     }
-    output
 }
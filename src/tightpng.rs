@@ -50,9 +50,15 @@ impl Encoding for TightPngEncoding {
 
 /// Encode as `TightPng` using PNG compression.
 ///
-/// This is the only compression mode used by `TightPng` encoding.
+/// This is the only compression mode used by `TightPng` encoding. Tight also uses it
+/// for truecolor rectangles when [`crate::tight::TruecolorCodec`] selects PNG.
 #[allow(clippy::cast_possible_truncation)] // TightPng compact length encoding uses variable-length u8 packing per RFC 6143
-fn encode_tightpng_png(data: &[u8], width: u16, height: u16, compression: u8) -> BytesMut {
+pub(crate) fn encode_tightpng_png(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    compression: u8,
+) -> BytesMut {
     use png::{BitDepth, ColorType, Encoder};
 
     // Convert RGBA to RGB (PNG encoder will handle this)
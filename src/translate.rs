@@ -36,6 +36,7 @@
 
 use crate::PixelFormat;
 use bytes::BytesMut;
+use std::io;
 
 /// Translates pixel data from server format (RGBA32) to client's requested format.
 ///
@@ -88,6 +89,94 @@ pub fn translate_pixels(
     dst
 }
 
+/// 4x4 ordered dither thresholds (0-15) used by [`reduce_to_16bpp_dithered`].
+const BAYER_4X4: [[u16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Reduces RGBA32 pixels to a 16bpp true-colour format such as RGB565, for serving
+/// a lower depth than the client asked for to save bandwidth.
+///
+/// The result has 2 bytes per pixel in `target`'s layout and endianness, ready for
+/// the Raw or ZRLE encoders with `target` as the client format. Channels are
+/// truncated to the target depth; see [`reduce_to_16bpp_dithered`] to trade the
+/// resulting banding for noise.
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidInput`] if `target` is not a valid 16bpp
+/// true-colour format or `rgba` is not a whole number of pixels.
+pub fn reduce_to_16bpp(rgba: &[u8], target: &PixelFormat) -> io::Result<Vec<u8>> {
+    check_16bpp_target(rgba, target)?;
+    Ok(translate_pixels(rgba, &PixelFormat::rgba32(), target).to_vec())
+}
+
+/// Same as [`reduce_to_16bpp`], with a 4x4 ordered (Bayer) dither applied before
+/// truncation, so smooth gradients keep their average colour instead of banding.
+///
+/// `width` is the row length in pixels, which positions the dither pattern.
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidInput`] if `target` is not a valid 16bpp
+/// true-colour format, `width` is 0, or `rgba` is not a whole number of rows.
+pub fn reduce_to_16bpp_dithered(
+    rgba: &[u8],
+    width: usize,
+    target: &PixelFormat,
+) -> io::Result<Vec<u8>> {
+    check_16bpp_target(rgba, target)?;
+    if width == 0 || !rgba.len().is_multiple_of(width * 4) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "reduce_to_16bpp: {} bytes is not a whole number of {width}-pixel rows",
+                rgba.len()
+            ),
+        ));
+    }
+
+    // Offset within one target step, in 8-bit units, for a threshold of 0-15
+    let dither = |value: u8, threshold: u16, max: u16| {
+        let offset = u32::from(threshold) * 255 / (16 * u32::from(max.max(1)));
+        value.saturating_add(offset as u8)
+    };
+
+    let mut dst = BytesMut::with_capacity(rgba.len() / 2);
+    for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+        let threshold = BAYER_4X4[(i / width) % 4][(i % width) % 4];
+        pack_pixel(
+            &mut dst,
+            dither(pixel[0], threshold, target.red_max),
+            dither(pixel[1], threshold, target.green_max),
+            dither(pixel[2], threshold, target.blue_max),
+            target,
+        );
+    }
+    Ok(dst.to_vec())
+}
+
+/// Validates the arguments shared by the `reduce_to_16bpp` functions.
+fn check_16bpp_target(rgba: &[u8], target: &PixelFormat) -> io::Result<()> {
+    if target.bits_per_pixel != 16 || target.true_colour_flag == 0 || !target.is_valid() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "reduce_to_16bpp: target must be a 16bpp true-colour format, got {}bpp depth {}",
+                target.bits_per_pixel, target.depth
+            ),
+        ));
+    }
+    if !rgba.len().is_multiple_of(4) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "reduce_to_16bpp: {} bytes is not a whole number of RGBA pixels",
+                rgba.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
 /// Extracts RGB components from a pixel in the given format.
 ///
 /// # Arguments
@@ -98,7 +187,7 @@ pub fn translate_pixels(
 /// # Returns
 ///
 /// A tuple `(r, g, b)` with each component as a u8 value (0-255).
-fn extract_rgb(pixel: &[u8], format: &PixelFormat) -> (u8, u8, u8) {
+pub(crate) fn extract_rgb(pixel: &[u8], format: &PixelFormat) -> (u8, u8, u8) {
     // Read pixel value based on bitsPerPixel
     let pixel_value = match format.bits_per_pixel {
         8 => u32::from(pixel[0]),
@@ -268,7 +357,7 @@ fn pixel_formats_equal(a: &PixelFormat, b: &PixelFormat) -> bool {
         && a.depth == b.depth
         && (a.big_endian_flag == b.big_endian_flag || a.bits_per_pixel == 8)
         && a.true_colour_flag == b.true_colour_flag
-        && (!a.true_colour_flag != 0
+        && (a.true_colour_flag == 0
             || (a.red_max == b.red_max
                 && a.green_max == b.green_max
                 && a.blue_max == b.blue_max
@@ -292,6 +381,20 @@ mod tests {
         assert_eq!(&src[..], &dst[..]);
     }
 
+    #[test]
+    fn test_shifts_alone_require_translation() {
+        // Same depth and maxima as RGBA32, channels at other shifts
+        let src = [0x11u8, 0x22, 0x33, 0xFF];
+        let rgba = PixelFormat::rgba32();
+        for (format, expected) in [
+            (PixelFormat::bgrx32(), [0x33, 0x22, 0x11, 0x00]),
+            (PixelFormat::xrgb32(), [0x00, 0x11, 0x22, 0x33]),
+            (PixelFormat::abgr32(), [0x00, 0x33, 0x22, 0x11]),
+        ] {
+            assert_eq!(&translate_pixels(&src, &rgba, &format)[..], &expected);
+        }
+    }
+
     #[test]
     fn test_rgba32_to_rgb565() {
         let server_format = PixelFormat::rgba32();
@@ -320,6 +423,87 @@ mod tests {
         assert_eq!(value, 0xF800);
     }
 
+    /// Horizontal red, vertical green and diagonal blue ramps over a 64x64 frame
+    fn gradient_64x64() -> Vec<u8> {
+        (0..64u8)
+            .flat_map(|y| (0..64u8).flat_map(move |x| [x * 4, y * 4, (x + y) * 2, 255]))
+            .collect()
+    }
+
+    #[test]
+    fn test_reduce_to_16bpp_rgb565_gradient() {
+        let rgba = gradient_64x64();
+        let target = PixelFormat::rgb565();
+        let reduced = reduce_to_16bpp(&rgba, &target).unwrap();
+        assert_eq!(reduced.len(), 64 * 64 * 2);
+
+        for (pixel, out) in rgba.chunks_exact(4).zip(reduced.chunks_exact(2)) {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(u16::from);
+            let expected = ((r * 31 / 255) << 11) | ((g * 63 / 255) << 5) | (b * 31 / 255);
+            assert_eq!(u16::from_le_bytes([out[0], out[1]]), expected);
+        }
+    }
+
+    #[cfg(feature = "zrle")]
+    #[test]
+    fn test_reduce_to_16bpp_feeds_zrle() {
+        let rgba = gradient_64x64();
+        let target = PixelFormat::rgb565();
+        let reduced = reduce_to_16bpp(&rgba, &target).unwrap();
+
+        let encoded = crate::zrle::encode_zrle(&reduced, 64, 64, &target, 6).unwrap();
+        let decoded = crate::decode::decode_zrle(
+            &encoded,
+            64,
+            64,
+            &target,
+            &mut flate2::Decompress::new(true),
+        )
+        .unwrap();
+        assert_eq!(decoded, reduced);
+    }
+
+    #[test]
+    fn test_reduce_to_16bpp_rejects_other_targets() {
+        let rgba = gradient_64x64();
+        let color_mapped = PixelFormat {
+            true_colour_flag: 0,
+            ..PixelFormat::rgb565()
+        };
+        for target in [PixelFormat::rgba32(), PixelFormat::bgr233(), color_mapped] {
+            let err = reduce_to_16bpp(&rgba, &target).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(reduce_to_16bpp(&rgba, &PixelFormat::rgb555()).is_ok());
+        assert!(reduce_to_16bpp(&rgba[..6], &PixelFormat::rgb565()).is_err());
+        assert!(reduce_to_16bpp_dithered(&rgba, 0, &PixelFormat::rgb565()).is_err());
+        assert!(reduce_to_16bpp_dithered(&rgba, 48, &PixelFormat::rgb565()).is_err());
+    }
+
+    #[test]
+    fn test_reduce_to_16bpp_dithered_keeps_average_level() {
+        // Red 4 is half a 5-bit step: truncation loses it, dithering spreads it
+        let rgba = [4u8, 0, 0, 255].repeat(16 * 16);
+        let target = PixelFormat::rgb565();
+        let red = |reduced: &[u8]| -> Vec<u16> {
+            reduced
+                .chunks_exact(2)
+                .map(|p| u16::from_le_bytes([p[0], p[1]]) >> 11)
+                .collect()
+        };
+
+        let plain = red(&reduce_to_16bpp(&rgba, &target).unwrap());
+        assert!(plain.iter().all(|&r| r == 0));
+
+        let dithered = red(&reduce_to_16bpp_dithered(&rgba, 16, &target).unwrap());
+        let mean = f64::from(dithered.iter().sum::<u16>()) / 256.0 * 255.0 / 31.0;
+        assert!((mean - 4.0).abs() < 1.0, "mean red {mean}");
+
+        // Full-scale channels never overflow the target range
+        let white = reduce_to_16bpp_dithered(&[255u8; 64], 4, &target).unwrap();
+        assert!(white.chunks_exact(2).all(|p| p == [0xFF, 0xFF]));
+    }
+
     #[test]
     fn test_extract_rgb_rgba32() {
         let format = PixelFormat::rgba32();
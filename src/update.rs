@@ -0,0 +1,467 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for assembling `FramebufferUpdate` messages.
+//!
+//! # `LastRect`
+//!
+//! A `FramebufferUpdate` normally announces its rectangle count up front. When the
+//! count is not known in advance (e.g. Tight may split a region into a variable
+//! number of sub-rectangles), a server that negotiated the `LastRect`
+//! pseudo-encoding (-224) can instead send a count of `0xFFFF`, write rectangles as
+//! they are produced, and finish with the header from [`encode_last_rect`]:
+//!
+//! ```text
+//! [0 (message type)][padding][0xFFFF]
+//! [rect header][payload] ... [rect header][payload]
+//! [encode_last_rect()]
+//! ```
+//!
+//! # Merging updates
+//!
+//! [`merge_updates`] combines rectangle lists from several encode passes into one
+//! update, dropping rectangles that a later one completely repaints when that is
+//! safe for the connection's compression streams and any `CopyRect` sources.
+//!
+//! # Building an update
+//!
+//! [`FramebufferUpdateBuilder`] collects rectangles of any mix of encodings and
+//! writes the complete message, including the rectangle count or the `LastRect`
+//! terminator.
+
+use crate::{
+    ENCODING_COPYRECT, ENCODING_LAST_RECT, ENCODING_TIGHT, ENCODING_ZLIB, ENCODING_ZLIBHEX,
+    ENCODING_ZRLE, ENCODING_ZYWRLE,
+};
+use bytes::{BufMut, BytesMut};
+use std::io;
+
+/// Rectangle count announcing that the update is terminated by a `LastRect` rectangle.
+pub const LAST_RECT_COUNT: u16 = 0xFFFF;
+
+/// Encodes the `LastRect` rectangle header that terminates a `FramebufferUpdate`
+/// sent with a rectangle count of [`LAST_RECT_COUNT`].
+///
+/// The header has zero position and size, encoding type -224 and no payload.
+///
+/// # Returns
+///
+/// 12 bytes: x, y, width, height (all 0, big-endian u16) + encoding type (big-endian i32)
+#[must_use]
+pub fn encode_last_rect() -> BytesMut {
+    let mut buf = BytesMut::with_capacity(12);
+    buf.put_u16(0); // x
+    buf.put_u16(0); // y
+    buf.put_u16(0); // width
+    buf.put_u16(0); // height
+    buf.put_i32(ENCODING_LAST_RECT);
+    buf
+}
+
+/// One encoded rectangle of a `FramebufferUpdate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedRect {
+    /// Left edge in framebuffer coordinates.
+    pub x: u16,
+    /// Top edge in framebuffer coordinates.
+    pub y: u16,
+    /// Width in pixels.
+    pub width: u16,
+    /// Height in pixels.
+    pub height: u16,
+    /// Encoding type sent in the rectangle header (e.g. [`crate::ENCODING_ZRLE`]).
+    pub encoding: i32,
+    /// Encoded payload following the rectangle header.
+    pub data: BytesMut,
+}
+
+impl EncodedRect {
+    /// Whether this rectangle covers every pixel of `other`.
+    fn contains(&self, other: &EncodedRect) -> bool {
+        let right = |r: &EncodedRect| u32::from(r.x) + u32::from(r.width);
+        let bottom = |r: &EncodedRect| u32::from(r.y) + u32::from(r.height);
+        self.x <= other.x
+            && self.y <= other.y
+            && right(self) >= right(other)
+            && bottom(self) >= bottom(other)
+    }
+
+    /// Whether this rectangle repaints its whole area without reading the framebuffer.
+    /// `CopyRect` reads other pixels and pseudo-encodings paint nothing.
+    fn is_opaque(&self) -> bool {
+        self.encoding >= 0 && self.encoding != ENCODING_COPYRECT
+    }
+
+    /// Whether this rectangle's payload is part of a zlib stream that persists across
+    /// rectangles. Dropping such a rectangle would desync the client's inflater for
+    /// every later rectangle of the same encoding.
+    fn uses_shared_stream(&self) -> bool {
+        matches!(
+            self.encoding,
+            ENCODING_ZLIB | ENCODING_TIGHT | ENCODING_ZLIBHEX | ENCODING_ZRLE | ENCODING_ZYWRLE
+        )
+    }
+
+    /// Whether this rectangle may be dropped when a later one covers it.
+    fn is_droppable(&self) -> bool {
+        self.encoding >= 0 && !self.uses_shared_stream()
+    }
+}
+
+/// Merges rectangle lists from several encode passes into one update.
+///
+/// Rectangles keep their order, list by list, since clients apply them in sequence
+/// and the last writer wins for any pixel. An earlier rectangle is dropped when a
+/// later one covers it completely, as its pixels would be stale on arrival. Only
+/// pixel-painting rectangles supersede others, and some rectangles are never dropped:
+///
+/// - pseudo-encodings (negative types) paint nothing and are always kept
+/// - Zlib, Tight, `ZlibHex`, ZRLE and ZYWRLE payloads continue a zlib stream that
+///   persists across rectangles, so the client needs every one of them to stay in
+///   sync
+/// - `CopyRect` is a barrier: it may read any pixel painted before it, so nothing
+///   before a `CopyRect` is superseded by a rectangle after it
+#[must_use]
+pub fn merge_updates(lists: Vec<Vec<EncodedRect>>) -> Vec<EncodedRect> {
+    let rects: Vec<EncodedRect> = lists.into_iter().flatten().collect();
+
+    // Walk backwards so each rectangle is only checked against later opaque ones
+    let mut keep = vec![true; rects.len()];
+    let mut covering: Vec<&EncodedRect> = Vec::new();
+    for (i, rect) in rects.iter().enumerate().rev() {
+        if rect.encoding == ENCODING_COPYRECT {
+            covering.clear();
+        } else if rect.is_droppable() && covering.iter().any(|later| later.contains(rect)) {
+            keep[i] = false;
+        } else if rect.is_opaque() {
+            covering.push(rect);
+        }
+    }
+
+    rects
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(rect, keep)| keep.then_some(rect))
+        .collect()
+}
+
+/// Assembles a `FramebufferUpdate` message from rectangles pushed one at a time.
+///
+/// By default the message announces its rectangle count, which allows at most
+/// `0xFFFE` rectangles since `0xFFFF` is reserved for [`LAST_RECT_COUNT`]. After
+/// [`FramebufferUpdateBuilder::with_last_rect`] the count is `0xFFFF` and the message
+/// ends with [`encode_last_rect`], which lifts the limit but requires a client that
+/// negotiated the `LastRect` pseudo-encoding.
+#[derive(Debug, Clone, Default)]
+pub struct FramebufferUpdateBuilder {
+    rects: BytesMut,
+    count: usize,
+    last_rect: bool,
+}
+
+impl FramebufferUpdateBuilder {
+    /// Creates an empty update that announces its rectangle count.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Terminates the update with a `LastRect` rectangle instead of counting rectangles.
+    #[must_use]
+    pub fn with_last_rect(mut self) -> Self {
+        self.last_rect = true;
+        self
+    }
+
+    /// Number of rectangles pushed so far, not counting the `LastRect` terminator.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Whether no rectangles have been pushed yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Appends a rectangle header and its encoded payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rectangle extends past coordinate 65535, if `payload`
+    /// is empty for a pixel encoding (pseudo-encodings, with negative types, may have
+    /// no payload), if `encoding` is `LastRect` (the builder writes it), or if a
+    /// counted update already holds `0xFFFE` rectangles
+    pub fn push(
+        &mut self,
+        encoding: i32,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        if u32::from(x) + u32::from(width) > u32::from(u16::MAX)
+            || u32::from(y) + u32::from(height) > u32::from(u16::MAX)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FramebufferUpdate: rectangle {width}x{height} at ({x}, {y}) exceeds 16-bit coordinates"),
+            ));
+        }
+        if payload.is_empty() && encoding >= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("FramebufferUpdate: empty payload for encoding {encoding}"),
+            ));
+        }
+        if encoding == ENCODING_LAST_RECT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "FramebufferUpdate: LastRect is written by finish(), use with_last_rect()",
+            ));
+        }
+        if !self.last_rect && self.count >= usize::from(LAST_RECT_COUNT - 1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "FramebufferUpdate: a counted update holds at most {} rectangles, use with_last_rect()",
+                    LAST_RECT_COUNT - 1
+                ),
+            ));
+        }
+
+        self.rects.reserve(12 + payload.len());
+        self.rects.put_u16(x);
+        self.rects.put_u16(y);
+        self.rects.put_u16(width);
+        self.rects.put_u16(height);
+        self.rects.put_i32(encoding);
+        self.rects.extend_from_slice(payload);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Writes the complete message: type, padding, rectangle count and rectangles,
+    /// followed by the `LastRect` terminator when enabled.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)] // push() keeps counted updates below 0xFFFF
+    pub fn finish(self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(4 + self.rects.len() + 12);
+        buf.put_u8(0); // message type: FramebufferUpdate
+        buf.put_u8(0); // padding
+        buf.put_u16(if self.last_rect {
+            LAST_RECT_COUNT
+        } else {
+            self.count as u16
+        });
+        buf.extend_from_slice(&self.rects);
+        if self.last_rect {
+            buf.extend_from_slice(&encode_last_rect());
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_rect_header_layout() {
+        let buf = encode_last_rect();
+        assert_eq!(
+            &buf[..],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0x20],
+            "zero-sized rect with encoding -224"
+        );
+        assert_eq!(i32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]), -224);
+    }
+
+    fn rect(x: u16, y: u16, width: u16, height: u16, encoding: i32, tag: u8) -> EncodedRect {
+        EncodedRect {
+            x,
+            y,
+            width,
+            height,
+            encoding,
+            data: BytesMut::from(&[tag][..]),
+        }
+    }
+
+    #[test]
+    fn test_merge_updates_last_writer_wins() {
+        let raw = crate::ENCODING_RAW;
+        let hextile = crate::ENCODING_HEXTILE;
+        let first = vec![
+            rect(0, 0, 64, 64, raw, 1),
+            rect(64, 0, 64, 64, raw, 2),
+            rect(0, 64, 32, 32, hextile, 3),
+        ];
+        let second = vec![
+            // Repaints the first tile entirely and part of the second
+            rect(0, 0, 96, 64, hextile, 4),
+            // Copies over the third rectangle, which may still be its source
+            rect(0, 64, 32, 32, ENCODING_COPYRECT, 5),
+        ];
+
+        let merged = merge_updates(vec![first, second]);
+        let tags: Vec<u8> = merged.iter().map(|r| r.data[0]).collect();
+        assert_eq!(tags, [2, 3, 4, 5], "stale tile 1 dropped, order kept");
+
+        // An identical region sent twice keeps only the later copy
+        let merged = merge_updates(vec![
+            vec![rect(8, 8, 16, 16, raw, 1)],
+            vec![rect(8, 8, 16, 16, raw, 2)],
+        ]);
+        assert_eq!(merged, [rect(8, 8, 16, 16, raw, 2)]);
+    }
+
+    #[test]
+    fn test_merge_updates_keeps_shared_stream_rects() {
+        // Each of these continues a persistent zlib stream on the client
+        for encoding in [
+            crate::ENCODING_ZLIB,
+            crate::ENCODING_TIGHT,
+            crate::ENCODING_ZLIBHEX,
+            crate::ENCODING_ZRLE,
+            crate::ENCODING_ZYWRLE,
+        ] {
+            let merged = merge_updates(vec![
+                vec![rect(8, 8, 16, 16, encoding, 1)],
+                vec![rect(0, 0, 64, 64, crate::ENCODING_RAW, 2)],
+            ]);
+            let tags: Vec<u8> = merged.iter().map(|r| r.data[0]).collect();
+            assert_eq!(tags, [1, 2], "encoding {encoding} must not be dropped");
+        }
+    }
+
+    #[test]
+    fn test_merge_updates_copyrect_is_a_barrier() {
+        let raw = crate::ENCODING_RAW;
+        let merged = merge_updates(vec![
+            // Source pixels for the CopyRect below
+            vec![rect(0, 0, 16, 16, raw, 1)],
+            // Copies (0, 0) to (32, 0), then the source is repainted
+            vec![
+                rect(32, 0, 16, 16, ENCODING_COPYRECT, 2),
+                rect(0, 0, 64, 64, raw, 3),
+            ],
+        ]);
+        let tags: Vec<u8> = merged.iter().map(|r| r.data[0]).collect();
+        assert_eq!(
+            tags,
+            [1, 2, 3],
+            "source painted before the CopyRect is kept"
+        );
+
+        // Rectangles after the barrier still supersede each other
+        let merged = merge_updates(vec![vec![
+            rect(32, 0, 16, 16, ENCODING_COPYRECT, 1),
+            rect(0, 0, 16, 16, raw, 2),
+            rect(0, 0, 64, 64, raw, 3),
+        ]]);
+        let tags: Vec<u8> = merged.iter().map(|r| r.data[0]).collect();
+        assert_eq!(tags, [1, 3]);
+    }
+
+    /// Reads (x, y, width, height, encoding) of a rectangle header at `pos`.
+    fn read_header(buf: &[u8], pos: usize) -> (u16, u16, u16, u16, i32) {
+        let u16_at = |i: usize| u16::from_be_bytes([buf[pos + i], buf[pos + i + 1]]);
+        let encoding = i32::from_be_bytes(buf[pos + 8..pos + 12].try_into().unwrap());
+        (u16_at(0), u16_at(2), u16_at(4), u16_at(6), encoding)
+    }
+
+    #[cfg(all(feature = "tight", feature = "zrle"))]
+    #[test]
+    fn test_builder_mixed_encodings_parse_back() {
+        use crate::tight::{try_encode_tight_rects, SimpleTightCompressor, TightOptions};
+        use crate::{PixelFormat, ENCODING_TIGHT, ENCODING_ZRLE};
+
+        let pf = PixelFormat::rgba32();
+        let data: Vec<u8> = (0..32 * 16)
+            .flat_map(|i| [u8::try_from(i % 7).unwrap() * 30, 0, 90, 255])
+            .collect();
+        let tight = try_encode_tight_rects(
+            &data,
+            32,
+            16,
+            10,
+            6,
+            &pf,
+            &TightOptions::default(),
+            &mut SimpleTightCompressor::new(6),
+        )
+        .unwrap();
+        let zrle = crate::zrle::encode_zrle(&data, 32, 16, &pf, 6).unwrap();
+        let copy_source = [0, 0, 0, 16]; // source (0, 16)
+
+        for streaming in [false, true] {
+            let mut builder = FramebufferUpdateBuilder::new();
+            if streaming {
+                builder = builder.with_last_rect();
+            }
+            let mut layout = Vec::new();
+            for (x, y, w, h, payload) in &tight {
+                builder
+                    .push(ENCODING_TIGHT, *x, *y, *w, *h, payload)
+                    .unwrap();
+                layout.push(((*x, *y, *w, *h, ENCODING_TIGHT), payload.len()));
+            }
+            builder.push(ENCODING_ZRLE, 32, 0, 32, 16, &zrle).unwrap();
+            layout.push(((32, 0, 32, 16, ENCODING_ZRLE), zrle.len()));
+            builder
+                .push(ENCODING_COPYRECT, 0, 16, 64, 16, &copy_source)
+                .unwrap();
+            layout.push(((0, 16, 64, 16, ENCODING_COPYRECT), 4));
+            assert_eq!(builder.len(), layout.len());
+            let update = builder.finish();
+
+            assert_eq!(&update[..2], &[0, 0], "message type and padding");
+            let count = u16::from_be_bytes([update[2], update[3]]);
+            if streaming {
+                assert_eq!(count, LAST_RECT_COUNT);
+                layout.push(((0, 0, 0, 0, ENCODING_LAST_RECT), 0));
+            } else {
+                assert_eq!(usize::from(count), layout.len());
+            }
+
+            let mut pos = 4;
+            for (header, payload_len) in &layout {
+                assert_eq!(read_header(&update, pos), *header);
+                pos += 12 + payload_len;
+            }
+            assert_eq!(pos, update.len(), "no bytes after the last rectangle");
+        }
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_rectangles() {
+        let mut builder = FramebufferUpdateBuilder::new();
+        assert!(
+            builder.push(0, 65000, 0, 1000, 1, &[1]).is_err(),
+            "past 65535"
+        );
+        assert!(
+            builder.push(0, 0, 0, 1, 1, &[]).is_err(),
+            "empty Raw payload"
+        );
+        assert!(builder.push(ENCODING_LAST_RECT, 0, 0, 0, 0, &[]).is_err());
+        // Pseudo-encodings such as DesktopSize carry no payload
+        builder.push(-223, 0, 0, 800, 600, &[]).unwrap();
+        assert_eq!(builder.len(), 1);
+        assert_eq!(read_header(&builder.finish(), 4), (0, 0, 800, 600, -223));
+    }
+}
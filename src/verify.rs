@@ -0,0 +1,537 @@
+// Copyright 2025 Dustin McAfee
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encode-then-decode verification against the in-crate decoders.
+//!
+//! [`encode_and_verify`] encodes a rectangle, decodes it again with [`crate::decode`]
+//! and compares the RGB values with the input. It is meant for integration tests
+//! and for servers that want to check critical frames at runtime; every call costs
+//! a full decode on top of the encode.
+
+use crate::common::check_rgba_len;
+use crate::decode;
+use crate::translate::{extract_rgb, translate_pixels};
+use crate::{Encoding, PixelFormat, RawEncoding, ENCODING_RAW, ENCODING_ZLIB};
+use bytes::BytesMut;
+use flate2::{Compress, Compression, Decompress};
+use std::{fmt, io};
+
+/// Largest mean absolute per-channel error accepted for a JPEG rectangle.
+///
+/// Tight's lowest JPEG quality (32) stays well below this on photographic content;
+/// larger errors point at a broken encoder or decoder rather than compression loss.
+pub const JPEG_MEAN_ERROR_TOLERANCE: f32 = 16.0;
+
+/// Why [`encode_and_verify`] rejected a rectangle.
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The encoding has no in-crate decoder in this build, or cannot encode in the
+    /// requested pixel format.
+    Unsupported(i32),
+    /// The encoder failed.
+    Encode(io::Error),
+    /// The in-crate decoder rejected the encoded data.
+    Decode(io::Error),
+    /// A lossless rectangle decoded to a different color.
+    Mismatch {
+        /// Column of the first differing pixel.
+        x: u16,
+        /// Row of the first differing pixel.
+        y: u16,
+        /// RGB of the input pixel in the client's pixel format.
+        expected: [u8; 3],
+        /// RGB of the decoded pixel.
+        actual: [u8; 3],
+    },
+    /// A JPEG rectangle's mean error exceeds [`JPEG_MEAN_ERROR_TOLERANCE`].
+    ToleranceExceeded {
+        /// Left edge of the rectangle.
+        x: u16,
+        /// Top edge of the rectangle.
+        y: u16,
+        /// Width of the rectangle.
+        width: u16,
+        /// Height of the rectangle.
+        height: u16,
+        /// Mean absolute per-channel error over the rectangle.
+        mean_error: f32,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(encoding) => {
+                write!(f, "Verify: encoding {encoding} cannot be verified")
+            }
+            Self::Encode(e) => write!(f, "Verify: encoding failed: {e}"),
+            Self::Decode(e) => write!(f, "Verify: decoding failed: {e}"),
+            Self::Mismatch {
+                x,
+                y,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Verify: pixel ({x}, {y}) decoded to {actual:?}, expected {expected:?}"
+            ),
+            Self::ToleranceExceeded {
+                x,
+                y,
+                width,
+                height,
+                mean_error,
+            } => write!(
+                f,
+                "Verify: {width}x{height} JPEG rectangle at ({x}, {y}) has mean error \
+                 {mean_error:.1}, above {JPEG_MEAN_ERROR_TOLERANCE}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Encode(e) | Self::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded (sub)rectangle in the client's pixel format.
+struct DecodedRect {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    pixels: Vec<u8>,
+    lossy: bool,
+}
+
+/// Encodes a rectangle, decodes it with the in-crate decoder and checks that the RGB
+/// values survive, returning the encoded payload.
+///
+/// Lossless output must reproduce every pixel exactly, as quantized to
+/// `pixel_format`. JPEG rectangles, which Tight sends for `quality` below 10 with the
+/// `turbojpeg` feature, may differ by up to [`JPEG_MEAN_ERROR_TOLERANCE`] on average.
+///
+/// Supported encodings are Raw, Zlib, Hextile, `ZlibHex`, ZRLE and Tight, each when
+/// its feature is enabled. Raw, ZRLE and Tight encode in `pixel_format`; the others
+/// only in formats compatible with RGBA32. Payloads are framed as by
+/// [`crate::encode_payload_only`], with zlib-based encodings on a fresh stream.
+/// Tight sub-rectangles are concatenated, as by [`crate::TightEncoding`].
+///
+/// Raw has no decoder to speak of: its payload is the expected pixels, so verifying
+/// it only checks the input length and always succeeds otherwise. It is accepted so
+/// callers can verify whatever encoding they negotiated without special cases.
+///
+/// Empty rectangles (zero width or height) verify trivially once encoded.
+/// # Errors
+///
+/// Returns a [`VerifyError`] if the encoding is unsupported, encoding or decoding
+/// fails, or the decoded pixels diverge from `data`
+pub fn encode_and_verify(
+    encoding: i32,
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+    pixel_format: &PixelFormat,
+) -> Result<BytesMut, VerifyError> {
+    check_rgba_len(data, width, height, "Verify").map_err(VerifyError::Encode)?;
+    let data = &data[..width as usize * height as usize * 4];
+    let rgba32_only = || {
+        if pixel_format.is_compatible_with_rgba32() {
+            Ok(())
+        } else {
+            Err(VerifyError::Unsupported(encoding))
+        }
+    };
+    let whole = |pixels| DecodedRect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+        pixels,
+        lossy: false,
+    };
+    let level = Compression::new(u32::from(compression.min(9)));
+
+    let (encoded, decoded) = match encoding {
+        ENCODING_RAW => {
+            let raw = if pixel_format.is_compatible_with_rgba32() {
+                RawEncoding.encode(data, width, height, quality, compression)
+            } else {
+                translate_pixels(data, &PixelFormat::rgba32(), pixel_format)
+            };
+            let pixels = raw.to_vec();
+            (raw, vec![whole(pixels)])
+        }
+        ENCODING_ZLIB => {
+            rgba32_only()?;
+            let encoded = crate::encode_zlib_persistent(data, &mut Compress::new(level, true))
+                .map_err(VerifyError::Encode)?;
+            let pixels = decode::decode_zlib(
+                &encoded,
+                width,
+                height,
+                pixel_format,
+                &mut Decompress::new(true),
+            )
+            .map_err(VerifyError::Decode)?;
+            (BytesMut::from(&encoded[..]), vec![whole(pixels)])
+        }
+        #[cfg(feature = "hextile")]
+        crate::ENCODING_HEXTILE => {
+            rgba32_only()?;
+            let encoded = crate::HextileEncoding.encode(data, width, height, quality, compression);
+            let pixels = decode::decode_hextile(&encoded, width, height, pixel_format)
+                .map_err(VerifyError::Decode)?;
+            (encoded, vec![whole(pixels)])
+        }
+        #[cfg(feature = "zlibhex")]
+        crate::ENCODING_ZLIBHEX => {
+            rgba32_only()?;
+            let encoded = crate::encode_zlibhex_persistent(
+                data,
+                width,
+                height,
+                &mut Compress::new(level, true),
+            )
+            .map_err(VerifyError::Encode)?;
+            let pixels = decode::decode_zlibhex(
+                &encoded,
+                width,
+                height,
+                pixel_format,
+                &mut Decompress::new(true),
+            )
+            .map_err(VerifyError::Decode)?;
+            (BytesMut::from(&encoded[..]), vec![whole(pixels)])
+        }
+        #[cfg(feature = "zrle")]
+        crate::ENCODING_ZRLE => {
+            // ZRLE takes pixels already in the client's format
+            let client_pixels = translate_pixels(data, &PixelFormat::rgba32(), pixel_format);
+            let encoded =
+                crate::zrle::encode_zrle(&client_pixels, width, height, pixel_format, compression)
+                    .map_err(VerifyError::Encode)?;
+            let pixels = decode::decode_zrle(
+                &encoded,
+                width,
+                height,
+                pixel_format,
+                &mut Decompress::new(true),
+            )
+            .map_err(VerifyError::Decode)?;
+            (BytesMut::from(&encoded[..]), vec![whole(pixels)])
+        }
+        #[cfg(feature = "tight")]
+        crate::ENCODING_TIGHT => {
+            encode_and_decode_tight(data, width, height, quality, compression, pixel_format)?
+        }
+        _ => return Err(VerifyError::Unsupported(encoding)),
+    };
+
+    let expected = translate_pixels(data, &PixelFormat::rgba32(), pixel_format);
+    for rect in &decoded {
+        compare_rect(&expected, width, rect, pixel_format)?;
+    }
+    Ok(encoded)
+}
+
+/// Encodes with Tight and decodes every sub-rectangle on one client-side decoder.
+#[cfg(feature = "tight")]
+fn encode_and_decode_tight(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    quality: u8,
+    compression: u8,
+    pixel_format: &PixelFormat,
+) -> Result<(BytesMut, Vec<DecodedRect>), VerifyError> {
+    use crate::tight::{try_encode_tight_rects, SimpleTightCompressor, TightOptions, TIGHT_JPEG};
+
+    let rects = try_encode_tight_rects(
+        data,
+        width,
+        height,
+        quality,
+        compression,
+        pixel_format,
+        &TightOptions::default(),
+        &mut SimpleTightCompressor::new(compression),
+    )
+    .map_err(VerifyError::Encode)?;
+
+    let mut client = decode::TightDecoder::new();
+    let mut encoded = BytesMut::new();
+    let mut decoded = Vec::with_capacity(rects.len());
+    for (x, y, w, h, buf) in rects {
+        let pixels = client
+            .decode_rect(&buf, w, h, pixel_format)
+            .map_err(VerifyError::Decode)?;
+        decoded.push(DecodedRect {
+            x,
+            y,
+            width: w,
+            height: h,
+            pixels,
+            lossy: buf[0] == TIGHT_JPEG << 4,
+        });
+        encoded.extend_from_slice(&buf);
+    }
+    Ok((encoded, decoded))
+}
+
+/// Compares one decoded rectangle with the same area of `expected`, a frame of
+/// `frame_width` pixels in `pixel_format`.
+#[allow(clippy::cast_precision_loss)] // Error sums and pixel counts stay far below 2^24
+fn compare_rect(
+    expected: &[u8],
+    frame_width: u16,
+    rect: &DecodedRect,
+    pixel_format: &PixelFormat,
+) -> Result<(), VerifyError> {
+    let bpp = (pixel_format.bits_per_pixel / 8) as usize;
+    let decoded_len = rect.width as usize * rect.height as usize * bpp;
+    if rect.pixels.len() != decoded_len {
+        return Err(VerifyError::Decode(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Verify: decoded {} bytes for a {}x{} rectangle, expected {decoded_len}",
+                rect.pixels.len(),
+                rect.width,
+                rect.height
+            ),
+        )));
+    }
+    if decoded_len == 0 {
+        return Ok(());
+    }
+
+    let mut error_sum = 0u64;
+    for (row, decoded_row) in rect
+        .pixels
+        .chunks_exact(rect.width as usize * bpp)
+        .enumerate()
+    {
+        let start = ((rect.y as usize + row) * frame_width as usize + rect.x as usize) * bpp;
+        let expected_row = &expected[start..start + decoded_row.len()];
+        for (col, (want, got)) in expected_row
+            .chunks_exact(bpp)
+            .zip(decoded_row.chunks_exact(bpp))
+            .enumerate()
+        {
+            let (er, eg, eb) = extract_rgb(want, pixel_format);
+            let (ar, ag, ab) = extract_rgb(got, pixel_format);
+            if rect.lossy {
+                error_sum += u64::from(er.abs_diff(ar))
+                    + u64::from(eg.abs_diff(ag))
+                    + u64::from(eb.abs_diff(ab));
+            } else if (er, eg, eb) != (ar, ag, ab) {
+                #[allow(clippy::cast_possible_truncation)] // Offsets within a u16 rectangle
+                return Err(VerifyError::Mismatch {
+                    x: rect.x + col as u16,
+                    y: rect.y + row as u16,
+                    expected: [er, eg, eb],
+                    actual: [ar, ag, ab],
+                });
+            }
+        }
+    }
+
+    if rect.lossy {
+        let mean_error = error_sum as f32 / (rect.width as usize * rect.height as usize * 3) as f32;
+        if mean_error > JPEG_MEAN_ERROR_TOLERANCE {
+            return Err(VerifyError::ToleranceExceeded {
+                x: rect.x,
+                y: rect.y,
+                width: rect.width,
+                height: rect.height,
+                mean_error,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ENCODING_HEXTILE, ENCODING_TIGHT, ENCODING_ZLIBHEX, ENCODING_ZRLE};
+
+    /// Flat background, a noisy band and a few colored boxes
+    #[allow(clippy::cast_possible_truncation)] // Box shading wraps by design
+    fn test_frame(width: usize, height: usize) -> Vec<u8> {
+        let mut state = 0x2468_ACE1u32;
+        (0..width * height)
+            .flat_map(|i| {
+                let (col, row) = (i % width, i / width);
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let [r, g, b, _] = state.to_be_bytes();
+                if (20..30).contains(&row) {
+                    [r, g, b, 255]
+                } else if col % 32 < 12 && row % 24 < 10 {
+                    [200, (col * 3) as u8, 40, 255]
+                } else {
+                    [16, 32, 48, 255]
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lossless_encodings_verify() {
+        let data = test_frame(100, 75);
+        let encodings = [
+            (ENCODING_RAW, true, true),
+            (ENCODING_ZLIB, true, false),
+            (ENCODING_HEXTILE, cfg!(feature = "hextile"), false),
+            (ENCODING_ZLIBHEX, cfg!(feature = "zlibhex"), false),
+            (ENCODING_ZRLE, cfg!(feature = "zrle"), true),
+            (ENCODING_TIGHT, cfg!(feature = "tight"), false),
+        ];
+
+        for (encoding, enabled, any_format) in encodings {
+            let verify = |pf: &PixelFormat| encode_and_verify(encoding, &data, 100, 75, 10, 6, pf);
+            if !enabled {
+                assert!(matches!(
+                    verify(&PixelFormat::rgba32()),
+                    Err(VerifyError::Unsupported(_))
+                ));
+                continue;
+            }
+            let encoded = verify(&PixelFormat::rgba32())
+                .unwrap_or_else(|e| panic!("encoding {encoding}: {e}"));
+            assert!(!encoded.is_empty());
+            // Raw and ZRLE also verify in a 16-bit format
+            if any_format {
+                verify(&PixelFormat::rgb565())
+                    .unwrap_or_else(|e| panic!("encoding {encoding} at 16 bpp: {e}"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported_and_undersized_inputs_are_rejected() {
+        let data = test_frame(8, 8);
+        assert!(matches!(
+            encode_and_verify(ENCODING_ZLIB, &data, 8, 8, 10, 6, &PixelFormat::rgb565()),
+            Err(VerifyError::Unsupported(ENCODING_ZLIB))
+        ));
+        assert!(matches!(
+            encode_and_verify(-1, &data, 8, 8, 10, 6, &PixelFormat::rgba32()),
+            Err(VerifyError::Unsupported(-1))
+        ));
+        assert!(matches!(
+            encode_and_verify(
+                ENCODING_RAW,
+                &data[..100],
+                8,
+                8,
+                10,
+                6,
+                &PixelFormat::rgba32()
+            ),
+            Err(VerifyError::Encode(_))
+        ));
+    }
+
+    #[test]
+    fn test_empty_rectangles_verify() {
+        let encodings = [
+            (ENCODING_RAW, true),
+            (ENCODING_ZLIB, true),
+            (ENCODING_HEXTILE, cfg!(feature = "hextile")),
+            (ENCODING_ZLIBHEX, cfg!(feature = "zlibhex")),
+            (ENCODING_ZRLE, cfg!(feature = "zrle")),
+            (ENCODING_TIGHT, cfg!(feature = "tight")),
+        ];
+        for (encoding, enabled) in encodings {
+            if !enabled {
+                continue;
+            }
+            for (width, height) in [(0, 5), (5, 0), (0, 0)] {
+                encode_and_verify(encoding, &[], width, height, 10, 6, &PixelFormat::rgba32())
+                    .unwrap_or_else(|e| panic!("encoding {encoding} {width}x{height}: {e}"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_lossy_rectangles_are_held_to_the_mean_tolerance() {
+        let pf = PixelFormat::rgba32();
+        let expected = test_frame(16, 16);
+        let shifted = |delta: u8| DecodedRect {
+            x: 0,
+            y: 0,
+            width: 16,
+            height: 16,
+            pixels: expected
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    [
+                        p[0].saturating_add(delta),
+                        p[1],
+                        p[2].saturating_sub(delta),
+                        0,
+                    ]
+                })
+                .collect(),
+            lossy: true,
+        };
+        compare_rect(&expected, 16, &shifted(12), &pf).unwrap();
+        assert!(matches!(
+            compare_rect(&expected, 16, &shifted(40), &pf),
+            Err(VerifyError::ToleranceExceeded {
+                width: 16,
+                height: 16,
+                ..
+            })
+        ));
+
+        // The same small error is a mismatch when the rectangle is lossless
+        let strict = DecodedRect {
+            lossy: false,
+            ..shifted(12)
+        };
+        let error = compare_rect(&expected, 16, &strict, &pf).unwrap_err();
+        assert!(matches!(error, VerifyError::Mismatch { .. }), "{error}");
+    }
+
+    #[cfg(feature = "turbojpeg")]
+    #[test]
+    #[allow(clippy::cast_possible_truncation)] // Channel values stay below 256
+    fn test_tight_jpeg_verifies_within_tolerance() {
+        // Smooth photo-like content at the best and worst JPEG quality levels
+        let data: Vec<u8> = (0..64usize * 64)
+            .flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, (i % 97) as u8, 255])
+            .collect();
+        for quality in [0, 9] {
+            encode_and_verify(
+                crate::ENCODING_TIGHT,
+                &data,
+                64,
+                64,
+                quality,
+                6,
+                &PixelFormat::rgba32(),
+            )
+            .unwrap_or_else(|e| panic!("quality {quality}: {e}"));
+        }
+    }
+}
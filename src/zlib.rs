@@ -16,14 +16,78 @@
 //!
 //! Simple zlib compression on raw pixel data using the client's pixel format.
 
-use bytes::{BufMut, BytesMut};
-use flate2::{Compress, FlushCompress};
+use crate::common::{compress_sync_flush, put_length_prefix};
+use crate::translate::translate_pixels;
+use crate::{Encoding, PixelFormat, RawEncoding};
+use bytes::BytesMut;
+use flate2::{Compress, Compression};
 use std::io;
 
+/// Implements the VNC "Zlib" encoding (encoding 6): raw pixels in the client's
+/// pixel format, zlib-compressed.
+///
+/// Each call uses a fresh zlib stream, so every rectangle decodes on its own; use
+/// [`encode_zlib_persistent_with_format`] with one compressor per client connection
+/// instead, as RFC 6143 requires a single stream per connection.
+///
+/// When the input holds fewer than `width * height` pixels or compression fails,
+/// [`Encoding::encode`] returns the pixels Raw-encoded in `pixel_format` instead,
+/// with missing pixels zero-filled, so the caller never gets an empty payload.
+#[derive(Debug, Clone)]
+pub struct ZlibEncoding {
+    /// Client pixel format the RGBA input is translated to before compression.
+    pub pixel_format: PixelFormat,
+}
+
+impl Default for ZlibEncoding {
+    /// RGBX, the format [`encode_zlib_persistent`] sends.
+    fn default() -> Self {
+        Self {
+            pixel_format: PixelFormat::rgba32(),
+        }
+    }
+}
+
+impl Encoding for ZlibEncoding {
+    fn encode(
+        &self,
+        data: &[u8],
+        width: u16,
+        height: u16,
+        _quality: u8,
+        compression: u8,
+    ) -> BytesMut {
+        let expected_size = width as usize * height as usize * 4;
+        if data.len() < expected_size {
+            return self.encode_raw(data, expected_size);
+        }
+        let data = &data[..expected_size];
+        let mut compressor = Compress::new(Compression::new(u32::from(compression.min(9))), true);
+        match encode_zlib_persistent_with_format(data, &self.pixel_format, &mut compressor) {
+            Ok(encoded) => BytesMut::from(&encoded[..]),
+            Err(_) => self.encode_raw(data, expected_size),
+        }
+    }
+}
+
+impl ZlibEncoding {
+    /// Raw fallback: `data` zero-padded to `expected_size` bytes of RGBA, sent in
+    /// `pixel_format`.
+    fn encode_raw(&self, data: &[u8], expected_size: usize) -> BytesMut {
+        let mut rgba = data[..data.len().min(expected_size)].to_vec();
+        rgba.resize(expected_size, 0);
+        if self.pixel_format.is_compatible_with_rgba32() {
+            RawEncoding.encode(&rgba, 0, 0, 0, 0)
+        } else {
+            translate_pixels(&rgba, &PixelFormat::rgba32(), &self.pixel_format)
+        }
+    }
+}
+
 /// Encodes pixel data using Zlib with a persistent compressor (RFC 6143 compliant).
 ///
 /// This maintains compression state across rectangles as required by RFC 6143.
-/// The implementation matches standard VNC protocol's approach: single `deflate()` call per rectangle.
+/// The implementation matches standard VNC protocol's approach: every rectangle ends with a zlib sync flush.
 ///
 /// # Arguments
 /// * `data` - RGBA pixel data (4 bytes per pixel)
@@ -35,48 +99,115 @@ use std::io;
 ///
 /// # Errors
 ///
-/// Returns an error if zlib compression fails
-#[allow(clippy::cast_possible_truncation)] // Zlib total_in/total_out limited to buffer size
+/// Returns an error if zlib compression fails or the compressed payload is 4 GiB or larger
 pub fn encode_zlib_persistent(data: &[u8], compressor: &mut Compress) -> io::Result<Vec<u8>> {
-    // Convert RGBA to RGBX (client pixel format for 32bpp)
-    // R at byte 0, G at byte 1, B at byte 2, padding at byte 3
-    let mut pixel_data = Vec::with_capacity(data.len());
-    for chunk in data.chunks_exact(4) {
-        pixel_data.push(chunk[0]); // R
-        pixel_data.push(chunk[1]); // G
-        pixel_data.push(chunk[2]); // B
-        pixel_data.push(0); // Padding
-    }
+    encode_zlib_persistent_with_format(data, &PixelFormat::rgba32(), compressor)
+}
 
-    // Calculate maximum compressed size (zlib overhead formula)
-    // From zlib.h: compressed size ≤ uncompressed + (uncompressed/1000) + 12
-    let max_compressed_size = pixel_data.len() + (pixel_data.len() / 1000) + 12;
-    let mut compressed_output = vec![0u8; max_compressed_size];
-
-    // Track total_in and total_out before compression
-    let previous_in = compressor.total_in();
-    let previous_out = compressor.total_out();
-
-    // Single deflate() call with Z_SYNC_FLUSH (RFC 6143 Section 7.7.2)
-    compressor.compress(&pixel_data, &mut compressed_output, FlushCompress::Sync)?;
-
-    // Calculate actual compressed length and consumed input
-    let compressed_len = (compressor.total_out() - previous_out) as usize;
-    let total_consumed = (compressor.total_in() - previous_in) as usize;
-
-    // Verify all input was consumed
-    if total_consumed < pixel_data.len() {
-        return Err(io::Error::other(format!(
-            "Zlib: incomplete compression {}/{}",
-            total_consumed,
-            pixel_data.len()
-        )));
-    }
+/// Same as [`encode_zlib_persistent`], sending pixels in `pixel_format`.
+///
+/// Formats compatible with RGBA32 are sent as RGBX with a zero padding byte, like
+/// Raw; any other format is translated channel by channel, so its padding bits
+/// are zero and alpha is never copied into a colour channel.
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails or the compressed payload is 4 GiB or larger
+pub fn encode_zlib_persistent_with_format(
+    data: &[u8],
+    pixel_format: &PixelFormat,
+    compressor: &mut Compress,
+) -> io::Result<Vec<u8>> {
+    encode_zlib_persistent_with_flush(data, pixel_format, compressor, false)
+}
+
+/// Same as [`encode_zlib_persistent_with_format`]; with `strict_flush` every
+/// rectangle ends with the zlib sync-flush marker ([`crate::common::ZLIB_SYNC_MARKER`]),
+/// even one that adds nothing to the stream, for clients that only decode
+/// rectangles ending on a flush boundary.
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails or the compressed payload is 4 GiB or larger
+pub fn encode_zlib_persistent_with_flush(
+    data: &[u8],
+    pixel_format: &PixelFormat,
+    compressor: &mut Compress,
+    strict_flush: bool,
+) -> io::Result<Vec<u8>> {
+    let pixel_data = if pixel_format.is_compatible_with_rgba32() {
+        // R at byte 0, G at byte 1, B at byte 2, padding at byte 3
+        let mut pixel_data = Vec::with_capacity(data.len());
+        for chunk in data.chunks_exact(4) {
+            pixel_data.push(chunk[0]); // R
+            pixel_data.push(chunk[1]); // G
+            pixel_data.push(chunk[2]); // B
+            pixel_data.push(0); // Padding
+        }
+        pixel_data
+    } else {
+        translate_pixels(data, &PixelFormat::rgba32(), pixel_format).to_vec()
+    };
+
+    // Z_SYNC_FLUSH at the end of every rectangle (RFC 6143 Section 7.7.2)
+    let compressed_output = compress_sync_flush(compressor, &pixel_data, strict_flush)?;
+    let compressed_len = compressed_output.len();
 
     // Build result: 4-byte big-endian length + compressed data
     let mut result = BytesMut::with_capacity(4 + compressed_len);
-    result.put_u32(compressed_len as u32);
+    put_length_prefix(&mut result, compressed_len)?;
     result.extend_from_slice(&compressed_output[..compressed_len]);
 
     Ok(result.to_vec())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ZLIB_SYNC_MARKER;
+    use flate2::{Decompress, FlushDecompress};
+
+    #[test]
+    fn test_short_input_falls_back_to_raw() {
+        let encoder = ZlibEncoding::default();
+        // Two of four pixels: the rest are zero-filled rather than dropped
+        let raw = encoder.encode(&[1, 2, 3, 255, 4, 5, 6, 255], 2, 2, 0, 6);
+        assert_eq!(&raw[..], &[1, 2, 3, 0, 4, 5, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let rgb565 = ZlibEncoding {
+            pixel_format: PixelFormat::rgb565(),
+        };
+        assert_eq!(rgb565.encode(&[255, 255, 255, 255], 2, 1, 0, 6).len(), 4);
+    }
+
+    #[test]
+    fn test_extra_input_is_ignored() {
+        let encoder = ZlibEncoding::default();
+        let data = [7u8; 4 * 4];
+        assert_eq!(
+            encoder.encode(&data, 2, 1, 0, 6),
+            encoder.encode(&data[..8], 2, 1, 0, 6)
+        );
+    }
+
+    #[test]
+    fn test_strict_flush_ends_every_rectangle_with_sync_marker() {
+        let pf = PixelFormat::rgba32();
+        let frame = [40u8, 80, 120, 255].repeat(16 * 16);
+        // The empty rectangle follows a flushed one, leaving nothing new to compress
+        let frames: [&[u8]; 3] = [&frame, &[], &frame[..4]];
+
+        let mut compressor = Compress::new(Compression::new(6), true);
+        let mut decompressor = Decompress::new(true);
+        for data in frames {
+            let encoded =
+                encode_zlib_persistent_with_flush(data, &pf, &mut compressor, true).unwrap();
+            assert!(encoded[4..].ends_with(&ZLIB_SYNC_MARKER));
+            let mut out = Vec::with_capacity(data.len() + 64);
+            decompressor
+                .decompress_vec(&encoded[4..], &mut out, FlushDecompress::Sync)
+                .unwrap();
+            assert_eq!(out.len(), data.len());
+        }
+    }
+}
@@ -18,9 +18,10 @@
 //! bandwidth efficiency while maintaining the tile-based structure.
 
 use super::HextileEncoding;
+use crate::common::{compress_sync_flush, put_length_prefix};
 use crate::Encoding;
-use bytes::{BufMut, BytesMut};
-use flate2::{Compress, FlushCompress};
+use bytes::BytesMut;
+use flate2::Compress;
 use std::io;
 
 /// Encodes pixel data using `ZlibHex` with a persistent compressor (RFC 6143 compliant).
@@ -41,8 +42,7 @@ use std::io;
 ///
 /// # Errors
 ///
-/// Returns an error if zlib compression fails
-#[allow(clippy::cast_possible_truncation)] // Zlib total_in/total_out limited to buffer size
+/// Returns an error if zlib compression fails or the compressed payload is 4 GiB or larger
 pub fn encode_zlibhex_persistent(
     data: &[u8],
     width: u16,
@@ -53,34 +53,13 @@ pub fn encode_zlibhex_persistent(
     let hextile_encoder = HextileEncoding;
     let hextile_data = hextile_encoder.encode(data, width, height, 0, 0);
 
-    // Calculate maximum compressed size (zlib overhead formula)
-    // From zlib.h: compressed size ≤ uncompressed + (uncompressed/1000) + 12
-    let max_compressed_size = hextile_data.len() + (hextile_data.len() / 1000) + 12;
-    let mut compressed_output = vec![0u8; max_compressed_size];
-
-    // Track total_in and total_out before compression
-    let previous_in = compressor.total_in();
-    let previous_out = compressor.total_out();
-
-    // Single deflate() call with Z_SYNC_FLUSH (RFC 6143 Section 7.7.2)
-    compressor.compress(&hextile_data, &mut compressed_output, FlushCompress::Sync)?;
-
-    // Calculate actual compressed length
-    let compressed_len = (compressor.total_out() - previous_out) as usize;
-    let consumed_len = (compressor.total_in() - previous_in) as usize;
-
-    // Verify all input was consumed
-    if consumed_len < hextile_data.len() {
-        return Err(io::Error::other(format!(
-            "ZlibHex: incomplete compression {}/{}",
-            consumed_len,
-            hextile_data.len()
-        )));
-    }
+    // Z_SYNC_FLUSH at the end of every rectangle (RFC 6143 Section 7.7.2)
+    let compressed_output = compress_sync_flush(compressor, &hextile_data, false)?;
+    let compressed_len = compressed_output.len();
 
     // Build result: 4-byte big-endian length + compressed data
     let mut result = BytesMut::with_capacity(4 + compressed_len);
-    result.put_u32(compressed_len as u32);
+    put_length_prefix(&mut result, compressed_len)?;
     result.extend_from_slice(&compressed_output[..compressed_len]);
 
     Ok(result.to_vec())
@@ -36,15 +36,33 @@
 //!
 
 use bytes::{BufMut, BytesMut};
-use flate2::write::ZlibEncoder;
-use flate2::{Compress, Compression, FlushCompress};
+use flate2::{Compress, Compression, Crc, Decompress, FlushDecompress, Status};
 use std::collections::HashMap;
-use std::io::Write;
+use std::time::{Duration, Instant};
 
-use crate::{Encoding, PixelFormat};
+use crate::common::{
+    compress_finish, compress_sync_flush, output_hash, put_length_prefix, DeflateStream,
+    FramingCursor, FramingError, SubEncodingHistogram, ZlibStrategy, ZlibStream,
+};
+use crate::update::EncodedRect;
+use crate::{Encoding, PixelFormat, RowOrder, ENCODING_COPYRECT, ENCODING_ZRLE};
 
 const TILE_SIZE: usize = 64;
 
+/// Largest palette usable by the packed palette sub-encodings (2-16).
+pub const ZRLE_MAX_PACKED_PALETTE: usize = 16;
+
+/// Largest palette usable by the palette RLE sub-encodings (130-255).
+pub const ZRLE_MAX_RLE_PALETTE: usize = 127;
+
+/// Suggested [`ZrleOptions::raw_transition_ratio`]. On noisy tiles, raw pixels
+/// compressed better under zlib than plain RLE from about 0.4 upwards, so half of
+/// adjacent pixels differing is safely past the crossover.
+pub const SUGGESTED_RAW_TRANSITION_RATIO: f32 = 0.5;
+
+/// Palette collection stops at this many colors; no palette sub-encoding can use more.
+const PALETTE_SCAN_LIMIT: usize = 256;
+
 /// Calculates the number of bytes per input pixel based on the pixel format.
 /// This is determined by `bits_per_pixel` / 8.
 #[inline]
@@ -84,19 +102,18 @@ fn bytes_per_cpixel(pf: &PixelFormat) -> usize {
 /// Extracts a pixel value from raw bytes according to the pixel format.
 /// Returns a u32 containing the pixel value (for internal processing).
 #[inline]
-fn read_pixel(data: &[u8], pf: &PixelFormat) -> u32 {
-    let bpp = bytes_per_pixel(pf);
-    match bpp {
+fn read_pixel(data: &[u8], fmt: &FormatInfo) -> u32 {
+    match fmt.bpp {
         1 => u32::from(data[0]),
         2 => {
-            if pf.big_endian_flag != 0 {
+            if fmt.big_endian {
                 u32::from(u16::from_be_bytes([data[0], data[1]]))
             } else {
                 u32::from(u16::from_le_bytes([data[0], data[1]]))
             }
         }
         4 => {
-            if pf.big_endian_flag != 0 {
+            if fmt.big_endian {
                 u32::from_be_bytes([data[0], data[1], data[2], data[3]])
             } else {
                 u32::from_le_bytes([data[0], data[1], data[2], data[3]])
@@ -104,7 +121,7 @@ fn read_pixel(data: &[u8], pf: &PixelFormat) -> u32 {
         }
         _ => {
             // Handle 3-byte case (24bpp)
-            if pf.big_endian_flag != 0 {
+            if fmt.big_endian {
                 u32::from(data[0]) << 16 | u32::from(data[1]) << 8 | u32::from(data[2])
             } else {
                 u32::from(data[0]) | u32::from(data[1]) << 8 | u32::from(data[2]) << 16
@@ -127,17 +144,36 @@ fn use_cpixel_24a(pf: &PixelFormat) -> bool {
     (rgb_in_lower_bytes && !big_endian) || (rgb_in_upper_bytes && big_endian)
 }
 
+/// Per-format constants derived once per encode call instead of once per tile or pixel.
+#[derive(Debug, Clone, Copy)]
+struct FormatInfo {
+    bpp: usize,
+    cpixel_size: usize,
+    cpixel_24a: bool,
+    big_endian: bool,
+}
+
+impl FormatInfo {
+    fn new(pf: &PixelFormat) -> Self {
+        Self {
+            bpp: bytes_per_pixel(pf),
+            cpixel_size: bytes_per_cpixel(pf),
+            cpixel_24a: use_cpixel_24a(pf),
+            big_endian: pf.big_endian_flag != 0,
+        }
+    }
+}
+
 /// Writes a CPIXEL value to the buffer according to the pixel format.
 /// For 3-byte CPIXEL (depth <= 24, bpp=32), writes only the significant 3 bytes.
 /// Uses 24A format (bytes 0,1,2) or 24B format (bytes 1,2,3) based on pixel layout.
 #[inline]
 #[allow(clippy::cast_possible_truncation)]
-fn write_cpixel(buf: &mut BytesMut, pixel: u32, pf: &PixelFormat) {
-    let cpixel_size = bytes_per_cpixel(pf);
-    match cpixel_size {
+fn write_cpixel(buf: &mut BytesMut, pixel: u32, fmt: &FormatInfo) {
+    match fmt.cpixel_size {
         1 => buf.put_u8(pixel as u8),
         2 => {
-            if pf.big_endian_flag != 0 {
+            if fmt.big_endian {
                 buf.put_u16(pixel as u16);
             } else {
                 buf.put_u16_le(pixel as u16);
@@ -145,12 +181,12 @@ fn write_cpixel(buf: &mut BytesMut, pixel: u32, pf: &PixelFormat) {
         }
         3 => {
             // 3-byte CPIXEL: output bytes in client's byte order
-            let bytes = if pf.big_endian_flag != 0 {
+            let bytes = if fmt.big_endian {
                 pixel.to_be_bytes()
             } else {
                 pixel.to_le_bytes()
             };
-            if use_cpixel_24a(pf) {
+            if fmt.cpixel_24a {
                 // 24A: write bytes 0, 1, 2
                 buf.put_u8(bytes[0]);
                 buf.put_u8(bytes[1]);
@@ -163,7 +199,7 @@ fn write_cpixel(buf: &mut BytesMut, pixel: u32, pf: &PixelFormat) {
             }
         }
         4 => {
-            if pf.big_endian_flag != 0 {
+            if fmt.big_endian {
                 buf.put_u32(pixel);
             } else {
                 buf.put_u32_le(pixel);
@@ -173,6 +209,58 @@ fn write_cpixel(buf: &mut BytesMut, pixel: u32, pf: &PixelFormat) {
     }
 }
 
+/// Reads a CPIXEL written by [`write_cpixel`], returning the pixel value.
+#[inline]
+fn read_cpixel_value(data: &[u8], fmt: &FormatInfo) -> u32 {
+    let mut bytes = [0u8; 4];
+    match (fmt.cpixel_size, fmt.cpixel_24a) {
+        (3, true) => bytes[..3].copy_from_slice(&data[..3]),
+        (3, false) => bytes[1..].copy_from_slice(&data[..3]),
+        (size, _) => {
+            // Narrower values sit at the low-order end of the 4 bytes
+            let start = if fmt.big_endian { 4 - size } else { 0 };
+            bytes[start..start + size].copy_from_slice(&data[..size]);
+        }
+    }
+    if fmt.big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Encodes `pixel` (a value in `pf`'s layout) as a ZRLE CPIXEL.
+///
+/// CPIXEL is the pixel in `pf`'s byte order, except that 32bpp true-colour formats
+/// with depth 24 or less whose colour bits fit in three bytes drop the unused byte:
+/// - **24A** (bytes 0, 1, 2 of the byte-order representation) when the colours fit
+///   in the least significant three bytes and `pf` is little-endian, or fit in the
+///   most significant three bytes and `pf` is big-endian.
+/// - **24B** (bytes 1, 2, 3) otherwise.
+///
+/// Every other format uses the full `bits_per_pixel / 8` bytes.
+#[must_use]
+pub fn cpixel_bytes(pixel: u32, pf: &PixelFormat) -> Vec<u8> {
+    let fmt = FormatInfo::new(pf);
+    let mut buf = BytesMut::with_capacity(fmt.cpixel_size);
+    write_cpixel(&mut buf, pixel, &fmt);
+    buf.to_vec()
+}
+
+/// Decodes a ZRLE CPIXEL from the start of `data`, the inverse of [`cpixel_bytes`].
+///
+/// Returns the pixel value in `pf`'s layout and the number of bytes consumed
+/// (1, 2, 3 or 4).
+///
+/// # Panics
+///
+/// Panics if `data` is shorter than the CPIXEL size for `pf`.
+#[must_use]
+pub fn read_cpixel(data: &[u8], pf: &PixelFormat) -> (u32, usize) {
+    let fmt = FormatInfo::new(pf);
+    (read_cpixel_value(data, &fmt), fmt.cpixel_size)
+}
+
 /// Analyzes pixel data to count RLE runs, single pixels, and unique colors.
 /// Returns: (runs, `single_pixels`, `palette_vec`)
 /// CRITICAL: The palette Vec must preserve insertion order (order colors first appear)
@@ -192,7 +280,7 @@ fn analyze_runs_and_palette(pixels: &[u32]) -> (usize, usize, Vec<u32>) {
         let color = pixels[i];
 
         // For small palettes (common case), linear search is faster than HashMap
-        if palette.len() < 256 && !palette.contains(&color) {
+        if palette.len() < PALETTE_SCAN_LIMIT && !palette.contains(&color) {
             palette.push(color);
         }
 
@@ -211,366 +299,2057 @@ fn analyze_runs_and_palette(pixels: &[u32]) -> (usize, usize, Vec<u32>) {
     (runs, single_pixels, palette)
 }
 
-/// Encodes a rectangle of pixel data using ZRLE with a persistent compressor.
-/// This maintains compression state across rectangles as required by RFC 6143.
-///
-/// The input data should be in the client's pixel format (as negotiated via `SetPixelFormat`).
-/// The encoder uses CPIXEL format for output as specified in RFC 6143.
-///
-/// # Errors
-///
-/// Returns an error if zlib compression fails or if the input buffer is too small
-#[allow(dead_code)]
-#[allow(clippy::cast_possible_truncation)] // ZRLE protocol requires u8/u16/u32 packing of pixel data
-pub fn encode_zrle_persistent(
-    data: &[u8],
-    width: u16,
-    height: u16,
-    pixel_format: &PixelFormat,
-    compressor: &mut Compress,
-) -> std::io::Result<Vec<u8>> {
-    let width = width as usize;
-    let height = height as usize;
-    let bpp = bytes_per_pixel(pixel_format);
-    let expected_size = width * height * bpp;
-    if data.len() < expected_size {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!(
-                "ZRLE: input buffer size mismatch: got {} bytes, expected {} bytes for {}x{} image ({} bytes per pixel)",
-                data.len(),
-                expected_size,
-                width,
-                height,
-                bpp
-            ),
-        ));
+/// Whether every `stride`th pixel of `pixels` already holds more than `limit` distinct
+/// colors. The sample can only undercount the tile's colors, so `true` is always
+/// correct for the whole tile. A `stride` of 0 disables sampling.
+fn sampled_colors_exceed(pixels: &[u32], stride: usize, limit: usize) -> bool {
+    if stride == 0 || pixels.len().div_ceil(stride) <= limit {
+        return false;
     }
-    let mut uncompressed_data = BytesMut::new();
-
-    for y in (0..height).step_by(TILE_SIZE) {
-        for x in (0..width).step_by(TILE_SIZE) {
-            let tile_w = (width - x).min(TILE_SIZE);
-            let tile_h = (height - y).min(TILE_SIZE);
-
-            // Extract tile pixel data
-            let tile_data = extract_tile(data, width, x, y, tile_w, tile_h, bpp);
-
-            // Analyze and encode the tile
-            encode_tile(
-                &mut uncompressed_data,
-                &tile_data,
-                tile_w,
-                tile_h,
-                pixel_format,
-            );
+    let mut seen = Vec::with_capacity(limit + 1);
+    for &pixel in pixels.iter().step_by(stride) {
+        if !seen.contains(&pixel) {
+            seen.push(pixel);
+            if seen.len() > limit {
+                return true;
+            }
         }
     }
-
-    // Compress using persistent compressor with Z_SYNC_FLUSH
-    // RFC 6143: use persistent zlib stream with dictionary for compression continuity
-    let input = &uncompressed_data[..];
-    let mut output_buf = vec![0u8; input.len() * 2 + 1024]; // Generous buffer
-
-    let before_out = compressor.total_out();
-
-    // Single compress call with Z_SYNC_FLUSH - this should handle all input
-    compressor.compress(input, &mut output_buf, FlushCompress::Sync)?;
-
-    let produced = (compressor.total_out() - before_out) as usize;
-    let compressed_output = &output_buf[..produced];
-
-    // Build result with length prefix (big-endian) + compressed data
-    let mut result = BytesMut::with_capacity(4 + compressed_output.len());
-    result.put_u32(compressed_output.len() as u32);
-    result.extend_from_slice(compressed_output);
-
-    #[cfg(feature = "debug-logging")]
-    log::info!(
-        "ZRLE: compressed {}->{}  bytes ({}x{} tiles)",
-        uncompressed_data.len(),
-        compressed_output.len(),
-        width,
-        height
-    );
-
-    Ok(result.to_vec())
+    false
 }
 
-/// Encodes a rectangle of pixel data using the ZRLE encoding.
-/// This creates a new compressor for each rectangle (non-RFC compliant, deprecated).
-///
-/// The input data should be in the client's pixel format (as negotiated via `SetPixelFormat`).
-/// The encoder uses CPIXEL format for output as specified in RFC 6143.
-///
-/// # Errors
-///
-/// Returns an error if zlib compression fails or if the input buffer is too small
-#[allow(clippy::cast_possible_truncation)] // ZRLE protocol requires u8/u16/u32 packing of pixel data
-pub fn encode_zrle(
-    data: &[u8],
-    width: u16,
-    height: u16,
-    pixel_format: &PixelFormat,
-    compression: u8,
-) -> std::io::Result<Vec<u8>> {
-    let width = width as usize;
-    let height = height as usize;
-    let bpp = bytes_per_pixel(pixel_format);
-    let expected_size = width * height * bpp;
-    if data.len() < expected_size {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!(
-                "ZRLE: input buffer size mismatch: got {} bytes, expected {} bytes for {}x{} image ({} bytes per pixel)",
-                data.len(),
-                expected_size,
-                width,
-                height,
-                bpp
-            ),
-        ));
+/// Counts RLE runs and single pixels without collecting a palette.
+/// Returns: (runs, `single_pixels`)
+fn count_runs(pixels: &[u32]) -> (usize, usize) {
+    let mut runs = 0;
+    let mut single_pixels = 0;
+    for run in pixels.chunk_by(|a, b| a == b) {
+        if run.len() == 1 {
+            single_pixels += 1;
+        } else {
+            runs += 1;
+        }
     }
+    (runs, single_pixels)
+}
 
-    let compression_level = match compression {
-        0 => Compression::fast(),
-        1..=3 => Compression::new(u32::from(compression)),
-        4..=6 => Compression::default(),
-        _ => Compression::best(),
-    };
-    let mut zlib_encoder = ZlibEncoder::new(Vec::new(), compression_level);
-    let mut uncompressed_data = BytesMut::new();
-
-    for y in (0..height).step_by(TILE_SIZE) {
-        for x in (0..width).step_by(TILE_SIZE) {
-            let tile_w = (width - x).min(TILE_SIZE);
-            let tile_h = (height - y).min(TILE_SIZE);
+/// Fraction of adjacent pixel pairs that differ in a tile of `pixels` pixels split
+/// into `total_runs` runs (counting single pixels).
+#[allow(clippy::cast_precision_loss)] // Tiles hold at most 4096 pixels
+fn transition_ratio(total_runs: usize, pixels: usize) -> f32 {
+    if pixels < 2 {
+        return 0.0;
+    }
+    (total_runs - 1) as f32 / (pixels - 1) as f32
+}
 
-            // Extract tile pixel data
-            let tile_data = extract_tile(data, width, x, y, tile_w, tile_h, bpp);
+/// Tile palette for one ordering of a two-color frame's colors.
+struct PaletteOrder {
+    palette: [u32; 2],
+    color_to_idx: HashMap<u32, u8>,
+}
 
-            // Analyze and encode the tile
-            encode_tile(
-                &mut uncompressed_data,
-                &tile_data,
-                tile_w,
-                tile_h,
-                pixel_format,
-            );
+impl PaletteOrder {
+    fn new(first: u32, second: u32) -> Self {
+        Self {
+            palette: [first, second],
+            color_to_idx: HashMap::from([(first, 0), (second, 1)]),
         }
     }
-
-    zlib_encoder.write_all(&uncompressed_data)?;
-    let compressed = zlib_encoder.finish()?;
-
-    // ZRLE requires a 4-byte big-endian length prefix before the zlib data
-    let mut result = BytesMut::with_capacity(4 + compressed.len());
-    result.put_u32(compressed.len() as u32); // big-endian length
-    result.extend_from_slice(&compressed);
-
-    Ok(result.to_vec())
 }
 
-/// Encodes a single tile, choosing the best sub-encoding.
-/// Handles variable pixel formats according to RFC 6143.
-#[allow(clippy::cast_possible_truncation)] // ZRLE palette indices and run lengths limited to u8 per RFC 6143
-fn encode_tile(
-    buf: &mut BytesMut,
-    tile_data: &[u8],
-    width: usize,
-    height: usize,
-    pf: &PixelFormat,
-) {
-    let cpixel_size = bytes_per_cpixel(pf);
-    let bpp = bytes_per_pixel(pf);
+/// Cached palettes for a frame that uses exactly two colors (e.g. a terminal).
+///
+/// Every non-solid tile of such a frame has both colors, in the order they first
+/// appear in the tile, so its palette and index map can be picked from the tile's
+/// first pixel instead of being rebuilt by a palette scan.
+struct TwoColorFrame {
+    orders: [PaletteOrder; 2],
+}
 
-    // Quick check for solid color by scanning pixel data directly (avoid allocation)
-    if tile_data.len() >= bpp {
-        let first_pixel = read_pixel(&tile_data[0..bpp], pf);
-        let mut is_solid = true;
-
-        for chunk in tile_data.chunks_exact(bpp).skip(1) {
-            if read_pixel(chunk, pf) != first_pixel {
-                is_solid = false;
-                break;
+impl TwoColorFrame {
+    /// Returns the frame's palettes if `data` contains exactly two colors.
+    fn detect(data: &[u8], fmt: &FormatInfo) -> Option<Self> {
+        let mut pixels = data
+            .chunks_exact(fmt.bpp)
+            .map(|chunk| read_pixel(chunk, fmt));
+        let first = pixels.next()?;
+        let mut second = None;
+        for pixel in pixels {
+            if pixel == first {
+                continue;
+            }
+            match second {
+                None => second = Some(pixel),
+                Some(color) if color == pixel => {}
+                Some(_) => return None,
             }
         }
+        let second = second?;
+        Some(Self {
+            orders: [
+                PaletteOrder::new(first, second),
+                PaletteOrder::new(second, first),
+            ],
+        })
+    }
 
-        if is_solid {
-            encode_solid_color_tile(buf, first_pixel, pf);
-            return;
+    /// Returns the palette ordering for a tile whose first pixel is `first_pixel`.
+    fn order_for(&self, first_pixel: u32) -> &PaletteOrder {
+        if self.orders[0].palette[0] == first_pixel {
+            &self.orders[0]
+        } else {
+            &self.orders[1]
         }
     }
+}
 
-    // Convert to u32 pixels for analysis
-    let pixels = pixels_to_u32(tile_data, pf);
-    let (runs, single_pixels, palette) = analyze_runs_and_palette(&pixels);
+/// ZRLE tile sub-encoding types (RFC 6143 section 7.7.6).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubEncoding {
+    /// Uncompressed CPIXELs (sub-encoding 0).
+    Raw,
+    /// A single CPIXEL covering the whole tile (sub-encoding 1).
+    Solid,
+    /// Palette of 2-[`ZRLE_MAX_PACKED_PALETTE`] colors with bit-packed indices (sub-encodings 2-16).
+    PackedPalette,
+    /// Run-length encoded CPIXELs (sub-encoding 128).
+    PlainRle,
+    /// Palette of up to [`ZRLE_MAX_RLE_PALETTE`] colors with run-length encoded indices
+    /// (sub-encodings 130-255).
+    PaletteRle,
+}
+
+/// Estimated payload sizes (excluding the sub-encoding byte) for each candidate sub-encoding.
+///
+/// A candidate is `None` when the tile's palette is too large for that sub-encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EstimatedSizes {
+    /// Size of the tile as raw CPIXELs.
+    pub raw: usize,
+    /// Size of the tile with plain RLE.
+    pub plain_rle: usize,
+    /// Size of the tile with palette RLE (palettes of up to [`ZRLE_MAX_RLE_PALETTE`] colors).
+    pub palette_rle: Option<usize>,
+    /// Size of the tile with a packed palette (palettes of up to [`ZRLE_MAX_PACKED_PALETTE`] colors).
+    pub packed_palette: Option<usize>,
+}
 
-    let mut use_rle = false;
-    let mut use_palette = false;
+/// Result of analyzing a single ZRLE tile without emitting any bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileAnalysis {
+    /// The sub-encoding the encoder would choose for this tile.
+    pub chosen: SubEncoding,
+    /// Number of distinct colors (capped at 256).
+    pub palette_len: usize,
+    /// Number of runs of length 2 or more.
+    pub runs: usize,
+    /// Number of pixels not part of a run.
+    pub singles: usize,
+    /// Cost-model estimates for each candidate sub-encoding.
+    pub estimated_sizes: EstimatedSizes,
+}
 
+/// Applies the ZRLE cost model to pick a sub-encoding for a non-solid tile.
+///
+/// Candidates are checked in order raw, plain RLE, palette RLE, packed palette,
+/// and a later candidate only wins if it is strictly smaller. The one exception is
+/// a tie between palette RLE and packed palette, which
+/// [`ZrleOptions::prefer_rle_on_tie`] decides. Candidates disabled in
+/// [`ZrleOptions::compat`] are estimated but never chosen.
+fn choose_subencoding(
+    width: usize,
+    height: usize,
+    cpixel_size: usize,
+    runs: usize,
+    single_pixels: usize,
+    palette_size: usize,
+    options: &ZrleOptions,
+) -> (SubEncoding, EstimatedSizes) {
+    let compat = options.compat;
     // Start assuming raw encoding size
-    let mut estimated_bytes = width * height * cpixel_size;
+    let raw_bytes = width * height * cpixel_size;
+    let mut chosen = SubEncoding::Raw;
+    let mut estimated_bytes = raw_bytes;
 
     let plain_rle_bytes = (cpixel_size + 1) * (runs + single_pixels);
 
-    if plain_rle_bytes < estimated_bytes {
-        use_rle = true;
+    if compat.plain_rle && plain_rle_bytes < estimated_bytes {
+        chosen = SubEncoding::PlainRle;
         estimated_bytes = plain_rle_bytes;
     }
 
-    if palette.len() < 128 {
-        let palette_size = palette.len();
+    let mut palette_rle = None;
+    let mut packed_palette = None;
 
+    if palette_size <= ZRLE_MAX_RLE_PALETTE {
         // Palette RLE encoding
         let palette_rle_bytes = cpixel_size * palette_size + 2 * runs + single_pixels;
+        palette_rle = Some(palette_rle_bytes);
 
-        if palette_rle_bytes < estimated_bytes {
-            use_rle = true;
-            use_palette = true;
+        if compat.palette_rle && palette_rle_bytes < estimated_bytes {
+            chosen = SubEncoding::PaletteRle;
             estimated_bytes = palette_rle_bytes;
         }
 
         // Packed palette encoding (no RLE)
-        if palette_size < 17 {
-            let bits_per_packed_pixel = match palette_size {
-                2 => 1,
-                3..=4 => 2,
-                _ => 4, // 5-16 colors
-            };
+        if palette_size <= ZRLE_MAX_PACKED_PALETTE {
+            let bits_per_packed_pixel = packed_bits_per_pixel(palette_size);
             // Per RFC 6143: each row is padded to byte boundary
             let bytes_per_row = (width * bits_per_packed_pixel).div_ceil(8);
             let packed_bytes = cpixel_size * palette_size + bytes_per_row * height;
+            packed_palette = Some(packed_bytes);
 
-            if packed_bytes < estimated_bytes {
-                use_rle = false;
-                use_palette = true;
-                // No need to update estimated_bytes, this is the last check
+            let packed_wins_tie = !options.prefer_rle_on_tie
+                && chosen == SubEncoding::PaletteRle
+                && packed_bytes == estimated_bytes;
+            if compat.packed_palette && (packed_bytes < estimated_bytes || packed_wins_tie) {
+                chosen = SubEncoding::PackedPalette;
             }
         }
     }
 
-    if use_palette {
-        // Palette (Packed Palette or Packed Palette RLE)
-        // Build index lookup from palette (preserves insertion order)
-        let color_to_idx: HashMap<_, _> = palette
-            .iter()
-            .enumerate()
-            .map(|(i, &c)| (c, i as u8))
-            .collect();
-
-        if use_rle {
-            // Packed Palette RLE
-            encode_packed_palette_rle_tile(buf, &pixels, &palette, &color_to_idx, pf);
-        } else {
-            // Packed Palette (no RLE)
-            encode_packed_palette_tile(buf, &pixels, width, height, &palette, &color_to_idx, pf);
-        }
-    } else {
-        // Raw or Plain RLE
-        if use_rle {
-            // Plain RLE - encode directly to buffer (avoid intermediate Vec)
-            buf.put_u8(128);
-            encode_rle_to_buf(buf, &pixels, pf);
-        } else {
-            // Raw
-            encode_raw_tile(buf, &pixels, pf);
-        }
-    }
+    (
+        chosen,
+        EstimatedSizes {
+            raw: raw_bytes,
+            plain_rle: plain_rle_bytes,
+            palette_rle,
+            packed_palette,
+        },
+    )
 }
 
-/// Extracts a tile from the full framebuffer.
-/// Optimized to use a single allocation and bulk copy operations.
+/// Analyzes a tile and reports which sub-encoding ZRLE would choose, without emitting bytes.
+///
+/// This runs the same cost model as the encoder with default [`ZrleOptions`], so it
+/// can be used by tuning and analysis tools to see why a particular sub-encoding
+/// was picked.
 ///
 /// # Arguments
-/// * `full_frame` - The complete framebuffer data
-/// * `frame_width` - Width of the framebuffer in pixels
-/// * `x`, `y` - Top-left corner of the tile in the framebuffer
-/// * `width`, `height` - Dimensions of the tile in pixels
-/// * `bpp` - Bytes per pixel
-#[allow(clippy::uninit_vec)] // Performance optimization: all bytes written via bulk copy before return
-fn extract_tile(
-    full_frame: &[u8],
-    frame_width: usize,
-    x: usize,
-    y: usize,
+/// * `tile_data` - Tile pixels in the client's pixel format (at most 64x64)
+/// * `width`, `height` - Tile dimensions in pixels
+/// * `pf` - Pixel format of `tile_data`
+#[must_use]
+pub fn analyze_tile(
+    tile_data: &[u8],
     width: usize,
     height: usize,
-    bpp: usize,
-) -> Vec<u8> {
-    let tile_size = width * height * bpp;
-    let mut tile_data = Vec::with_capacity(tile_size);
+    pf: &PixelFormat,
+) -> TileAnalysis {
+    let fmt = FormatInfo::new(pf);
+    let cpixel_size = fmt.cpixel_size;
+    let pixels = pixels_to_u32(tile_data, &fmt);
+    let (runs, singles, palette) = analyze_runs_and_palette(&pixels);
+    let (mut chosen, estimated_sizes) = choose_subencoding(
+        width,
+        height,
+        cpixel_size,
+        runs,
+        singles,
+        palette.len(),
+        &ZrleOptions::default(),
+    );
 
-    // Use unsafe for performance - we know the capacity is correct
-    unsafe {
-        tile_data.set_len(tile_size);
+    // The encoder detects solid tiles before running the cost model
+    if palette.len() == 1 {
+        chosen = SubEncoding::Solid;
     }
 
-    let row_bytes = width * bpp;
-    for row in 0..height {
-        let src_start = ((y + row) * frame_width + x) * bpp;
-        let dst_start = row * row_bytes;
-        tile_data[dst_start..dst_start + row_bytes]
-            .copy_from_slice(&full_frame[src_start..src_start + row_bytes]);
+    TileAnalysis {
+        chosen,
+        palette_len: palette.len(),
+        runs,
+        singles,
+        estimated_sizes,
     }
-    tile_data
 }
 
-/// Converts pixel data to u32 values for internal processing.
-/// Works with any pixel format by using the pixel format's bytes per pixel.
-fn pixels_to_u32(data: &[u8], pf: &PixelFormat) -> Vec<u32> {
-    let bpp = bytes_per_pixel(pf);
-    data.chunks_exact(bpp)
-        .map(|chunk| read_pixel(chunk, pf))
-        .collect()
+/// Cost-model estimates against encoded sizes for one tile, as reported by
+/// [`analyze_compression`].
+///
+/// Sizes exclude the sub-encoding byte and are measured before zlib.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileCompression {
+    /// Left edge of the tile within the frame.
+    pub x: u16,
+    /// Top edge of the tile within the frame.
+    pub y: u16,
+    /// Tile width (64 except at the right edge).
+    pub w: u16,
+    /// Tile height (64 except at the bottom edge).
+    pub h: u16,
+    /// The sub-encoding the encoder chose.
+    pub chosen: SubEncoding,
+    /// Size of the tile as the encoder sent it.
+    pub chosen_size: usize,
+    /// Cost-model estimates for each candidate, as [`analyze_tile`] reports them.
+    pub estimated_sizes: EstimatedSizes,
+    /// Sizes measured by encoding the tile with each candidate. The palette
+    /// candidates are `None` when the tile has a single color or too many colors.
+    pub actual_sizes: EstimatedSizes,
 }
 
-/// Sub-encoding for a tile with a single color.
-fn encode_solid_color_tile(buf: &mut BytesMut, color: u32, pf: &PixelFormat) {
-    buf.put_u8(1); // Solid color sub-encoding
-    write_cpixel(buf, color, pf);
+impl TileCompression {
+    /// The candidate with the smallest measured size and that size, or the chosen
+    /// sub-encoding when it is [`SubEncoding::Solid`]. Ties go to the earlier of raw,
+    /// plain RLE, palette RLE and packed palette.
+    #[must_use]
+    pub fn smallest(&self) -> (SubEncoding, usize) {
+        if self.chosen == SubEncoding::Solid {
+            return (self.chosen, self.chosen_size);
+        }
+        let sizes = &self.actual_sizes;
+        [
+            (SubEncoding::Raw, Some(sizes.raw)),
+            (SubEncoding::PlainRle, Some(sizes.plain_rle)),
+            (SubEncoding::PaletteRle, sizes.palette_rle),
+            (SubEncoding::PackedPalette, sizes.packed_palette),
+        ]
+        .into_iter()
+        .filter_map(|(mode, size)| Some((mode, size?)))
+        .min_by_key(|&(_, size)| size)
+        .unwrap_or((SubEncoding::Raw, sizes.raw))
+    }
+
+    /// Raw tile size divided by the chosen sub-encoding's size: the compression the
+    /// sub-encoding achieved before zlib.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // Tiles hold at most 4096 pixels
+    pub fn ratio(&self) -> f64 {
+        self.actual_sizes.raw as f64 / self.chosen_size.max(1) as f64
+    }
 }
 
-/// Sub-encoding for raw pixel data.
-fn encode_raw_tile(buf: &mut BytesMut, pixels: &[u32], pf: &PixelFormat) {
-    buf.put_u8(0); // Raw sub-encoding
-    for &pixel in pixels {
-        write_cpixel(buf, pixel, pf);
+/// Reports, for every tile of a frame, the sub-encoding chosen, its size, and the
+/// estimated and measured sizes of every candidate.
+///
+/// For checking the encoder's cost model (see [`analyze_tile`]) against reality. Each
+/// tile is encoded as [`encode_zrle`] encodes it with default [`ZrleOptions`], then
+/// once more per candidate into a scratch buffer. The estimates ignore the extra
+/// length bytes of runs longer than 256 pixels, so RLE candidates can measure larger
+/// than estimated on flat tiles.
+///
+/// # Errors
+///
+/// Returns an error if the input buffer is too small
+#[allow(clippy::cast_possible_truncation)] // Tile offsets and sizes stay within the u16 frame
+pub fn analyze_compression(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> std::io::Result<Vec<TileCompression>> {
+    let (width_px, height_px) = (usize::from(width), usize::from(height));
+    let mut ctx = FrameContext::new(pixel_format, &ZrleOptions::default());
+    let bpp = ctx.fmt.bpp;
+    let expected_size = checked_frame_size(width_px, height_px, bpp)?;
+    if data.len() < expected_size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "ZRLE: input buffer size mismatch: got {} bytes, expected {expected_size} bytes for {width}x{height} image ({bpp} bytes per pixel)",
+                data.len()
+            ),
+        ));
     }
+    ctx.two_color = TwoColorFrame::detect(&data[..expected_size], &ctx.fmt);
+
+    let mut report = Vec::new();
+    let mut scratch = BytesMut::new();
+    for y in (0..height_px).step_by(TILE_SIZE) {
+        for x in (0..width_px).step_by(TILE_SIZE) {
+            let tile_w = (width_px - x).min(TILE_SIZE);
+            let tile_h = (height_px - y).min(TILE_SIZE);
+            let tile = TileView::in_frame(data, width_px, x, y, tile_w, tile_h, bpp);
+            let tile_data = tile.to_vec();
+
+            scratch.clear();
+            let chosen = encode_tile(&mut scratch, tile, tile_w, tile_h, &ctx, None);
+            report.push(TileCompression {
+                x: x as u16,
+                y: y as u16,
+                w: tile_w as u16,
+                h: tile_h as u16,
+                chosen,
+                chosen_size: scratch.len() - 1,
+                estimated_sizes: analyze_tile(&tile_data, tile_w, tile_h, pixel_format)
+                    .estimated_sizes,
+                actual_sizes: measure_candidates(
+                    &tile_data,
+                    tile_w,
+                    tile_h,
+                    &ctx.fmt,
+                    &mut scratch,
+                ),
+            });
+        }
+    }
+    Ok(report)
 }
 
-/// Sub-encoding for a tile with a small palette.
-#[allow(clippy::cast_possible_truncation)] // ZRLE palette size limited to 16 colors (u8) per RFC 6143
-fn encode_packed_palette_tile(
-    buf: &mut BytesMut,
-    pixels: &[u32],
+/// Encodes a tile with every candidate sub-encoding its palette allows into
+/// `scratch` and returns their sizes, excluding the sub-encoding byte.
+#[allow(clippy::cast_possible_truncation)] // Palette RLE allows at most 127 colors, so indices fit u8
+fn measure_candidates(
+    tile_data: &[u8],
     width: usize,
     height: usize,
-    palette: &[u32],
-    color_to_idx: &HashMap<u32, u8>,
-    pf: &PixelFormat,
-) {
-    let palette_size = palette.len();
-    let bits_per_pixel = match palette_size {
-        2 => 1,
-        3..=4 => 2,
-        _ => 4,
+    fmt: &FormatInfo,
+    scratch: &mut BytesMut,
+) -> EstimatedSizes {
+    let pixels = pixels_to_u32(tile_data, fmt);
+    let (_, _, palette) = analyze_runs_and_palette(&pixels);
+    let mut measure = |encode: &dyn Fn(&mut BytesMut)| {
+        scratch.clear();
+        encode(scratch);
+        scratch.len() - 1
     };
 
-    buf.put_u8(palette_size as u8); // Packed palette sub-encoding
-
-    // Write palette as CPIXEL - in insertion order
-    for &color in palette {
-        write_cpixel(buf, color, pf);
+    let raw = measure(&|buf| encode_raw_tile(buf, &pixels, fmt));
+    let plain_rle = measure(&|buf| {
+        buf.put_u8(128);
+        encode_rle_to_buf(buf, &pixels, fmt);
+    });
+    if !(2..=ZRLE_MAX_RLE_PALETTE).contains(&palette.len()) {
+        return EstimatedSizes {
+            raw,
+            plain_rle,
+            palette_rle: None,
+            packed_palette: None,
+        };
     }
 
-    // Write packed pixel data ROW BY ROW per RFC 6143 ZRLE specification
-    // Critical: Each row must be byte-aligned
-    for row in 0..height {
-        let mut packed_byte = 0;
-        let mut nbits = 0;
-        let row_start = row * width;
-        let row_end = row_start + width;
+    let color_to_idx: HashMap<u32, u8> = palette
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| (color, i as u8))
+        .collect();
+    let palette_rle = measure(&|buf| {
+        encode_packed_palette_rle_tile(buf, &pixels, &palette, &color_to_idx, fmt);
+    });
+    let packed_palette = (palette.len() <= ZRLE_MAX_PACKED_PALETTE).then(|| {
+        measure(&|buf| {
+            encode_packed_palette_tile(buf, &pixels, width, height, &palette, &color_to_idx, fmt);
+        })
+    });
+    EstimatedSizes {
+        raw,
+        plain_rle,
+        palette_rle: Some(palette_rle),
+        packed_palette,
+    }
+}
+
+/// Frame-wide color histogram collected while encoding with [`encode_zrle_with_histogram`].
+///
+/// Colors are pixel values in the client's pixel format, as read from the input data.
+/// Counts accumulate across calls, so one histogram can cover several rectangles.
+#[derive(Debug, Clone, Default)]
+pub struct ColorHistogram {
+    counts: HashMap<u32, usize>,
+}
+
+impl ColorHistogram {
+    /// Creates an empty histogram.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many pixels of `color` have been seen.
+    #[must_use]
+    pub fn count(&self, color: u32) -> usize {
+        self.counts.get(&color).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of distinct colors seen.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Returns `true` if no pixels have been recorded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Returns the total number of pixels recorded.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Returns up to `n` colors with the highest counts, most frequent first.
+    /// Ties are ordered by color value so the result is deterministic.
+    #[must_use]
+    pub fn dominant(&self, n: usize) -> Vec<(u32, usize)> {
+        let mut entries: Vec<(u32, usize)> = self.counts.iter().map(|(&c, &n)| (c, n)).collect();
+        entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        entries.truncate(n);
+        entries
+    }
+
+    /// Iterates over all `(color, count)` pairs in unspecified order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+        self.counts.iter().map(|(&c, &n)| (c, n))
+    }
+
+    fn add(&mut self, color: u32, count: usize) {
+        *self.counts.entry(color).or_insert(0) += count;
+    }
+}
+
+/// Options controlling ZRLE output.
+///
+/// The defaults produce standard output and match the plain encoding functions.
+#[derive(Debug, Clone)]
+pub struct ZrleOptions {
+    /// Client-bug workaround: emit solid tiles as a 2-entry packed palette whose
+    /// entries are both the tile color (all indices 0) instead of the solid
+    /// sub-encoding (1).
+    ///
+    /// Only enable this for clients known to mis-render solid tiles. A palette of
+    /// one color cannot be used because its sub-encoding byte equals the solid one.
+    /// The workaround costs `ceil(width / 8) * height` index bytes plus one extra
+    /// CPIXEL per solid tile. Same as disabling [`ZrleCompat::solid`].
+    pub solid_tile_as_palette: bool,
+    /// Row order of the input frame; bottom-up frames are sent top-down.
+    pub row_order: RowOrder,
+    /// When palette RLE and packed palette are estimated to the same size, pick
+    /// palette RLE (`true`, the default) or packed palette (`false`).
+    ///
+    /// RLE is usually cheaper for clients to decode; packed palette output has a
+    /// fixed size per row.
+    pub prefer_rle_on_tie: bool,
+    /// Before the full palette scan of a tile, sample every `n`th pixel; if the sample
+    /// alone has more than [`ZRLE_MAX_RLE_PALETTE`] colors, no palette sub-encoding
+    /// is possible and the scan is skipped, saving CPU on photographic tiles.
+    ///
+    /// A sample never has more colors than the tile, so output is unchanged. `0`
+    /// disables sampling; the default is 4.
+    pub palette_sample_stride: usize,
+    /// How to send tiles cut short by the right and bottom edges of the frame.
+    ///
+    /// Anything but [`EdgeTiles::Clip`] is non-standard: every tile is sent as a full
+    /// 64x64 tile, which only decoders built for it understand, such as
+    /// [`crate::decode::decode_zrle_full_tiles`]. Padding pixels are counted by
+    /// [`encode_zrle_with_histogram`] like real ones.
+    pub edge_tiles: EdgeTiles,
+    /// Send raw instead of plain RLE when at least this fraction of adjacent pixels
+    /// (in row-major order) differ, as an entropy estimate of the tile.
+    ///
+    /// Plain RLE is chosen on its uncompressed size, but its run lengths interleave
+    /// with the pixels and hide repetition from the outer zlib stream; on noisy
+    /// tiles raw pixels compress smaller. Palette sub-encodings are never replaced.
+    /// `None` (the default) keeps the cost model's choice; see
+    /// [`SUGGESTED_RAW_TRANSITION_RATIO`].
+    pub raw_transition_ratio: Option<f32>,
+    /// Guarantee that every rectangle's zlib data ends with a sync flush
+    /// ([`crate::common::ZLIB_SYNC_MARKER`]), for clients that only decode rectangles ending on a
+    /// flush boundary.
+    ///
+    /// Rectangles are always sync-flushed, but the C zlib backends of `flate2` emit
+    /// nothing for a rectangle with no tile data after a previous flush; this option
+    /// sends an empty stored block instead. Only persistent-stream encoding ([`ZrleSession`]) honours it.
+    pub strict_flush: bool,
+    /// Sub-encodings the encoder may use, to route around client decoder bugs.
+    ///
+    /// Tiles that would use a disabled sub-encoding fall back to the smallest
+    /// allowed one; raw is always allowed. The default enables everything.
+    pub compat: ZrleCompat,
+    /// Deflate strategy of the zlib stream, e.g. [`ZlibStrategy::Rle`] to trade size
+    /// for speed.
+    ///
+    /// Used by [`encode_zrle`] and [`ZrleSession`]; [`encode_zrle_persistent`] keeps
+    /// the strategy of the caller's stream.
+    pub strategy: ZlibStrategy,
+}
+
+impl Default for ZrleOptions {
+    fn default() -> Self {
+        Self {
+            solid_tile_as_palette: false,
+            row_order: RowOrder::TopDown,
+            prefer_rle_on_tie: true,
+            palette_sample_stride: 4,
+            edge_tiles: EdgeTiles::Clip,
+            raw_transition_ratio: None,
+            strict_flush: false,
+            compat: ZrleCompat::default(),
+            strategy: ZlibStrategy::Default,
+        }
+    }
+}
+
+/// Per-sub-encoding enable flags for [`ZrleOptions::compat`].
+///
+/// Raw tiles cannot be disabled, since every tile can be sent raw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)] // Independent enable flags, not a state machine
+pub struct ZrleCompat {
+    /// Allow the solid sub-encoding (1). When disabled, solid tiles are sent as a
+    /// 2-entry palette whose entries are both the tile color: packed if allowed,
+    /// else palette RLE, else plain RLE, else raw.
+    pub solid: bool,
+    /// Allow packed palette sub-encodings (2-16).
+    pub packed_palette: bool,
+    /// Allow plain RLE (128).
+    pub plain_rle: bool,
+    /// Allow palette RLE sub-encodings (130-255).
+    pub palette_rle: bool,
+}
+
+impl Default for ZrleCompat {
+    fn default() -> Self {
+        Self {
+            solid: true,
+            packed_palette: true,
+            plain_rle: true,
+            palette_rle: true,
+        }
+    }
+}
+
+/// Handling of edge tiles smaller than 64x64 (see [`ZrleOptions::edge_tiles`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgeTiles {
+    /// Send edge tiles at their clipped size, as RFC 6143 specifies.
+    #[default]
+    Clip,
+    /// Pad edge tiles to 64x64 by repeating the last column and row. Adds no colors
+    /// and extends runs, so padding costs little.
+    Replicate,
+    /// Pad edge tiles to 64x64 with all-zero pixels.
+    Zero,
+}
+
+/// Optional statistics gathered while encoding the tiles of a frame.
+#[derive(Default)]
+struct FrameStats<'a> {
+    colors: Option<&'a mut ColorHistogram>,
+    modes: Option<&'a mut SubEncodingHistogram>,
+}
+
+/// Per-frame state shared by every tile of a rectangle.
+struct FrameContext {
+    fmt: FormatInfo,
+    two_color: Option<TwoColorFrame>,
+    options: ZrleOptions,
+}
+
+impl FrameContext {
+    fn new(pixel_format: &PixelFormat, options: &ZrleOptions) -> Self {
+        Self {
+            fmt: FormatInfo::new(pixel_format),
+            two_color: None,
+            options: options.clone(),
+        }
+    }
+}
+
+/// Byte size of a `width` x `height` frame at `bpp` bytes per pixel.
+///
+/// Checked because 65535x65535 at 4 bytes per pixel overflows a 32-bit `usize`;
+/// a wrapped size would let a too-small buffer pass validation.
+fn checked_frame_size(width: usize, height: usize, bpp: usize) -> std::io::Result<usize> {
+    width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(bpp))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("ZRLE: {width}x{height} image at {bpp} bytes per pixel overflows usize"),
+            )
+        })
+}
+
+/// Validates the input size and appends every tile of the rectangle to `tiles` as
+/// uncompressed ZRLE tile data.
+fn encode_frame_tiles(
+    tiles: &mut BytesMut,
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    options: &ZrleOptions,
+    mut stats: FrameStats<'_>,
+) -> std::io::Result<()> {
+    let width = width as usize;
+    let height = height as usize;
+    let mut ctx = FrameContext::new(pixel_format, options);
+    let bpp = ctx.fmt.bpp;
+    let expected_size = checked_frame_size(width, height, bpp)?;
+    if data.len() < expected_size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "ZRLE: input buffer size mismatch: got {} bytes, expected {} bytes for {}x{} image ({} bytes per pixel)",
+                data.len(),
+                expected_size,
+                width,
+                height,
+                bpp
+            ),
+        ));
+    }
+    // Zero padding can add a third color to tiles of a two-color frame
+    if ctx.options.edge_tiles != EdgeTiles::Zero {
+        ctx.two_color = TwoColorFrame::detect(&data[..expected_size], &ctx.fmt);
+    }
+
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            let tile_w = (width - x).min(TILE_SIZE);
+            let tile_h = (height - y).min(TILE_SIZE);
+
+            // Read the tile in place, taking bottom-up input from the far end
+            let tile = match ctx.options.row_order {
+                RowOrder::TopDown => TileView::in_frame(data, width, x, y, tile_w, tile_h, bpp),
+                RowOrder::BottomUp => {
+                    let source_y = height - y - tile_h;
+                    TileView::in_frame(data, width, x, source_y, tile_w, tile_h, bpp).flipped()
+                }
+            };
+            let padded;
+            let (tile, tile_w, tile_h) = if ctx.options.edge_tiles != EdgeTiles::Clip
+                && (tile_w < TILE_SIZE || tile_h < TILE_SIZE)
+            {
+                padded = pad_tile(&tile.to_vec(), tile_w, tile_h, bpp, ctx.options.edge_tiles);
+                let padded = TileView::contiguous(&padded, TILE_SIZE, TILE_SIZE, bpp);
+                (padded, TILE_SIZE, TILE_SIZE)
+            } else {
+                (tile, tile_w, tile_h)
+            };
+
+            // Analyze and encode the tile
+            let mode = encode_tile(
+                tiles,
+                tile,
+                tile_w,
+                tile_h,
+                &ctx,
+                stats.colors.as_deref_mut(),
+            );
+            if let Some(modes) = stats.modes.as_deref_mut() {
+                count_mode(modes, mode);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Pads a `width` x `height` tile to 64x64 as [`EdgeTiles::Replicate`] or
+/// [`EdgeTiles::Zero`] describes.
+fn pad_tile(tile: &[u8], width: usize, height: usize, bpp: usize, edge: EdgeTiles) -> Vec<u8> {
+    let row_bytes = width * bpp;
+    let padded_row_bytes = TILE_SIZE * bpp;
+    let mut padded = vec![0u8; TILE_SIZE * padded_row_bytes];
+    for (row, dst) in padded.chunks_exact_mut(padded_row_bytes).enumerate() {
+        let src_row = if row < height {
+            row
+        } else if edge == EdgeTiles::Replicate {
+            height - 1
+        } else {
+            break;
+        };
+        let src = &tile[src_row * row_bytes..(src_row + 1) * row_bytes];
+        dst[..row_bytes].copy_from_slice(src);
+        if edge == EdgeTiles::Replicate {
+            let last = &src[row_bytes - bpp..];
+            for pixel in dst[row_bytes..].chunks_exact_mut(bpp) {
+                pixel.copy_from_slice(last);
+            }
+        }
+    }
+    padded
+}
+
+/// Encodes a rectangle of pixel data using ZRLE with a persistent compressor.
+/// This maintains compression state across rectangles as required by RFC 6143.
+///
+/// The input data should be in the client's pixel format (as negotiated via `SetPixelFormat`).
+/// The encoder uses CPIXEL format for output as specified in RFC 6143.
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails, if the input buffer is too small, or if the
+/// compressed payload is 4 GiB or larger
+pub fn encode_zrle_persistent(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    compressor: &mut Compress,
+) -> std::io::Result<Vec<u8>> {
+    encode_zrle_persistent_impl(
+        data,
+        width,
+        height,
+        pixel_format,
+        &ZrleOptions::default(),
+        compressor,
+        &mut BytesMut::new(),
+    )
+}
+
+/// Shared implementation of [`encode_zrle_persistent`] and [`ZrleSession::encode`].
+///
+/// `tiles` is scratch space for the uncompressed tile data; it is cleared first, so a
+/// caller encoding many frames can keep its capacity between calls.
+#[allow(clippy::cast_possible_truncation)] // ZRLE protocol requires u8/u16/u32 packing of pixel data
+fn encode_zrle_persistent_impl(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    options: &ZrleOptions,
+    compressor: &mut impl DeflateStream,
+    tiles: &mut BytesMut,
+) -> std::io::Result<Vec<u8>> {
+    tiles.clear();
+    encode_frame_tiles(
+        tiles,
+        data,
+        width,
+        height,
+        pixel_format,
+        options,
+        FrameStats::default(),
+    )?;
+    compress_tile_stream(tiles, compressor, options.strict_flush)
+}
+
+/// Compresses an uncompressed tile stream on the connection's persistent zlib stream
+/// and adds the 4-byte length prefix.
+fn compress_tile_stream(
+    uncompressed_data: &[u8],
+    compressor: &mut impl DeflateStream,
+    strict_flush: bool,
+) -> std::io::Result<Vec<u8>> {
+    // RFC 6143: use persistent zlib stream with dictionary for compression continuity,
+    // ending every rectangle with Z_SYNC_FLUSH
+    let compressed_output = compress_sync_flush(compressor, uncompressed_data, strict_flush)?;
+
+    // Build result with length prefix (big-endian) + compressed data
+    let mut result = BytesMut::with_capacity(4 + compressed_output.len());
+    put_length_prefix(&mut result, compressed_output.len())?;
+    result.extend_from_slice(&compressed_output);
+
+    #[cfg(feature = "debug-logging")]
+    log::info!(
+        "ZRLE: compressed {}->{} bytes",
+        uncompressed_data.len(),
+        compressed_output.len()
+    );
+
+    Ok(result.to_vec())
+}
+
+/// Encodes a rectangle of pixel data using the ZRLE encoding.
+/// This creates a new compressor for each rectangle (non-RFC compliant, deprecated).
+///
+/// The input data should be in the client's pixel format (as negotiated via `SetPixelFormat`).
+/// The encoder uses CPIXEL format for output as specified in RFC 6143.
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails, if the input buffer is too small, or if the
+/// compressed payload is 4 GiB or larger
+pub fn encode_zrle(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    compression: u8,
+) -> std::io::Result<Vec<u8>> {
+    encode_zrle_impl(
+        data,
+        width,
+        height,
+        pixel_format,
+        compression,
+        &ZrleOptions::default(),
+        FrameStats::default(),
+    )
+}
+
+/// Encodes like [`encode_zrle`] with non-default [`ZrleOptions`].
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails, if the input buffer is too small, or if the
+/// compressed payload is 4 GiB or larger
+pub fn encode_zrle_with_options(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    compression: u8,
+    options: &ZrleOptions,
+) -> std::io::Result<Vec<u8>> {
+    encode_zrle_impl(
+        data,
+        width,
+        height,
+        pixel_format,
+        compression,
+        options,
+        FrameStats::default(),
+    )
+}
+
+/// Encodes like [`encode_zrle`] while counting every pixel's color into `histogram`.
+///
+/// The histogram is filled during tile analysis, so no second pass over the frame
+/// is needed. Output bytes are identical to [`encode_zrle`].
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails, if the input buffer is too small, or if the
+/// compressed payload is 4 GiB or larger
+pub fn encode_zrle_with_histogram(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    compression: u8,
+    histogram: &mut ColorHistogram,
+) -> std::io::Result<Vec<u8>> {
+    encode_zrle_impl(
+        data,
+        width,
+        height,
+        pixel_format,
+        compression,
+        &ZrleOptions::default(),
+        FrameStats {
+            colors: Some(histogram),
+            ..FrameStats::default()
+        },
+    )
+}
+
+/// Encodes like [`encode_zrle`] while counting the sub-encoding of every tile into
+/// `modes`.
+///
+/// Output bytes are identical to [`encode_zrle`].
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails, if the input buffer is too small, or if the
+/// compressed payload is 4 GiB or larger
+pub fn encode_zrle_with_stats(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    compression: u8,
+    modes: &mut SubEncodingHistogram,
+) -> std::io::Result<Vec<u8>> {
+    encode_zrle_impl(
+        data,
+        width,
+        height,
+        pixel_format,
+        compression,
+        &ZrleOptions::default(),
+        FrameStats {
+            modes: Some(modes),
+            ..FrameStats::default()
+        },
+    )
+}
+
+/// Encodes like [`encode_zrle`] and also returns [`crate::output_hash`] of the
+/// encoded bytes.
+///
+/// Each call uses a fresh zlib stream, so identical input always produces identical
+/// output and hash; a proxy can use the hash to send one cached payload to every
+/// client that needs the same rectangle.
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails, if the input buffer is too small, or if the
+/// compressed payload is 4 GiB or larger
+pub fn encode_zrle_with_hash(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    compression: u8,
+) -> std::io::Result<(Vec<u8>, u64)> {
+    let encoded = encode_zrle(data, width, height, pixel_format, compression)?;
+    let hash = output_hash(&encoded);
+    Ok((encoded, hash))
+}
+
+/// Returns the uncompressed ZRLE tile stream for debugging.
+///
+/// This is the concatenated tile data that [`encode_zrle`] would zlib-compress, with
+/// no length prefix, so sub-encoding bytes can be inspected directly. It is also the
+/// TRLE payload for the same 64x64 tiling.
+///
+/// # Errors
+///
+/// Returns an error if the input buffer is too small
+pub fn debug_tile_stream(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> std::io::Result<Vec<u8>> {
+    let mut tiles = BytesMut::new();
+    encode_frame_tiles(
+        &mut tiles,
+        data,
+        width,
+        height,
+        pixel_format,
+        &ZrleOptions::default(),
+        FrameStats::default(),
+    )?;
+    Ok(tiles.to_vec())
+}
+
+/// Strips the length prefix and zlib layer from a ZRLE rectangle, returning its tile
+/// stream: the TRLE body for the same 64x64 tiling (see [`debug_tile_stream`]).
+///
+/// ZRLE compresses every rectangle of a connection on one zlib stream, so
+/// `decompressor` must be the connection's decompressor, fed the rectangles in the
+/// order they were received; a fresh one only works for the first rectangle.
+///
+/// # Errors
+///
+/// Returns an error if `encoded` is shorter than its length prefix says or the zlib
+/// data is corrupt
+#[allow(clippy::cast_possible_truncation)] // Input consumed by one call is bounded by the slice length
+pub fn zrle_to_trle(encoded: &[u8], decompressor: &mut Decompress) -> std::io::Result<Vec<u8>> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    let Some((prefix, rest)) = encoded.split_first_chunk::<4>() else {
+        return Err(invalid(format!(
+            "ZRLE: {} bytes is too short for the length prefix",
+            encoded.len()
+        )));
+    };
+    let len = u32::from_be_bytes(*prefix) as usize;
+    let input = rest.get(..len).ok_or_else(|| {
+        invalid(format!(
+            "ZRLE: length prefix says {len} bytes but only {} follow",
+            rest.len()
+        ))
+    })?;
+
+    let mut tiles = Vec::with_capacity(len * 4 + 1024);
+    let mut consumed = 0;
+    loop {
+        let (before_in, before_out) = (decompressor.total_in(), decompressor.total_out());
+        let status =
+            decompressor.decompress_vec(&input[consumed..], &mut tiles, FlushDecompress::Sync)?;
+        consumed += (decompressor.total_in() - before_in) as usize;
+        let output_full = tiles.len() == tiles.capacity();
+        if status == Status::StreamEnd || (consumed == input.len() && !output_full) {
+            return Ok(tiles);
+        }
+        if !output_full && decompressor.total_out() == before_out && consumed < input.len() {
+            return Err(invalid(
+                "ZRLE: zlib data stopped making progress".to_string(),
+            ));
+        }
+        tiles.reserve(tiles.capacity().max(1024));
+    }
+}
+
+/// Compresses a TRLE tile stream into a ZRLE rectangle: the inverse of [`zrle_to_trle`].
+///
+/// `tiles` must use ZRLE's 64x64 tiling. As with [`encode_zrle_persistent`],
+/// `compressor` must be the connection's persistent zlib stream.
+///
+/// # Errors
+///
+/// Returns an error if zlib compression fails or the compressed payload is 4 GiB or larger
+pub fn trle_to_zrle(tiles: &[u8], compressor: &mut Compress) -> std::io::Result<Vec<u8>> {
+    compress_tile_stream(tiles, compressor, false)
+}
+
+/// Checks that an encoded ZRLE rectangle holds exactly the zlib data its 4-byte
+/// length prefix declares, so a proxy can catch truncated or padded rectangles
+/// before forwarding them.
+///
+/// Only the framing is checked: the zlib data belongs to the connection's
+/// persistent stream and cannot be inflated on its own.
+///
+/// # Errors
+///
+/// Returns [`FramingError::Truncated`] if the prefix or the data is cut short, and
+/// [`FramingError::TrailingBytes`] if bytes follow the declared data
+pub fn validate_framing(data: &[u8]) -> Result<(), FramingError> {
+    let mut cursor = FramingCursor::new(data);
+    let prefix = cursor.take(4)?;
+    let len = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+    cursor.take(len as usize)?;
+    cursor.finish()
+}
+
+/// Position, size and sub-encoding of one tile in a ZRLE rectangle, as listed by
+/// [`inspect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileInfo {
+    /// Left edge of the tile within the rectangle.
+    pub x: u16,
+    /// Top edge of the tile within the rectangle.
+    pub y: u16,
+    /// Tile width (64 except at the right edge).
+    pub w: u16,
+    /// Tile height (64 except at the bottom edge).
+    pub h: u16,
+    /// Sub-encoding byte as sent: 0 raw, 1 solid, 2-16 packed palette, 128 plain
+    /// RLE, 130-255 palette RLE.
+    pub subencoding: u8,
+    /// Palette entries sent with the tile: 1 for solid tiles, 0 for raw and plain RLE.
+    pub palette_len: usize,
+}
+
+/// Lists the tiles of a ZRLE rectangle with their sub-encodings, without
+/// reconstructing pixels.
+///
+/// The zlib layer is inflated on a fresh stream, so this works on the output of
+/// [`encode_zrle`] or on the first rectangle of a connection; for later rectangles
+/// of a persistent stream, inflate with [`zrle_to_trle`] and call [`inspect_tiles`].
+///
+/// # Errors
+///
+/// Returns an error if the zlib data is corrupt or the tile stream is truncated or
+/// uses an unassigned sub-encoding
+pub fn inspect(
+    encoded: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> std::io::Result<Vec<TileInfo>> {
+    let tiles = zrle_to_trle(encoded, &mut Decompress::new(true))?;
+    inspect_tiles(&tiles, width, height, pixel_format)
+}
+
+/// Lists the tiles of an uncompressed ZRLE tile stream (see [`zrle_to_trle`]).
+///
+/// Only tile headers, palettes and run lengths are read; pixel bodies are skipped
+/// using their computed lengths.
+///
+/// # Errors
+///
+/// Returns an error if the stream is truncated, has bytes after the last tile, or
+/// uses an unassigned sub-encoding
+pub fn inspect_tiles(
+    tiles: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> std::io::Result<Vec<TileInfo>> {
+    let cpixel = FormatInfo::new(pixel_format).cpixel_size;
+    let mut pos = 0;
+    let mut take = |len: usize| -> std::io::Result<&[u8]> {
+        let bytes = tiles.get(pos..pos + len).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("ZRLE: tile stream truncated at offset {pos} (need {len} bytes)"),
+            )
+        })?;
+        pos += len;
+        Ok(bytes)
+    };
+
+    let mut infos = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            let (w, h) = ((width - x).min(64), (height - y).min(64));
+            let pixels = usize::from(w) * usize::from(h);
+            let subencoding = take(1)?[0];
+            let palette_len = match subencoding {
+                0 => {
+                    take(pixels * cpixel)?;
+                    0
+                }
+                1 => {
+                    take(cpixel)?;
+                    1
+                }
+                2..=16 => {
+                    let palette_len = usize::from(subencoding);
+                    let row_bytes =
+                        (usize::from(w) * packed_bits_per_pixel(palette_len)).div_ceil(8);
+                    take(palette_len * cpixel + row_bytes * usize::from(h))?;
+                    palette_len
+                }
+                128 | 130..=255 => {
+                    let palette_len = usize::from(subencoding - 128);
+                    take(palette_len * cpixel)?;
+                    let mut covered = 0;
+                    while covered < pixels {
+                        let has_run = if palette_len == 0 {
+                            take(cpixel)?;
+                            true
+                        } else {
+                            take(1)?[0] & 0x80 != 0
+                        };
+                        covered += 1;
+                        if has_run {
+                            loop {
+                                let byte = take(1)?[0];
+                                covered += usize::from(byte);
+                                if byte != 255 {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    palette_len
+                }
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("ZRLE: unassigned sub-encoding {subencoding} at ({x}, {y})"),
+                    ))
+                }
+            };
+            infos.push(TileInfo {
+                x,
+                y,
+                w,
+                h,
+                subencoding,
+                palette_len,
+            });
+        }
+    }
+
+    if pos != tiles.len() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("ZRLE: {} bytes after the last tile", tiles.len() - pos),
+        ));
+    }
+    Ok(infos)
+}
+
+/// Picks the highest compression level (0-9) at which [`encode_zrle`] of `sample`
+/// finishes within `time_budget`.
+///
+/// Levels are timed in increasing order on a fresh zlib stream and the search stops
+/// at the first one over budget, so the cost is a handful of encodes of the sample.
+/// Level 0 is the floor: it is returned when even level 0 misses the budget, or when
+/// `sample` is too small for `width` x `height`. Encode time depends on content as
+/// well as size, so pass a representative frame.
+#[must_use]
+pub fn auto_compression(
+    sample: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    time_budget: Duration,
+) -> u8 {
+    let mut best = 0;
+    for level in 0..=9 {
+        let start = Instant::now();
+        if encode_zrle(sample, width, height, pixel_format, level).is_err()
+            || start.elapsed() > time_budget
+        {
+            break;
+        }
+        best = level;
+    }
+    best
+}
+
+/// Shared implementation of the non-persistent [`encode_zrle`] variants.
+#[allow(clippy::cast_possible_truncation)] // ZRLE protocol requires u8/u16/u32 packing of pixel data
+fn encode_zrle_impl(
+    data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+    compression: u8,
+    options: &ZrleOptions,
+    stats: FrameStats<'_>,
+) -> std::io::Result<Vec<u8>> {
+    let mut uncompressed_data = BytesMut::new();
+    encode_frame_tiles(
+        &mut uncompressed_data,
+        data,
+        width,
+        height,
+        pixel_format,
+        options,
+        stats,
+    )?;
+
+    let compressed = compress_finish(
+        &mut ZlibStream::new(compression_level(compression), options.strategy),
+        &uncompressed_data,
+    )?;
+
+    // ZRLE requires a 4-byte big-endian length prefix before the zlib data
+    let mut result = BytesMut::with_capacity(4 + compressed.len());
+    put_length_prefix(&mut result, compressed.len())?;
+    result.extend_from_slice(&compressed);
+
+    Ok(result.to_vec())
+}
+
+/// Maps the VNC compression knob (0-9) to a zlib level; levels above 9 map like 9.
+fn compression_level(compression: u8) -> Compression {
+    match compression {
+        0 => Compression::fast(),
+        1..=3 => Compression::new(u32::from(compression)),
+        4..=6 => Compression::default(),
+        _ => Compression::best(),
+    }
+}
+
+/// A ZRLE encoder owning the persistent zlib stream for one client connection.
+///
+/// RFC 6143 requires a single zlib stream per connection, so frames must be encoded
+/// in order with the same session and the client must keep one decompressor.
+/// The compression level uses the same 0-9 mapping as [`encode_zrle`].
+pub struct ZrleSession {
+    compressor: ZlibStream,
+    options: ZrleOptions,
+    /// Uncompressed tile data, kept so its capacity is reused from frame to frame
+    tiles: BytesMut,
+}
+
+impl ZrleSession {
+    /// Creates a session whose zlib stream uses the level for `compression` (0-9).
+    #[must_use]
+    pub fn new(compression: u8) -> Self {
+        Self::with_options(compression, ZrleOptions::default())
+    }
+
+    /// Creates a session with non-default [`ZrleOptions`].
+    #[must_use]
+    pub fn with_options(compression: u8, options: ZrleOptions) -> Self {
+        Self {
+            compressor: ZlibStream::new(compression_level(compression), options.strategy),
+            options,
+            tiles: BytesMut::new(),
+        }
+    }
+
+    /// Encodes a rectangle on this session's persistent stream.
+    ///
+    /// See [`encode_zrle_persistent`] for the input and output format.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if zlib compression fails, if the input buffer is too small, or if the
+    /// compressed payload is 4 GiB or larger
+    pub fn encode(
+        &mut self,
+        data: &[u8],
+        width: u16,
+        height: u16,
+        pixel_format: &PixelFormat,
+    ) -> std::io::Result<Vec<u8>> {
+        encode_zrle_persistent_impl(
+            data,
+            width,
+            height,
+            pixel_format,
+            &self.options,
+            &mut self.compressor,
+            &mut self.tiles,
+        )
+    }
+
+    /// Starts over after the desktop is resized to `new_width` x `new_height`.
+    ///
+    /// Resets the zlib stream, keeping its level, so no earlier frame is referenced,
+    /// and replaces the tile buffer with one no larger than the previous frame's tile
+    /// data or the new desktop, which grows as later frames need. The next frame is a
+    /// keyframe on a fresh zlib stream: the client must start a new decompressor for
+    /// it too, e.g. as part of a reconnect or a renegotiated encoding. A client that
+    /// keeps its stream across the `DesktopSize` update, as RFC 6143 clients do, must
+    /// stay on the current session instead.
+    pub fn on_resize(&mut self, new_width: u16, new_height: u16) {
+        self.compressor.reset();
+        let previous = self.tiles.len();
+        let pixels = usize::from(new_width) * usize::from(new_height);
+        self.tiles = BytesMut::with_capacity(previous.min(pixels * 4));
+    }
+}
+
+/// ZRLE sub-encoding byte marking an XOR-delta tile, sent by [`ZrleDeltaSession`]
+/// in the non-standard XOR mode (`zrle-xor-delta` feature).
+///
+/// It is followed by a standard tile whose pixels are the XOR of the current and
+/// previous frame's pixels. RFC 6143 leaves sub-encodings 17-127 unassigned.
+pub const ZRLE_XOR_DELTA: u8 = 127;
+
+/// How a [`ZrleDeltaSession`] sends a frame that follows one of the same size.
+#[cfg(feature = "zrle-xor-delta")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZrleDeltaMode {
+    /// Unchanged tiles become `CopyRect` rectangles and changed ones standard ZRLE.
+    #[default]
+    CopyRect,
+    /// Non-standard: the frame is one ZRLE rectangle in which every tile with at
+    /// least half its pixels unchanged is sent as [`ZRLE_XOR_DELTA`] against the
+    /// previous frame, which zlib squeezes to almost nothing for slowly changing
+    /// content. Only for clients that decode it, e.g. with
+    /// [`crate::decode::decode_zrle_xor_delta`].
+    Xor,
+}
+
+/// A [`ZrleSession`] that only re-encodes the 64x64 tiles that changed since the
+/// previous frame.
+///
+/// Each tile's CRC-32 is remembered between frames. Unchanged tiles are reported as
+/// `CopyRect` rectangles from their own position, which leave the client's pixels
+/// as they are; runs of changed tiles in a tile row are ZRLE-encoded on the
+/// session's persistent stream. The first frame, and any frame whose size differs
+/// from the previous one, is encoded in full. Call [`ZrleDeltaSession::reset`]
+/// when the client's framebuffer may no longer match the last frame (e.g. after a
+/// pixel format change). A CRC collision would hide a change, with a chance of
+/// about 1 in 2^32 per changed tile. The `zrle-xor-delta` feature adds a
+/// non-standard mode that sends XOR deltas against the previous frame instead.
+pub struct ZrleDeltaSession {
+    session: ZrleSession,
+    /// Frame size and row-major tile CRCs of the previous frame
+    previous: Option<(u16, u16, Vec<u32>)>,
+    #[cfg(feature = "zrle-xor-delta")]
+    mode: ZrleDeltaMode,
+    /// Pixels of the previous frame, kept in XOR mode only
+    #[cfg(feature = "zrle-xor-delta")]
+    reference: Vec<u8>,
+}
+
+/// The rectangles produced by [`ZrleDeltaSession::encode`] for one frame.
+#[derive(Debug, Clone, Default)]
+pub struct ZrleDeltaFrame {
+    /// ZRLE rectangles for the changed tiles, in the order they were encoded. They
+    /// share one zlib stream, so they must be sent in this order.
+    pub zrle: Vec<EncodedRect>,
+    /// `CopyRect` rectangles covering the unchanged tiles, each copying from its
+    /// own position.
+    pub copy: Vec<EncodedRect>,
+}
+
+impl ZrleDeltaSession {
+    /// Creates a delta session whose zlib stream uses the level for `compression` (0-9).
+    #[must_use]
+    pub fn new(compression: u8) -> Self {
+        Self {
+            session: ZrleSession::new(compression),
+            previous: None,
+            #[cfg(feature = "zrle-xor-delta")]
+            mode: ZrleDeltaMode::CopyRect,
+            #[cfg(feature = "zrle-xor-delta")]
+            reference: Vec::new(),
+        }
+    }
+
+    /// Creates a delta session that sends changes in `mode`.
+    #[cfg(feature = "zrle-xor-delta")]
+    #[must_use]
+    pub fn with_mode(compression: u8, mode: ZrleDeltaMode) -> Self {
+        Self {
+            mode,
+            ..Self::new(compression)
+        }
+    }
+
+    /// Forgets the previous frame, so the next frame is encoded in full.
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    /// Encodes a frame, re-encoding only the tiles that changed since the last call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input buffer is too small or zlib compression fails
+    #[allow(clippy::cast_possible_truncation)] // Tile offsets and widths stay within the u16 frame
+    pub fn encode(
+        &mut self,
+        data: &[u8],
+        width: u16,
+        height: u16,
+        pixel_format: &PixelFormat,
+    ) -> std::io::Result<ZrleDeltaFrame> {
+        let bpp = bytes_per_pixel(pixel_format);
+        let (width_px, height_px) = (usize::from(width), usize::from(height));
+        let expected_size = checked_frame_size(width_px, height_px, bpp)?;
+        if data.len() < expected_size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "ZRLE: buffer size mismatch: got {} bytes, expected {} bytes for {}x{} with {} bpp",
+                    data.len(),
+                    expected_size,
+                    width,
+                    height,
+                    bpp
+                ),
+            ));
+        }
+
+        #[cfg(feature = "zrle-xor-delta")]
+        if self.mode == ZrleDeltaMode::Xor {
+            return self.encode_xor(&data[..expected_size], width, height, pixel_format);
+        }
+
+        let tiles_x = width_px.div_ceil(TILE_SIZE);
+        let tiles_y = height_px.div_ceil(TILE_SIZE);
+        let mut crcs = Vec::with_capacity(tiles_x * tiles_y);
+        for ty in 0..tiles_y {
+            for tx in 0..tiles_x {
+                let (x, y) = (tx * TILE_SIZE, ty * TILE_SIZE);
+                let tile_w = TILE_SIZE.min(width_px - x);
+                let tile_h = TILE_SIZE.min(height_px - y);
+                let mut crc = Crc::new();
+                for row in y..y + tile_h {
+                    let start = (row * width_px + x) * bpp;
+                    crc.update(&data[start..start + tile_w * bpp]);
+                }
+                crcs.push(crc.sum());
+            }
+        }
+
+        let changed: Vec<bool> = match &self.previous {
+            Some((w, h, previous)) if (*w, *h) == (width, height) => {
+                crcs.iter().zip(previous).map(|(a, b)| a != b).collect()
+            }
+            _ => vec![true; crcs.len()],
+        };
+
+        let mut frame = ZrleDeltaFrame::default();
+        for ty in 0..tiles_y {
+            let y = ty * TILE_SIZE;
+            let run_h = TILE_SIZE.min(height_px - y);
+            let mut tx = 0;
+            while tx < tiles_x {
+                // Group equal neighbours in this tile row into one rectangle
+                let run_changed = changed[ty * tiles_x + tx];
+                let start = tx;
+                while tx < tiles_x && changed[ty * tiles_x + tx] == run_changed {
+                    tx += 1;
+                }
+                let x = start * TILE_SIZE;
+                let run_w = (tx * TILE_SIZE).min(width_px) - x;
+
+                let mut rect = EncodedRect {
+                    x: x as u16,
+                    y: y as u16,
+                    width: run_w as u16,
+                    height: run_h as u16,
+                    encoding: ENCODING_COPYRECT,
+                    data: BytesMut::with_capacity(4),
+                };
+                if run_changed {
+                    let mut region = Vec::with_capacity(run_w * run_h * bpp);
+                    for row in y..y + run_h {
+                        let offset = (row * width_px + x) * bpp;
+                        region.extend_from_slice(&data[offset..offset + run_w * bpp]);
+                    }
+                    let encoded =
+                        self.session
+                            .encode(&region, rect.width, rect.height, pixel_format)?;
+                    rect.encoding = ENCODING_ZRLE;
+                    rect.data.extend_from_slice(&encoded);
+                    frame.zrle.push(rect);
+                } else {
+                    rect.data.put_u16(rect.x); // src-x-position
+                    rect.data.put_u16(rect.y); // src-y-position
+                    frame.copy.push(rect);
+                }
+            }
+        }
+
+        self.previous = Some((width, height, crcs));
+        Ok(frame)
+    }
+
+    /// [`ZrleDeltaMode::Xor`]: one ZRLE rectangle with XOR-delta tiles where they pay off
+    #[cfg(feature = "zrle-xor-delta")]
+    fn encode_xor(
+        &mut self,
+        data: &[u8],
+        width: u16,
+        height: u16,
+        pixel_format: &PixelFormat,
+    ) -> std::io::Result<ZrleDeltaFrame> {
+        let reference = match self.previous {
+            Some((w, h, _)) if (w, h) == (width, height) => Some(&self.reference[..]),
+            _ => None,
+        };
+        let session = &mut self.session;
+        session.tiles.clear();
+        encode_xor_delta_tiles(
+            &mut session.tiles,
+            data,
+            reference,
+            usize::from(width),
+            usize::from(height),
+            pixel_format,
+            &session.options,
+        );
+        let encoded = compress_tile_stream(
+            &session.tiles,
+            &mut session.compressor,
+            session.options.strict_flush,
+        )?;
+
+        self.previous = Some((width, height, Vec::new()));
+        self.reference.clear();
+        self.reference.extend_from_slice(data);
+        Ok(ZrleDeltaFrame {
+            zrle: vec![EncodedRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+                encoding: ENCODING_ZRLE,
+                data: BytesMut::from(&encoded[..]),
+            }],
+            copy: Vec::new(),
+        })
+    }
+}
+
+/// Appends the tiles of `data` to `tiles`, sending a tile as [`ZRLE_XOR_DELTA`]
+/// against `reference` (the previous frame) when at least half its pixels are unchanged
+#[cfg(feature = "zrle-xor-delta")]
+fn encode_xor_delta_tiles(
+    tiles: &mut BytesMut,
+    data: &[u8],
+    reference: Option<&[u8]>,
+    width: usize,
+    height: usize,
+    pixel_format: &PixelFormat,
+    options: &ZrleOptions,
+) {
+    let mut ctx = FrameContext::new(pixel_format, options);
+    ctx.two_color = TwoColorFrame::detect(data, &ctx.fmt);
+    // Deltas hold colors of their own, so the frame's two-color shortcut does not apply
+    let delta_ctx = FrameContext::new(pixel_format, options);
+    let bpp = ctx.fmt.bpp;
+
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            let tile_w = (width - x).min(TILE_SIZE);
+            let tile_h = (height - y).min(TILE_SIZE);
+            let tile = TileView::in_frame(data, width, x, y, tile_w, tile_h, bpp);
+
+            if let Some(reference) = reference {
+                let previous = TileView::in_frame(reference, width, x, y, tile_w, tile_h, bpp);
+                let unchanged = tile
+                    .pixels(bpp)
+                    .zip(previous.pixels(bpp))
+                    .filter(|(a, b)| a == b)
+                    .count();
+                if unchanged * 2 >= tile_w * tile_h {
+                    let delta: Vec<u8> = tile
+                        .rows()
+                        .zip(previous.rows())
+                        .flat_map(|(a, b)| a.iter().zip(b).map(|(a, b)| a ^ b))
+                        .collect();
+                    tiles.put_u8(ZRLE_XOR_DELTA);
+                    let delta = TileView::contiguous(&delta, tile_w, tile_h, bpp);
+                    encode_tile(tiles, delta, tile_w, tile_h, &delta_ctx, None);
+                    continue;
+                }
+            }
+            encode_tile(tiles, tile, tile_w, tile_h, &ctx, None);
+        }
+    }
+}
+
+/// Encodes one ZRLE tile (at most 64x64) into `buf` for callers doing their own tiling.
+///
+/// `tile_data` holds `width * height` pixels in the client's pixel format, row-major
+/// with no padding. The tile is appended uncompressed: callers must concatenate tiles
+/// in ZRLE order (left to right, top to bottom), zlib-compress the result and prefix
+/// it with its 4-byte big-endian length, as [`encode_zrle`] does.
+///
+/// # Errors
+///
+/// Returns an error if the tile is larger than 64x64 or `tile_data` is too small
+pub fn encode_zrle_tile(
+    buf: &mut BytesMut,
+    tile_data: &[u8],
+    width: u16,
+    height: u16,
+    pixel_format: &PixelFormat,
+) -> std::io::Result<()> {
+    let width = width as usize;
+    let height = height as usize;
+    if width > TILE_SIZE || height > TILE_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("ZRLE: tile {width}x{height} exceeds {TILE_SIZE}x{TILE_SIZE}"),
+        ));
+    }
+    let ctx = FrameContext::new(pixel_format, &ZrleOptions::default());
+    let expected_size = width * height * ctx.fmt.bpp;
+    if tile_data.len() < expected_size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "ZRLE: tile buffer size mismatch: got {} bytes, expected {} bytes",
+                tile_data.len(),
+                expected_size
+            ),
+        ));
+    }
+    let tile = TileView::contiguous(tile_data, width, height, ctx.fmt.bpp);
+    encode_tile(buf, tile, width, height, &ctx, None);
+    Ok(())
+}
+
+/// Encodes a single tile, choosing the best sub-encoding, and returns the one used.
+/// Handles variable pixel formats according to RFC 6143.
+#[allow(clippy::cast_possible_truncation)] // ZRLE palette indices and run lengths limited to u8 per RFC 6143
+fn encode_tile(
+    buf: &mut BytesMut,
+    tile: TileView<'_>,
+    width: usize,
+    height: usize,
+    ctx: &FrameContext,
+    histogram: Option<&mut ColorHistogram>,
+) -> SubEncoding {
+    let fmt = &ctx.fmt;
+    let cpixel_size = fmt.cpixel_size;
+    let bpp = fmt.bpp;
+
+    // Quick check for solid color by scanning pixel data directly (avoid allocation)
+    let mut tile_pixels = tile.pixels(bpp);
+    if let Some(first) = tile_pixels.next() {
+        let first_pixel = read_pixel(first, fmt);
+        let is_solid = tile_pixels.all(|chunk| read_pixel(chunk, fmt) == first_pixel);
+
+        if is_solid {
+            if let Some(histogram) = histogram {
+                histogram.add(first_pixel, width * height);
+            }
+            if ctx.options.solid_tile_as_palette || !ctx.options.compat.solid {
+                return encode_solid_fallback(buf, first_pixel, width, height, ctx);
+            }
+            encode_solid_color_tile(buf, first_pixel, fmt);
+            return SubEncoding::Solid;
+        }
+    }
+
+    // Convert to u32 pixels for analysis, reading the rows in place
+    let pixels: Vec<u32> = tile
+        .pixels(bpp)
+        .map(|chunk| read_pixel(chunk, fmt))
+        .collect();
+
+    // Two-color frames reuse the cached palette instead of scanning the tile
+    let collected;
+    let mut palette_size = None;
+    let (runs, single_pixels, palette, cached_map) = if let Some(frame) = &ctx.two_color {
+        let (runs, single_pixels) = count_runs(&pixels);
+        let order = frame.order_for(pixels[0]);
+        (
+            runs,
+            single_pixels,
+            &order.palette[..],
+            Some(&order.color_to_idx),
+        )
+    } else if sampled_colors_exceed(
+        &pixels,
+        ctx.options.palette_sample_stride,
+        ZRLE_MAX_RLE_PALETTE,
+    ) {
+        // Too many colors for any palette: only raw and plain RLE remain
+        let (runs, single_pixels) = count_runs(&pixels);
+        palette_size = Some(ZRLE_MAX_RLE_PALETTE + 1);
+        (runs, single_pixels, &[][..], None)
+    } else {
+        let (runs, single_pixels, palette) = analyze_runs_and_palette(&pixels);
+        collected = palette;
+        (runs, single_pixels, &collected[..], None)
+    };
+
+    if let Some(histogram) = histogram {
+        for &pixel in &pixels {
+            histogram.add(pixel, 1);
+        }
+    }
+
+    let (mut chosen, _) = choose_subencoding(
+        width,
+        height,
+        cpixel_size,
+        runs,
+        single_pixels,
+        palette_size.unwrap_or(palette.len()),
+        &ctx.options,
+    );
+    if chosen == SubEncoding::PlainRle
+        && ctx
+            .options
+            .raw_transition_ratio
+            .is_some_and(|ratio| transition_ratio(runs + single_pixels, pixels.len()) >= ratio)
+    {
+        // High-entropy tile: leave the repetition to zlib
+        chosen = SubEncoding::Raw;
+    }
+    let use_palette = matches!(chosen, SubEncoding::PackedPalette | SubEncoding::PaletteRle);
+    let use_rle = matches!(chosen, SubEncoding::PlainRle | SubEncoding::PaletteRle);
+
+    if use_palette {
+        // Palette (Packed Palette or Packed Palette RLE)
+        // Build index lookup from palette (preserves insertion order)
+        let built;
+        let color_to_idx = if let Some(map) = cached_map {
+            map
+        } else {
+            built = palette
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| (c, i as u8))
+                .collect::<HashMap<_, _>>();
+            &built
+        };
+
+        if use_rle {
+            // Packed Palette RLE
+            encode_packed_palette_rle_tile(buf, &pixels, palette, color_to_idx, fmt);
+        } else {
+            // Packed Palette (no RLE)
+            encode_packed_palette_tile(buf, &pixels, width, height, palette, color_to_idx, fmt);
+        }
+    } else {
+        // Raw or Plain RLE
+        if use_rle {
+            // Plain RLE - encode directly to buffer (avoid intermediate Vec)
+            buf.put_u8(128);
+            encode_rle_to_buf(buf, &pixels, fmt);
+        } else {
+            // Raw
+            encode_raw_tile(buf, &pixels, fmt);
+        }
+    }
+    chosen
+}
+
+/// Counts one tile sent with `mode` into `modes`.
+fn count_mode(modes: &mut SubEncodingHistogram, mode: SubEncoding) {
+    match mode {
+        SubEncoding::Raw => modes.raw += 1,
+        SubEncoding::Solid => modes.solid += 1,
+        SubEncoding::PackedPalette => modes.packed_palette += 1,
+        SubEncoding::PlainRle => modes.plain_rle += 1,
+        SubEncoding::PaletteRle => modes.palette_rle += 1,
+    }
+}
+
+/// A tile read in place from a larger frame, one row slice at a time.
+///
+/// Solid and palette detection walk the rows straight from the source buffer, so
+/// tiles of a wide framebuffer are analysed without first being copied out.
+#[derive(Clone, Copy)]
+struct TileView<'a> {
+    data: &'a [u8],
+    /// Byte offset of the tile's first row in `data`
+    start: usize,
+    /// Bytes from one source row to the next
+    stride: usize,
+    /// Bytes in one tile row
+    row_bytes: usize,
+    height: usize,
+    /// Yield source rows last to first, for bottom-up input
+    bottom_up: bool,
+}
+
+impl<'a> TileView<'a> {
+    /// A tile stored row-major with no padding.
+    fn contiguous(data: &'a [u8], width: usize, height: usize, bpp: usize) -> Self {
+        Self {
+            data,
+            start: 0,
+            stride: width * bpp,
+            row_bytes: width * bpp,
+            height,
+            bottom_up: false,
+        }
+    }
+
+    /// The `width` x `height` tile at (`x`, `y`) of a frame `frame_width` pixels wide.
+    fn in_frame(
+        frame: &'a [u8],
+        frame_width: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        bpp: usize,
+    ) -> Self {
+        Self {
+            data: frame,
+            start: (y * frame_width + x) * bpp,
+            stride: frame_width * bpp,
+            row_bytes: width * bpp,
+            height,
+            bottom_up: false,
+        }
+    }
+
+    /// The same tile with its rows in reverse order.
+    fn flipped(self) -> Self {
+        Self {
+            bottom_up: !self.bottom_up,
+            ..self
+        }
+    }
+
+    /// The tile's rows, top to bottom as sent.
+    fn rows(self) -> impl Iterator<Item = &'a [u8]> {
+        (0..self.height).map(move |row| {
+            let row = if self.bottom_up {
+                self.height - 1 - row
+            } else {
+                row
+            };
+            let start = self.start + row * self.stride;
+            &self.data[start..start + self.row_bytes]
+        })
+    }
+
+    /// The tile's pixels in sending order, `bpp` bytes each.
+    fn pixels(self, bpp: usize) -> impl Iterator<Item = &'a [u8]> {
+        self.rows().flat_map(move |row| row.chunks_exact(bpp))
+    }
+
+    /// Copies the tile out row-major, for the paths that need it contiguous.
+    fn to_vec(self) -> Vec<u8> {
+        let mut tile = Vec::with_capacity(self.row_bytes * self.height);
+        for row in self.rows() {
+            tile.extend_from_slice(row);
+        }
+        tile
+    }
+}
+
+/// Converts pixel data to u32 values for internal processing.
+/// Works with any pixel format by using the pixel format's bytes per pixel.
+fn pixels_to_u32(data: &[u8], fmt: &FormatInfo) -> Vec<u32> {
+    data.chunks_exact(fmt.bpp)
+        .map(|chunk| read_pixel(chunk, fmt))
+        .collect()
+}
+
+/// Bits per index for the packed palette sub-encoding (palettes of 2-16 colors).
+///
+/// Fixed by RFC 6143: 1 bit for 2 colors, 2 bits for 3-4 and 4 bits for 5-16.
+#[inline]
+fn packed_bits_per_pixel(palette_size: usize) -> usize {
+    match palette_size {
+        2 => 1,
+        3..=4 => 2,
+        _ => 4, // 5-16 colors
+    }
+}
+
+/// Sub-encoding for a tile with a single color.
+fn encode_solid_color_tile(buf: &mut BytesMut, color: u32, fmt: &FormatInfo) {
+    buf.put_u8(1); // Solid color sub-encoding
+    write_cpixel(buf, color, fmt);
+}
+
+/// Client-bug workaround for solid tiles: a 2-color packed palette with both
+/// entries set to `color` and every index 0 (see [`ZrleOptions::solid_tile_as_palette`]).
+fn encode_solid_as_palette_tile(
+    buf: &mut BytesMut,
+    color: u32,
+    width: usize,
+    height: usize,
+    fmt: &FormatInfo,
+) {
+    buf.put_u8(2); // Packed palette sub-encoding, 2 colors
+    write_cpixel(buf, color, fmt);
+    write_cpixel(buf, color, fmt);
+    // 1 bit per pixel, rows padded to a byte boundary, all indices 0
+    buf.put_bytes(0, width.div_ceil(8) * height);
+}
+
+/// Sends a solid tile without the solid sub-encoding, using the first one
+/// [`ZrleOptions::compat`] allows out of a 2-entry packed palette, a 2-entry
+/// palette RLE, plain RLE and raw.
+fn encode_solid_fallback(
+    buf: &mut BytesMut,
+    color: u32,
+    width: usize,
+    height: usize,
+    ctx: &FrameContext,
+) -> SubEncoding {
+    let (fmt, compat) = (&ctx.fmt, ctx.options.compat);
+    if compat.packed_palette {
+        encode_solid_as_palette_tile(buf, color, width, height, fmt);
+        return SubEncoding::PackedPalette;
+    }
+    let pixels = vec![color; width * height];
+    if compat.palette_rle {
+        // A 1-entry palette RLE would be sub-encoding 129, which RFC 6143 leaves unused
+        let color_to_idx = HashMap::from([(color, 0)]);
+        encode_packed_palette_rle_tile(buf, &pixels, &[color, color], &color_to_idx, fmt);
+        SubEncoding::PaletteRle
+    } else if compat.plain_rle {
+        buf.put_u8(128);
+        encode_rle_to_buf(buf, &pixels, fmt);
+        SubEncoding::PlainRle
+    } else {
+        encode_raw_tile(buf, &pixels, fmt);
+        SubEncoding::Raw
+    }
+}
+
+/// Sub-encoding for raw pixel data.
+fn encode_raw_tile(buf: &mut BytesMut, pixels: &[u32], fmt: &FormatInfo) {
+    buf.put_u8(0); // Raw sub-encoding
+    for &pixel in pixels {
+        write_cpixel(buf, pixel, fmt);
+    }
+}
+
+/// Sub-encoding for a tile with a small palette.
+#[allow(clippy::cast_possible_truncation)] // ZRLE palette size limited to 16 colors (u8) per RFC 6143
+fn encode_packed_palette_tile(
+    buf: &mut BytesMut,
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+    palette: &[u32],
+    color_to_idx: &HashMap<u32, u8>,
+    fmt: &FormatInfo,
+) {
+    let palette_size = palette.len();
+    let bits_per_pixel = packed_bits_per_pixel(palette_size);
+    debug_assert!(
+        (2..=ZRLE_MAX_PACKED_PALETTE).contains(&palette_size)
+            && palette_size <= 1 << bits_per_pixel,
+        "{palette_size} colors cannot be packed at {bits_per_pixel} bits"
+    );
+
+    buf.put_u8(palette_size as u8); // Packed palette sub-encoding
+
+    // Write palette as CPIXEL - in insertion order
+    for &color in palette {
+        write_cpixel(buf, color, fmt);
+    }
+    let indices_start = buf.len();
+
+    // Write packed pixel data ROW BY ROW per RFC 6143 ZRLE specification
+    // Critical: Each row must be byte-aligned
+    for row in 0..height {
+        let mut packed_byte = 0;
+        let mut nbits = 0;
+        let row_start = row * width;
+        let row_end = row_start + width;
 
         for &pixel in &pixels[row_start..row_end] {
             let idx = color_to_idx[&pixel];
@@ -591,6 +2370,13 @@ fn encode_packed_palette_tile(
             buf.put_u8(packed_byte);
         }
     }
+
+    // Decoders read exactly this many index bytes: byte-aligned rows at the width
+    // the palette size implies
+    debug_assert_eq!(
+        buf.len() - indices_start,
+        height * (width * bits_per_pixel).div_ceil(8)
+    );
 }
 
 /// Sub-encoding for a tile with a small palette and RLE.
@@ -600,14 +2386,14 @@ fn encode_packed_palette_rle_tile(
     pixels: &[u32],
     palette: &[u32],
     color_to_idx: &HashMap<u32, u8>,
-    pf: &PixelFormat,
+    fmt: &FormatInfo,
 ) {
     let palette_size = palette.len();
     buf.put_u8(128 | (palette_size as u8)); // Packed palette RLE sub-encoding
 
     // Write palette as CPIXEL
     for &color in palette {
-        write_cpixel(buf, color, pf);
+        write_cpixel(buf, color, fmt);
     }
 
     // Write RLE data using palette indices per RFC 6143 specification
@@ -641,7 +2427,7 @@ fn encode_packed_palette_rle_tile(
 
 /// Encodes pixel data using run-length encoding directly to buffer (optimized).
 #[allow(clippy::cast_possible_truncation)] // ZRLE run lengths encoded as u8 per RFC 6143
-fn encode_rle_to_buf(buf: &mut BytesMut, pixels: &[u32], pf: &PixelFormat) {
+fn encode_rle_to_buf(buf: &mut BytesMut, pixels: &[u32], fmt: &FormatInfo) {
     let mut i = 0;
     while i < pixels.len() {
         let color = pixels[i];
@@ -650,7 +2436,7 @@ fn encode_rle_to_buf(buf: &mut BytesMut, pixels: &[u32], pf: &PixelFormat) {
             run_len += 1;
         }
         // Write CPIXEL
-        write_cpixel(buf, color, pf);
+        write_cpixel(buf, color, fmt);
 
         // Encode run length - 1 per RFC 6143 ZRLE specification
         // Length encoding: write 255 for each full 255-length chunk, then remainder
@@ -667,38 +2453,82 @@ fn encode_rle_to_buf(buf: &mut BytesMut, pixels: &[u32], pf: &PixelFormat) {
 }
 
 /// Implements the VNC "ZRLE" (Zlib Run-Length Encoding).
-pub struct ZrleEncoding;
+///
+/// Each call uses a fresh zlib stream; use [`ZrleSession`] for a client connection.
+/// When ZRLE fails (for example on an undersized buffer), the rectangle is encoded
+/// with the `fallback` encoding instead (see [`crate::encode_fallback`]). The
+/// [`Encoding`] trait cannot say which encoding was used, so servers that enable a
+/// fallback should call [`ZrleEncoding::encode_with_fallback`] to label the
+/// rectangle correctly.
+#[derive(Debug, Clone, Copy)]
+pub struct ZrleEncoding {
+    /// Encoding used when ZRLE fails, or `None` to produce no output.
+    pub fallback: Option<i32>,
+}
 
-impl Encoding for ZrleEncoding {
-    fn encode(
+impl Default for ZrleEncoding {
+    /// Falls back to Raw, as earlier versions did.
+    fn default() -> Self {
+        Self {
+            fallback: Some(crate::ENCODING_RAW),
+        }
+    }
+}
+
+impl ZrleEncoding {
+    /// Encodes as ZRLE, or with the fallback encoding if ZRLE fails.
+    ///
+    /// Returns the encoding type actually used together with its data.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ZRLE error when no fallback is set, or an error if the fallback
+    /// encoding is unsupported.
+    pub fn encode_with_fallback(
         &self,
         data: &[u8],
         width: u16,
         height: u16,
-        _quality: u8,
+        quality: u8,
         compression: u8,
-    ) -> BytesMut {
+    ) -> std::io::Result<(i32, BytesMut)> {
         // ZRLE doesn't use quality, but it does use compression.
         let pixel_format = PixelFormat::rgba32(); // Assuming RGBA32 for now
-        if let Ok(encoded_data) = encode_zrle(data, width, height, &pixel_format, compression) {
-            BytesMut::from(&encoded_data[..])
-        } else {
-            // Fallback to Raw encoding if ZRLE fails.
-            let mut buf = BytesMut::with_capacity(data.len());
-            for chunk in data.chunks_exact(4) {
-                buf.put_u8(chunk[0]); // R
-                buf.put_u8(chunk[1]); // G
-                buf.put_u8(chunk[2]); // B
-                buf.put_u8(0); // Padding
+        match encode_zrle(data, width, height, &pixel_format, compression) {
+            Ok(encoded_data) => Ok((ENCODING_ZRLE, BytesMut::from(&encoded_data[..]))),
+            Err(e) => {
+                let Some(fallback) = self.fallback else {
+                    return Err(e);
+                };
+                let buf =
+                    crate::encode_fallback(fallback, data, width, height, quality, compression)?;
+                Ok((fallback, buf))
             }
-            buf
         }
     }
 }
 
+impl Encoding for ZrleEncoding {
+    fn encode(
+        &self,
+        data: &[u8],
+        width: u16,
+        height: u16,
+        quality: u8,
+        compression: u8,
+    ) -> BytesMut {
+        crate::common::encode_or_raw_on_panic("ZRLE", data, width, height, || {
+            self.encode_with_fallback(data, width, height, quality, compression)
+                .map(|(_, buf)| buf)
+                .unwrap_or_default()
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::ZLIB_SYNC_MARKER;
     use crate::PixelFormat;
 
     /// Test that reproduces the GitHub issue #1 buffer overflow.
@@ -742,6 +2572,705 @@ mod tests {
         );
     }
 
+    /// Edge tiles 1-3 pixels wide or tall pack palette indices into padded bytes
+    /// per row; every sub-encoding must still round-trip exactly.
+    #[test]
+    fn test_edge_tiles_one_to_three_pixels_round_trip() {
+        type Pattern = fn(usize, usize) -> usize;
+
+        let pf = PixelFormat::rgba32();
+        let color = |index: usize| {
+            let [i, ..] = index.to_le_bytes();
+            [
+                i.wrapping_mul(37),
+                i.wrapping_mul(91),
+                i.wrapping_mul(13),
+                0,
+            ]
+        };
+        let patterns: [(&str, Pattern); 6] = [
+            ("solid", |_, _| 0),
+            ("two-color", |x, y| (x + y) % 2),
+            ("three-color", |x, y| (x + 2 * y) % 3),
+            ("eleven-color", |x, y| (x * 3 + y * 5) % 11),
+            ("palette runs", |x, y| (x / 8 + y / 8) % 5),
+            ("long runs", |x, y| x / 16 + (y / 2) * 8),
+        ];
+
+        let mut edge_subencodings = std::collections::BTreeSet::new();
+        for width in [65u16, 66, 67] {
+            for height in [65u16, 66, 67] {
+                for (name, pattern) in patterns {
+                    let data: Vec<u8> = (0..height as usize)
+                        .flat_map(|y| (0..width as usize).flat_map(move |x| color(pattern(x, y))))
+                        .collect();
+                    let encoded = encode_zrle(&data, width, height, &pf, 6).unwrap();
+                    let mut decompressor = Decompress::new(true);
+                    let decoded =
+                        crate::decode::decode_zrle(&encoded, width, height, &pf, &mut decompressor)
+                            .unwrap();
+                    assert!(decoded == data, "{name} at {width}x{height}");
+
+                    for tile in inspect(&encoded, width, height, &pf).unwrap() {
+                        if tile.w < 4 || tile.h < 4 {
+                            edge_subencodings.insert(tile.subencoding);
+                        }
+                    }
+                }
+            }
+        }
+        // The narrow edge tiles covered solid, 1/2/4-bit packed, plain and palette RLE
+        for subencoding in [1, 2, 3, 11, 128] {
+            assert!(
+                edge_subencodings.contains(&subencoding),
+                "{subencoding} missing from {edge_subencodings:?}"
+            );
+        }
+        assert!(edge_subencodings.iter().any(|&s| s >= 130));
+    }
+
+    /// Builds a 32x32 RGBA tile matching one quadrant of the 64x64 golden fixture.
+    fn fixture_quadrant(solid: bool) -> Vec<u8> {
+        let mut data = Vec::with_capacity(32 * 32 * 4);
+        for y in 0..32 {
+            for x in 0..32 {
+                let px = if solid {
+                    [0, 0, 200, 255]
+                } else if (x + y) % 2 == 0 {
+                    [255, 255, 255, 255]
+                } else {
+                    [0, 0, 0, 255]
+                };
+                data.extend_from_slice(&px);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_analyze_tile_solid_quadrant() {
+        let pf = PixelFormat::rgba32();
+        let analysis = analyze_tile(&fixture_quadrant(true), 32, 32, &pf);
+        assert_eq!(analysis.chosen, SubEncoding::Solid);
+        assert_eq!(analysis.palette_len, 1);
+        assert_eq!(analysis.runs, 1);
+        assert_eq!(analysis.singles, 0);
+    }
+
+    #[test]
+    fn test_analyze_tile_checkerboard_quadrant() {
+        let pf = PixelFormat::rgba32();
+        let analysis = analyze_tile(&fixture_quadrant(false), 32, 32, &pf);
+        assert_eq!(analysis.chosen, SubEncoding::PackedPalette);
+        assert_eq!(analysis.palette_len, 2);
+        // Each row boundary joins two same-colored pixels into a run of 2
+        assert_eq!(analysis.runs, 31);
+        assert_eq!(analysis.singles, 32 * 32 - 62);
+
+        let sizes = analysis.estimated_sizes;
+        assert_eq!(sizes.raw, 32 * 32 * 3);
+        assert_eq!(sizes.plain_rle, 4 * (31 + 962));
+        assert_eq!(sizes.palette_rle, Some(2 * 3 + 2 * 31 + 962));
+        // 2 palette CPIXELs + 32 rows of 4 bytes (1 bit per pixel)
+        assert_eq!(sizes.packed_palette, Some(2 * 3 + 32 * 4));
+    }
+
+    #[test]
+    fn test_analyze_tile_gradient_prefers_raw() {
+        let pf = PixelFormat::rgba32();
+        let data: Vec<u8> = (0..64u8).flat_map(|i| [i, i, i, 255]).collect();
+        let analysis = analyze_tile(&data, 64, 1, &pf);
+        assert_eq!(analysis.chosen, SubEncoding::Raw);
+        assert_eq!(analysis.estimated_sizes.palette_rle, Some(64 * 3 + 64));
+        assert_eq!(analysis.estimated_sizes.packed_palette, None);
+    }
+
+    #[test]
+    fn test_32bit_presets_use_3_byte_cpixels() {
+        for (pf, lower_bytes) in [
+            (PixelFormat::rgbx32(), true),
+            (PixelFormat::bgrx32(), true),
+            (PixelFormat::xrgb32(), false),
+            (PixelFormat::abgr32(), false),
+        ] {
+            assert_eq!(bytes_per_cpixel(&pf), 3, "{pf:?}");
+            // Little-endian: 24A (bytes 0-2) when colors sit in the low bytes, else 24B
+            assert_eq!(use_cpixel_24a(&pf), lower_bytes, "{pf:?}");
+        }
+    }
+
+    /// `pf` with the given byte order.
+    fn with_endianness(mut pf: PixelFormat, big_endian: bool) -> PixelFormat {
+        pf.big_endian_flag = u8::from(big_endian);
+        pf
+    }
+
+    #[test]
+    fn test_read_pixel_is_host_independent() {
+        // Expected values are built with shifts, never with the host byte order
+        let value32 = 0x11u32 << 24 | 0x22 << 16 | 0x33 << 8 | 0x44;
+        let value16 = 0xABu32 << 8 | 0xCD;
+        let cases = [
+            (
+                PixelFormat::rgba32(),
+                false,
+                vec![0x44, 0x33, 0x22, 0x11],
+                value32,
+            ),
+            (
+                PixelFormat::rgba32(),
+                true,
+                vec![0x11, 0x22, 0x33, 0x44],
+                value32,
+            ),
+            (PixelFormat::rgb565(), false, vec![0xCD, 0xAB], value16),
+            (PixelFormat::rgb565(), true, vec![0xAB, 0xCD], value16),
+            (PixelFormat::bgr233(), true, vec![0x5A], 0x5A),
+        ];
+        for (pf, big_endian, bytes, expected) in cases {
+            let fmt = FormatInfo::new(&with_endianness(pf, big_endian));
+            assert_eq!(read_pixel(&bytes, &fmt), expected, "{bytes:02x?}");
+        }
+    }
+
+    #[test]
+    fn test_write_cpixel_roundtrips_through_read_cpixel() {
+        let mut rgb32_depth32 = PixelFormat::rgba32();
+        rgb32_depth32.depth = 32;
+        let formats = [
+            (PixelFormat::bgr233(), 1),
+            (PixelFormat::rgb565(), 2),
+            (PixelFormat::rgb555(), 2),
+            (PixelFormat::rgbx32(), 3),
+            (PixelFormat::xrgb32(), 3),
+            (rgb32_depth32, 4),
+        ];
+        for (pf, cpixel_size) in formats {
+            for big_endian in [false, true] {
+                let pf = with_endianness(pf.clone(), big_endian);
+                let fmt = FormatInfo::new(&pf);
+                assert_eq!(fmt.cpixel_size, cpixel_size, "{pf:?}");
+                for (r, g, b) in [(0u8, 0u8, 0u8), (1, 2, 3), (255, 128, 7), (255, 255, 255)] {
+                    let pixel = (u32::from(r) * u32::from(pf.red_max) / 255) << pf.red_shift
+                        | (u32::from(g) * u32::from(pf.green_max) / 255) << pf.green_shift
+                        | (u32::from(b) * u32::from(pf.blue_max) / 255) << pf.blue_shift;
+                    let mut buf = BytesMut::new();
+                    write_cpixel(&mut buf, pixel, &fmt);
+                    assert_eq!(buf.len(), cpixel_size);
+                    assert_eq!(read_cpixel_value(&buf, &fmt), pixel, "{pf:?} {pixel:08x}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_public_cpixel_roundtrip() {
+        // rgbx32 keeps colours in the low bytes, xrgb32 in the high bytes
+        let cases = [
+            (PixelFormat::rgb565(), false, 0xF81F, vec![0x1F, 0xF8]),
+            (PixelFormat::rgb565(), true, 0x07E0, vec![0x07, 0xE0]),
+            (
+                PixelFormat::rgbx32(),
+                false,
+                0x0033_2211,
+                vec![0x11, 0x22, 0x33],
+            ),
+            (
+                PixelFormat::rgbx32(),
+                true,
+                0x0033_2211,
+                vec![0x33, 0x22, 0x11],
+            ),
+            (
+                PixelFormat::xrgb32(),
+                false,
+                0x3322_1100,
+                vec![0x11, 0x22, 0x33],
+            ),
+            (
+                PixelFormat::xrgb32(),
+                true,
+                0x3322_1100,
+                vec![0x33, 0x22, 0x11],
+            ),
+        ];
+        for (pf, big_endian, pixel, expected) in cases {
+            let pf = with_endianness(pf, big_endian);
+            let bytes = cpixel_bytes(pixel, &pf);
+            assert_eq!(bytes, expected, "{pf:?}");
+            assert_eq!(read_cpixel(&bytes, &pf), (pixel, bytes.len()), "{pf:?}");
+        }
+
+        let mut depth32 = PixelFormat::rgba32();
+        depth32.depth = 32;
+        for pixel in [0, 0x0102_0304, 0xFFFF_FFFF] {
+            let bytes = cpixel_bytes(pixel, &depth32);
+            assert_eq!(bytes, pixel.to_le_bytes());
+            // Trailing bytes are left for the caller
+            let mut data = bytes.clone();
+            data.push(0xEE);
+            assert_eq!(read_cpixel(&data, &depth32), (pixel, 4));
+        }
+    }
+
+    #[test]
+    fn test_zrle_encoding_falls_back_to_raw() {
+        let data: Vec<u8> = (0..16u8).flat_map(|i| [i, 2 * i, 3 * i, 255]).collect();
+        let short = &data[..data.len() - 8];
+
+        let (encoding, buf) = ZrleEncoding::default()
+            .encode_with_fallback(short, 4, 4, 0, 6)
+            .unwrap();
+        assert_eq!(encoding, crate::ENCODING_RAW);
+        // Raw RGBX covering the whole rectangle, missing pixels black
+        assert_eq!(buf.len(), 4 * 4 * 4);
+        for (out, input) in buf.chunks_exact(4).zip(short.chunks_exact(4)) {
+            assert_eq!(out, [input[0], input[1], input[2], 0]);
+        }
+        assert!(buf[short.len()..].iter().all(|&b| b == 0));
+        assert_eq!(ZrleEncoding::default().encode(short, 4, 4, 0, 6), buf);
+
+        let strict = ZrleEncoding { fallback: None };
+        assert!(strict.encode_with_fallback(short, 4, 4, 0, 6).is_err());
+        assert!(strict.encode(short, 4, 4, 0, 6).is_empty());
+        let (encoding, _) = strict.encode_with_fallback(&data, 4, 4, 0, 6).unwrap();
+        assert_eq!(encoding, ENCODING_ZRLE);
+    }
+
+    /// 64x64 tile cycling through `colors` distinct colors with no two neighbours equal.
+    #[allow(clippy::cast_possible_truncation)] // Test palettes stay below 256 colors
+    fn cycling_tile(colors: usize) -> Vec<u8> {
+        (0..64 * 64)
+            .flat_map(|i| [(i % colors) as u8, 0, 0, 255])
+            .collect()
+    }
+
+    /// Frame of four colors in horizontal runs of 16 pixels
+    #[allow(clippy::cast_possible_truncation)] // Band indices stay below 4
+    fn run_frame(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let band = ((i % width) / 16 + i / width) % 4;
+                [band as u8 * 60, 255 - band as u8 * 60, 40, 255]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stats_count_every_tile() {
+        let pf = PixelFormat::rgba32();
+        let frame = run_frame(64, 64);
+
+        // One tile of a few colors with runs
+        let mut modes = SubEncodingHistogram::default();
+        let encoded = encode_zrle_with_stats(&frame, 64, 64, &pf, 6, &mut modes).unwrap();
+        assert_eq!(encoded, encode_zrle(&frame, 64, 64, &pf, 6).unwrap());
+        assert_eq!(modes.total(), 1);
+        assert_eq!(modes.palette_rle, 1);
+
+        // Counts accumulate: a 100x75 frame adds 2x2 tiles
+        encode_zrle_with_stats(&run_frame(100, 75), 100, 75, &pf, 6, &mut modes).unwrap();
+        assert_eq!(modes.total(), 5);
+    }
+
+    #[test]
+    fn test_palette_limit_boundaries() {
+        let pf = PixelFormat::rgba32();
+        let cases = [
+            (ZRLE_MAX_PACKED_PALETTE, SubEncoding::PackedPalette),
+            (ZRLE_MAX_PACKED_PALETTE + 1, SubEncoding::PaletteRle),
+            (ZRLE_MAX_RLE_PALETTE, SubEncoding::PaletteRle),
+            (ZRLE_MAX_RLE_PALETTE + 1, SubEncoding::Raw),
+        ];
+        for (colors, expected) in cases {
+            let analysis = analyze_tile(&cycling_tile(colors), 64, 64, &pf);
+            assert_eq!(analysis.palette_len, colors);
+            assert_eq!(analysis.chosen, expected, "{colors} colors");
+
+            let sizes = analysis.estimated_sizes;
+            assert_eq!(
+                sizes.packed_palette.is_some(),
+                colors <= ZRLE_MAX_PACKED_PALETTE
+            );
+            assert_eq!(sizes.palette_rle.is_some(), colors <= ZRLE_MAX_RLE_PALETTE);
+        }
+    }
+
+    #[test]
+    fn test_zrle_session_reuses_dictionary() {
+        use flate2::{Decompress, FlushDecompress};
+
+        // Noisy frame that zlib cannot shrink much on its own
+        let mut state = 0x1234_5678u32;
+        let data: Vec<u8> = (0..64 * 64 * 4)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state.to_le_bytes()[0]
+            })
+            .collect();
+        let pf = PixelFormat::rgba32();
+
+        let mut session = ZrleSession::new(6);
+        let first = session.encode(&data, 64, 64, &pf).unwrap();
+        let second = session.encode(&data, 64, 64, &pf).unwrap();
+        assert!(
+            second.len() * 4 < first.len(),
+            "second frame should reference the first: {} vs {} bytes",
+            second.len(),
+            first.len()
+        );
+
+        // Both frames inflate to the same tile data on one client-side stream
+        let mut decompressor = Decompress::new(true);
+        let mut inflate = |encoded: &[u8]| {
+            let mut out = Vec::with_capacity(64 * 64 * 4 + 1024);
+            decompressor
+                .decompress_vec(&encoded[4..], &mut out, FlushDecompress::Sync)
+                .unwrap();
+            out
+        };
+        assert_eq!(inflate(&first), inflate(&second));
+    }
+
+    /// 256x256 terminal-like frame: light text glyph pattern on a dark background.
+    fn two_color_frame() -> Vec<u8> {
+        let mut data = Vec::with_capacity(256 * 256 * 4);
+        for y in 0..256 {
+            for x in 0..256 {
+                let ink = y % 16 < 10 && (x * 7 + y * 3) % 11 < 4 && x % 8 != 7;
+                let px = if ink {
+                    [230, 230, 230, 255]
+                } else {
+                    [20, 20, 40, 255]
+                };
+                data.extend_from_slice(&px);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_two_color_frame_skips_palette_analysis() {
+        let data = two_color_frame();
+        let pf = PixelFormat::rgba32();
+        let mut ctx = FrameContext::new(&pf, &ZrleOptions::default());
+        let scan_ctx = FrameContext::new(&pf, &ZrleOptions::default());
+        ctx.two_color = TwoColorFrame::detect(&data, &ctx.fmt);
+        assert!(ctx.two_color.is_some(), "frame has two colors");
+
+        // The cached palette must give exactly the tiles a palette scan gives
+        for y in (0..256).step_by(TILE_SIZE) {
+            for x in (0..256).step_by(TILE_SIZE) {
+                let tile = TileView::in_frame(&data, 256, x, y, TILE_SIZE, TILE_SIZE, 4);
+                let mut scanned = BytesMut::new();
+                let mode = encode_tile(&mut scanned, tile, TILE_SIZE, TILE_SIZE, &scan_ctx, None);
+                let mut cached = BytesMut::new();
+                encode_tile(&mut cached, tile, TILE_SIZE, TILE_SIZE, &ctx, None);
+
+                assert_eq!(mode, SubEncoding::PackedPalette);
+                assert_eq!(cached, scanned, "tile at ({x}, {y})");
+            }
+        }
+
+        // The public encoder takes the fast path on its own
+        let encoded = encode_zrle(&data, 256, 256, &pf, 6).unwrap();
+        let decoded =
+            crate::decode::decode_zrle(&encoded, 256, 256, &pf, &mut Decompress::new(true))
+                .unwrap();
+        for (out, input) in decoded.chunks_exact(4).zip(data.chunks_exact(4)) {
+            assert_eq!(out[..3], input[..3]);
+        }
+    }
+
+    #[test]
+    fn test_palette_sampling_skips_scan_without_changing_output() {
+        let pf = PixelFormat::rgba32();
+        let sampled_ctx = FrameContext::new(&pf, &ZrleOptions::default());
+        let full_ctx = FrameContext::new(
+            &pf,
+            &ZrleOptions {
+                palette_sample_stride: 0,
+                ..ZrleOptions::default()
+            },
+        );
+        let stride = ZrleOptions::default().palette_sample_stride;
+        let encode = |tile: &[u8], ctx: &FrameContext| {
+            let mut buf = BytesMut::new();
+            let tile = TileView::contiguous(tile, TILE_SIZE, TILE_SIZE, 4);
+            encode_tile(&mut buf, tile, TILE_SIZE, TILE_SIZE, ctx, None);
+            buf
+        };
+        let exceeds = |tile: &[u8], stride| {
+            let pixels = pixels_to_u32(tile, &sampled_ctx.fmt);
+            sampled_colors_exceed(&pixels, stride, ZRLE_MAX_RLE_PALETTE)
+        };
+
+        let mut state = 0x5EED_1234u32;
+        let photo: Vec<u8> = (0..TILE_SIZE * TILE_SIZE)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let [r, g, b, _] = state.to_be_bytes();
+                [r, g, b, 255]
+            })
+            .collect();
+        assert!(
+            exceeds(&photo, stride),
+            "photographic tile skips the palette scan"
+        );
+        assert!(!exceeds(&photo, 0), "a zero stride disables sampling");
+        let sampled = encode(&photo, &sampled_ctx);
+        assert_eq!(sampled[0], 0, "raw sub-encoding");
+        assert_eq!(sampled, encode(&photo, &full_ctx));
+
+        let low_color = cycling_tile(5);
+        assert!(!exceeds(&low_color, stride), "low-color tile is scanned");
+        let sampled = encode(&low_color, &sampled_ctx);
+        assert_eq!(sampled[0], 5, "packed palette of 5 colors");
+        assert_eq!(sampled, encode(&low_color, &full_ctx));
+    }
+
+    #[test]
+    fn test_two_color_detection_rejects_other_frames() {
+        let fmt = FormatInfo::new(&PixelFormat::rgba32());
+        let solid = [1u8, 2, 3, 255].repeat(16);
+        assert!(TwoColorFrame::detect(&solid, &fmt).is_none());
+
+        let mut three = two_color_frame();
+        three[4..8].copy_from_slice(&[255, 0, 0, 255]);
+        assert!(TwoColorFrame::detect(&three, &fmt).is_none());
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)] // noise pattern is reduced mod 251
+    fn test_session_reuses_tile_buffer() {
+        let pf = PixelFormat::rgba32();
+        let (width, height) = (128u16, 96u16);
+        let data: Vec<u8> = (0..usize::from(width) * usize::from(height) * 4)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect();
+
+        let mut session = ZrleSession::new(6);
+        let mut compressor = Compress::new(Compression::new(6), true);
+        let mut first_buffer = None;
+        for _ in 0..10 {
+            let reused = session.encode(&data, width, height, &pf).unwrap();
+            let fresh = encode_zrle_persistent(&data, width, height, &pf, &mut compressor).unwrap();
+            assert_eq!(reused, fresh);
+
+            // Only the first frame allocates; later ones write into the same buffer
+            let buffer = (session.tiles.as_ptr(), session.tiles.capacity());
+            assert_eq!(*first_buffer.get_or_insert(buffer), buffer);
+        }
+    }
+
+    #[test]
+    fn test_plain_rle_runs_ending_on_tile_boundary() {
+        let pf = PixelFormat::rgba32();
+        // Plain RLE or raw only, so solid tiles are sent as one run too
+        let options = ZrleOptions {
+            compat: ZrleCompat {
+                solid: false,
+                packed_palette: false,
+                palette_rle: false,
+                plain_rle: true,
+            },
+            ..ZrleOptions::default()
+        };
+        let (solid, run, single) = ([10, 20, 30, 0], [40, 50, 60, 0], [70, 80, 90, 0]);
+        // 128x68: two 64x64 tiles over two 64x4 tiles. The left tiles are solid: one
+        // run of 4096, and one of 256 whose length - 1 is exactly 255. The top right
+        // tile is a run of 4095 ending one pixel before the tile does; the bottom
+        // right one is a single pixel, then a run of 255 to the end of the tile.
+        let data: Vec<u8> = (0..128 * 68)
+            .flat_map(|i| {
+                let pos = (i % 128, i / 128);
+                if pos.0 < 64 {
+                    solid
+                } else if pos == (127, 63) || pos == (64, 64) {
+                    single
+                } else {
+                    run
+                }
+            })
+            .collect();
+
+        let encoded = encode_zrle_with_options(&data, 128, 68, &pf, 6, &options).unwrap();
+        let tiles = zrle_to_trle(&encoded, &mut Decompress::new(true)).unwrap();
+        let rle = |color: [u8; 4], lengths: &[u8]| [&color[..3], lengths].concat();
+        let mut expected = vec![128];
+        expected.extend(rle(solid, &[[255; 16].as_slice(), &[15]].concat()));
+        expected.push(128);
+        expected.extend(rle(run, &[[255; 16].as_slice(), &[14]].concat()));
+        expected.extend(rle(single, &[0]));
+        expected.push(128);
+        expected.extend(rle(solid, &[255, 0]));
+        expected.push(128);
+        expected.extend(rle(single, &[0]));
+        expected.extend(rle(run, &[254]));
+        assert_eq!(tiles, expected);
+
+        // No byte is left over after the last tile, and every pixel decodes
+        let infos = inspect_tiles(&tiles, 128, 68, &pf).unwrap();
+        assert!(infos.iter().all(|info| info.subencoding == 128));
+        assert_eq!(
+            crate::decode::decode_zrle_tiles(&tiles, 128, 68, &pf).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_on_resize_starts_a_fresh_stream() {
+        let pf = PixelFormat::rgba32();
+        let frame = |width: usize, height: usize, seed: usize| -> Vec<u8> {
+            // Alpha is not sent, so it decodes as 0
+            (0..width * height * 4)
+                .map(|i| {
+                    if i % 4 == 3 {
+                        0
+                    } else {
+                        (i * seed % 251).to_le_bytes()[0]
+                    }
+                })
+                .collect()
+        };
+        let small = frame(100, 75, 31);
+        let large = frame(160, 120, 17);
+
+        let mut session = ZrleSession::new(6);
+        let first = session.encode(&small, 100, 75, &pf).unwrap();
+        session.on_resize(160, 120);
+        let second = session.encode(&large, 160, 120, &pf).unwrap();
+
+        // Each frame decodes on its own decompressor, so neither depends on the other
+        for (encoded, width, height, data) in
+            [(&first, 100, 75, &small), (&second, 160, 120, &large)]
+        {
+            let mut decompressor = Decompress::new(true);
+            let decoded =
+                crate::decode::decode_zrle(encoded, width, height, &pf, &mut decompressor).unwrap();
+            assert_eq!(&decoded, data);
+        }
+
+        // And the resized session matches a brand-new one
+        assert_eq!(
+            second,
+            ZrleSession::new(6).encode(&large, 160, 120, &pf).unwrap()
+        );
+
+        // The tile buffer is sized from the last frame, not the new desktop
+        let previous = session.tiles.len();
+        session.on_resize(1920, 1080);
+        assert!(session.tiles.capacity() <= previous);
+        session.encode(&large, 160, 120, &pf).unwrap();
+        session.on_resize(8, 8);
+        assert!(session.tiles.capacity() <= 8 * 8 * 4);
+        let mut fresh = ZrleSession::new(6);
+        fresh.on_resize(1920, 1080);
+        assert_eq!(fresh.tiles.capacity(), 0);
+    }
+
+    #[test]
+    fn test_compression_above_9_behaves_as_9() {
+        let pf = PixelFormat::rgba32();
+        let data: Vec<u8> = (0..100 * 75 * 4usize)
+            .map(|i| (i * 13 % 251).to_le_bytes()[0])
+            .collect();
+        assert_eq!(
+            encode_zrle(&data, 100, 75, &pf, 15).unwrap(),
+            encode_zrle(&data, 100, 75, &pf, 9).unwrap()
+        );
+        assert_eq!(
+            ZrleSession::new(255).encode(&data, 100, 75, &pf).unwrap(),
+            ZrleSession::new(9).encode(&data, 100, 75, &pf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_strided_tile_encodes_like_a_copied_one() {
+        let pf = PixelFormat::rgba32();
+        let ctx = FrameContext::new(&pf, &ZrleOptions::default());
+        // 100x75 frame of runs, so tiles take the palette and RLE paths
+        let frame: Vec<u8> = (0..100 * 75)
+            .flat_map(|i: usize| {
+                let shade = u8::try_from(i / 7 % 5).unwrap() * 50;
+                [shade, 255 - shade, 0, 0]
+            })
+            .collect();
+
+        for (x, y, w, h) in [(0, 0, 64, 64), (64, 0, 36, 64), (64, 64, 36, 11)] {
+            let view = TileView::in_frame(&frame, 100, x, y, w, h, 4);
+            let copied: Vec<u8> = (y..y + h)
+                .flat_map(|row| frame[(row * 100 + x) * 4..(row * 100 + x + w) * 4].to_vec())
+                .collect();
+            assert_eq!(view.to_vec(), copied);
+
+            let mut in_place = BytesMut::new();
+            let mut from_copy = BytesMut::new();
+            encode_tile(&mut in_place, view, w, h, &ctx, None);
+            let copy_view = TileView::contiguous(&copied, w, h, 4);
+            encode_tile(&mut from_copy, copy_view, w, h, &ctx, None);
+            assert_eq!(in_place, from_copy, "tile at ({x}, {y})");
+
+            // Flipped views yield the source rows last to first
+            let flipped: Vec<u8> = copied.rchunks_exact(w * 4).flatten().copied().collect();
+            assert_eq!(view.flipped().to_vec(), flipped);
+        }
+    }
+
+    #[test]
+    fn test_delta_session_reencodes_only_changed_tile() {
+        let pf = PixelFormat::rgba32();
+        let mut data: Vec<u8> = (0..128u8)
+            .flat_map(|y| (0..128u8).flat_map(move |x| [x, y, x ^ y, 255]))
+            .collect();
+        let mut session = ZrleDeltaSession::new(6);
+
+        let first = session.encode(&data, 128, 128, &pf).unwrap();
+        assert!(first.copy.is_empty());
+        let first_bytes: usize = first.zrle.iter().map(|r| r.data.len()).sum();
+
+        // Change one pixel in the bottom-right tile
+        let offset = (100 * 128 + 100) * 4;
+        data[offset..offset + 4].copy_from_slice(&[255, 0, 0, 255]);
+        let second = session.encode(&data, 128, 128, &pf).unwrap();
+
+        assert_eq!(second.zrle.len(), 1);
+        let zrle = &second.zrle[0];
+        assert_eq!((zrle.x, zrle.y, zrle.width, zrle.height), (64, 64, 64, 64));
+        assert_eq!(zrle.encoding, crate::ENCODING_ZRLE);
+        assert!(
+            zrle.data.len() * 2 < first_bytes,
+            "{} bytes",
+            zrle.data.len()
+        );
+
+        let copies: Vec<_> = second
+            .copy
+            .iter()
+            .map(|r| (r.x, r.y, r.width, r.height, r.encoding, &r.data[..]))
+            .collect();
+        assert_eq!(
+            copies,
+            [
+                (0, 0, 128, 64, ENCODING_COPYRECT, &[0, 0, 0, 0][..]),
+                (0, 64, 64, 64, ENCODING_COPYRECT, &[0, 0, 0, 64][..]),
+            ]
+        );
+
+        // A repeated frame is all CopyRect; a reset re-encodes everything
+        let third = session.encode(&data, 128, 128, &pf).unwrap();
+        assert!(third.zrle.is_empty());
+        assert_eq!(third.copy.len(), 2);
+        session.reset();
+        assert!(session
+            .encode(&data, 128, 128, &pf)
+            .unwrap()
+            .copy
+            .is_empty());
+    }
+
     /// Test buffer size validation - should return error, not panic
     #[test]
     fn test_zrle_buffer_too_small() {
@@ -755,4 +3284,339 @@ mod tests {
         let result = encode_zrle(&data, width, height, &pf, 6);
         assert!(result.is_err(), "Should return error for undersized buffer");
     }
+
+    #[test]
+    fn test_auto_compression_follows_time_budget() {
+        let pf = PixelFormat::rgba32();
+        let sample = cycling_tile(40).repeat(4);
+        let (width, height) = (128, 128);
+
+        let generous = auto_compression(&sample, width, height, &pf, Duration::from_secs(60));
+        assert_eq!(generous, 9, "every level fits a minute");
+
+        let tiny = auto_compression(&sample, width, height, &pf, Duration::ZERO);
+        assert_eq!(
+            tiny, 0,
+            "nothing fits a zero budget, so level 0 is the floor"
+        );
+
+        let undersized = auto_compression(&sample[..16], width, height, &pf, Duration::MAX);
+        assert_eq!(undersized, 0);
+    }
+
+    #[cfg(feature = "zrle-xor-delta")]
+    #[test]
+    fn test_xor_delta_mode_shrinks_nearly_identical_frames() {
+        use crate::decode::{decode_zrle_tiles, decode_zrle_xor_delta};
+
+        let pf = PixelFormat::rgba32();
+        let mut state = 0x2468_ACE0u32;
+        let first: Vec<u8> = (0..128 * 128)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let [r, g, b, _] = state.to_be_bytes();
+                [r, g, b, 255]
+            })
+            .collect();
+        // Scattered single-pixel changes touch every tile, so CopyRect could not help
+        let mut second = first.clone();
+        for pixel in second.chunks_exact_mut(4).step_by(37) {
+            pixel[0] ^= 0x80;
+        }
+
+        let mut session = ZrleDeltaSession::with_mode(6, ZrleDeltaMode::Xor);
+        let frame1 = session.encode(&first, 128, 128, &pf).unwrap();
+        let frame2 = session.encode(&second, 128, 128, &pf).unwrap();
+        assert!(frame2.copy.is_empty());
+        let delta = &frame2.zrle[0].data;
+        let independent = encode_zrle(&second, 128, 128, &pf, 6).unwrap();
+        assert!(
+            delta.len() * 10 < independent.len(),
+            "delta {} bytes vs independent {} bytes",
+            delta.len(),
+            independent.len()
+        );
+
+        let mut decompressor = Decompress::new(true);
+        let tiles = zrle_to_trle(&frame1.zrle[0].data, &mut decompressor).unwrap();
+        let decoded1 = decode_zrle_tiles(&tiles, 128, 128, &pf).unwrap();
+        let tiles = zrle_to_trle(delta, &mut decompressor).unwrap();
+        assert_eq!(tiles[0], ZRLE_XOR_DELTA);
+        let decoded2 = decode_zrle_xor_delta(&tiles, &decoded1, 128, 128, &pf).unwrap();
+        for (decoded, expected) in [(&decoded1, &first), (&decoded2, &second)] {
+            for (out, input) in decoded.chunks_exact(4).zip(expected.chunks_exact(4)) {
+                assert_eq!(out[..3], input[..3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_packed_palette_bit_widths_round_trip() {
+        let pf = PixelFormat::rgba32();
+        // An odd width exercises the per-row padding
+        let (width, height) = (61usize, 7usize);
+        for (colors, bits) in [(2usize, 1usize), (3, 2), (4, 2), (5, 4), (16, 4)] {
+            let tile: Vec<u8> = (0..width * height)
+                .flat_map(|i| [u8::try_from(i % colors * 15).unwrap(), 0, 0, 255])
+                .collect();
+            let mut buf = BytesMut::new();
+            encode_zrle_tile(&mut buf, &tile, 61, 7, &pf).unwrap();
+
+            assert_eq!(usize::from(buf[0]), colors, "packed palette sub-encoding");
+            let index_bytes = height * (width * bits).div_ceil(8);
+            assert_eq!(
+                buf.len(),
+                1 + colors * 3 + index_bytes,
+                "{colors} colors must pack at {bits} bits per index"
+            );
+            let decoded = crate::decode::decode_zrle_tiles(&buf, 61, 7, &pf).unwrap();
+            for (out, input) in decoded.chunks_exact(4).zip(tile.chunks_exact(4)) {
+                assert_eq!(out[..3], input[..3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_size_overflow_is_an_error() {
+        let err = checked_frame_size(usize::MAX / 2, 3, 4).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(checked_frame_size(usize::MAX / 4, 1, 4).is_ok());
+
+        // The largest frame overflows on 32-bit targets and is merely too large for
+        // the buffer elsewhere; either way it must be an error, not a panic or a read
+        // past the end
+        let overflows = checked_frame_size(65535, 65535, 4).is_err();
+        assert_eq!(overflows, cfg!(target_pointer_width = "32"));
+        let mut compressor = Compress::new(Compression::default(), true);
+        for result in [
+            encode_zrle(&[0; 16], 65535, 65535, &PixelFormat::rgba32(), 6),
+            encode_zrle_persistent(
+                &[0; 16],
+                65535,
+                65535,
+                &PixelFormat::rgba32(),
+                &mut compressor,
+            ),
+        ] {
+            assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_edge_tiles_padded_to_full_tiles() {
+        let pf = PixelFormat::rgba32();
+        // 70x5: one full-width tile and one 6x5 edge tile
+        let (width, height) = (70u16, 5u16);
+        let data: Vec<u8> = (0..70 * 5)
+            .flat_map(|i| {
+                [
+                    u8::try_from(i % 70).unwrap(),
+                    u8::try_from(i / 70).unwrap(),
+                    9,
+                    255,
+                ]
+            })
+            .collect();
+        let mut clipped = BytesMut::new();
+        encode_frame_tiles(
+            &mut clipped,
+            &data,
+            width,
+            height,
+            &pf,
+            &ZrleOptions::default(),
+            FrameStats::default(),
+        )
+        .unwrap();
+
+        for edge in [EdgeTiles::Replicate, EdgeTiles::Zero] {
+            let options = ZrleOptions {
+                edge_tiles: edge,
+                ..ZrleOptions::default()
+            };
+            let mut tiles = BytesMut::new();
+            encode_frame_tiles(
+                &mut tiles,
+                &data,
+                width,
+                height,
+                &pf,
+                &options,
+                FrameStats::default(),
+            )
+            .unwrap();
+            assert_ne!(tiles, clipped, "{edge:?} tiles differ from clipped ones");
+
+            // Every tile decodes as a full 64x64 tile
+            let full = crate::decode::decode_zrle_tiles(&tiles, 128, 64, &pf).unwrap();
+            let pixel = |x: usize, y: usize| &full[(y * 128 + x) * 4..(y * 128 + x) * 4 + 3];
+            let (last_x, last_y) = (69, 4);
+            for (x, y) in [(100, 2), (69, 40), (127, 63)] {
+                let expected = match edge {
+                    EdgeTiles::Replicate => pixel(x.min(last_x), y.min(last_y)).to_vec(),
+                    _ => vec![0, 0, 0],
+                };
+                assert_eq!(pixel(x, y), &expected[..], "{edge:?} padding at ({x}, {y})");
+            }
+
+            let decoded =
+                crate::decode::decode_zrle_full_tiles(&tiles, width, height, &pf).unwrap();
+            for (out, input) in decoded.chunks_exact(4).zip(data.chunks_exact(4)) {
+                assert_eq!(out[..3], input[..3], "{edge:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_hash_matches_for_identical_rectangles() {
+        let pf = PixelFormat::rgba32();
+        let frame = |seed: u8| -> Vec<u8> {
+            (0..48 * 48)
+                .flat_map(|i: usize| [seed, u8::try_from(i % 48).unwrap(), 7, 255])
+                .collect()
+        };
+        let (first, first_hash) = encode_zrle_with_hash(&frame(1), 48, 48, &pf, 6).unwrap();
+        let (again, again_hash) = encode_zrle_with_hash(&frame(1), 48, 48, &pf, 6).unwrap();
+        let (_, other_hash) = encode_zrle_with_hash(&frame(2), 48, 48, &pf, 6).unwrap();
+
+        assert_eq!(first, again);
+        assert_eq!(first_hash, again_hash);
+        assert_ne!(first_hash, other_hash);
+        assert_eq!(first_hash, crate::output_hash(&first));
+        assert_eq!(first, encode_zrle(&frame(1), 48, 48, &pf, 6).unwrap());
+    }
+
+    #[test]
+    fn test_noisy_tile_selects_raw_above_transition_ratio() {
+        let pf = PixelFormat::rgba32();
+        // Random colors where about half of the pixels repeat their left neighbor:
+        // plain RLE is smaller before zlib, raw is smaller after it
+        let mut state = 0x2468_ACE0u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            state
+        };
+        let mut color = [0u8; 4];
+        let data: Vec<u8> = (0..128 * 64)
+            .flat_map(|_| {
+                if next() >> 31 == 0 {
+                    let [r, g, b, _] = next().to_be_bytes();
+                    color = [r, g, b, 255];
+                }
+                color
+            })
+            .collect();
+        let options = ZrleOptions {
+            raw_transition_ratio: Some(SUGGESTED_RAW_TRANSITION_RATIO),
+            ..ZrleOptions::default()
+        };
+        let tiles = |options: &ZrleOptions| {
+            let mut tiles = BytesMut::new();
+            encode_frame_tiles(
+                &mut tiles,
+                &data,
+                128,
+                64,
+                &pf,
+                options,
+                FrameStats::default(),
+            )
+            .unwrap();
+            tiles
+        };
+
+        assert_eq!(
+            tiles(&ZrleOptions::default())[0],
+            128,
+            "plain RLE by default"
+        );
+        assert_eq!(tiles(&options)[0], 0, "raw above the transition ratio");
+        let default_len = encode_zrle(&data, 128, 64, &pf, 6).unwrap().len();
+        let raw_len = encode_zrle_with_options(&data, 128, 64, &pf, 6, &options)
+            .unwrap()
+            .len();
+        assert!(raw_len < default_len, "{raw_len} vs {default_len} bytes");
+    }
+
+    #[test]
+    fn test_strict_flush_ends_every_rectangle_with_sync_marker() {
+        let pf = PixelFormat::rgba32();
+        let noise: Vec<u8> = (0..100u32 * 75 * 4)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13).to_le_bytes()[0])
+            .collect();
+        let solid = [40u8, 80, 120, 255].repeat(100 * 75);
+        // The empty rectangle follows a flushed one, leaving nothing new to compress
+        let frames: [(&[u8], u16, u16); 5] = [
+            (&noise, 100, 75),
+            (&solid, 100, 75),
+            (&[], 0, 0),
+            (&noise, 100, 75),
+            (&noise[..4], 1, 1),
+        ];
+
+        let options = ZrleOptions {
+            strict_flush: true,
+            ..ZrleOptions::default()
+        };
+        let mut session = ZrleSession::with_options(6, options);
+        let mut decompressor = Decompress::new(true);
+        for (data, width, height) in frames {
+            let encoded = session.encode(data, width, height, &pf).unwrap();
+            assert!(
+                encoded[4..].ends_with(&ZLIB_SYNC_MARKER),
+                "{width}x{height} rectangle does not end on a sync flush"
+            );
+            // The stream stays valid for the client, including the empty stored block
+            let mut out = Vec::with_capacity(64 * 64 * 4 * 4 + 1024);
+            decompressor
+                .decompress_vec(&encoded[4..], &mut out, FlushDecompress::Sync)
+                .unwrap();
+            let decoded = crate::decode::decode_zrle_tiles(&out, width, height, &pf).unwrap();
+            let rgb = |pixels: &[u8]| -> Vec<u8> {
+                pixels
+                    .chunks_exact(4)
+                    .flat_map(|p| [p[0], p[1], p[2]])
+                    .collect()
+            };
+            assert_eq!(rgb(&decoded), rgb(data));
+        }
+    }
+
+    #[test]
+    fn test_validate_framing_checks_length_prefix() {
+        let data = [10u8, 20, 30, 255].repeat(70 * 10);
+        let pf = PixelFormat::rgba32();
+        let mut session = ZrleSession::new(6);
+        for _ in 0..2 {
+            let encoded = session.encode(&data, 70, 10, &pf).unwrap();
+            let len = encoded.len();
+            assert_eq!(validate_framing(&encoded), Ok(()));
+            assert_eq!(
+                validate_framing(&encoded[..len - 1]),
+                Err(FramingError::Truncated {
+                    expected: len,
+                    actual: len - 1
+                })
+            );
+            let mut padded = encoded.clone();
+            padded.extend_from_slice(&[0, 0]);
+            assert_eq!(
+                validate_framing(&padded),
+                Err(FramingError::TrailingBytes {
+                    expected: len,
+                    actual: len + 2
+                })
+            );
+        }
+
+        // A cut-off prefix, and an empty rectangle
+        assert_eq!(
+            validate_framing(&[0, 0, 1]),
+            Err(FramingError::Truncated {
+                expected: 4,
+                actual: 3
+            })
+        );
+        assert_eq!(validate_framing(&[0, 0, 0, 0]), Ok(()));
+    }
 }
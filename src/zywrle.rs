@@ -408,8 +408,13 @@ fn pack_coeff(buf: &[i32], dst: &mut [u8], r: usize, width: usize, height: usize
 /// * `level` - ZYWRLE quality level (1-3, higher = more quality/less compression)
 /// * `buf` - Temporary coefficient buffer (must be at least width*height i32s)
 ///
+/// The coefficient buffer is only scratch space, so one buffer sized for the largest
+/// frame can be reused across frames to avoid allocating it per call. Its previous
+/// contents do not affect the result. [`ZywrleContext`] manages such a buffer.
+///
 /// # Returns
 /// Transformed pixel data ready for ZRLE encoding, or None if dimensions too small
+/// or `buf` holds fewer than `width * height` coefficients
 #[allow(clippy::uninit_vec)] // Performance optimization: all bytes written before return (see SAFETY comment)
 pub fn zywrle_analyze(
     src: &[u8],
@@ -418,6 +423,10 @@ pub fn zywrle_analyze(
     level: usize,
     buf: &mut [i32],
 ) -> Option<Vec<u8>> {
+    if buf.len() < width * height {
+        return None;
+    }
+
     let (w, h) = calc_aligned_size(width, height, level);
     if w == 0 || h == 0 {
         return None;
@@ -494,3 +503,88 @@ pub fn zywrle_analyze(
 
     Some(dst)
 }
+
+/// Reusable ZYWRLE state owning the wavelet coefficient buffer.
+///
+/// The buffer grows to fit the largest frame seen and is reused afterwards, so
+/// steady-state encoding does not allocate coefficient storage.
+#[derive(Debug, Default)]
+pub struct ZywrleContext {
+    buf: Vec<i32>,
+}
+
+impl ZywrleContext {
+    /// Creates a context with an empty coefficient buffer.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of coefficients the buffer can currently hold.
+    #[must_use]
+    pub fn buffer_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Performs ZYWRLE analysis, growing the coefficient buffer if needed.
+    ///
+    /// See [`zywrle_analyze`] for the arguments and return value.
+    pub fn analyze(
+        &mut self,
+        src: &[u8],
+        width: usize,
+        height: usize,
+        level: usize,
+    ) -> Option<Vec<u8>> {
+        let needed = width * height;
+        if self.buf.len() < needed {
+            self.buf.resize(needed, 0);
+        }
+        zywrle_analyze(src, width, height, level, &mut self.buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::cast_possible_truncation)] // Gradient values wrap intentionally
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| [(i * 3) as u8, (i * 5) as u8, (i * 7) as u8, 255])
+            .collect()
+    }
+
+    #[test]
+    fn test_undersized_buffer_returns_none() {
+        let src = gradient(64, 64);
+        let mut buf = vec![0i32; 64 * 64 - 1];
+        assert!(zywrle_analyze(&src, 64, 64, 1, &mut buf).is_none());
+    }
+
+    #[test]
+    fn test_context_reuses_buffer_across_frame_sizes() {
+        let mut ctx = ZywrleContext::new();
+
+        let small = gradient(64, 64);
+        let large = gradient(100, 75);
+
+        let first = ctx.analyze(&large, 100, 75, 2).unwrap();
+        assert_eq!(ctx.buffer_len(), 100 * 75);
+
+        // Smaller frame reuses the existing buffer despite its stale contents
+        let second = ctx.analyze(&small, 64, 64, 2).unwrap();
+        assert_eq!(ctx.buffer_len(), 100 * 75);
+
+        let mut fresh = vec![0i32; 64 * 64];
+        assert_eq!(
+            second,
+            zywrle_analyze(&small, 64, 64, 2, &mut fresh).unwrap()
+        );
+        let mut fresh = vec![0i32; 100 * 75];
+        assert_eq!(
+            first,
+            zywrle_analyze(&large, 100, 75, 2, &mut fresh).unwrap()
+        );
+    }
+}
@@ -6,6 +6,7 @@
 //! - Pixel data endianness follows the PixelFormat's big_endian_flag
 
 use flate2::read::ZlibDecoder;
+use flate2::Decompress;
 use rfb_encodings::PixelFormat;
 use std::io::Read;
 
@@ -175,6 +176,16 @@ pub fn decode_zrle(
     pf: &PixelFormat,
 ) -> Result<Vec<u8>, String> {
     let tile_data = decode_zrle_to_tiles(encoded)?;
+    decode_zrle_tile_stream(&tile_data, width, height, pf)
+}
+
+/// Decode uncompressed ZRLE tile data (the zlib payload) to raw pixels
+pub fn decode_zrle_tile_stream(
+    tile_data: &[u8],
+    width: u16,
+    height: u16,
+    pf: &PixelFormat,
+) -> Result<Vec<u8>, String> {
     let width = width as usize;
     let height = height as usize;
     let cpixel_size = bytes_per_cpixel(pf);
@@ -397,6 +408,20 @@ pub fn decode_zrle(
     Ok(output)
 }
 
+/// Decode ZlibHex encoding using a persistent decompressor
+/// Format: 4-byte length (big-endian) + zlib compressed Hextile data
+/// Delegates to the library's public decoder so client code and tests share one implementation
+pub fn decode_zlibhex(
+    encoded: &[u8],
+    width: u16,
+    height: u16,
+    pf: &PixelFormat,
+    decompressor: &mut Decompress,
+) -> Result<Vec<u8>, String> {
+    rfb_encodings::decode::decode_zlibhex(encoded, width, height, pf, decompressor)
+        .map_err(|e| format!("ZlibHex decode failed: {}", e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,50 @@
+// Encoder registration under reduced feature sets
+// Run with a minimal build: cargo test --test features --no-default-features --features zrle
+
+use rfb_encodings::{
+    get_encoder, ENCODING_CORRE, ENCODING_HEXTILE, ENCODING_RAW, ENCODING_RRE, ENCODING_TIGHT,
+    ENCODING_TIGHTPNG,
+};
+
+#[test]
+fn get_encoder_only_returns_enabled_encodings() {
+    // Raw is always available
+    assert!(get_encoder(ENCODING_RAW).is_some());
+
+    let gated = [
+        (ENCODING_RRE, cfg!(feature = "rre")),
+        (ENCODING_CORRE, cfg!(feature = "corre")),
+        (ENCODING_HEXTILE, cfg!(feature = "hextile")),
+        (ENCODING_TIGHT, cfg!(feature = "tight")),
+        (ENCODING_TIGHTPNG, cfg!(feature = "tightpng")),
+    ];
+    for (encoding, enabled) in gated {
+        assert_eq!(
+            get_encoder(encoding).is_some(),
+            enabled,
+            "encoding {encoding} registration does not match its feature"
+        );
+    }
+}
+
+#[test]
+fn raw_encoder_works_without_optional_encodings() {
+    let encoder = get_encoder(ENCODING_RAW).unwrap();
+    let data = [1, 2, 3, 255, 4, 5, 6, 255];
+    let encoded = encoder.encode(&data, 2, 1, 0, 0);
+    assert_eq!(encoded.len(), 8);
+}
+
+#[cfg(feature = "zrle")]
+#[test]
+fn zrle_is_usable_in_a_minimal_build() {
+    use flate2::{Compress, Compression};
+    use rfb_encodings::zrle::encode_zrle_persistent;
+    use rfb_encodings::PixelFormat;
+
+    let data = [0u8; 4 * 4 * 4];
+    let mut compressor = Compress::new(Compression::default(), true);
+    let encoded =
+        encode_zrle_persistent(&data, 4, 4, &PixelFormat::rgba32(), &mut compressor).unwrap();
+    assert!(encoded.len() > 4);
+}
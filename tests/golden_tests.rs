@@ -2,20 +2,49 @@
 // Run normally: cargo test --test golden_tests
 // Generate expected outputs: cargo test --test golden_tests --features generate-golden
 //
-// NOTE: Some encodings (Hextile, RRE, CoRRE) use HashMap which has non-deterministic
-// iteration order. These are tested for regression only - output may vary between runs
-// but should be consistent within the same build/platform.
-
-use flate2::{Compress, Compression};
+// NOTE: Hextile, RRE and CoRRE are smoke-tested only. Their background color choice
+// breaks ties by first appearance, so output is deterministic, but no goldens are kept.
+//
+// Requires the default encoding features; see tests/features.rs for reduced builds.
+
+#![cfg(all(
+    feature = "zrle",
+    feature = "tight",
+    feature = "hextile",
+    feature = "rre",
+    feature = "corre",
+    feature = "zlibhex",
+    feature = "zywrle"
+))]
+
+use bytes::BytesMut;
+use flate2::write::ZlibEncoder;
+use flate2::{Compress, Compression, Decompress};
+use rfb_encodings::corre::try_encode_corre;
+use rfb_encodings::rre::try_encode_rre;
+use rfb_encodings::tight::{
+    is_text_like, try_encode_tight_rects, SimpleTightCompressor, TightOptions,
+    STREAM_ID_FULL_COLOR, STREAM_ID_INDEXED,
+};
 use rfb_encodings::zlib::encode_zlib_persistent;
 use rfb_encodings::zlibhex::encode_zlibhex_persistent;
-use rfb_encodings::zrle::encode_zrle;
+use rfb_encodings::zrle::{
+    analyze_compression, analyze_tile, debug_tile_stream, encode_zrle, encode_zrle_tile,
+    encode_zrle_with_histogram, encode_zrle_with_options, trle_to_zrle, zrle_to_trle,
+    ColorHistogram, ZrleCompat, ZrleOptions,
+};
 use rfb_encodings::zywrle::zywrle_analyze;
-use rfb_encodings::{get_encoder, PixelFormat};
 use rfb_encodings::{
-    ENCODING_CORRE, ENCODING_HEXTILE, ENCODING_RAW, ENCODING_RRE, ENCODING_TIGHT, ENCODING_TIGHTPNG,
+    encode_multi_format, encode_payload_only, encode_raw_with_row_order, flip_rows, get_encoder,
+    Encoding, PixelFormat, RowOrder, ZlibEncoding,
+};
+use rfb_encodings::{
+    ENCODING_CORRE, ENCODING_HEXTILE, ENCODING_RAW, ENCODING_RRE, ENCODING_TIGHT,
+    ENCODING_TIGHTPNG, ENCODING_ZLIB, ENCODING_ZRLE,
 };
 
+use std::io::{ErrorKind, Write};
+
 #[cfg(feature = "generate-golden")]
 use std::path::Path;
 
@@ -99,6 +128,17 @@ fn golden_raw_100x75() {
     golden_check("frame_100x75.raw", &encoded);
 }
 
+#[test]
+fn payload_only_raw_has_no_framing() {
+    let input = load_64x64();
+    let payload = encode_payload_only(ENCODING_RAW, &input, 64, 64, 85, 6).unwrap();
+    assert_eq!(payload.len(), 64 * 64 * 4, "pixels only, no headers");
+    assert_eq!(
+        &payload[..],
+        &encode_with_trait(ENCODING_RAW, &input, 64, 64)[..]
+    );
+}
+
 // --- ZRLE encoding (zlib may vary by OS, but deterministic per-platform) ---
 
 #[test]
@@ -117,6 +157,64 @@ fn golden_zrle_100x75() {
     golden_check("frame_100x75.zrle", &encoded);
 }
 
+#[test]
+fn payload_only_zrle_keeps_just_the_length_prefix() {
+    let input = load_64x64();
+    let payload = encode_payload_only(ENCODING_ZRLE, &input, 64, 64, 85, 6).unwrap();
+    let (prefix, zlib_data) = payload.split_at(4);
+    assert_eq!(
+        u32::from_be_bytes(prefix.try_into().unwrap()) as usize,
+        zlib_data.len(),
+        "the spec's length prefix is the only framing"
+    );
+    assert_eq!(
+        zrle_to_trle(&payload, &mut Decompress::new(true)).unwrap(),
+        debug_tile_stream(&input, 64, 64, &PixelFormat::rgba32()).unwrap()
+    );
+}
+
+#[test]
+fn payload_only_corre_starts_with_the_subrect_count() {
+    let input = load_64x64();
+    let payload = encode_payload_only(ENCODING_CORRE, &input, 64, 64, 85, 6).unwrap();
+    let body = try_encode_corre(&input, 64, 64).unwrap();
+    let (count, rest) = payload.split_at(4);
+    assert_eq!(
+        rest,
+        &body[..],
+        "background pixel and subrects follow the count"
+    );
+    assert_eq!(
+        u32::from_be_bytes(count.try_into().unwrap()) as usize,
+        (body.len() - 4) / 8
+    );
+
+    // RRE's encoder already writes the count
+    let payload = encode_payload_only(ENCODING_RRE, &input, 64, 64, 85, 6).unwrap();
+    assert_eq!(&payload[..], &try_encode_rre(&input, 64, 64).unwrap()[..]);
+}
+
+#[test]
+fn payload_only_tight_is_a_single_rectangle() {
+    // Gradient with no solid areas, small enough not to be split
+    let input: Vec<u8> = (0..32u32 * 32)
+        .flat_map(|i| [(i % 32 * 8) as u8, (i / 32 * 8) as u8, 128, 255])
+        .collect();
+    let payload = encode_payload_only(ENCODING_TIGHT, &input, 32, 32, 85, 6).unwrap();
+    assert_eq!(
+        &payload[..],
+        &encode_with_trait(ENCODING_TIGHT, &input, 32, 32)[..]
+    );
+
+    // Past the maximum Tight rectangle size the encoder splits, which needs headers
+    let input: Vec<u8> = (0..512u32 * 256)
+        .flat_map(|i| i.wrapping_mul(2_654_435_761).to_le_bytes())
+        .collect();
+    let err = encode_payload_only(ENCODING_TIGHT, &input, 512, 256, 85, 6).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("sub-rectangles"), "{err}");
+}
+
 // --- Zlib encoding (zlib may vary by OS) ---
 
 #[test]
@@ -135,6 +233,162 @@ fn golden_zlib_100x75() {
     golden_check("frame_100x75.zlib", &encoded);
 }
 
+/// The trait path compresses on a fresh stream, so it matches a fresh persistent one
+#[test]
+fn golden_zlib_via_trait() {
+    let input = load_64x64();
+    golden_check(
+        "frame_64x64.zlib",
+        &encode_with_trait(ENCODING_ZLIB, &input, 64, 64),
+    );
+    let input = load_100x75();
+    golden_check(
+        "frame_100x75.zlib",
+        &encode_with_trait(ENCODING_ZLIB, &input, 100, 75),
+    );
+}
+
+/// One source frame encoded for RGBA32, BGRX32, XRGB32 and RGB565 clients decodes
+/// correctly in each format
+#[test]
+fn multi_format_encodes_shared_frame_per_format() {
+    let input = load_100x75();
+    let region = (10u16, 5u16, 64u16, 48u16);
+    let formats = [
+        PixelFormat::rgba32(),
+        PixelFormat::bgrx32(),
+        PixelFormat::xrgb32(),
+        PixelFormat::rgb565(),
+    ];
+    let cropped: Vec<u8> = input
+        .chunks(100 * 4)
+        .skip(5)
+        .take(48)
+        .flat_map(|row| row[10 * 4..74 * 4].to_vec())
+        .collect();
+
+    for encoding in [ENCODING_RAW, ENCODING_ZLIB, ENCODING_ZRLE] {
+        let encoded =
+            encode_multi_format(encoding, &input, 100, 75, region, &formats, 85, 6).unwrap();
+        assert_eq!(encoded.len(), formats.len());
+        for ((format, data), want) in encoded.iter().zip(&formats) {
+            assert_eq!(format!("{format:?}"), format!("{want:?}"));
+            let decoded = match encoding {
+                ENCODING_RAW => data.to_vec(),
+                ENCODING_ZLIB => rfb_encodings::decode::decode_zlib(
+                    data,
+                    64,
+                    48,
+                    format,
+                    &mut Decompress::new(true),
+                )
+                .unwrap(),
+                _ => rfb_encodings::decode::decode_zrle(
+                    data,
+                    64,
+                    48,
+                    format,
+                    &mut Decompress::new(true),
+                )
+                .unwrap(),
+            };
+            if format.is_compatible_with_rgba32() {
+                assert!(compare_rgb_only(&decoded, &cropped), "{encoding}");
+            } else {
+                let expected = client_pixels(&cropped, format);
+                assert_eq!(decoded, expected, "{encoding} {format:?}");
+            }
+        }
+    }
+
+    // Encodings without client-format support reject other formats
+    let err = encode_multi_format(ENCODING_HEXTILE, &input, 100, 75, region, &formats, 85, 6)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    let err = encode_multi_format(
+        ENCODING_RAW,
+        &input,
+        100,
+        75,
+        (50, 0, 64, 8),
+        &formats,
+        85,
+        6,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn multi_format_encodes_tight_in_each_client_format() {
+    // Gradient with no solid areas, so Tight keeps it as a single rectangle
+    let input: Vec<u8> = (0..48u32 * 32)
+        .flat_map(|i| [(i % 48 * 5) as u8, (i / 48 * 8) as u8, 200, 255])
+        .collect();
+    let formats = [
+        PixelFormat::rgba32(),
+        PixelFormat::bgrx32(),
+        PixelFormat::xrgb32(),
+        PixelFormat::rgb565(),
+    ];
+    let encoded = encode_multi_format(
+        ENCODING_TIGHT,
+        &input,
+        48,
+        32,
+        (0, 0, 48, 32),
+        &formats,
+        rfb_encodings::tight::JPEG_DISABLED_QUALITY,
+        6,
+    )
+    .unwrap();
+
+    for ((format, data), want) in encoded.iter().zip(&formats) {
+        assert_eq!(format!("{format:?}"), format!("{want:?}"));
+        let decoded = rfb_encodings::decode::TightDecoder::new()
+            .decode_rect(data, 48, 32, format)
+            .unwrap();
+        if format.is_compatible_with_rgba32() {
+            assert!(compare_rgb_only(&decoded, &input));
+        } else {
+            assert_eq!(decoded, client_pixels(&input, format), "{format:?}");
+        }
+    }
+}
+
+/// Zlib through the trait decodes to the input in every client format
+#[test]
+fn zlib_trait_round_trips_client_formats() {
+    let input = load_100x75();
+    for pixel_format in [
+        PixelFormat::rgba32(),
+        PixelFormat::bgrx32(),
+        PixelFormat::xrgb32(),
+        PixelFormat::rgb565(),
+    ] {
+        let encoder = ZlibEncoding {
+            pixel_format: pixel_format.clone(),
+        };
+        let encoded = encoder.encode(&input, 100, 75, 85, 6);
+        let decoded = rfb_encodings::decode::decode_zlib(
+            &encoded,
+            100,
+            75,
+            &pixel_format,
+            &mut Decompress::new(true),
+        )
+        .unwrap();
+        if pixel_format.is_compatible_with_rgba32() {
+            // RGBX: the padding byte is zeroed rather than carrying alpha
+            assert!(compare_rgb_only(&decoded, &input));
+            assert!(decoded.chunks_exact(4).all(|p| p[3] == 0));
+        } else {
+            let expected = client_pixels(&input, &pixel_format);
+            assert_eq!(decoded, expected, "{pixel_format:?}");
+        }
+    }
+}
+
 // --- Tight encoding (uses zlib internally) ---
 
 #[test]
@@ -151,6 +405,222 @@ fn golden_tight_100x75() {
     golden_check("frame_100x75.tight", &encoded);
 }
 
+#[test]
+fn golden_tight_explicit_copy_filter_64x64() {
+    let input = load_64x64();
+    let options = TightOptions {
+        explicit_copy_filter: true,
+        ..TightOptions::default()
+    };
+    let mut compressor = SimpleTightCompressor::new(6);
+    let rects = try_encode_tight_rects(
+        &input,
+        64,
+        64,
+        10,
+        6,
+        &PixelFormat::rgba32(),
+        &options,
+        &mut compressor,
+    )
+    .unwrap();
+    // Full-color rectangles carry the explicit-filter bit and copy filter id 0
+    assert_eq!(rects.len(), 1);
+    assert_eq!(rects[0].4[0], (STREAM_ID_FULL_COLOR | 0x04) << 4);
+    assert_eq!(rects[0].4[1], 0x00);
+
+    let encoded: Vec<u8> = rects.iter().flat_map(|r| r.4.to_vec()).collect();
+    golden_check("frame_64x64.tight_explicit_copy", &encoded);
+}
+
+/// 32x32 frame cycling through `colors` distinct colors in 2x3 blocks, so every
+/// color first appears on row 0 in index order
+fn indexed_frame(colors: u8) -> Vec<u8> {
+    (0..32usize * 32)
+        .flat_map(|p| {
+            let (x, y) = (p % 32, p / 32);
+            let i = u8::try_from((x / 2 + y / 3 * 3) % usize::from(colors)).unwrap();
+            indexed_color(i)
+        })
+        .collect()
+}
+
+fn indexed_color(i: u8) -> [u8; 4] {
+    [
+        i.wrapping_mul(37),
+        i.wrapping_mul(91).wrapping_add(20),
+        255 - i.wrapping_mul(53),
+        255,
+    ]
+}
+
+/// Indexed Tight sends the palette in first-appearance order, so its output is stable
+fn golden_tight_indexed(colors: u8, name: &str) {
+    let input = indexed_frame(colors);
+    let mut compressor = SimpleTightCompressor::new(6);
+    let rects = try_encode_tight_rects(
+        &input,
+        32,
+        32,
+        10,
+        6,
+        &PixelFormat::rgba32(),
+        &TightOptions {
+            indexed_palette: true,
+            ..TightOptions::default()
+        },
+        &mut compressor,
+    )
+    .unwrap();
+    assert_eq!(rects.len(), 1);
+    let payload = &rects[0].4;
+    assert_eq!(payload[0], (STREAM_ID_INDEXED | 0x04) << 4);
+    assert_eq!(payload[1], 0x01, "palette filter");
+    assert_eq!(payload[2], colors - 1);
+    let palette: Vec<u8> = (0..colors)
+        .flat_map(|i| indexed_color(i)[..3].to_vec())
+        .collect();
+    assert_eq!(&payload[3..3 + palette.len()], &palette[..]);
+
+    golden_check(name, payload);
+}
+
+#[test]
+fn golden_tight_indexed_4_colors() {
+    golden_tight_indexed(4, "indexed_32x32_4.tight");
+}
+
+#[test]
+fn golden_tight_indexed_12_colors() {
+    golden_tight_indexed(12, "indexed_32x32_12.tight");
+}
+
+/// The fixture's checkerboard quadrant reads as text, its gradients do not
+#[test]
+fn tight_is_text_like_classifies_fixture_quadrants() {
+    let input = load_64x64();
+    let quadrant = |qx: usize, qy: usize| -> Vec<u8> {
+        (qy * 32..qy * 32 + 32)
+            .flat_map(|y| input[(y * 64 + qx * 32) * 4..(y * 64 + qx * 32 + 32) * 4].to_vec())
+            .collect()
+    };
+    assert!(is_text_like(&quadrant(1, 1), 32, 32), "checkerboard");
+    assert!(
+        !is_text_like(&quadrant(0, 0), 32, 32),
+        "horizontal gradient"
+    );
+    assert!(!is_text_like(&quadrant(1, 0), 32, 32), "vertical gradient");
+    assert!(!is_text_like(&quadrant(0, 1), 32, 32), "solid");
+}
+
+/// 96x64 photo-like frame: smooth gradients with per-pixel grain, so nearly
+/// every pixel has a distinct color
+#[cfg(feature = "turbojpeg")]
+fn photo_frame() -> Vec<u8> {
+    (0..96usize * 64)
+        .flat_map(|i| {
+            let (x, y) = (i % 96, i / 96);
+            let grain = (i.wrapping_mul(2_654_435_761) >> 13) % 9;
+            [
+                (x * 2 + grain) as u8,
+                (y * 3 + grain) as u8,
+                ((x + y) + grain * 2) as u8,
+                255,
+            ]
+        })
+        .collect()
+}
+
+/// JPEG marker codes in stream order, skipping entropy-coded data; EOI must end the image
+#[cfg(feature = "turbojpeg")]
+fn jpeg_markers(jpeg: &[u8]) -> Vec<u8> {
+    let mut markers = Vec::new();
+    let mut pos = 0;
+    while pos + 1 < jpeg.len() {
+        assert_eq!(jpeg[pos], 0xFF, "expected a marker at byte {pos}");
+        let marker = jpeg[pos + 1];
+        markers.push(marker);
+        pos += 2;
+        match marker {
+            0xD8 => {}
+            0xD9 => {
+                assert_eq!(pos, jpeg.len(), "data after EOI");
+                break;
+            }
+            _ => {
+                let len = usize::from(u16::from_be_bytes([jpeg[pos], jpeg[pos + 1]]));
+                pos += len;
+                if marker == 0xDA {
+                    // Scan data runs to the next marker that is not a stuffed 0xFF00 or RST
+                    while !(jpeg[pos] == 0xFF
+                        && jpeg[pos + 1] != 0
+                        && !(0xD0..=0xD7).contains(&jpeg[pos + 1]))
+                    {
+                        pos += 1;
+                    }
+                }
+            }
+        }
+    }
+    markers
+}
+
+/// Tight compact length (1-3 bytes, 7 bits each) at the start of `buf`, and its size
+#[cfg(feature = "turbojpeg")]
+fn compact_length(buf: &[u8]) -> (usize, usize) {
+    let mut len = 0;
+    for (i, &byte) in buf.iter().take(3).enumerate() {
+        len |= usize::from(byte & if i == 2 { 0xFF } else { 0x7F }) << (7 * i);
+        if byte & 0x80 == 0 || i == 2 {
+            return (len, i + 1);
+        }
+    }
+    unreachable!("empty compact length")
+}
+
+/// JPEG bytes vary with the libjpeg-turbo version, so check structure rather than bytes
+#[cfg(feature = "turbojpeg")]
+#[test]
+fn tight_jpeg_structure_on_photo_fixture() {
+    use rfb_encodings::jpeg::jpeg_is_deterministic;
+
+    let input = photo_frame();
+    let rects = try_encode_tight_rects(
+        &input,
+        96,
+        64,
+        2,
+        6,
+        &PixelFormat::rgba32(),
+        &TightOptions::default(),
+        &mut SimpleTightCompressor::new(6),
+    )
+    .unwrap();
+    let jpegs: Vec<&[u8]> = rects
+        .iter()
+        .filter(|r| r.4[0] == 0x90)
+        .map(|r| {
+            let (len, size) = compact_length(&r.4[1..]);
+            assert_eq!(r.4.len(), 1 + size + len, "JPEG length prefix");
+            &r.4[1 + size..]
+        })
+        .collect();
+
+    assert!(!jpeg_is_deterministic());
+    assert!(!jpegs.is_empty(), "photo content is sent as JPEG");
+    for jpeg in jpegs {
+        let markers = jpeg_markers(jpeg);
+        assert_eq!(markers.first(), Some(&0xD8), "starts with SOI");
+        assert_eq!(markers.last(), Some(&0xD9), "ends with EOI");
+        for (marker, name) in [(0xDB, "DQT"), (0xC0, "SOF0"), (0xC4, "DHT"), (0xDA, "SOS")] {
+            assert!(markers.contains(&marker), "missing {name} marker");
+        }
+        let dqt = markers.iter().position(|&m| m == 0xDB).unwrap();
+        let sos = markers.iter().position(|&m| m == 0xDA).unwrap();
+        assert!(dqt < sos, "quantization tables precede the scan");
+    }
+}
+
 // --- TightPNG encoding (PNG compression) ---
 
 #[test]
@@ -371,6 +841,27 @@ fn roundtrip_zlib_decompresses_64x64() {
 
 mod decoders;
 
+/// RGBA pixels laid out by hand in one of the client formats the multi-format
+/// tests use, as an oracle independent of `translate_pixels`
+fn client_pixels(rgba: &[u8], format: &PixelFormat) -> Vec<u8> {
+    let scale =
+        |value: u8, max: u16| u16::try_from(u32::from(value) * u32::from(max) / 255).unwrap();
+    rgba.chunks_exact(4)
+        .flat_map(|p| {
+            let (r, g, b) = (p[0], p[1], p[2]);
+            match (format.bits_per_pixel, format.red_shift, format.blue_shift) {
+                (32, 16, 0) => vec![b, g, r, 0],
+                (32, 8, 24) => vec![0, r, g, b],
+                (16, 11, 0) => {
+                    let value = scale(r, 31) << 11 | scale(g, 63) << 5 | scale(b, 31);
+                    value.to_le_bytes().to_vec()
+                }
+                _ => panic!("no hand-built layout for {format:?}"),
+            }
+        })
+        .collect()
+}
+
 /// Compare two pixel buffers ignoring the alpha/padding byte (4th byte of each pixel)
 fn compare_rgb_only(decoded: &[u8], input: &[u8]) -> bool {
     if decoded.len() != input.len() {
@@ -512,3 +1003,510 @@ fn roundtrip_zrle_16bpp() {
         "ZRLE 16bpp round-trip failed: decoded doesn't match input"
     );
 }
+
+/// The histogram sink sees every pixel and does not change the encoded bytes
+#[test]
+fn zrle_histogram_64x64_dominant_colors() {
+    let input = load_64x64();
+    let pf = PixelFormat::rgba32();
+    let mut histogram = ColorHistogram::new();
+    let encoded = encode_zrle_with_histogram(&input, 64, 64, &pf, 6, &mut histogram).unwrap();
+
+    assert_eq!(encoded, encode_zrle(&input, 64, 64, &pf, 6).unwrap());
+    assert_eq!(histogram.total(), 64 * 64);
+
+    // RGBA32 pixels are read as little-endian u32 values
+    let blue = u32::from_le_bytes([0, 0, 200, 255]);
+    let black = u32::from_le_bytes([0, 0, 0, 255]);
+    let white = u32::from_le_bytes([255, 255, 255, 255]);
+    let top: Vec<u32> = histogram.dominant(3).iter().map(|&(c, _)| c).collect();
+    assert_eq!(top, vec![blue, black, white]);
+    assert_eq!(histogram.count(blue), 32 * 32);
+    // The checkerboard quadrant is half white, half black
+    assert_eq!(histogram.count(white), 32 * 32 / 2);
+    assert!(histogram.count(black) >= 32 * 32 / 2);
+}
+
+/// The solid-tile workaround emits a 2-color packed palette of identical entries
+/// that still decodes to the solid color
+#[test]
+fn zrle_solid_tile_as_palette_workaround() {
+    let pf = PixelFormat::rgba32();
+    let color = [10u8, 20, 30, 255];
+    let input: Vec<u8> = color.repeat(64 * 64);
+
+    let default = encode_zrle(&input, 64, 64, &pf, 6).unwrap();
+    assert_eq!(decoders::decode_zrle_to_tiles(&default).unwrap()[0], 1);
+
+    let options = ZrleOptions {
+        solid_tile_as_palette: true,
+        ..ZrleOptions::default()
+    };
+    let encoded = encode_zrle_with_options(&input, 64, 64, &pf, 6, &options).unwrap();
+    let tiles = decoders::decode_zrle_to_tiles(&encoded).unwrap();
+    // Sub-encoding 2, two 3-byte CPIXELs, then 8 index bytes per row
+    assert_eq!(tiles[0], 2);
+    assert_eq!(&tiles[1..4], &color[..3]);
+    assert_eq!(&tiles[4..7], &color[..3]);
+    assert_eq!(tiles.len(), 1 + 6 + 8 * 64);
+    assert!(tiles[7..].iter().all(|&b| b == 0));
+
+    let decoded = decoders::decode_zrle(&encoded, 64, 64, &pf).unwrap();
+    assert!(compare_rgb_only(&decoded, &input));
+}
+
+/// A tile where palette RLE and packed palette cost the same follows `prefer_rle_on_tie`
+#[test]
+fn zrle_prefer_rle_on_tie_picks_configured_subencoding() {
+    let pf = PixelFormat::rgba32();
+    // Four 16-pixel runs alternating between two colors in an 8x8 tile
+    let input: Vec<u8> = [
+        [200u8, 0, 0, 255],
+        [0, 0, 200, 255],
+        [200, 0, 0, 255],
+        [0, 0, 200, 255],
+    ]
+    .iter()
+    .flat_map(|color| color.repeat(16))
+    .collect();
+
+    // Both cost two 3-byte CPIXELs plus 8 bytes: 2 per run, or 1 index byte per row
+    let sizes = analyze_tile(&input, 8, 8, &pf).estimated_sizes;
+    assert_eq!(sizes.palette_rle, Some(14));
+    assert_eq!(sizes.packed_palette, Some(14));
+
+    for (prefer_rle_on_tie, subencoding) in [(true, 128 + 2), (false, 2)] {
+        let options = ZrleOptions {
+            prefer_rle_on_tie,
+            ..ZrleOptions::default()
+        };
+        let encoded = encode_zrle_with_options(&input, 8, 8, &pf, 6, &options).unwrap();
+        let tiles = decoders::decode_zrle_to_tiles(&encoded).unwrap();
+        assert_eq!(
+            tiles[0], subencoding,
+            "prefer_rle_on_tie = {prefer_rle_on_tie}"
+        );
+        assert_eq!(tiles.len(), 1 + 14);
+
+        let decoded = decoders::decode_zrle(&encoded, 8, 8, &pf).unwrap();
+        assert!(compare_rgb_only(&decoded, &input));
+    }
+
+    // The default keeps the RLE choice made by the plain encoder
+    assert_eq!(
+        encode_zrle(&input, 8, 8, &pf, 6).unwrap(),
+        encode_zrle_with_options(&input, 8, 8, &pf, 6, &ZrleOptions::default()).unwrap()
+    );
+}
+
+/// Encodes `input` with the given sub-encoding flags and returns the first tile's
+/// sub-encoding byte, after checking that the rectangle still decodes exactly
+fn zrle_subencoding_with_compat(input: &[u8], compat: ZrleCompat) -> u8 {
+    let pf = PixelFormat::rgba32();
+    let options = ZrleOptions {
+        compat,
+        ..ZrleOptions::default()
+    };
+    let encoded = encode_zrle_with_options(input, 64, 64, &pf, 6, &options).unwrap();
+    let decoded = decoders::decode_zrle(&encoded, 64, 64, &pf).unwrap();
+    assert!(compare_rgb_only(&decoded, input), "{compat:?}");
+    decoders::decode_zrle_to_tiles(&encoded).unwrap()[0]
+}
+
+/// Disabling solid sends solid tiles as a 2-color palette, then RLE, then raw
+#[test]
+fn zrle_compat_solid_disabled_falls_back_to_palette() {
+    let input: Vec<u8> = [10u8, 20, 30, 255].repeat(64 * 64);
+    let all = ZrleCompat::default();
+    assert_eq!(zrle_subencoding_with_compat(&input, all), 1);
+
+    let no_solid = ZrleCompat {
+        solid: false,
+        ..all
+    };
+    assert_eq!(zrle_subencoding_with_compat(&input, no_solid), 2);
+    let no_packed = ZrleCompat {
+        packed_palette: false,
+        ..no_solid
+    };
+    assert_eq!(zrle_subencoding_with_compat(&input, no_packed), 128 + 2);
+    let no_palette_rle = ZrleCompat {
+        palette_rle: false,
+        ..no_packed
+    };
+    assert_eq!(zrle_subencoding_with_compat(&input, no_palette_rle), 128);
+    let raw_only = ZrleCompat {
+        plain_rle: false,
+        ..no_palette_rle
+    };
+    assert_eq!(zrle_subencoding_with_compat(&input, raw_only), 0);
+}
+
+/// Disabling packed palette moves a checkerboard tile to the next-smallest
+/// sub-encoding: palette RLE, or raw when palette RLE is disabled too
+#[test]
+fn zrle_compat_packed_palette_disabled_falls_back_to_rle_or_raw() {
+    let input: Vec<u8> = (0..64 * 64)
+        .flat_map(|i| {
+            if (i % 64 + i / 64) % 2 == 0 {
+                [255u8, 255, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        })
+        .collect();
+    let all = ZrleCompat::default();
+    assert_eq!(zrle_subencoding_with_compat(&input, all), 2);
+
+    let no_packed = ZrleCompat {
+        packed_palette: false,
+        ..all
+    };
+    assert_eq!(zrle_subencoding_with_compat(&input, no_packed), 128 + 2);
+    // Plain RLE of single pixels costs more than raw
+    let no_palette = ZrleCompat {
+        palette_rle: false,
+        ..no_packed
+    };
+    assert_eq!(zrle_subencoding_with_compat(&input, no_palette), 0);
+}
+
+/// The cost model picks the smallest sub-encoding on the fixtures. Slack: at least
+/// 90% of tiles must pick the measured smallest exactly, and none may exceed it by
+/// more than 5%.
+#[test]
+fn zrle_cost_model_picks_smallest_subencoding_on_fixtures() {
+    let fixtures = [(load_64x64(), 64u16, 64u16), (load_100x75(), 100, 75)];
+    let formats = [
+        PixelFormat::rgba32(),
+        PixelFormat::rgb565(),
+        PixelFormat::bgr233(),
+    ];
+    let (mut tiles, mut exact) = (0, 0);
+    for (data, width, height) in &fixtures {
+        for pf in &formats {
+            let client =
+                rfb_encodings::translate::translate_pixels(data, &PixelFormat::rgba32(), pf);
+            for tile in analyze_compression(&client, *width, *height, pf).unwrap() {
+                let (_, smallest) = tile.smallest();
+                tiles += 1;
+                if tile.chosen_size == smallest {
+                    exact += 1;
+                }
+                assert!(
+                    tile.chosen_size * 100 <= smallest * 105,
+                    "{tile:?} chose {} bytes, smallest is {smallest}",
+                    tile.chosen_size
+                );
+                assert!(tile.ratio() >= 1.0, "{tile:?}");
+            }
+        }
+    }
+    assert_eq!(tiles, 3 * (1 + 4));
+    assert!(
+        exact * 10 >= tiles * 9,
+        "{exact} of {tiles} tiles chose the smallest"
+    );
+}
+
+/// Runs longer than 256 pixels need extra length bytes the estimates leave out
+#[test]
+fn zrle_compression_report_measures_long_runs() {
+    let mut data = [0u8, 0, 0, 255].repeat(64 * 64);
+    data[..4].copy_from_slice(&[255, 255, 255, 255]);
+    let report = analyze_compression(&data, 64, 64, &PixelFormat::rgba32()).unwrap();
+    assert_eq!(report.len(), 1);
+    let tile = &report[0];
+    assert_eq!((tile.x, tile.y, tile.w, tile.h), (0, 0, 64, 64));
+    // One single pixel, then a 4095-pixel run: 16 extra length bytes for RLE
+    assert_eq!(tile.estimated_sizes.plain_rle, 2 * 4);
+    assert_eq!(tile.actual_sizes.plain_rle, 2 * 4 + 16);
+    assert_eq!(
+        tile.actual_sizes.palette_rle,
+        tile.estimated_sizes.palette_rle.map(|size| size + 16)
+    );
+    assert_eq!(tile.actual_sizes.raw, tile.estimated_sizes.raw);
+    assert_eq!(
+        tile.actual_sizes.packed_palette,
+        tile.estimated_sizes.packed_palette
+    );
+    assert_eq!(tile.smallest(), (tile.chosen, tile.chosen_size));
+}
+
+/// Tiling by hand with `encode_zrle_tile` reproduces `encode_zrle` byte for byte
+#[test]
+fn zrle_manual_tiling_100x75_matches_encode_zrle() {
+    let input = load_100x75();
+    let pf = PixelFormat::rgba32();
+    let (width, bpp) = (100usize, 4usize);
+
+    // 100x75 is a 2x2 grid of tiles: 64+36 columns by 64+11 rows
+    let mut tiles = BytesMut::new();
+    for (y, tile_h) in [(0, 64), (64, 11)] {
+        for (x, tile_w) in [(0, 64), (64, 36)] {
+            let mut tile = Vec::with_capacity(tile_w * tile_h * bpp);
+            for row in y..y + tile_h {
+                let start = (row * width + x) * bpp;
+                tile.extend_from_slice(&input[start..start + tile_w * bpp]);
+            }
+            #[allow(clippy::cast_possible_truncation)] // tile sizes are at most 64
+            encode_zrle_tile(&mut tiles, &tile, tile_w as u16, tile_h as u16, &pf).unwrap();
+        }
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&tiles).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let mut manual = u32::try_from(compressed.len())
+        .unwrap()
+        .to_be_bytes()
+        .to_vec();
+    manual.extend_from_slice(&compressed);
+
+    assert_eq!(manual, encode_zrle(&input, 100, 75, &pf, 6).unwrap());
+}
+
+/// Each 32-bit preset stores (R, G, B) = (0x11, 0x22, 0x33) in its documented byte
+/// order, and ZRLE sends and decodes that pixel unchanged
+#[test]
+fn zrle_32bit_presets_roundtrip_channel_order() {
+    for (name, pf, memory) in [
+        ("rgbx32", PixelFormat::rgbx32(), [0x11, 0x22, 0x33, 0x00]),
+        ("bgrx32", PixelFormat::bgrx32(), [0x33, 0x22, 0x11, 0x00]),
+        ("xrgb32", PixelFormat::xrgb32(), [0x00, 0x11, 0x22, 0x33]),
+        ("abgr32", PixelFormat::abgr32(), [0x00, 0x33, 0x22, 0x11]),
+    ] {
+        let value = (0x11u32 << pf.red_shift) | (0x22 << pf.green_shift) | (0x33 << pf.blue_shift);
+        assert_eq!(value.to_le_bytes(), memory, "{name}");
+
+        let input = memory.repeat(64 * 64);
+        let encoded = encode_zrle(&input, 64, 64, &pf, 6).unwrap();
+
+        // Solid tile: sub-encoding 1 and the 3 color bytes, padding dropped
+        let tiles = decoders::decode_zrle_to_tiles(&encoded).unwrap();
+        let color: Vec<u8> = memory.iter().copied().filter(|&b| b != 0).collect();
+        assert_eq!(tiles[0], 1, "{name}");
+        assert_eq!(&tiles[1..4], &color[..], "{name}");
+
+        let decoded = decoders::decode_zrle(&encoded, 64, 64, &pf).unwrap();
+        assert_eq!(decoded, input, "{name}");
+    }
+}
+
+/// The debug tile stream is the zlib payload of `encode_zrle` and parses back to the input
+#[test]
+fn zrle_debug_tile_stream_100x75() {
+    let input = load_100x75();
+    let pf = PixelFormat::rgba32();
+    let tiles = debug_tile_stream(&input, 100, 75, &pf).unwrap();
+
+    let encoded = encode_zrle(&input, 100, 75, &pf, 6).unwrap();
+    assert_eq!(tiles, decoders::decode_zrle_to_tiles(&encoded).unwrap());
+
+    let decoded = decoders::decode_zrle_tile_stream(&tiles, 100, 75, &pf).unwrap();
+    assert!(compare_rgb_only(&decoded, &input));
+}
+
+/// ZRLE -> TRLE -> ZRLE keeps the tile stream and the decoded image, across
+/// rectangles sharing one zlib stream on each hop
+#[test]
+fn zrle_trle_transcode_roundtrip() {
+    let pf = PixelFormat::rgba32();
+    let frames = [(load_64x64(), 64, 64), (load_100x75(), 100, 75)];
+    let mut server = Compress::new(Compression::new(6), true);
+    let mut proxy_in = Decompress::new(true);
+    let mut proxy_out = Compress::new(Compression::new(6), true);
+    let mut client = Decompress::new(true);
+
+    for (input, width, height) in &frames {
+        let encoded =
+            rfb_encodings::encode_zrle_persistent(input, *width, *height, &pf, &mut server)
+                .unwrap();
+        let trle = zrle_to_trle(&encoded, &mut proxy_in).unwrap();
+        assert_eq!(
+            trle,
+            debug_tile_stream(input, *width, *height, &pf).unwrap()
+        );
+
+        let reencoded = trle_to_zrle(&trle, &mut proxy_out).unwrap();
+        let tiles = zrle_to_trle(&reencoded, &mut client).unwrap();
+        assert_eq!(tiles, trle);
+        let decoded = decoders::decode_zrle_tile_stream(&tiles, *width, *height, &pf).unwrap();
+        assert!(compare_rgb_only(&decoded, input));
+    }
+
+    // Length prefix promising more bytes than follow
+    assert!(zrle_to_trle(&[0, 0, 0, 9, 1], &mut Decompress::new(true)).is_err());
+}
+
+/// Bottom-up input encodes to the vertically flipped image of top-down input
+#[test]
+fn bottom_up_rows_encode_flipped_100x75() {
+    let input = load_100x75();
+    let mut flipped = input.clone();
+    flip_rows(&mut flipped, 100 * 4);
+    let pf = PixelFormat::rgba32();
+
+    let raw = encode_raw_with_row_order(&input, 100, 75, RowOrder::BottomUp);
+    assert_eq!(
+        raw,
+        encode_raw_with_row_order(&flipped, 100, 75, RowOrder::TopDown)
+    );
+    assert_eq!(
+        encode_raw_with_row_order(&input, 100, 75, RowOrder::TopDown),
+        encode_with_trait(ENCODING_RAW, &input, 100, 75)
+    );
+
+    let options = ZrleOptions {
+        row_order: RowOrder::BottomUp,
+        ..ZrleOptions::default()
+    };
+    let zrle = encode_zrle_with_options(&input, 100, 75, &pf, 6, &options).unwrap();
+    let decoded = decoders::decode_zrle(&zrle, 100, 75, &pf).unwrap();
+    assert!(compare_rgb_only(&decoded, &flipped));
+    assert!(!compare_rgb_only(&decoded, &input));
+}
+
+/// Assert the 4-byte prefix is the big-endian payload length
+fn assert_be_length_prefix(name: &str, encoded: &[u8]) {
+    let prefix = [encoded[0], encoded[1], encoded[2], encoded[3]];
+    let payload_len = encoded.len() - 4;
+    assert_eq!(u32::from_be_bytes(prefix) as usize, payload_len, "{name}");
+    // Payloads over 255 bytes tell big-endian from little-endian apart
+    assert!(
+        payload_len > 255,
+        "{name}: payload too small to check byte order"
+    );
+    assert_ne!(u32::from_le_bytes(prefix) as usize, payload_len, "{name}");
+}
+
+#[test]
+fn length_prefix_is_big_endian_for_all_zlib_encoders() {
+    let input = load_100x75();
+    let pf = PixelFormat::rgba32();
+
+    let zrle = encode_zrle(&input, 100, 75, &pf, 6).unwrap();
+    assert_be_length_prefix("ZRLE", &zrle);
+
+    let mut compressor = Compress::new(Compression::new(6), true);
+    let zrle =
+        rfb_encodings::encode_zrle_persistent(&input, 100, 75, &pf, &mut compressor).unwrap();
+    assert_be_length_prefix("ZRLE persistent", &zrle);
+
+    let mut compressor = Compress::new(Compression::new(6), true);
+    let zlib = encode_zlib_persistent(&input, &mut compressor).unwrap();
+    assert_be_length_prefix("Zlib", &zlib);
+
+    let mut compressor = Compress::new(Compression::new(6), true);
+    let zlibhex = encode_zlibhex_persistent(&input, 100, 75, &mut compressor).unwrap();
+    assert_be_length_prefix("ZlibHex", &zlibhex);
+}
+
+/// Encode ZlibHex and decode it with a matching persistent decompressor
+fn roundtrip_zlibhex(input: &[u8], width: u16, height: u16) {
+    let pf = PixelFormat::rgba32();
+    let mut compressor = Compress::new(Compression::new(6), true);
+    let mut decompressor = Decompress::new(true);
+
+    // Two rectangles on the same stream: the second only decodes if both sides
+    // share one zlib stream with its dictionary carried over
+    for _ in 0..2 {
+        let encoded = encode_zlibhex_persistent(input, width, height, &mut compressor).unwrap();
+        let decoded = decoders::decode_zlibhex(&encoded, width, height, &pf, &mut decompressor)
+            .expect("ZlibHex decode failed");
+        assert_eq!(decoded.len(), input.len(), "ZlibHex decoded size mismatch");
+        assert!(
+            compare_rgb_only(&decoded, input),
+            "ZlibHex round-trip failed: RGB components don't match"
+        );
+    }
+}
+
+#[test]
+fn roundtrip_zlibhex_full_64x64() {
+    roundtrip_zlibhex(&load_64x64(), 64, 64);
+}
+
+#[test]
+fn roundtrip_zlibhex_full_100x75() {
+    roundtrip_zlibhex(&load_100x75(), 100, 75);
+}
+
+#[test]
+fn zlibhex_output_is_deterministic() {
+    let input = load_100x75();
+    let encode = || {
+        let mut compressor = Compress::new(Compression::new(6), true);
+        encode_zlibhex_persistent(&input, 100, 75, &mut compressor).unwrap()
+    };
+    assert_eq!(encode(), encode());
+}
+
+#[test]
+fn zrle_raw_transition_ratio_does_not_grow_fixtures() {
+    use rfb_encodings::zrle::SUGGESTED_RAW_TRANSITION_RATIO;
+
+    let pf = PixelFormat::rgba32();
+    let options = ZrleOptions {
+        raw_transition_ratio: Some(SUGGESTED_RAW_TRANSITION_RATIO),
+        ..ZrleOptions::default()
+    };
+    for (input, width, height) in [(load_64x64(), 64, 64), (load_100x75(), 100, 75)] {
+        let default = encode_zrle(&input, width, height, &pf, 6).unwrap();
+        let entropy = encode_zrle_with_options(&input, width, height, &pf, 6, &options).unwrap();
+        assert!(
+            entropy.len() <= default.len(),
+            "{width}x{height}: {} bytes vs {} by default",
+            entropy.len(),
+            default.len()
+        );
+        let decoded = decoders::decode_zrle(&entropy, width, height, &pf).unwrap();
+        for (out, px) in decoded.chunks_exact(4).zip(input.chunks_exact(4)) {
+            assert_eq!(out[..3], px[..3]);
+        }
+    }
+}
+
+#[test]
+fn zrle_inspect_lists_tiles_of_100x75() {
+    use rfb_encodings::zrle::{inspect, inspect_tiles, TileInfo};
+
+    let input = load_100x75();
+    let pf = PixelFormat::rgba32();
+    let encoded = encode_zrle(&input, 100, 75, &pf, 6).unwrap();
+    let tiles = inspect(&encoded, 100, 75, &pf).unwrap();
+
+    // 2x2 grid clipped at the right and bottom edges
+    let grid: Vec<_> = tiles.iter().map(|t| (t.x, t.y, t.w, t.h)).collect();
+    assert_eq!(
+        grid,
+        [
+            (0, 0, 64, 64),
+            (64, 0, 36, 64),
+            (0, 64, 64, 11),
+            (64, 64, 36, 11)
+        ]
+    );
+    // The noisy fixture is sent raw
+    assert!(tiles
+        .iter()
+        .all(|t| t.subencoding == 0 && t.palette_len == 0));
+    let stream = debug_tile_stream(&input, 100, 75, &pf).unwrap();
+    assert_eq!(inspect_tiles(&stream, 100, 75, &pf).unwrap(), tiles);
+
+    // The few-color 64x64 fixture is a single palette RLE tile
+    let input = load_64x64();
+    let encoded = encode_zrle(&input, 64, 64, &pf, 6).unwrap();
+    let tiles = inspect(&encoded, 64, 64, &pf).unwrap();
+    assert_eq!(tiles.len(), 1);
+    let TileInfo {
+        subencoding,
+        palette_len,
+        ..
+    } = tiles[0];
+    assert_eq!(usize::from(subencoding), 128 + palette_len);
+    assert!(palette_len >= 2, "palette of {palette_len}");
+
+    // A truncated tile stream is reported, not skipped
+    assert!(inspect_tiles(&stream[..stream.len() - 1], 100, 75, &pf).is_err());
+}